@@ -0,0 +1,123 @@
+// Benchmarks of the per-object work of the transform and load steps, run with `cargo bench`.
+// The helpers are internal to huracan-core, so we pull them in (they're dependency free) directly.
+use std::{io::Cursor, str::FromStr};
+
+use bson::Document;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::json;
+use sui_types::base_types::ObjectType;
+
+#[allow(dead_code)]
+#[path = "../src/ids.rs"]
+mod ids;
+#[allow(dead_code)]
+#[path = "../src/types.rs"]
+mod types;
+#[allow(dead_code)]
+#[path = "../src/utils.rs"]
+mod utils;
+
+use types::{glob_matches, TypeAliases, TypePattern};
+use utils::{check_obj_type_from_string_vec, coin_balance, object_bson_bytes, object_update, type_params, KioskObject};
+
+// roughly what we get for a coin from sui_multiGetObjects
+fn coin_object() -> serde_json::Value {
+	json!({
+		"objectId": "0x5d7b2ee1b3c8b8f7f0a3f5e5a0c6a1d1c0e7e5e0f0f0a3c5d7b2ee1b3c8b8f7f",
+		"version": "0x1a2b3c",
+		"digest": "7Ljq9SCRyuR6BY6JYTnHjBbmBhwkNjd1Fi6TTgG6mS3o",
+		"type": "0x2::coin::Coin<0x2::sui::SUI>",
+		"owner": { "AddressOwner": "0x7d20dcdb2bca4f508ea9613994683eb4e76e9c4ed371169677c1be02aaf0b58e" },
+		"previousTransaction": "9XcL3ZyQVZzN9wK3sY3gUu8Rk8k6bKq2J7y6X8cP5Dtq",
+		"storageRebate": "988000",
+		"content": {
+			"dataType": "moveObject",
+			"type": "0x2::coin::Coin<0x2::sui::SUI>",
+			"hasPublicTransfer": true,
+			"fields": {
+				"balance": "18446744073709551",
+				"id": { "id": "0x5d7b2ee1b3c8b8f7f0a3f5e5a0c6a1d1c0e7e5e0f0f0a3c5d7b2ee1b3c8b8f7f" },
+			},
+		},
+		"bcs": {
+			"dataType": "moveObject",
+			"type": "0x2::coin::Coin<0x2::sui::SUI>",
+			"hasPublicTransfer": true,
+			"version": 1715004,
+			"bcsBytes": "XXsu4bPIuPfwo/XloMah0cDn5eDw8KPF17LuGzyLj38vF7+HRucAAA==",
+		},
+	})
+}
+
+fn serialization(c: &mut Criterion) {
+	let object = coin_object();
+	let bytes = object_bson_bytes(&object).unwrap();
+	c.bench_function("object_bson_bytes", |b| b.iter(|| object_bson_bytes(black_box(&object)).unwrap()));
+	c.bench_function("object_bson_bytes_via_document", |b| {
+		b.iter(|| {
+			let mut bytes = Vec::with_capacity(4096);
+			bson::to_document(black_box(&object)).unwrap().to_writer(&mut bytes).unwrap();
+			bytes
+		})
+	});
+	c.bench_function("document_from_bytes", |b| {
+		b.iter(|| Document::from_reader(&mut Cursor::new(black_box(&bytes))).unwrap())
+	});
+}
+
+fn type_tags(c: &mut Criterion) {
+	let pool = "0xdee9::clob_v2::Pool<0x2::sui::SUI, 0x5d4b302506645c37ff133b98c4b50a5ae14841659738d6d733d59d0d217a93bf::coin::COIN>";
+	let ty = ObjectType::from_str("0x2::coin::Coin<0x2::sui::SUI>").unwrap();
+	let list = vec!["0x2::coin::Coin<0x2::sui::SUI>".to_string(), "0x3::staking_pool::StakedSui".to_string()];
+	c.bench_function("type_params", |b| b.iter(|| type_params(black_box(pool))));
+	c.bench_function("glob_matches", |b| b.iter(|| glob_matches(black_box("0x*::clob_v2::Pool<*>"), black_box(pool))));
+	let pattern = TypePattern::new("0xdee9::clob_v2::Pool<*>", &TypeAliases::default());
+	c.bench_function("type_pattern_matches", |b| b.iter(|| pattern.matches(black_box(pool))));
+	c.bench_function("check_obj_type_from_string_vec", |b| {
+		b.iter_batched(|| list.clone(), |list| check_obj_type_from_string_vec(black_box(&ty), list), BatchSize::SmallInput)
+	});
+}
+
+fn content(c: &mut Criterion) {
+	let doc = bson::to_document(&coin_object()).unwrap();
+	c.bench_function("coin_balance", |b| b.iter(|| coin_balance(black_box(&doc))));
+	c.bench_function("kiosk_object", |b| b.iter(|| KioskObject::from_doc(black_box(&doc))));
+}
+
+fn batch_documents(c: &mut Criterion) {
+	let doc = bson::to_document(&coin_object()).unwrap();
+	let ids = (0..1000).map(|i| format!("0x{:064x}", i)).collect::<Vec<_>>();
+	c.bench_function("object_update_x1000", |b| {
+		b.iter(|| ids.iter().map(|id| object_update(id, "0x1a2b3c", false, black_box(&doc), None, "mainnet")).collect::<Vec<_>>())
+	});
+}
+
+// Per-object work of the load step with all derived collections enabled: before, each of the 10 derived writers
+// (history, balances, NFTs, owners, type stats, kiosks, staking, plugins, webhooks, topic routes) decoded the object
+// from its bytes again, now it's decoded once and the document is shared.
+fn load_decoding(c: &mut Criterion) {
+	let bytes = object_bson_bytes(&coin_object()).unwrap();
+	let derive = |object: &Document| {
+		black_box(coin_balance(object));
+		black_box(KioskObject::from_doc(object));
+		black_box(object.get_str("type").ok());
+	};
+	c.bench_function("load_decode_per_writer_x10", |b| {
+		b.iter(|| {
+			for _ in 0..10 {
+				derive(&Document::from_reader(&mut Cursor::new(black_box(&bytes))).unwrap());
+			}
+		})
+	});
+	c.bench_function("load_decode_once_x10", |b| {
+		b.iter(|| {
+			let object = Document::from_reader(&mut Cursor::new(black_box(&bytes))).unwrap();
+			for _ in 0..10 {
+				derive(&object);
+			}
+		})
+	});
+}
+
+criterion_group!(benches, serialization, type_tags, content, batch_documents, load_decoding);
+criterion_main!(benches);
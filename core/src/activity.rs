@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use bson::{doc, DateTime, Document};
+use mongodb::{Database, IndexModel};
+use sui_sdk::rpc_types::{ObjectChange, SuiTransactionBlockDataAPI, SuiTransactionBlockResponse};
+use sui_types::object::Owner;
+
+use crate::{_prelude::*, influx::write_metric_mongo_write_error, mongo::mongo_collection_name};
+
+// With `activity.enabled`, the checkpoint scan records which addresses took part in every transaction block in a
+// separate collection, e.g. prod_testnet_objects_address_activity, with one document per address and transaction,
+// keyed by `<address>:<digest>`. Everything that happened to a wallet is then a single range query on its address.
+// Roles:
+// - sender: the address sent the transaction block
+// - recipient: the address received objects, created for it or transferred to it
+// - owner: objects owned by the address were changed by someone else
+pub fn address_activity_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_address_activity")
+}
+
+pub async fn ensure_address_activity_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&address_activity_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "address": 1, "ts": -1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "tx": 1 }).build(), None).await?;
+	Ok(())
+}
+
+fn address_owner(owner: &Owner) -> Option<String> {
+	match owner {
+		Owner::AddressOwner(address) => Some(address.to_string()),
+		_ => None,
+	}
+}
+
+fn activity_documents(block: &SuiTransactionBlockResponse) -> Vec<Document> {
+	let Some(sender) = block.transaction.as_ref().map(|t| t.data.sender().to_string()) else { return Vec::new() };
+	// address -> (roles, affected objects)
+	let mut activity = BTreeMap::<String, (Vec<&str>, Vec<String>)>::new();
+	let mut add = |address: String, role: &'static str, object_id: Option<String>| {
+		let (roles, objects) = activity.entry(address).or_default();
+		if !roles.contains(&role) {
+			roles.push(role);
+		}
+		if let Some(object_id) = object_id.filter(|object_id| !objects.contains(object_id)) {
+			objects.push(object_id);
+		}
+	};
+	add(sender.clone(), "sender", None);
+	for change in block.object_changes.iter().flatten() {
+		let (object_id, recipient, owner) = match change {
+			ObjectChange::Created { object_id, owner, .. } => (*object_id, address_owner(owner), None),
+			ObjectChange::Transferred { object_id, recipient, .. } => (*object_id, address_owner(recipient), None),
+			ObjectChange::Mutated { object_id, owner, .. } => (*object_id, None, address_owner(owner)),
+			ObjectChange::Deleted { object_id, .. } | ObjectChange::Wrapped { object_id, .. } => {
+				add(sender.clone(), "sender", Some(object_id.to_string()));
+				continue
+			}
+			ObjectChange::Published { .. } => continue,
+		};
+		add(sender.clone(), "sender", Some(object_id.to_string()));
+		if let Some(recipient) = recipient.filter(|recipient| *recipient != sender) {
+			add(recipient, "recipient", Some(object_id.to_string()));
+		}
+		if let Some(owner) = owner.filter(|owner| *owner != sender) {
+			add(owner, "owner", Some(object_id.to_string()));
+		}
+	}
+	activity
+		.into_iter()
+		.map(|(address, (roles, objects))| {
+			let mut row = doc! {
+				"_id": format!("{}:{}", address, block.digest),
+				"address": address,
+				"tx": block.digest.to_string(),
+				"roles": roles,
+				"objects": objects,
+			};
+			if let Some(cp) = block.checkpoint {
+				row.insert("checkpoint", cp as i64);
+			}
+			if let Some(ts) = block.timestamp_ms {
+				row.insert("ts", DateTime::from_millis(ts as i64));
+			}
+			row
+		})
+		.collect()
+}
+
+// Stores the address activity of a page of the checkpoint scan. Transactions are immutable, so storing the same
+// activity again is a no-op.
+pub async fn store_activity(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	db: &Database,
+	blocks: &[SuiTransactionBlockResponse],
+) {
+	let updates = blocks
+		.iter()
+		.flat_map(activity_documents)
+		.map(|row| {
+			doc! {
+				"q": doc! { "_id": row.get_str("_id").unwrap() },
+				"u": doc! { "$setOnInsert": row },
+				"upsert": true,
+				"multi": false,
+			}
+		})
+		.collect::<Vec<_>>();
+	if updates.is_empty() {
+		return
+	}
+	let collection = address_activity_collection_name(cfg);
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		match db.run_command(doc! { "update": &collection, "updates": updates.clone(), "ordered": false }, None).await {
+			Ok(_) => break,
+			Err(err) => {
+				write_metric_mongo_write_error().await;
+				if retries_left == 0 {
+					error!(error = ?err, "failed to store {} address activity rows", updates.len());
+					break
+				}
+				warn!("error storing address activity, will retry {} more times: {:?}", retries_left, err);
+				retries_left -= 1;
+			}
+		}
+	}
+}
@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+use serde_json::json;
+
+use crate::{_prelude::*, conf::AlertsConfig};
+
+#[derive(Copy, Clone, Debug)]
+pub enum Stage {
+	Extract,
+	Transform,
+	Load,
+}
+
+impl Stage {
+	const ALL: [Stage; 3] = [Stage::Extract, Stage::Transform, Stage::Load];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Stage::Extract => "extract",
+			Stage::Transform => "transform",
+			Stage::Load => "load",
+		}
+	}
+}
+
+// errors per stage since the last check, indexed by `Stage as usize`
+static ERRORS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+// latest known number of checkpoints we're behind the chain tip
+static CHECKPOINTS_BEHIND: AtomicU64 = AtomicU64::new(0);
+
+pub fn record_error(stage: Stage) {
+	ERRORS[stage as usize].fetch_add(1, Relaxed);
+}
+
+pub fn record_checkpoints_behind(behind_by: u64) {
+	CHECKPOINTS_BEHIND.store(behind_by, Relaxed);
+}
+
+// Checks error counts and chain lag every `intervalms`, and posts an alert to the configured webhook whenever one
+// of them exceeds its threshold. The same alert is sent again at most once per `cooldownms`.
+pub fn spawn_alerts(cfg: &AppConfig) {
+	let cfg = cfg.alerts.clone();
+	if !cfg.enabled {
+		return
+	}
+	tokio::spawn(async move {
+		let client = reqwest::Client::new();
+		let mut last_sent = HashMap::new();
+		loop {
+			tokio::time::sleep(Duration::from_millis(cfg.intervalms)).await;
+			let mut alerts = Vec::new();
+			for stage in Stage::ALL {
+				let errors = ERRORS[stage as usize].swap(0, Relaxed);
+				if errors > cfg.maxerrors {
+					alerts.push((
+						stage.name(),
+						format!("{} errors in {} stage within the last {}s", errors, stage.name(), cfg.intervalms / 1000),
+					));
+				}
+			}
+			let behind_by = CHECKPOINTS_BEHIND.load(Relaxed);
+			if behind_by > cfg.maxcheckpointsbehind {
+				alerts.push(("lag", format!("indexer is behind by {} checkpoints", behind_by)));
+			}
+
+			let cooldown = Duration::from_millis(cfg.cooldownms);
+			for (key, summary) in alerts {
+				if last_sent.get(key).map_or(false, |sent: &Instant| sent.elapsed() < cooldown) {
+					continue
+				}
+				last_sent.insert(key, Instant::now());
+				warn!("AlertWarning: {}", summary);
+				let res = client
+					.post(&cfg.webhookurl)
+					.json(&payload(&cfg, key, &summary))
+					.send()
+					.await
+					.and_then(|res| res.error_for_status());
+				if let Err(err) = res {
+					warn!(error = ?err, "AlertWarning: Failed posting alert to webhook.");
+				}
+			}
+		}
+	});
+}
+
+// Slack (incoming webhooks) just needs a `text`, PagerDuty needs an Events API v2 payload.
+fn payload(cfg: &AlertsConfig, key: &str, summary: &str) -> serde_json::Value {
+	let app = deployment_name();
+	if cfg.format == "pagerduty" {
+		json!({
+			"routing_key": cfg.routingkey,
+			"event_action": "trigger",
+			"dedup_key": format!("{}-{}", app, key),
+			"payload": {
+				"summary": format!("[{}] {}", app, summary),
+				"source": app,
+				"severity": "error",
+			},
+		})
+	} else {
+		json!({ "text": format!("[{}] {}", app, summary) })
+	}
+}
+
+// e.g. "prod_mainnet_objects", so alerts from different deployments can be told apart
+fn deployment_name() -> String {
+	let cfg = crate::conf::get_config_singleton();
+	format!("{}_{}_{}", cfg.env, cfg.net, cfg.mongo.collectionbase)
+}
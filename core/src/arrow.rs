@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use arrow::{
+	array::{ArrayRef, BooleanBuilder, StringBuilder, UInt64Builder},
+	datatypes::{DataType, Field, Schema, SchemaRef},
+	record_batch::RecordBatch,
+};
+use bson::{Bson, Document};
+use futures::{Stream, StreamExt};
+
+use crate::etl::ObjectItem;
+
+// Loaded objects as Arrow RecordBatches, one row per change, for the Parquet sink (see parquet.rs) and for services
+// embedding the pipeline that want to feed DataFusion, Polars or the like directly, e.g. from a sink:
+//
+//   let batch = huracan_core::arrow::record_batch(chunk, objects, cfg.network())?;
+//
+// The schema is stable: columns are only ever added, at the end, which bumps `SCHEMA_VERSION`. Objects are taken as
+// sui-sdk serializes them, which is how sinks and plugins see them, not as stored (see `naming.style`).
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub fn schema() -> SchemaRef {
+	Arc::new(Schema::new(vec![
+		Field::new("object_id", DataType::Utf8, false),
+		Field::new("version", DataType::UInt64, false),
+		// 0 for changes ingested via polling, which don't know their checkpoint
+		Field::new("checkpoint", DataType::UInt64, false),
+		// unix timestamp in ms of the checkpoint, if known
+		Field::new("timestamp_ms", DataType::UInt64, true),
+		Field::new("deleted", DataType::Boolean, false),
+		Field::new("network", DataType::Utf8, false),
+		// all of the following are null for deletions
+		Field::new("type", DataType::Utf8, true),
+		// "address", "object", "shared" or "immutable"
+		Field::new("owner_kind", DataType::Utf8, true),
+		// the owning address or object, null for shared and immutable objects
+		Field::new("owner", DataType::Utf8, true),
+		Field::new("previous_transaction", DataType::Utf8, true),
+		Field::new("storage_rebate", DataType::UInt64, true),
+		// the object's content as relaxed extended JSON
+		Field::new("content", DataType::Utf8, true),
+		// the object's BCS bytes, base64 encoded as by sui-sdk
+		Field::new("bcs", DataType::Utf8, true),
+	]))
+}
+
+// One row per item of `chunk`. `objects` holds the object data of each item, if it isn't a deletion, as passed to
+// plugins and sinks.
+pub fn record_batch(chunk: &[ObjectItem], objects: &[Option<Document>], network: &str) -> anyhow::Result<RecordBatch> {
+	let n = chunk.len();
+	let mut object_id = StringBuilder::with_capacity(n, n * 66);
+	let mut version = UInt64Builder::with_capacity(n);
+	let mut checkpoint = UInt64Builder::with_capacity(n);
+	let mut timestamp_ms = UInt64Builder::with_capacity(n);
+	let mut deleted = BooleanBuilder::with_capacity(n);
+	let mut network_ = StringBuilder::with_capacity(n, n * network.len());
+	let mut type_ = StringBuilder::new();
+	let mut owner_kind = StringBuilder::new();
+	let mut owner = StringBuilder::new();
+	let mut previous_transaction = StringBuilder::new();
+	let mut storage_rebate = UInt64Builder::with_capacity(n);
+	let mut content = StringBuilder::new();
+	let mut bcs = StringBuilder::new();
+	for (item, object) in chunk.iter().zip(objects.iter().chain(std::iter::repeat(&None))) {
+		object_id.append_value(item.id.to_string());
+		version.append_value(item.version.value());
+		checkpoint.append_value(item.cp);
+		timestamp_ms.append_option(item.ts_sui);
+		deleted.append_value(item.deletion);
+		network_.append_value(network);
+		let object = object.as_ref().filter(|_| !item.deletion);
+		type_.append_option(object.and_then(|o| o.get_str("type").ok()));
+		let (kind, owned_by) = object.map_or((None, None), |o| owner_of(o));
+		owner_kind.append_option(kind);
+		owner.append_option(owned_by);
+		previous_transaction.append_option(object.and_then(|o| o.get_str("previousTransaction").ok()));
+		storage_rebate.append_option(object.and_then(|o| o.get_str("storageRebate").ok()?.parse().ok()));
+		content.append_option(
+			object
+				.and_then(|o| o.get_document("content").ok())
+				.map(|c| Bson::Document(c.clone()).into_relaxed_extjson().to_string()),
+		);
+		bcs.append_option(object.and_then(|o| o.get_document("bcs").ok()?.get_str("bcsBytes").ok()));
+	}
+	let columns: Vec<ArrayRef> = vec![
+		Arc::new(object_id.finish()),
+		Arc::new(version.finish()),
+		Arc::new(checkpoint.finish()),
+		Arc::new(timestamp_ms.finish()),
+		Arc::new(deleted.finish()),
+		Arc::new(network_.finish()),
+		Arc::new(type_.finish()),
+		Arc::new(owner_kind.finish()),
+		Arc::new(owner.finish()),
+		Arc::new(previous_transaction.finish()),
+		Arc::new(storage_rebate.finish()),
+		Arc::new(content.finish()),
+		Arc::new(bcs.finish()),
+	];
+	Ok(RecordBatch::try_new(schema(), columns)?)
+}
+
+// The same for a stream of chunks, e.g. collected by a sink.
+pub fn record_batches<'a, S>(chunks: S, network: String) -> impl Stream<Item = anyhow::Result<RecordBatch>> + 'a
+where
+	S: Stream<Item = (Vec<ObjectItem>, Vec<Option<Document>>)> + 'a,
+{
+	chunks.map(move |(chunk, objects)| record_batch(&chunk, &objects, &network))
+}
+
+fn owner_of(object: &Document) -> (Option<&'static str>, Option<&str>) {
+	match object.get("owner") {
+		Some(Bson::Document(owner)) => {
+			if let Ok(address) = owner.get_str("AddressOwner") {
+				(Some("address"), Some(address))
+			} else if let Ok(parent) = owner.get_str("ObjectOwner") {
+				(Some("object"), Some(parent))
+			} else if owner.contains_key("Shared") {
+				(Some("shared"), None)
+			} else {
+				(None, None)
+			}
+		}
+		Some(Bson::String(owner)) if owner == "Immutable" => (Some("immutable"), None),
+		_ => (None, None),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use arrow::array::{Array, BooleanArray, StringArray, UInt64Array};
+	use bson::doc;
+
+	use super::*;
+	use crate::etl::test_item;
+
+	#[test]
+	fn test_record_batch() {
+		let object = doc! {
+			"type": "0x2::coin::Coin<0x2::sui::SUI>",
+			"owner": { "AddressOwner": "0x7" },
+			"previousTransaction": "tx",
+			"storageRebate": "100",
+			"content": { "dataType": "moveObject", "fields": { "balance": "10" } },
+		};
+		let batch = record_batch(&[test_item(3, false), test_item(3, true)], &[Some(object), None], "testnet").unwrap();
+		assert_eq!(batch.schema(), schema());
+		assert_eq!(batch.num_rows(), 2);
+		let column = |name: &str| batch.column(batch.schema().index_of(name).unwrap()).clone();
+		let strings = |name: &str| column(name).as_any().downcast_ref::<StringArray>().unwrap().clone();
+		assert_eq!(strings("type").value(0), "0x2::coin::Coin<0x2::sui::SUI>");
+		assert!(strings("type").is_null(1));
+		assert_eq!(strings("owner_kind").value(0), "address");
+		assert_eq!(strings("owner").value(0), "0x7");
+		assert_eq!(column("storage_rebate").as_any().downcast_ref::<UInt64Array>().unwrap().value(0), 100);
+		assert!(column("deleted").as_any().downcast_ref::<BooleanArray>().unwrap().value(1));
+		assert!(strings("content").value(0).contains("\"balance\":\"10\""));
+	}
+}
@@ -0,0 +1,166 @@
+use bson::{doc, Bson, Document};
+use mongodb::{
+	error::{TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT},
+	options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions},
+	ClientSession, Collection, Database, IndexModel,
+};
+
+use crate::{
+	_prelude::*,
+	etl::ObjectItem,
+	mongo::{is_duplicate_key_error, mongo_collection_name},
+	utils::coin_balance,
+};
+
+// With `balances.enabled`, the load step maintains the total balance per address and coin type in a separate
+// collection, e.g. prod_testnet_objects_balances, so wallets don't need to sum up all of their coins on every read.
+// Balances are derived from the coin objects themselves: for every coin, we remember the owner and balance it last
+// contributed (collection suffix `_balance_coins`), and apply the difference to its new owner / balance.
+// Transaction balance changes are deltas, which we couldn't apply idempotently, so they're not used.
+// Moving a coin forward and applying its difference to the balances happen in one transaction: if we only got as far as
+// the coin, the difference would be lost for good, as the coin's next version only applies the difference to this one.
+// This needs MongoDB to run as a replica set (or sharded cluster), which we check on startup.
+// MongoDB has no unsigned integers, so balances are only correct up to 2^63 - 1.
+pub fn balances_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_balances")
+}
+
+fn balance_coins_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_balance_coins")
+}
+
+pub async fn ensure_balances_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let hello = db.run_command(doc! { "hello": 1 }, None).await?;
+	if !hello.contains_key("setName") && hello.get_str("msg").ok() != Some("isdbgrid") {
+		anyhow::bail!("balances.enabled needs MongoDB to run as a replica set or sharded cluster, for transactions")
+	}
+	let coll = db.collection::<Document>(&balances_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "address": 1, "coin_type": 1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "coin_type": 1, "balance": -1 }).build(), None).await?;
+	Ok(())
+}
+
+// Applies the balance changes of all coins in `chunk`, which has just been loaded, with `objects` as fetched (see
+// `etl::load_batched()`). Transient transaction errors, e.g. write conflicts, are retried. If a coin still fails, its
+// transaction is rolled back, so the balances stay off by the coin's last change only until it changes again, and its
+// next version applies the difference to the version we had before.
+pub async fn update_balances(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	db: &Database,
+	chunk: &[ObjectItem],
+	objects: &[Document],
+) {
+	let coins = db.collection::<Document>(&balance_coins_collection_name(cfg));
+	let balances = db.collection::<Document>(&balances_collection_name(cfg));
+	for (item, object) in chunk.iter().zip(objects) {
+		if let Err(err) = update_coin(db, pc.mongo.retries, &coins, &balances, item, object).await {
+			warn!(object_id = ?item.id, error = ?err, "BalanceWarning: Failed updating balances for coin.");
+		}
+	}
+}
+
+async fn update_coin(
+	db: &Database,
+	retries: usize,
+	coins: &Collection<Document>,
+	balances: &Collection<Document>,
+	item: &ObjectItem,
+	object: &Document,
+) -> anyhow::Result<()> {
+	let mut session = db.client().start_session(None).await?;
+	let mut retries_left = retries;
+	loop {
+		session.start_transaction(None).await?;
+		let result = match apply_coin(&mut session, coins, balances, item, object).await {
+			Ok(true) => session.commit_transaction().await.map_err(anyhow::Error::from),
+			// nothing to do
+			Ok(false) => session.abort_transaction().await.map_err(anyhow::Error::from),
+			Err(err) => {
+				// the server aborts it, too, if the session is still around
+				session.abort_transaction().await.ok();
+				Err(err)
+			}
+		};
+		let Err(err) = result else { return Ok(()) };
+		let transient = err.downcast_ref::<mongodb::error::Error>().map_or(false, |err| {
+			err.contains_label(TRANSIENT_TRANSACTION_ERROR) || err.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT)
+		});
+		if !transient || retries_left == 0 {
+			return Err(err)
+		}
+		retries_left -= 1;
+	}
+}
+
+// Returns whether there's anything to commit.
+async fn apply_coin(
+	session: &mut ClientSession,
+	coins: &Collection<Document>,
+	balances: &Collection<Document>,
+	item: &ObjectItem,
+	object: &Document,
+) -> anyhow::Result<bool> {
+	let v = item.version.value() as i64;
+	// only move forward, so replayed or out of order versions don't count twice
+	let filter = doc! { "_id": item.id.to_string(), "version_": { "$lt": v } };
+	let (update, upsert, new) = if item.deletion {
+		// if we don't know the object, it either wasn't a coin or we've never seen it, so there's nothing to undo
+		(doc! { "$set": { "version_": v, "address": Bson::Null, "balance": 0i64 } }, false, None)
+	} else {
+		let Some((coin_type, owner, balance)) = coin_balance(object) else { return Ok(false) };
+		let update = doc! { "$set": {
+			"version_": v,
+			"coin_type": &coin_type,
+			"address": owner.clone().map_or(Bson::Null, Bson::String),
+			"balance": balance as i64,
+		}};
+		(update, true, owner.map(|owner| (owner, coin_type, balance as i64)))
+	};
+	let opts = FindOneAndUpdateOptions::builder().upsert(upsert).return_document(ReturnDocument::Before).build();
+	let previous = match coins.find_one_and_update_with_session(filter, update, opts, session).await {
+		Ok(previous) => previous,
+		// the coin is already stored with a newer version
+		Err(err) if is_duplicate_key_error(&err) => return Ok(false),
+		Err(err) => return Err(err.into()),
+	};
+	if previous.is_none() && item.deletion {
+		return Ok(false)
+	}
+	if let Some(previous) = &previous {
+		if let Ok(owner) = previous.get_str("address") {
+			let coin_type = previous.get_str("coin_type")?;
+			add(session, balances, owner, coin_type, -previous.get_i64("balance")?, -1).await?;
+		}
+	}
+	if let Some((owner, coin_type, balance)) = new {
+		add(session, balances, &owner, &coin_type, balance, 1).await?;
+	}
+	Ok(true)
+}
+
+async fn add(
+	session: &mut ClientSession,
+	balances: &Collection<Document>,
+	owner: &str,
+	coin_type: &str,
+	balance: i64,
+	coins: i64,
+) -> anyhow::Result<()> {
+	let id = format!("{}:{}", owner, coin_type);
+	balances
+		.update_one_with_session(
+			doc! { "_id": &id },
+			doc! {
+				"$inc": { "balance": balance, "coins": coins },
+				"$setOnInsert": { "address": owner, "coin_type": coin_type },
+			},
+			UpdateOptions::builder().upsert(true).build(),
+			session,
+		)
+		.await?;
+	if coins < 0 {
+		balances.delete_one_with_session(doc! { "_id": &id, "coins": { "$lte": 0 } }, None, session).await?;
+	}
+	Ok(())
+}
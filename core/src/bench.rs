@@ -0,0 +1,141 @@
+use std::{
+	fs::File,
+	io::{BufRead, BufReader, BufWriter, Write},
+	sync::atomic::AtomicU16,
+};
+
+use bson::Document;
+use futures::stream;
+
+use crate::{
+	_prelude::*,
+	cli::BenchArgs,
+	etl::{load_batched, spawn_checkpoint_poll, transform_batched, ObjectItem, StepStatus},
+	mongo::mongo_collection_name,
+	utils::percentile,
+};
+
+// Entrypoint for the `bench` subcommand.
+// Replays recorded object changes through the transform (RPC) and load (MongoDB) steps, using the batch sizes and
+// number of workers of the selected pipeline config, and reports throughput + per-batch latency for each step.
+// Loading goes to separate `_bench` collections, which are dropped again afterwards.
+pub async fn run_bench(cfg: &AppConfig, args: BenchArgs) -> anyhow::Result<()> {
+	let pc = cfg.pipeline(args.pipeline).clone();
+
+	if let Some(n) = args.record {
+		record(cfg, n, &args).await?;
+	}
+
+	let items = BufReader::new(File::open(&args.input).with_context(|| format!("cannot open {:?}", args.input))?)
+		.lines()
+		.map(|line| -> anyhow::Result<ObjectItem> { Ok(serde_json::from_str(&line?)?) })
+		.collect::<anyhow::Result<Vec<_>>>()?;
+	info!("BenchInfo: Replaying {} recorded object changes from {:?}.", items.len(), args.input);
+
+	let sui = cfg.sui().await?;
+	let default_num_workers = sui.configs.len();
+	let num_object_workers = pc.workers.object.unwrap_or(default_num_workers);
+	let num_mongo_workers = pc.workers.mongo.unwrap_or(default_num_workers);
+
+	// step 1: transform, running as many concurrent batches as we'd have object workers
+	let chunks = items.chunks(pc.objectqueries.batchsize).map(|c| c.to_vec()).collect::<Vec<_>>();
+	let started = Instant::now();
+	let results = stream::iter(chunks)
+		.map(|chunk| {
+			let sui = sui.clone();
+			async move {
+				let started = Instant::now();
+				let res = transform_batched(stream::iter(vec![chunk]), sui, None).await.collect::<Vec<_>>().await;
+				(started.elapsed(), res)
+			}
+		})
+		.buffer_unordered(num_object_workers)
+		.collect::<Vec<_>>()
+		.await;
+	let transform_elapsed = started.elapsed();
+	let mut transform_latencies = Vec::with_capacity(results.len());
+	let mut transformed = Vec::with_capacity(items.len());
+	let mut failed = 0;
+	for (latency, res) in results {
+		transform_latencies.push(latency);
+		for (status, item) in res {
+			if let StepStatus::Err(_) = status {
+				failed += 1;
+			} else {
+				transformed.push(item);
+			}
+		}
+	}
+
+	// step 2: load into a throwaway collection
+	let mut bench_cfg = cfg.clone();
+	bench_cfg.mongo.collectionbase = format!("{}_bench", cfg.mongo.collectionbase);
+	let db = bench_cfg.mongo.client(&pc.mongo).await?;
+	let num_loaded = transformed.len();
+	let chunks = transformed.chunks(pc.mongo.batchsize).map(|c| c.to_vec()).collect::<Vec<_>>();
+	let started = Instant::now();
+	let load_latencies = stream::iter(chunks)
+		.map(|chunk| {
+			let (cfg, pc, db) = (bench_cfg.clone(), pc.clone(), db.clone());
+			async move {
+				// load_batched() reports every item back to the pipeline tail, so we need enough room for all of them
+				let (last_tx, _last_rx) = tokio::sync::mpsc::channel(chunk.len().max(1));
+				let started = Instant::now();
+				load_batched(cfg, pc, stream::iter(vec![chunk]), db, last_tx).await;
+				started.elapsed()
+			}
+		})
+		.buffer_unordered(num_mongo_workers)
+		.collect::<Vec<_>>()
+		.await;
+	let load_elapsed = started.elapsed();
+
+	// everything loading wrote to: the objects collection, and whatever is derived from it (history, balances, routed
+	// and plugin collections, ...), all named after the bench collection base
+	let prefix = mongo_collection_name(&bench_cfg, "");
+	for name in db.list_collection_names(None).await? {
+		if name.starts_with(&prefix) {
+			db.collection::<Document>(&name).drop(None).await?;
+		}
+	}
+
+	println!("pipeline: {:?} ({} object workers, {} mongo workers)", args.pipeline, num_object_workers, num_mongo_workers);
+	print_report("transform", items.len(), transform_elapsed, transform_latencies);
+	if failed > 0 {
+		println!("           {} objects failed to be fetched", failed);
+	}
+	print_report("load", num_loaded, load_elapsed, load_latencies);
+
+	Ok(())
+}
+
+// Polls the chain for new object changes and stores the first `n` of them, so they can be replayed later on.
+async fn record(cfg: &AppConfig, n: usize, args: &BenchArgs) -> anyhow::Result<()> {
+	info!("BenchInfo: Recording {} object changes to {:?}.", n, args.input);
+	let sui = cfg.sui().await?;
+	let (items, _observed_cps) = spawn_checkpoint_poll(cfg, sui, Arc::new(AtomicU16::new(0))).await;
+	let mut out = BufWriter::new(File::create(&args.input)?);
+	let mut items = items.take(n);
+	while let Some((_, item)) = items.next().await {
+		serde_json::to_writer(&mut out, &item)?;
+		out.write_all(b"\n")?;
+	}
+	out.flush()?;
+	Ok(())
+}
+
+fn print_report(step: &str, num_items: usize, elapsed: Duration, mut latencies: Vec<Duration>) {
+	latencies.sort_unstable();
+	let ms = |p: f64| percentile(&latencies, p).map(|d| d.as_millis()).unwrap_or_default();
+	println!(
+		"{:>9}: {} objects in {:.2}s ({:.1} objects/s) // batch latency p50 {}ms / p90 {}ms / p99 {}ms / max {}ms",
+		step,
+		num_items,
+		elapsed.as_secs_f64(),
+		num_items as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+		ms(0.5),
+		ms(0.9),
+		ms(0.99),
+		ms(1.0),
+	);
+}
@@ -0,0 +1,123 @@
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use crate::_prelude::*;
+
+// The transport between the steps of the decoupled pipeline (see decoupled.rs). Messages are opaque payloads with a
+// key; brokers must deliver messages with the same key to the same consumer of a subscription, in order.
+#[async_trait]
+pub trait MessageBus: Send + Sync + 'static {
+	type Producer: BusProducer;
+	type Consumer: BusConsumer;
+
+	// Verifies the topic is set up the way we expect it to be, if needed.
+	async fn prepare_topic(&self, topic: &str) -> anyhow::Result<()>;
+	async fn producer(&self, topic: &str) -> anyhow::Result<Self::Producer>;
+	// Consumers of the same subscription share its messages by key.
+	async fn consumer(&self, topic: &str, subscription: &str) -> anyhow::Result<Self::Consumer>;
+}
+
+#[async_trait]
+pub trait BusProducer: Send + 'static {
+	// Might return before the broker has confirmed it received the message, see `flush()`.
+	async fn send(&mut self, key: String, payload: Vec<u8>) -> anyhow::Result<()>;
+	// Returns once the broker has confirmed all messages sent so far.
+	async fn flush(&mut self) -> anyhow::Result<()> {
+		Ok(())
+	}
+}
+
+#[async_trait]
+pub trait BusConsumer: Send + 'static {
+	// Whatever the broker needs to ack a message.
+	type Receipt: Send + Sync;
+
+	// Receives up to `max` messages, for at most `wait`. Returns an empty batch if nothing arrived in the meantime.
+	async fn recv_batch(&mut self, max: usize, wait: Duration) -> anyhow::Result<Vec<(Vec<u8>, Self::Receipt)>>;
+	// Marks messages as processed, so they won't be delivered to this subscription again.
+	async fn ack(&mut self, receipts: Vec<Self::Receipt>) -> anyhow::Result<()>;
+	// Hands back messages we failed to process, so they're delivered again after a backoff. Returns how many of
+	// them were given up on instead (e.g. moved to a dead-letter topic), which won't be delivered again.
+	async fn nack(&mut self, receipts: Vec<Self::Receipt>) -> anyhow::Result<usize>;
+}
+
+// Publishes whatever is sent to the returned queue to `topic` on the configured `bus`, in the background, as key and
+// payload. Sending waits while `queuesize` messages are waiting to be published already. Messages are kept until the
+// broker has confirmed them: batches that fail are published again (with a new producer) after a backoff, for as long
+// as it takes, so messages may be published more than once, but are never dropped. Meanwhile, the queue fills up.
+pub(crate) async fn spawn_publisher(
+	cfg: &AppConfig,
+	topic: &str,
+	queuesize: usize,
+) -> anyhow::Result<mpsc::Sender<(String, Vec<u8>)>> {
+	match cfg.bus.as_str() {
+		#[cfg(feature = "pulsar")]
+		"pulsar" => spawn_publisher_on(crate::pulsar::PulsarBus, topic, queuesize).await,
+		#[cfg(feature = "kafka")]
+		"kafka" => spawn_publisher_on(crate::kafka::KafkaBus::new(cfg), topic, queuesize).await,
+		#[cfg(feature = "redis")]
+		"redis" => spawn_publisher_on(crate::redisstreams::RedisBus::new(cfg)?, topic, queuesize).await,
+		bus => Err(anyhow!(
+			"unsupported bus: {} (expected: pulsar | kafka | redis, each requires the feature of the same name)",
+			bus
+		)),
+	}
+}
+
+const PUBLISH_BACKOFF_MS: u64 = 500;
+const PUBLISH_MAX_BACKOFF_MS: u64 = 30_000;
+
+async fn spawn_publisher_on<B: MessageBus>(
+	bus: B,
+	topic: &str,
+	queuesize: usize,
+) -> anyhow::Result<mpsc::Sender<(String, Vec<u8>)>> {
+	bus.prepare_topic(topic).await?;
+	let mut producer = Some(bus.producer(topic).await?);
+	let (tx, mut rx) = mpsc::channel::<(String, Vec<u8>)>(queuesize.max(1));
+	let topic = topic.to_string();
+	tokio::spawn(async move {
+		while let Some(message) = rx.recv().await {
+			// everything that's queued up right now goes out together
+			let mut batch = vec![message];
+			while let Ok(next) = rx.try_recv() {
+				batch.push(next);
+			}
+			let mut attempt = 0;
+			loop {
+				let Err(err) = publish_batch(&bus, &topic, &mut producer, &batch).await else { break };
+				// the producer may be broken for good, so the next attempt starts with a new one
+				producer = None;
+				attempt += 1;
+				let backoff = (PUBLISH_BACKOFF_MS << (attempt - 1).min(16)).min(PUBLISH_MAX_BACKOFF_MS);
+				warn!(
+					topic,
+					attempt,
+					error = ?err,
+					"BusWarning: Failed publishing {} messages, retrying in {}ms.",
+					batch.len(),
+					backoff
+				);
+				tokio::time::sleep(Duration::from_millis(backoff)).await;
+			}
+		}
+	});
+	Ok(tx)
+}
+
+// Sends all of `batch` and waits for the broker to confirm it, with a new producer if there's none.
+async fn publish_batch<B: MessageBus>(
+	bus: &B,
+	topic: &str,
+	producer: &mut Option<B::Producer>,
+	batch: &[(String, Vec<u8>)],
+) -> anyhow::Result<()> {
+	if producer.is_none() {
+		*producer = Some(bus.producer(topic).await?);
+	}
+	let producer = producer.as_mut().unwrap();
+	for (key, payload) in batch {
+		producer.send(key.clone(), payload.clone()).await?;
+	}
+	producer.flush().await
+}
@@ -0,0 +1,49 @@
+use serde_json::json;
+use tokio::sync::{mpsc, OnceCell};
+
+use crate::{_prelude::*, bus::spawn_publisher, etl::ObjectItem};
+
+// Applied writes waiting to be published, as key and payload. Only set up by `spawn_change_feed()`.
+static QUEUE: OnceCell<mpsc::Sender<(String, Vec<u8>)>> = OnceCell::const_new();
+
+// With `changefeed.enabled`, every write the load step applied to the objects collection (or the one it's routed to,
+// see routing.rs) is announced on the `changefeed.topic` topic of our `bus` (e.g. prod_testnet_objects_applied), as a
+// compact JSON event:
+// `{"object_id": "0x…", "version": 42, "operation": "insert" | "update" | "delete", "collection": "…", "network": "…"}`
+// Downstream caches and search indexes can then invalidate or refresh what they hold without tailing MongoDB's oplog.
+// Events are keyed by object id, so the events of an object arrive in order. They're published in the background,
+// but unlike webhooks they're never dropped: failed publishes are retried until the broker confirms them (see
+// `bus::spawn_publisher()`), and if the queue is full meanwhile, loading waits for it. An event may be published more
+// than once, though.
+pub async fn spawn_change_feed(cfg: &AppConfig) -> anyhow::Result<()> {
+	if !cfg.changefeed.enabled {
+		return Ok(())
+	}
+	let tx = spawn_publisher(cfg, &cfg.changefeed.topic, cfg.changefeed.queuesize).await?;
+	QUEUE.set(tx).map_err(|_| anyhow!("changefeed has already been set up"))
+}
+
+// To be called once a chunk has been written, each item to the collection at the same index of `collections`.
+// `inserted` are the indices of the items whose objects didn't exist before.
+pub async fn publish(cfg: &AppConfig, items: &[ObjectItem], collections: &[String], inserted: &HashSet<usize>) {
+	let Some(tx) = QUEUE.get() else { return };
+	for (i, (item, collection)) in items.iter().zip(collections).enumerate() {
+		let operation = match (item.deletion, inserted.contains(&i)) {
+			(true, _) => "delete",
+			(false, true) => "insert",
+			(false, false) => "update",
+		};
+		let event = json!({
+			"object_id": item.id.to_string(),
+			"version": item.version.value(),
+			"operation": operation,
+			"collection": collection,
+			"network": cfg.network(),
+		});
+		let payload = serde_json::to_vec(&event).unwrap();
+		if tx.send((item.id.to_string(), payload)).await.is_err() {
+			warn!("ChangeFeedWarning: Change feed stopped, not publishing applied writes anymore.");
+			return
+		}
+	}
+}
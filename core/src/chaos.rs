@@ -0,0 +1,40 @@
+use rand::Rng;
+use sui_sdk::error::{Error as SuiRpcError, SuiRpcResult};
+
+use crate::{
+	_prelude::*,
+	conf::{ChaosConfig, APPCONFIG},
+};
+
+// Fault injection for resilience testing, only compiled in with the `chaos` feature and only active with `--chaos`
+// (or chaos.enabled). At the configured rates, read API calls fail or are slowed down, and the object writes of the
+// load step fail, so retries, client rotation, the dead-letter store and ordering can be exercised end-to-end in
+// short runs. Don't set mongoerrorrate close to 1.0, the load step gives up (panics) once its retries are used up.
+
+fn active() -> Option<&'static ChaosConfig> {
+	APPCONFIG.get().map(|cfg| &cfg.chaos).filter(|chaos| chaos.enabled)
+}
+
+fn roll(rate: f64) -> bool {
+	rate > 0.0 && rand::thread_rng().gen_bool(rate.min(1.0))
+}
+
+pub async fn maybe_delay_rpc() {
+	if let Some(chaos) = active().filter(|chaos| roll(chaos.rpcslowrate)) {
+		tokio::time::sleep(Duration::from_millis(chaos.rpcslowms)).await;
+	}
+}
+
+pub fn maybe_fail_rpc<T>(res: SuiRpcResult<T>) -> SuiRpcResult<T> {
+	if let Some(chaos) = active().filter(|chaos| roll(chaos.rpcerrorrate)) {
+		return Err(SuiRpcError::DataError("chaos: injected RPC error".into()))
+	}
+	res
+}
+
+pub fn maybe_fail_mongo<T>(res: mongodb::error::Result<T>) -> mongodb::error::Result<T> {
+	if let Some(chaos) = active().filter(|chaos| roll(chaos.mongoerrorrate)) {
+		return Err(std::io::Error::new(std::io::ErrorKind::Other, "chaos: injected MongoDB write error").into())
+	}
+	res
+}
@@ -0,0 +1,90 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{options::ReplaceOptions, Database, IndexModel};
+use sui_sdk::rpc_types::{Checkpoint as SuiCheckpoint, CheckpointId};
+use sui_types::messages_checkpoint::CheckpointSequenceNumber;
+
+use crate::{
+	_prelude::*,
+	client::ClientPool,
+	epochs,
+	influx::write_metric_mongo_write_error,
+	mongo::mongo_collection_name,
+};
+
+// With `checkpoints.enabled`, the checkpoint scan additionally stores a summary of every checkpoint it completes,
+// e.g. prod_testnet_objects_checkpoint_summaries, keyed by sequence number. This is separate from the `_checkpoints`
+// collection, which only tracks our own progress and is rewritten as we go.
+// Objects (via their history), transactions and checkpoint summaries all carry the checkpoint sequence number, so this
+// is where timestamps and epochs can be joined in.
+pub fn checkpoint_summaries_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_checkpoint_summaries")
+}
+
+pub async fn ensure_checkpoint_summaries_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&checkpoint_summaries_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "ts": -1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "epoch": 1, "_id": 1 }).build(), None).await?;
+	Ok(())
+}
+
+fn checkpoint_document(cp: &SuiCheckpoint) -> Document {
+	let gas = &cp.epoch_rolling_gas_cost_summary;
+	doc! {
+		"_id": cp.sequence_number as i64,
+		"digest": cp.digest.to_string(),
+		"epoch": cp.epoch as i64,
+		"ts": DateTime::from_millis(cp.timestamp_ms as i64),
+		"tx_count": cp.transactions.len() as i64,
+		"network_total_transactions": cp.network_total_transactions as i64,
+		// totals of the epoch up to and including this checkpoint, as reported by Sui
+		"epoch_gas": {
+			"computation_cost": gas.computation_cost as i64,
+			"storage_cost": gas.storage_cost as i64,
+			"storage_rebate": gas.storage_rebate as i64,
+			"non_refundable_storage_fee": gas.non_refundable_storage_fee as i64,
+		},
+	}
+}
+
+// Fetches and stores the summary of checkpoint `cp`. Failing to do so is not fatal for the rest of the pipeline, the
+// summary will just be missing until the checkpoint is processed again.
+pub async fn store_checkpoint_summary(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	sui: &mut ClientPool,
+	db: &Database,
+	cp: CheckpointSequenceNumber,
+) {
+	let checkpoint = match sui.get_checkpoint(CheckpointId::SequenceNumber(cp)).await {
+		Ok(checkpoint) => checkpoint,
+		Err(err) => {
+			warn!(error = ?err, "ExtractionError: Failed fetching summary of checkpoint {}, not storing it.", cp);
+			return
+		}
+	};
+	let summary = checkpoint_document(&checkpoint);
+	let coll = db.collection::<Document>(&checkpoint_summaries_collection_name(cfg));
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		let res = coll
+			.replace_one(doc! { "_id": cp as i64 }, summary.clone(), ReplaceOptions::builder().upsert(true).build())
+			.await;
+		match res {
+			Ok(_) => break,
+			Err(err) => {
+				write_metric_mongo_write_error().await;
+				if retries_left == 0 {
+					error!(error = ?err, "failed to store summary of checkpoint {}", cp);
+					break
+				}
+				warn!("error storing summary of checkpoint {}, will retry {} more times: {:?}", cp, retries_left, err);
+				retries_left -= 1;
+			}
+		}
+	}
+	if checkpoint.end_of_epoch_data.is_some() {
+		if let Err(err) = epochs::store_epoch_end(cfg, db, &checkpoint).await {
+			warn!(error = ?err, "EpochWarning: Failed storing summary of epoch {}.", checkpoint.epoch);
+		}
+	}
+}
@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+use async_channel::Receiver as ACReceiver;
+use async_stream::stream;
+use futures::Stream;
+
+use crate::{_prelude::*, statsd};
+
+// Adaptive sizing of the multi-get chunks of the transform step, with `objectqueries.adaptive`. A fixed size and wait
+// is either too small for a local fullnode or too large for a rate limited public RPC, so instead we grow the chunks
+// step by step (up to `objectqueries.batchsize`) while multi-gets stay below `objectqueries.targetlatencyms`, and halve
+// them as soon as one gets slow or fails. The wait for filling a chunk shrinks along with its size, as there's no point
+// in waiting the full `batchwaittimeoutms` for a handful of objects.
+pub struct ChunkSizing {
+	pipeline:  String,
+	max:       usize,
+	maxwaitms: u64,
+	targetms:  u64,
+	size:      AtomicUsize,
+}
+
+impl ChunkSizing {
+	pub fn new(pc: &PipelineConfig) -> Self {
+		let max = pc.objectqueries.batchsize.max(1);
+		Self {
+			pipeline:  pc.name.clone(),
+			max,
+			maxwaitms: pc.objectqueries.batchwaittimeoutms,
+			targetms:  pc.objectqueries.targetlatencyms,
+			// start in the middle, it only takes a few chunks to get to either end
+			size:      AtomicUsize::new((max / 2).max(1)),
+		}
+	}
+
+	pub fn size(&self) -> usize {
+		self.size.load(Relaxed)
+	}
+
+	pub fn wait(&self) -> Duration {
+		Duration::from_millis((self.maxwaitms * self.size() as u64 / self.max as u64).max(10))
+	}
+
+	// To be called with the outcome of every multi-get.
+	pub fn record(&self, elapsed_ms: u64, ok: bool) {
+		let size = self.size();
+		let next = if !ok || elapsed_ms > self.targetms {
+			(size / 2).max(1)
+		} else if elapsed_ms < self.targetms / 2 {
+			(size + (self.max / 10).max(1)).min(self.max)
+		} else {
+			size
+		};
+		if next != size {
+			self.size.store(next, Relaxed);
+			debug!(pipeline = self.pipeline, "multi-get chunk size {} -> {} (took {}ms, ok: {})", size, next, elapsed_ms, ok);
+			statsd::gauge("objectqueries_chunk_size", next as u64, &[("pipeline", self.pipeline.as_str())]);
+		}
+	}
+}
+
+// Like `chunks_timeout()`, but with the size and wait of every chunk taken from `sizing` when it's started.
+pub fn adaptive_chunks<T: 'static>(rx: ACReceiver<T>, sizing: Arc<ChunkSizing>) -> impl Stream<Item = Vec<T>> {
+	stream! {
+		// the wait for a chunk starts with its first item
+		while let Ok(first) = rx.recv().await {
+			let size = sizing.size();
+			let deadline = Instant::now() + sizing.wait();
+			let mut chunk = Vec::with_capacity(size);
+			chunk.push(first);
+			while chunk.len() < size {
+				match time::timeout_at(deadline, rx.recv()).await {
+					Ok(Ok(item)) => chunk.push(item),
+					// closed or waited long enough
+					Ok(Err(_)) | Err(_) => break,
+				}
+			}
+			yield chunk;
+		}
+	}
+}
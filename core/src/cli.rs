@@ -0,0 +1,279 @@
+use std::path::PathBuf;
+
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+
+use crate::conf::{parse_sample_rate, AppConfig, ExtractConfig};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Sui object indexer")]
+pub struct Args {
+	#[command(subcommand)]
+	pub command:     Option<Command>,
+	/// Use only this RPC endpoint for the configured network, instead of the providers from the config file.
+	#[arg(long, global = true)]
+	pub rpc_url:     Option<String>,
+	/// Override `mongo.uri` from the config file.
+	#[arg(long, global = true)]
+	pub mongo_uri:   Option<String>,
+	/// Override `mongo.db` from the config file.
+	#[arg(long, global = true)]
+	pub database:    Option<String>,
+	/// Randomly inject RPC errors, slow RPC responses and MongoDB write failures at the rates from `chaos` in the
+	/// config file.
+	#[cfg(feature = "chaos")]
+	#[arg(long, global = true)]
+	pub chaos:       bool,
+	/// Only index this fraction of all transaction blocks, e.g. 0.01, chosen by their digest, so every instance picks
+	/// the same ones. Overrides `samplerate` from the config file. Meant for staging environments.
+	#[arg(long, global = true, value_parser = parse_sample_rate)]
+	pub sample_rate: Option<f64>,
+}
+
+impl Args {
+	// CLI flags take precedence over both the config file and APP_ env vars.
+	pub fn apply_overrides(&self, cfg: &mut AppConfig) {
+		if let Some(url) = &self.rpc_url {
+			cfg.override_rpc_url(url.clone());
+		}
+		if let Some(uri) = &self.mongo_uri {
+			cfg.mongo.uri = uri.clone();
+			// would be read by `load_secrets()` afterwards, replacing the URI we've been given
+			cfg.mongo.urifile = None;
+		}
+		if let Some(db) = &self.database {
+			cfg.mongo.db = db.clone();
+		}
+		#[cfg(feature = "chaos")]
+		if self.chaos {
+			cfg.chaos.enabled = true;
+		}
+		if self.sample_rate.is_some() {
+			cfg.samplerate = self.sample_rate;
+		}
+		if let Some(Command::Run(run_args)) = &self.command {
+			if let Some(extract) = run_args.start() {
+				cfg.extract = extract;
+			}
+		}
+	}
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+	/// Run the full indexing pipeline (default if no subcommand is given).
+	Run(RunArgs),
+	/// Poll for new transactions, fetch full object data and pretty-print matching objects to stdout.
+	/// Nothing is written to MongoDB or Pulsar.
+	Watch(WatchArgs),
+	/// Replay a recorded set of objects through transform and load against a throwaway collection and report
+	/// per-stage throughput and latency percentiles.
+	Bench(BenchArgs),
+	/// Re-inject items from the dead-letter store (the "retries" topic) into the pipeline, marking them as resolved
+	/// once they've been loaded successfully.
+	#[cfg(feature = "pulsar")]
+	ReplayDlq(ReplayDlqArgs),
+	/// Inspect the dead-letter topic of the raw or enriched topic used with `decoupled: true`, or with `--replay`
+	/// move its messages back to where they came from.
+	#[cfg(feature = "pulsar")]
+	DeadLetters(DeadLettersArgs),
+	/// Run transform and load again for the changes recorded in a checkpoint range (requires rawchanges.enabled).
+	Replay(ReplayArgs),
+	/// Collapse object versions older than a retention window into periodic keyframes (requires history.enabled).
+	Compact(CompactArgs),
+	/// Move our stored progress back to a given checkpoint (or the one before a given transaction), so everything
+	/// after it gets re-processed on the next run.
+	Rewind(RewindArgs),
+	/// Cross-check completed checkpoints, stored transactions and objects for a checkpoint range, and report every
+	/// part of it with a discrepancy.
+	Check(CheckArgs),
+	/// Split a checkpoint range into work items stored in MongoDB, to be processed by any number of `work` processes.
+	Coordinate(CoordinateArgs),
+	/// Repeatedly lease a work item created by `coordinate`, backfill its checkpoint range and mark it as done.
+	/// Exits once no work is left.
+	Work,
+}
+
+#[derive(ClapArgs, Clone, Debug, Default)]
+pub struct RunArgs {
+	/// Where to start, overriding `extract.start` from the config file: resume (from our last stored progress),
+	/// latest (the current chain tip), or genesis (index everything). See also --start-checkpoint and
+	/// --start-digest.
+	#[arg(
+		long,
+		value_parser = ["resume", "latest", "genesis"],
+		conflicts_with_all = ["start_checkpoint", "start_digest", "from_latest"]
+	)]
+	pub start:            Option<String>,
+	/// Start at this checkpoint: every older one will be treated as already completed.
+	#[arg(long, conflicts_with_all = ["start_digest", "from_latest"])]
+	pub start_checkpoint: Option<u64>,
+	/// Start at the checkpoint containing this transaction: every older one will be treated as already completed.
+	#[arg(long, conflicts_with = "from_latest")]
+	pub start_digest:     Option<String>,
+	/// Same as `--start latest`.
+	#[arg(long)]
+	pub from_latest:      bool,
+	/// Exit once we've caught up with the chain tip, instead of running indefinitely. Useful for cron-style
+	/// catch-up runs.
+	#[arg(long)]
+	pub exit_at_tip:      bool,
+	/// Maximum number of checkpoints we may be behind the chain tip to consider it reached. Defaults to
+	/// `backfillthreshold`.
+	#[arg(long, requires = "exit_at_tip")]
+	pub max_tip_lag:      Option<u64>,
+	/// Don't verify RPC and MongoDB connectivity before starting the pipeline.
+	#[arg(long)]
+	pub skip_self_check:  bool,
+	/// If another instance holds the instance lock, wait and take over once it stops renewing its lease, instead
+	/// of exiting. Requires `lock.enabled`.
+	#[arg(long)]
+	pub standby:          bool,
+}
+
+impl RunArgs {
+	// The start position given via flags, if any. The flags conflict with each other, so there's at most one.
+	fn start(&self) -> Option<ExtractConfig> {
+		let (start, checkpoint, digest) = match (&self.start, self.start_checkpoint, &self.start_digest) {
+			(Some(start), _, _) => (start.clone(), None, None),
+			(_, Some(cp), _) => ("checkpoint".to_string(), Some(cp), None),
+			(_, _, Some(digest)) => ("digest".to_string(), None, Some(digest.clone())),
+			_ if self.from_latest => ("latest".to_string(), None, None),
+			_ => return None,
+		};
+		Some(ExtractConfig { start, checkpoint, digest })
+	}
+}
+
+#[derive(ClapArgs, Clone, Debug)]
+pub struct WatchArgs {
+	/// Only print objects whose type starts with this, e.g. `0xdee9::clob_v2::Pool`.
+	#[arg(long = "type")]
+	pub type_: Option<String>,
+	/// Only print objects directly owned by this address or object id.
+	#[arg(long)]
+	pub owner: Option<String>,
+}
+
+#[derive(ClapArgs, Clone, Debug)]
+pub struct BenchArgs {
+	/// File with recorded objects to replay, one JSON encoded object change per line.
+	#[arg(long)]
+	pub input: PathBuf,
+	/// Before benchmarking, record this many fresh object changes from the chain into `--input`.
+	#[arg(long)]
+	pub record: Option<usize>,
+	/// Which pipeline config (batch sizes, number of workers) to benchmark.
+	#[arg(long, value_enum, default_value_t = PipelineKind::Backfill)]
+	pub pipeline: PipelineKind,
+}
+
+#[derive(ClapArgs, Clone, Debug)]
+pub struct ReplayDlqArgs {
+	/// Only replay items first seen at or after this unix timestamp (in ms).
+	#[arg(long)]
+	pub since:       Option<u64>,
+	/// Only replay items first seen at or before this unix timestamp (in ms).
+	#[arg(long)]
+	pub until:       Option<u64>,
+	/// Only replay items that failed with this class of error. Items recorded without one (by older versions) don't
+	/// match.
+	#[arg(long, value_parser = ["rpc_transient", "sink_unavailable", "missing_object_data"])]
+	pub error_class: Option<String>,
+	/// Which pipeline config (batch sizes, retries) to use for replaying.
+	#[arg(long, value_enum, default_value_t = PipelineKind::Livescan)]
+	pub pipeline:    PipelineKind,
+}
+
+#[derive(ClapArgs, Clone, Debug)]
+pub struct DeadLettersArgs {
+	/// Which pipeline's topics to look at.
+	#[arg(long, value_enum, default_value_t = PipelineKind::Livescan)]
+	pub pipeline: PipelineKind,
+	/// Which topic's dead letters to look at: raw (failed in transform) or enriched (failed in load).
+	#[arg(long, value_enum, default_value_t = DecoupledTopic::Raw)]
+	pub topic:    DecoupledTopic,
+	/// Re-publish dead letters to their original topic and remove them from the dead-letter topic, instead of
+	/// just printing them. Messages that can't be decoded are left in place.
+	#[arg(long)]
+	pub replay:   bool,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+pub enum DecoupledTopic {
+	Raw,
+	Enriched,
+}
+
+#[derive(ClapArgs, Clone, Debug)]
+pub struct ReplayArgs {
+	/// First checkpoint to replay.
+	#[arg(long)]
+	pub from:     u64,
+	/// Last checkpoint to replay.
+	#[arg(long)]
+	pub to:       u64,
+	/// Which pipeline config (batch sizes, retries) to use for replaying.
+	#[arg(long, value_enum, default_value_t = PipelineKind::Backfill)]
+	pub pipeline: PipelineKind,
+}
+
+#[derive(ClapArgs, Clone, Debug)]
+pub struct CompactArgs {
+	/// Versions newer than this many days are kept as they are.
+	#[arg(long, default_value_t = 30)]
+	pub retention_days: u64,
+	/// For older versions, keep only the latest version of each object per this many hours.
+	#[arg(long, default_value_t = 24)]
+	pub keyframe_hours: u64,
+	/// Number of versions to remove per delete operation.
+	#[arg(long, default_value_t = 10_000)]
+	pub batch_size: usize,
+	/// Only report how many versions would be removed.
+	#[arg(long)]
+	pub dry_run: bool,
+}
+
+#[derive(ClapArgs, Clone, Debug)]
+pub struct RewindArgs {
+	/// Last checkpoint to keep as completed; everything after it will be re-processed.
+	#[arg(long, conflicts_with = "digest")]
+	pub checkpoint: Option<u64>,
+	/// Re-process everything starting at the checkpoint containing this transaction.
+	#[arg(long)]
+	pub digest:     Option<String>,
+	/// Also remove all data stored for checkpoints after the rewind point (requires history.enabled).
+	#[arg(long)]
+	pub invalidate: bool,
+}
+
+#[derive(ClapArgs, Clone, Debug)]
+pub struct CheckArgs {
+	/// First checkpoint to check.
+	#[arg(long, default_value_t = 1)]
+	pub from:        u64,
+	/// Last checkpoint to check. Defaults to the latest one.
+	#[arg(long)]
+	pub to:          Option<u64>,
+	/// Number of checkpoints per reported range.
+	#[arg(long, default_value_t = 1000)]
+	pub bucket_size: u64,
+}
+
+#[derive(ClapArgs, Clone, Debug)]
+pub struct CoordinateArgs {
+	/// Lowest checkpoint to process.
+	#[arg(long, default_value_t = 1)]
+	pub from:       u64,
+	/// Highest checkpoint to process. Defaults to the latest one.
+	#[arg(long)]
+	pub to:         Option<u64>,
+	/// Number of checkpoints per work item.
+	#[arg(long, default_value_t = 10_000)]
+	pub chunk_size: u64,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+pub enum PipelineKind {
+	Backfill,
+	Livescan,
+}
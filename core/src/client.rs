@@ -1,11 +1,12 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, time::Duration};
 use macros::with_client_rotation;
 use sui_sdk::{
 	apis::ReadApi,
 	error::SuiRpcResult,
 	rpc_types::{
-		ObjectChange as SuiObjectChange, SuiGetPastObjectRequest, SuiObjectDataOptions, SuiObjectResponse,
-		SuiPastObjectResponse, SuiTransactionBlockResponseQuery, TransactionBlocksPage,
+		Checkpoint, CheckpointId, ObjectChange as SuiObjectChange, SuiGetPastObjectRequest, SuiObjectData, SuiObjectDataOptions, SuiObjectResponse,
+		SuiMoveNormalizedModule, SuiPastObjectResponse, SuiTransactionBlockResponse, SuiTransactionBlockResponseOptions,
+		SuiTransactionBlockResponseQuery, TransactionBlocksPage,
 	},
 	SuiClient, SuiClientBuilder,
 };
@@ -13,9 +14,11 @@ use sui_types::{
 	base_types::{ObjectID, SequenceNumber, TransactionDigest, VersionNumber},
 	messages_checkpoint::CheckpointSequenceNumber,
 };
-use sui_types::error::SuiObjectResponseError::*;
+use sui_types::{
+	error::SuiObjectResponseError::*, sui_system_state::sui_system_state_summary::SuiSystemStateSummary,
+};
 use tokio::time::Instant;
-use crate::{_prelude::*, conf::RpcProviderConfig, utils::check_obj_type_from_string_vec};
+use crate::{_prelude::*, conf::RpcProviderConfig, rpcauth, rpcfixtures, utils::{check_obj_type_from_string_vec, object_bson_bytes}};
 use crate::conf::get_config_singleton;
 use crate::influx::{write_metric_ingest_error, get_influx_timestamp_as_milliseconds, write_metric_rpc_request};
 
@@ -51,15 +54,34 @@ impl ClientPool {
 	pub async fn new(configs: Vec<RpcProviderConfig>) -> anyhow::Result<Self> {
 		let clients = Vec::with_capacity(configs.len());
 		let mut self_ = Self { configs, clients };
+		// all read API calls are answered from recorded responses, so we don't need any connection
+		if rpcfixtures::mode() == rpcfixtures::Mode::Replay {
+			return Ok(self_)
+		}
 		self_.clients.push(self_.make_client(0).await?);
 		Ok(self_)
 	}
 
+	// name of the provider the next request will go to
+	pub fn current_provider(&self) -> &str {
+		self.clients.first().map_or("fixtures", |c| &c.config.name)
+	}
+
 	#[with_client_rotation]
 	pub async fn get_latest_checkpoint_sequence_number(&mut self) -> SuiRpcResult<CheckpointSequenceNumber> {
 		get_latest_checkpoint_sequence_number().await
 	}
 
+	#[with_client_rotation]
+	pub async fn get_checkpoint(&mut self, id: CheckpointId) -> SuiRpcResult<Checkpoint> {
+		get_checkpoint(id).await
+	}
+
+	#[with_client_rotation]
+	pub async fn get_chain_identifier(&mut self) -> SuiRpcResult<String> {
+		get_chain_identifier().await
+	}
+
 	#[with_client_rotation]
 	pub async fn query_transaction_blocks(
 		&mut self,
@@ -71,6 +93,32 @@ impl ClientPool {
 		query_transaction_blocks(query.clone(), cursor, limit, descending_order).await
 	}
 
+	#[with_client_rotation]
+	pub async fn get_transaction_with_options(
+		&mut self,
+		digest: TransactionDigest,
+		options: SuiTransactionBlockResponseOptions,
+	) -> SuiRpcResult<SuiTransactionBlockResponse> {
+		get_transaction_with_options(digest, options.clone()).await
+	}
+
+	#[with_client_rotation]
+	pub async fn multi_get_transactions_with_options(
+		&mut self,
+		digests: Vec<TransactionDigest>,
+		options: SuiTransactionBlockResponseOptions,
+	) -> SuiRpcResult<Vec<SuiTransactionBlockResponse>> {
+		multi_get_transactions_with_options(digests.clone(), options.clone()).await
+	}
+
+	#[with_client_rotation]
+	pub async fn get_normalized_move_modules_by_package(
+		&mut self,
+		package: ObjectID,
+	) -> SuiRpcResult<BTreeMap<String, SuiMoveNormalizedModule>> {
+		get_normalized_move_modules_by_package(package).await
+	}
+
 	#[with_client_rotation]
 	pub async fn get_object_with_options(
 		&mut self,
@@ -108,9 +156,18 @@ impl ClientPool {
 		try_multi_get_parsed_past_object(past_objects.clone(), options.clone()).await
 	}
 
+	// Not rotated like the read API calls above, as the macro only covers the read API. Only used once per epoch
+	// check, so we don't expect to run into rate limits with it.
+	pub async fn get_latest_sui_system_state(&self) -> SuiRpcResult<SuiSystemStateSummary> {
+		if self.clients.is_empty() {
+			return Err(sui_sdk::error::Error::DataError("no recorded responses for the governance API".into()))
+		}
+		self.clients[0].sui.governance_api().get_latest_sui_system_state().await
+	}
+
 	async fn make_client(&self, id: usize) -> anyhow::Result<Client> {
 		let config = self.configs[id].clone();
-		let sui = SuiClientBuilder::default().build(&config.url).await?;
+		let sui = SuiClientBuilder::default().build(rpcauth::url(&config)?).await?;
 		Ok(Client { id, config, sui, backoff: None, reqs: 0 })
 	}
 }
@@ -149,24 +206,15 @@ pub async fn parse_get_object_response(id: &ObjectID, res: SuiObjectResponse) ->
 		let blacklist_packages = get_config_singleton().blacklist.clone().packages;
 		// Index all objects.
 		if whitelist_enabled == false && blacklist_enabled == false {
-			let mut bytes = Vec::with_capacity(4096);
-			let bson = bson::to_bson(&obj).unwrap();
-			bson.as_document().unwrap().to_writer(&mut bytes).unwrap();
-			return Some((obj.version, bytes))
+			return object_bytes(&obj).await
 		}
 		// Index only whitelisted objects.
 		if whitelist_packages != None && whitelist_enabled == true && check_obj_type_from_string_vec(&obj_type, whitelist_packages.unwrap()) == true {
-			let mut bytes = Vec::with_capacity(4096);
-			let bson = bson::to_bson(&obj).unwrap();
-			bson.as_document().unwrap().to_writer(&mut bytes).unwrap();
-			return Some((obj.version, bytes))
+			return object_bytes(&obj).await
 		}
 		// Index everything except blacklisted objects.
 		if blacklist_packages != None && blacklist_enabled == true && check_obj_type_from_string_vec(&obj_type, blacklist_packages.unwrap()) == false {
-			let mut bytes = Vec::with_capacity(4096);
-			let bson = bson::to_bson(&obj).unwrap();
-			bson.as_document().unwrap().to_writer(&mut bytes).unwrap();
-			return Some((obj.version, bytes))
+			return object_bytes(&obj).await
 		}
 	}
 	// TODO: Determine root cause of this error.
@@ -175,6 +223,17 @@ pub async fn parse_get_object_response(id: &ObjectID, res: SuiObjectResponse) ->
 	return None
 }
 
+async fn object_bytes(obj: &SuiObjectData) -> Option<(VersionNumber, Vec<u8>)> {
+	match object_bson_bytes(obj) {
+		Ok(bytes) => Some((obj.version, bytes)),
+		Err(err) => {
+			warn!(object_id = ?obj.object_id, error = ?err, "ExtractionError : object cannot be stored as BSON.");
+			write_metric_ingest_error(obj.object_id.to_string(), "bson_serialization".to_string()).await;
+			None
+		}
+	}
+}
+
 pub fn parse_change(change: SuiObjectChange) -> Option<(ObjectID, SequenceNumber, bool)> {
 	use sui_sdk::rpc_types::ObjectChange::*;
 	Some(match change {
@@ -0,0 +1,205 @@
+use crate::{
+	_prelude::*,
+	activity, alerts, balances, bench, changefeed, checkpoints,
+	cli::Command,
+	consistency, decode, etl, filters, gas, history, idempotency, migrations,
+	mongo::InstanceLock,
+	movecalls, nft, owners, packages,
+	plugins::{self, Plugin},
+	rawchanges, rewind, routing, selfcheck, staking, transactions, transfers, typestats, webhooks, workqueue,
+};
+
+// Runs one of our subcommands, as the `indexer` binary does. Expects `init()` to have been called.
+pub async fn run(cfg: &AppConfig, command: Command) -> anyhow::Result<()> {
+	match command {
+		Command::Run(run_args) => {
+			let lock = prepare_run(cfg, run_args.skip_self_check, run_args.standby, Vec::new()).await?;
+			if cfg.backfillonly == true && cfg.livescanonly == true {
+				panic!("livescanonly is true AND backfillonly is true. Reconfigure in config.yaml");
+			}
+			etl::apply_start(cfg).await?;
+			if cfg.backfillonly == true && cfg.livescanonly == false {
+				let start_checkpoint = cfg.backfillstartcheckpoint;
+				etl::run_backfill_only(cfg, start_checkpoint).await?;
+			}
+			else {
+				let exit_at_tip =
+					run_args.exit_at_tip.then(|| run_args.max_tip_lag.unwrap_or(cfg.backfillthreshold as u64));
+				etl::run(cfg, exit_at_tip).await.unwrap();
+			}
+			if let Some(lock) = lock {
+				lock.release().await?;
+			}
+		}
+		// the watch mode only talks to RPC, so we don't need a Pulsar connection
+		Command::Watch(watch_args) => etl::run_watch(cfg, watch_args).await?,
+		Command::Bench(bench_args) => bench::run_bench(cfg, bench_args).await?,
+		#[cfg(feature = "pulsar")]
+		Command::ReplayDlq(replay_args) => {
+			crate::pulsar::setup_pulsar_singleton().await;
+			let lock = acquire_instance_lock(cfg, false).await?;
+			crate::dlq::run_replay_dlq(cfg, replay_args).await?;
+			if let Some(lock) = lock {
+				lock.release().await?;
+			}
+		}
+		// doesn't touch any of our collections, so no need for the instance lock
+		#[cfg(feature = "pulsar")]
+		Command::DeadLetters(dead_letters_args) => {
+			crate::pulsar::setup_pulsar_singleton().await;
+			crate::dlq::run_dead_letters(cfg, dead_letters_args).await?;
+		}
+		Command::Compact(compact_args) => {
+			let lock = acquire_instance_lock(cfg, false).await?;
+			history::run_compact(cfg, compact_args).await?;
+			if let Some(lock) = lock {
+				lock.release().await?;
+			}
+		}
+		Command::Replay(replay_args) => {
+			let lock = acquire_instance_lock(cfg, false).await?;
+			rawchanges::run_replay(cfg, replay_args).await?;
+			if let Some(lock) = lock {
+				lock.release().await?;
+			}
+		}
+		// we must not rewind while an indexer is running, as it would just keep on storing its progress
+		Command::Rewind(rewind_args) => {
+			let lock = acquire_instance_lock(cfg, false).await?;
+			rewind::run_rewind(cfg, rewind_args).await?;
+			if let Some(lock) = lock {
+				lock.release().await?;
+			}
+		}
+		// only reads, so it can run next to a running indexer
+		Command::Check(check_args) => consistency::run_check(cfg, check_args).await?,
+		Command::Coordinate(coordinate_args) => workqueue::run_coordinate(cfg, coordinate_args).await?,
+		// workers are meant to run concurrently, so they don't take the instance lock
+		Command::Work => {
+			#[cfg(feature = "pulsar")]
+			crate::pulsar::setup_pulsar_singleton().await;
+			workqueue::run_worker(cfg).await?;
+		}
+	}
+
+	Ok(())
+}
+
+// Everything the pipeline needs before it can start, see `Command::Run`. `sinks` are set up next to our plugins, see
+// pipeline.rs.
+pub(crate) async fn prepare_run(
+	cfg: &AppConfig,
+	skip_self_check: bool,
+	standby: bool,
+	sinks: Vec<Box<dyn Plugin>>,
+) -> anyhow::Result<Option<InstanceLock>> {
+	if !skip_self_check {
+		selfcheck::run_self_check(cfg).await?;
+	}
+	if let Some(rate) = cfg.samplerate {
+		warn!(rate, "SamplingWarning: Only indexing a sample of all transaction blocks, the database will be incomplete.");
+	}
+	#[cfg(feature = "pulsar")]
+	crate::pulsar::setup_pulsar_singleton().await;
+	let lock = acquire_instance_lock(cfg, standby).await?;
+	{
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		selfcheck::verify_network(cfg, &db).await?;
+		migrations::run_migrations(cfg, &db).await?;
+		filters::spawn_watchlist_refresh(cfg, &db).await?;
+	}
+	alerts::spawn_alerts(cfg);
+	spawn_sinks(cfg).await?;
+	decode::spawn_decode_pool(cfg).await?;
+	if cfg.history.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		history::ensure_history_indexes(cfg, &db).await?;
+	}
+	if cfg.idempotency.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		idempotency::ensure_applied_indexes(cfg, &db).await?;
+	}
+	if cfg.rawchanges.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		rawchanges::ensure_raw_changes_indexes(cfg, &db).await?;
+	}
+	if cfg.transactions.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		transactions::ensure_transactions_indexes(cfg, &db).await?;
+	}
+	if cfg.gas.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		gas::ensure_gas_indexes(cfg, &db).await?;
+	}
+	if cfg.movecalls.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		movecalls::ensure_move_calls_indexes(cfg, &db).await?;
+	}
+	if cfg.transfers.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		transfers::ensure_transfers_indexes(cfg, &db).await?;
+	}
+	if cfg.activity.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		activity::ensure_address_activity_indexes(cfg, &db).await?;
+	}
+	if cfg.checkpoints.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		checkpoints::ensure_checkpoint_summaries_indexes(cfg, &db).await?;
+	}
+	if cfg.balances.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		balances::ensure_balances_indexes(cfg, &db).await?;
+	}
+	if cfg.nft.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		nft::ensure_nft_indexes(cfg, &db).await?;
+	}
+	if cfg.packages.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		packages::ensure_packages_indexes(cfg, &db).await?;
+	}
+	if cfg.owners.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		owners::ensure_owner_index_indexes(cfg, &db).await?;
+	}
+	if cfg.typestats.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		typestats::ensure_type_stats_indexes(cfg, &db).await?;
+	}
+	if cfg.staking.enabled {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		staking::ensure_staking_indexes(cfg, &db).await?;
+		staking::spawn_validator_snapshots(cfg);
+	}
+	if !cfg.plugins.enabled.is_empty() || !sinks.is_empty() {
+		let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+		plugins::setup_plugins(cfg, &db, sinks).await?;
+	}
+	Ok(lock)
+}
+
+// Everything loaded objects are passed on to besides MongoDB: webhooks, topic routes and the change feed. To be set up
+// by every command that loads objects (via `etl::load_batched()`), except for `bench`, which only writes to throwaway collections.
+pub(crate) async fn spawn_sinks(cfg: &AppConfig) -> anyhow::Result<()> {
+	#[cfg(feature = "pulsar")]
+	if cfg.bus == "pulsar" && (cfg.changefeed.enabled || cfg.routing.rules.iter().any(|rule| rule.topic.is_some())) {
+		crate::pulsar::setup_pulsar_singleton().await;
+	}
+	webhooks::spawn_webhooks(cfg)?;
+	routing::spawn_topic_routes(cfg).await?;
+	changefeed::spawn_change_feed(cfg).await?;
+	Ok(())
+}
+
+// Commands writing to our collections must hold the instance lock, so we never have two writers at the same time.
+pub async fn acquire_instance_lock(cfg: &AppConfig, standby: bool) -> anyhow::Result<Option<InstanceLock>> {
+	if !cfg.lock.enabled {
+		if standby {
+			return Err(anyhow!("--standby requires lock.enabled, otherwise we'd start writing right away"))
+		}
+		return Ok(None)
+	}
+	let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+	Ok(Some(InstanceLock::acquire(cfg, &db, standby).await?))
+}
@@ -0,0 +1,1144 @@
+use std::path::{Path, PathBuf};
+
+use figment::{
+	providers::{Env, Format, Yaml},
+	Figment,
+};
+use influxdb::Client;
+use mongodb::{
+	options::{ClientOptions, Compressor, ServerApi, ServerApiVersion, Tls, TlsOptions},
+	Database,
+};
+use tokio::sync::OnceCell;
+
+use crate::{_prelude::*, cli::PipelineKind, client::ClientPool, secrets, types::TypeAliases};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+	#[serde(default)]
+	pub name:                     String,
+	pub queuebuffers:             QueueBuffersConfig,
+	pub workers:                  WorkersConfig,
+	pub objectqueries:            ObjectQueriesConfig,
+	pub mongo:                    MongoPipelineStepConfig,
+	pub checkpointretries:        usize,
+	pub checkpointretrytimeoutms: u64,
+	pub tracklatency:             bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectQueriesConfig {
+	pub batchsize:          usize,
+	pub batchwaittimeoutms: u64,
+	// batchsize and batchwaittimeoutms become upper bounds, see chunksizing.rs
+	#[serde(default)]
+	pub adaptive:           bool,
+	#[serde(default = "default_target_latency_ms")]
+	pub targetlatencyms:    u64,
+}
+
+fn default_target_latency_ms() -> u64 {
+	500
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MongoPipelineStepConfig {
+	pub batchsize:          usize,
+	pub batchwaittimeoutms: u64,
+	pub retries:            usize,
+	pub zstdlevel:          i32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueueBuffersConfig {
+	pub checkpointout: usize,
+	pub cpcompletions: usize,
+	pub mongoinfactor: usize,
+	pub last:          usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkersConfig {
+	pub checkpoint: Option<usize>,
+	pub object:     Option<usize>,
+	pub mongo:      Option<usize>,
+	#[serde(default)]
+	pub ordered:    bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MongoConfig {
+	#[serde(default)]
+	pub uri:                      String,
+	// read `uri` from this file instead, see secrets.rs
+	#[serde(default)]
+	pub urifile:                  Option<String>,
+	pub db:                       String,
+	pub collectionbase:           String,
+	// driver defaults if unset
+	#[serde(default)]
+	pub maxpoolsize:              Option<u32>,
+	#[serde(default)]
+	pub minpoolsize:              Option<u32>,
+	#[serde(default)]
+	pub connecttimeoutms:         Option<u64>,
+	#[serde(default)]
+	pub serverselectiontimeoutms: Option<u64>,
+	// Must be either "zstd" (at the level of the pipeline's mongo step), "snappy" or "none"
+	#[serde(default = "default_mongo_compression")]
+	pub compression:              String,
+	// TLS options from the URI apply unless set
+	#[serde(default)]
+	pub tls:                      Option<TlsConfig>,
+}
+
+// For connections to MongoDB and RPC providers, on top of the system's root certificates.
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+	// PEM file with additional CA certificates to trust
+	pub cafile:            Option<String>,
+	// PEM file with the client certificate and its private key, to authenticate with
+	pub certfile:          Option<String>,
+	// never enable this outside of development setups
+	#[serde(default)]
+	pub allowinvalidcerts: bool,
+}
+
+fn default_mongo_compression() -> String {
+	"zstd".to_string()
+}
+
+impl MongoConfig {
+	pub async fn client(&self, pc: &MongoPipelineStepConfig) -> anyhow::Result<Database> {
+		let mut client_options = ClientOptions::parse(&self.uri).await?;
+		client_options.compressors = match self.compression.as_str() {
+			"zstd" => Some(vec![Compressor::Zstd { level: Some(pc.zstdlevel) }]),
+			"snappy" => Some(vec![Compressor::Snappy]),
+			"none" => None,
+			other => return Err(anyhow!("unknown mongo.compression {:?}, must be zstd, snappy or none", other)),
+		};
+		// only override what's set, so options from the URI still apply otherwise
+		if self.maxpoolsize.is_some() {
+			client_options.max_pool_size = self.maxpoolsize;
+		}
+		if self.minpoolsize.is_some() {
+			client_options.min_pool_size = self.minpoolsize;
+		}
+		if let Some(ms) = self.connecttimeoutms {
+			client_options.connect_timeout = Some(Duration::from_millis(ms));
+		}
+		if let Some(ms) = self.serverselectiontimeoutms {
+			client_options.server_selection_timeout = Some(Duration::from_millis(ms));
+		}
+		if let Some(tls) = &self.tls {
+			client_options.tls = Some(Tls::Enabled(
+				TlsOptions::builder()
+					.ca_file_path(tls.cafile.as_ref().map(PathBuf::from))
+					.cert_key_file_path(tls.certfile.as_ref().map(PathBuf::from))
+					.allow_invalid_certificates(tls.allowinvalidcerts)
+					.build(),
+			));
+		}
+		client_options.server_api = Some(ServerApi::builder().version(ServerApiVersion::V1).build());
+		let client = mongodb::Client::with_options(client_options)?;
+		Ok(client.database(&self.db))
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PulsarConfig {
+	pub url:         String,
+	// OAuth2 client credentials, unless `token` is set
+	#[serde(default)]
+	pub issuer:      String,
+	#[serde(default)]
+	pub credentials: String,
+	#[serde(default)]
+	pub audience:    String,
+	// token authentication, or read the token from `tokenfile`, see secrets.rs
+	#[serde(default)]
+	pub token:       Option<String>,
+	#[serde(default)]
+	pub tokenfile:   Option<String>,
+	pub topicbase:   String,
+	// number of partitions the raw + enriched topics are expected to have, 0 for non-partitioned topics
+	#[serde(default)]
+	pub partitions:  u32,
+	#[serde(default)]
+	pub redelivery:  RedeliveryConfig,
+	#[serde(default)]
+	pub producer:    PulsarProducerConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PulsarProducerConfig {
+	// Max number of messages / bytes per batch, batching is disabled unless batchsize is set.
+	pub batchsize:      Option<u32>,
+	pub batchbytes:     Option<usize>,
+	// Max time a message waits for its batch to fill up.
+	pub batchtimeoutms: Option<u64>,
+	// Must be either "none", "lz4" or "zstd"
+	pub compression:    String,
+	// Max time to wait for the broker to confirm a message (applies to all Pulsar operations).
+	pub sendtimeoutms:  u64,
+	// Max number of messages a producer has in flight, i.e. waiting for confirmation by the broker.
+	pub maxpending:     usize,
+}
+
+impl Default for PulsarProducerConfig {
+	fn default() -> PulsarProducerConfig {
+		PulsarProducerConfig {
+			batchsize:      None,
+			batchbytes:     None,
+			batchtimeoutms: None,
+			compression:    "none".to_string(),
+			sendtimeoutms:  30_000,
+			maxpending:     1,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedeliveryConfig {
+	// how often a message of the raw + enriched topics may be redelivered before it's moved to the dead-letter topic
+	pub maxredeliveries: u32,
+	// backoff before redelivering a message, doubled with every attempt up to maxbackoffms
+	pub backoffms:       u64,
+	pub maxbackoffms:    u64,
+}
+
+impl Default for RedeliveryConfig {
+	fn default() -> RedeliveryConfig {
+		RedeliveryConfig { maxredeliveries: 5, backoffms: 1_000, maxbackoffms: 60_000 }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaConfig {
+	// comma separated host:port list
+	pub brokers:         String,
+	// passed on to librdkafka as is, e.g. security.protocol, sasl.mechanisms, ...
+	#[serde(default)]
+	pub options:         HashMap<String, String>,
+	// how often a message may be nacked before it's moved to the dead-letter topic
+	#[serde(default = "default_kafka_max_redeliveries")]
+	pub maxredeliveries: u32,
+}
+
+fn default_kafka_max_redeliveries() -> u32 {
+	5
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisConfig {
+	// e.g. redis://127.0.0.1:6379
+	pub url:             String,
+	// approximate max number of entries kept per stream, unlimited if unset
+	pub maxlen:          Option<usize>,
+	// how long an entry may stay unacked before another consumer claims it
+	pub claimidlems:     u64,
+	// how often an entry may be delivered before it's moved to the dead-letter stream
+	pub maxredeliveries: u32,
+}
+
+impl Default for RedisConfig {
+	fn default() -> RedisConfig {
+		RedisConfig { url: "redis://127.0.0.1:6379".to_string(), maxlen: None, claimidlems: 30_000, maxredeliveries: 5 }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhooksConfig {
+	pub rules:     Vec<WebhookRule>,
+	// retries per delivery, with exponential backoff starting at backoffms (up to 5 minutes)
+	pub retries:   u32,
+	pub backoffms: u64,
+	pub timeoutms: u64,
+	// max number of loaded changes waiting to be delivered, per rule
+	pub queuesize: usize,
+}
+
+impl Default for WebhooksConfig {
+	fn default() -> WebhooksConfig {
+		WebhooksConfig { rules: Vec::new(), retries: 3, backoffms: 1_000, timeoutms: 10_000, queuesize: 10_000 }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingConfig {
+	#[serde(default)]
+	pub rules:     Vec<RouteRule>,
+	// max number of changes waiting to be published per topic, before loading waits
+	#[serde(default = "default_routing_queue_size")]
+	pub queuesize: usize,
+}
+
+fn default_routing_queue_size() -> usize {
+	10_000
+}
+
+impl Default for RoutingConfig {
+	fn default() -> RoutingConfig {
+		RoutingConfig { rules: Vec::new(), queuesize: default_routing_queue_size() }
+	}
+}
+
+// Where objects matching all of the given patterns go, see routing.rs.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteRule {
+	// patterns for the full object type and its owner, `*` matches anything
+	#[serde(rename = "type")]
+	pub type_:      Option<String>,
+	pub package:    Option<String>,
+	pub owner:      Option<String>,
+	// suffix of the collection to store matching objects in instead of the objects collection
+	pub collection: Option<String>,
+	// topic on `bus` to publish matching objects to
+	pub topic:      Option<String>,
+	// URL to post matching objects to, like `webhooks.rules`
+	pub webhook:    Option<String>,
+	// used to sign webhook requests via HMAC-SHA256
+	pub secret:     Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChangeFeedConfig {
+	pub enabled:   bool,
+	// on `bus`, named like the decoupled pipeline's topics, e.g. prod_testnet_objects_applied
+	pub topic:     String,
+	// max number of applied writes waiting to be published, before loading waits
+	pub queuesize: usize,
+}
+
+impl Default for ChangeFeedConfig {
+	fn default() -> ChangeFeedConfig {
+		ChangeFeedConfig { enabled: false, topic: "applied".to_string(), queuesize: 10_000 }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtractConfig {
+	// where `indexer run` starts, see `etl::apply_start()`: resume, latest, genesis, digest or checkpoint
+	pub start:      String,
+	// first checkpoint to index, for `start: checkpoint`
+	pub checkpoint: Option<u64>,
+	// transaction whose checkpoint is the first to index, for `start: digest`
+	pub digest:     Option<String>,
+}
+
+impl Default for ExtractConfig {
+	fn default() -> ExtractConfig {
+		ExtractConfig { start: "resume".to_string(), checkpoint: None, digest: None }
+	}
+}
+
+impl ExtractConfig {
+	pub fn validate(&self) -> anyhow::Result<()> {
+		match self.start.as_str() {
+			"resume" | "latest" | "genesis" | "digest" | "checkpoint" => {}
+			other => {
+				return Err(anyhow!(
+					"unknown extract.start '{}', expected one of: resume, latest, genesis, digest, checkpoint",
+					other
+				))
+			}
+		}
+		if (self.start == "checkpoint") != self.checkpoint.is_some() {
+			return Err(anyhow!("extract.checkpoint must be set exactly if extract.start is checkpoint"))
+		}
+		if (self.start == "digest") != self.digest.is_some() {
+			return Err(anyhow!("extract.digest must be set exactly if extract.start is digest"))
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookRule {
+	pub url:    String,
+	// patterns for the full object type and its owner, `*` matches anything
+	#[serde(rename = "type")]
+	pub type_:  Option<String>,
+	pub owner:  Option<String>,
+	// used to sign requests via HMAC-SHA256
+	pub secret: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxConfig {
+	pub database:  String,
+	#[serde(default)]
+	pub token:     String,
+	// read `token` from this file instead, see secrets.rs
+	#[serde(default)]
+	pub tokenfile: Option<String>,
+	pub url:       String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+	pub level:          CLevel,
+	pub filter:         Option<Vec<String>>,
+	// Must be either "stdout" or "logfile"
+	pub output:         String,
+	// Ignored unless output == "logfile".
+	// Please declare as absolute path, example: "/var/log/indexer.log"
+	pub logfilepath:    String,
+	pub tokioconsole:   bool,
+	// Log a warning with all involved object ids whenever an RPC object query, a MongoDB batch write or a full
+	// pipeline pass (from extraction until loaded) takes longer than this. Disabled if not set.
+	pub slowrpcms:      Option<u64>,
+	pub slowmongoms:    Option<u64>,
+	pub slowchunkms:    Option<u64>,
+	// Log Tokio runtime stats this often, needs the `diagnostics` feature, see diagnostics.rs
+	#[serde(default)]
+	pub runtimestatsms: Option<u64>,
+}
+
+impl Default for LogConfig {
+	fn default() -> LogConfig {
+		LogConfig {
+			level:          CLevel(Level::INFO),
+			filter:         None,
+			output:         "logfile".to_string(),
+			logfilepath:    "/var/log/indexer.log".to_string(),
+			tokioconsole:   false,
+			slowrpcms:      None,
+			slowmongoms:    None,
+			slowchunkms:    None,
+			runtimestatsms: None,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpcProviderConfig {
+	pub url:               String,
+	pub name:              String,
+	pub objectsquerylimit: usize,
+	// sent with every request, e.g. an API key (`x-api-key: ...`), see rpcauth.rs
+	#[serde(default)]
+	pub headers:           HashMap<String, String>,
+	#[serde(default)]
+	pub basicauth:         Option<BasicAuthConfig>,
+	#[serde(default)]
+	pub tls:               Option<TlsConfig>,
+}
+
+impl RpcProviderConfig {
+	// whether we need to reach it through a forwarder, see rpcauth.rs
+	pub(crate) fn forwarded(&self) -> bool {
+		!self.headers.is_empty() || self.basicauth.is_some() || self.tls.is_some()
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuthConfig {
+	pub username: String,
+	pub password: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SuiConfig {
+	pub testnet:  Vec<RpcProviderConfig>,
+	pub mainnet:  Vec<RpcProviderConfig>,
+	pub localnet: Vec<RpcProviderConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Whitelist {
+	pub enabled:  bool,
+	pub packages: Option<Vec<String>>,
+}
+
+impl Default for Whitelist {
+	fn default() -> Whitelist {
+		Whitelist { enabled: false, packages: None }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Blacklist {
+	pub enabled:  bool,
+	pub packages: Option<Vec<String>>,
+}
+
+impl Default for Blacklist {
+	fn default() -> Blacklist {
+		Blacklist { enabled: false, packages: None }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockConfig {
+	pub enabled: bool,
+	// How long a lease is valid without being renewed. Renewals happen every third of that.
+	pub leasems: u64,
+}
+
+impl Default for LockConfig {
+	fn default() -> LockConfig {
+		LockConfig { enabled: true, leasems: 30_000 }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+	#[serde(default)]
+	pub enabled:       bool,
+	// store versions as patches against the version before them, see delta.rs
+	#[serde(default)]
+	pub deltas:        bool,
+	// with `deltas`, store the full object again every this many versions of it
+	#[serde(default = "default_keyframe_every")]
+	pub keyframeevery: u64,
+}
+
+fn default_keyframe_every() -> u64 {
+	50
+}
+
+impl Default for HistoryConfig {
+	fn default() -> HistoryConfig {
+		HistoryConfig { enabled: false, deltas: false, keyframeevery: default_keyframe_every() }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RawChangesConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct InFlightConfig {
+	// both 0 = no limit, see inflight.rs
+	pub maxitems: u64,
+	pub maxbytes: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BcsOnlyConfig {
+	pub enabled:         bool,
+	// concurrent batches of the decode pool, see decode.rs
+	pub decodeworkers:   usize,
+	pub decodebatchsize: usize,
+	// how long to wait before looking again when there's nothing to decode
+	pub idlems:          u64,
+}
+
+impl Default for BcsOnlyConfig {
+	fn default() -> BcsOnlyConfig {
+		BcsOnlyConfig { enabled: false, decodeworkers: 4, decodebatchsize: 50, idlems: 5000 }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct IdempotencyConfig {
+	pub enabled:       bool,
+	// 0 keeps applied changes forever
+	pub retentiondays: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ContentHashConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReadBackConfig {
+	pub enabled: bool,
+	// number of written documents per chunk to read back, see readback.rs
+	pub samples: usize,
+}
+
+impl Default for ReadBackConfig {
+	fn default() -> ReadBackConfig {
+		ReadBackConfig { enabled: false, samples: 3 }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TransactionsConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GasConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MoveCallsConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TransfersConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ActivityConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CheckpointsConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BalancesConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TypeStatsConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NftConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PackagesConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct OwnersConfig {
+	pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct KioskConfig {
+	pub enabled: bool,
+}
+
+// Only used by the `parquet` plugin.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParquetConfig {
+	// where to write the files, created if needed
+	pub dir: String,
+}
+
+impl Default for ParquetConfig {
+	fn default() -> ParquetConfig {
+		ParquetConfig { dir: "parquet".to_string() }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StakingConfig {
+	pub enabled:      bool,
+	// how often to check whether a new epoch has started
+	pub epochcheckms: u64,
+}
+
+impl Default for StakingConfig {
+	fn default() -> StakingConfig {
+		StakingConfig { enabled: false, epochcheckms: 60_000 }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NamingConfig {
+	// key style of stored documents: sdk (as serialized by sui-sdk), camel or snake, see naming.rs
+	#[serde(default)]
+	pub style: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TypesConfig {
+	// package id -> the package id it stands for in types and type patterns, see types.rs
+	#[serde(default)]
+	pub aliases: HashMap<String, String>,
+}
+
+impl TypesConfig {
+	pub fn aliases(&self) -> anyhow::Result<TypeAliases> {
+		TypeAliases::new(&self.aliases).map_err(|err| anyhow!("types.aliases: {}", err))
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FiltersConfig {
+	// type patterns, `*` matches anything: if any are given, only objects of a matching type are indexed
+	#[serde(default)]
+	pub types:        Vec<String>,
+	// type patterns of objects never to index
+	#[serde(default)]
+	pub excludetypes: Vec<String>,
+	// if any are given, only objects owned by one of these addresses or objects are indexed
+	#[serde(default)]
+	pub owners:       Vec<String>,
+	// if any are given, only objects of types defined in one of these packages are indexed
+	#[serde(default)]
+	pub packages:     Vec<String>,
+	#[serde(default)]
+	pub watchlist:    WatchlistConfig,
+}
+
+// Only changes sent by or owned by one of these addresses are indexed, see filters.rs.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchlistConfig {
+	#[serde(default)]
+	pub addresses:  Vec<String>,
+	// also watch the addresses in the `_watchlist` collection, reloaded every `refreshms`
+	#[serde(default)]
+	pub collection: bool,
+	#[serde(default = "default_watchlist_refresh_ms")]
+	pub refreshms:  u64,
+}
+
+fn default_watchlist_refresh_ms() -> u64 {
+	10_000
+}
+
+impl Default for WatchlistConfig {
+	fn default() -> WatchlistConfig {
+		WatchlistConfig { addresses: Vec::new(), collection: false, refreshms: default_watchlist_refresh_ms() }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PluginsConfig {
+	// names of the protocol specific indexers to run, see plugins.rs
+	pub enabled: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertsConfig {
+	pub enabled:              bool,
+	pub webhookurl:           String,
+	// Must be either "slack" or "pagerduty"
+	pub format:               String,
+	// PagerDuty integration key, ignored for slack
+	pub routingkey:           Option<String>,
+	pub intervalms:           u64,
+	// per stage, within intervalms
+	pub maxerrors:            u64,
+	pub maxcheckpointsbehind: u64,
+	pub cooldownms:           u64,
+}
+
+impl Default for AlertsConfig {
+	fn default() -> AlertsConfig {
+		AlertsConfig {
+			enabled:              false,
+			webhookurl:           String::new(),
+			format:               "slack".to_string(),
+			routingkey:           None,
+			intervalms:           60_000,
+			maxerrors:            100,
+			maxcheckpointsbehind: 1000,
+			cooldownms:           15 * 60_000,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ChaosConfig {
+	// only has an effect in builds with the `chaos` feature, see chaos.rs
+	pub enabled:        bool,
+	// rates are fractions of calls / writes, between 0.0 and 1.0
+	pub rpcerrorrate:   f64,
+	pub rpcslowrate:    f64,
+	pub rpcslowms:      u64,
+	pub mongoerrorrate: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RpcFixturesConfig {
+	// Must be either "" (off), "record" or "replay", see rpcfixtures.rs
+	pub mode: String,
+	pub dir:  String,
+}
+
+// Where secrets referenced by `vault:` are read from, see secrets.rs. Falls back to VAULT_ADDR and VAULT_TOKEN.
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SecretsConfig {
+	#[serde(default)]
+	pub vault: VaultConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct VaultConfig {
+	pub addr:      Option<String>,
+	pub token:     Option<String>,
+	pub tokenfile: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SentryConfig {
+	pub dsn:        Option<String>,
+	// fraction of errors to send, between 0.0 and 1.0
+	pub samplerate: f32,
+}
+
+impl Default for SentryConfig {
+	fn default() -> SentryConfig {
+		SentryConfig { dsn: None, samplerate: 1.0 }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+	// set to false to only push metrics to statsd
+	pub influx:       bool,
+	// host:port of a StatsD / DogStatsD agent, disabled if not set
+	pub statsd:       Option<String>,
+	pub statsdprefix: String,
+	// send tags using the DogStatsD extension
+	pub dogstatsd:    bool,
+}
+
+impl Default for MetricsConfig {
+	fn default() -> MetricsConfig {
+		MetricsConfig { influx: true, statsd: None, statsdprefix: "indexer".to_string(), dogstatsd: false }
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppConfig {
+	pub env:                     String,
+	pub net:                     String,
+	// chain identifier our RPC providers must serve, required for chains other than mainnet and testnet to be checked
+	#[serde(default)]
+	pub chainid:                 Option<String>,
+	// only index this fraction of all transaction blocks (between 0 and 1), e.g. for staging, see `utils::sampled()`
+	#[serde(default)]
+	pub samplerate:              Option<f64>,
+	pub rocksdbfile:             String,
+	pub backfill:                PipelineConfig,
+	pub livescan:                PipelineConfig,
+	pub backfillthreshold:       usize,
+	pub pausepollonbackfill:     bool,
+	pub pollintervalms:          u64,
+	#[serde(default)]
+	pub finalizedonly:           bool,
+	#[serde(default)]
+	pub slimdocuments:           bool,
+	#[serde(default)]
+	pub naming:                  NamingConfig,
+	pub mongo:                   MongoConfig,
+	// not needed for builds without the `pulsar` feature
+	#[serde(default)]
+	pub pulsar:                  PulsarConfig,
+	pub influx:                  InfluxConfig,
+	#[serde(default)]
+	pub metrics:                 MetricsConfig,
+	pub sui:                     SuiConfig,
+	pub log:                     LogConfig,
+	pub backfillonly:            bool,
+	pub livescanonly:            bool,
+	#[serde(default)]
+	pub decoupled:               bool,
+	// Must be either "pulsar", "kafka" or "redis"
+	#[serde(default = "default_bus")]
+	pub bus:                     String,
+	#[serde(default)]
+	pub kafka:                   KafkaConfig,
+	#[serde(default)]
+	pub redis:                   RedisConfig,
+	#[serde(default)]
+	pub webhooks:                WebhooksConfig,
+	#[serde(default)]
+	pub changefeed:              ChangeFeedConfig,
+	#[serde(default)]
+	pub routing:                 RoutingConfig,
+	pub backfillstartcheckpoint: Option<u64>,
+	#[serde(default)]
+	pub extract:                 ExtractConfig,
+	pub whitelist:               Whitelist,
+	pub blacklist:               Blacklist,
+	#[serde(default)]
+	pub types:                   TypesConfig,
+	#[serde(default)]
+	pub filters:                 FiltersConfig,
+	#[serde(default)]
+	pub lock:                    LockConfig,
+	#[serde(default)]
+	pub history:                 HistoryConfig,
+	#[serde(default)]
+	pub rawchanges:              RawChangesConfig,
+	#[serde(default)]
+	pub idempotency:             IdempotencyConfig,
+	#[serde(default)]
+	pub contenthash:             ContentHashConfig,
+	#[serde(default)]
+	pub readback:                ReadBackConfig,
+	#[serde(default)]
+	pub bcsonly:                 BcsOnlyConfig,
+	#[serde(default)]
+	pub inflight:                InFlightConfig,
+	#[serde(default)]
+	pub transactions:            TransactionsConfig,
+	#[serde(default)]
+	pub gas:                     GasConfig,
+	#[serde(default)]
+	pub movecalls:               MoveCallsConfig,
+	#[serde(default)]
+	pub transfers:               TransfersConfig,
+	#[serde(default)]
+	pub activity:                ActivityConfig,
+	#[serde(default)]
+	pub checkpoints:             CheckpointsConfig,
+	#[serde(default)]
+	pub balances:                BalancesConfig,
+	#[serde(default)]
+	pub nft:                     NftConfig,
+	#[serde(default)]
+	pub packages:                PackagesConfig,
+	#[serde(default)]
+	pub owners:                  OwnersConfig,
+	#[serde(default)]
+	pub typestats:               TypeStatsConfig,
+	#[serde(default)]
+	pub kiosk:                   KioskConfig,
+	#[serde(default)]
+	pub plugins:                 PluginsConfig,
+	#[serde(default)]
+	pub parquet:                 ParquetConfig,
+	#[serde(default)]
+	pub staking:                 StakingConfig,
+	#[serde(default)]
+	pub alerts:                  AlertsConfig,
+	#[serde(default)]
+	pub sentry:                  SentryConfig,
+	#[serde(default)]
+	pub rpcfixtures:             RpcFixturesConfig,
+	#[serde(default)]
+	pub chaos:                   ChaosConfig,
+	#[serde(default)]
+	pub secrets:                 SecretsConfig,
+}
+
+fn default_bus() -> String {
+	"pulsar".to_string()
+}
+
+impl AppConfig {
+	pub fn new() -> anyhow::Result<Self> {
+		Self::load("config.yaml")
+	}
+
+	// Like `new()`, with the config file at `path`.
+	pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+		let mut config: AppConfig =
+			Figment::new().merge(Yaml::file(path)).merge(Env::prefixed("APP_").split("_")).extract()?;
+		config.backfill.name = "backfill".into();
+		config.livescan.name = "livescan".into();
+
+		// FIXME validate that the directory is either empty, doesn't exist or contains ONLY rocksDB data files
+		//			this is because we automatically remove the dir at runtime without further checks
+		//			so if you've misconfigured this
+		if config.rocksdbfile == "" || config.rocksdbfile == "/" {
+			panic!("please set config.rocksdbfile to a new or empty or existing RocksDB data dir; it can and will be deleted at runtime, as needed!");
+		}
+		config.extract.validate()?;
+		// there's nothing to backfill above the chain tip
+		if config.backfillonly && config.extract.start == "latest" {
+			return Err(anyhow!("extract.start: latest cannot be combined with backfillonly"))
+		}
+		if config.samplerate.map_or(false, |rate| !(rate > 0.0 && rate <= 1.0)) {
+			return Err(anyhow!("samplerate must be greater than 0 and at most 1"))
+		}
+		for rule in &config.routing.rules {
+			if rule.collection.is_none() && rule.topic.is_none() && rule.webhook.is_none() {
+				return Err(anyhow!("routing rules need at least one of collection, topic or webhook"))
+			}
+			// an object's owner can change, but it must always be stored in the same collection
+			if rule.collection.is_some() && rule.owner.is_some() {
+				return Err(anyhow!("routing rules with a collection can only match on type and package, not owner"))
+			}
+		}
+		if config.history.deltas && config.history.keyframeevery == 0 {
+			return Err(anyhow!("history.keyframeevery must be at least 1"))
+		}
+		if config.gas.enabled && !config.transactions.enabled {
+			return Err(anyhow!(
+				"gas.enabled requires transactions.enabled, as gas rollups are derived from newly stored transactions"
+			))
+		}
+
+		Ok(config)
+	}
+
+	// Reads credentials from the files and secret managers they're configured to come from, see secrets.rs. To be
+	// called before `init()`.
+	pub async fn load_secrets(&mut self) -> anyhow::Result<()> {
+		secrets::load_secrets(self).await
+	}
+
+	pub fn pipeline(&self, kind: PipelineKind) -> &PipelineConfig {
+		match kind {
+			PipelineKind::Backfill => &self.backfill,
+			PipelineKind::Livescan => &self.livescan,
+		}
+	}
+
+	// Stored with every object and message, to tell which network it's from: `net`, or for localnet (which may be any
+	// chain other than the public networks), the configured `chainid`, if any.
+	pub fn network(&self) -> &str {
+		match (self.net.as_str(), &self.chainid) {
+			("localnet", Some(chainid)) => chainid,
+			(net, _) => net,
+		}
+	}
+
+	pub async fn sui(&self) -> anyhow::Result<ClientPool> {
+		let providers = self.rpc_providers();
+		if providers.is_empty() {
+			panic!("no RPC providers configured for {}!", self.net);
+		}
+		Ok(ClientPool::new(providers.clone()).await?)
+	}
+
+	// Replaces all RPC providers for the configured network with a single one, keeping the query limit of the
+	// first previously configured provider.
+	pub fn override_rpc_url(&mut self, url: String) {
+		let providers = self.rpc_providers_mut();
+		let objectsquerylimit = providers.first().map(|p| p.objectsquerylimit).unwrap_or(50);
+		*providers = vec![RpcProviderConfig {
+			url,
+			name: "cli".into(),
+			objectsquerylimit,
+			headers: HashMap::new(),
+			basicauth: None,
+			tls: None,
+		}];
+	}
+
+	pub(crate) fn rpc_providers(&self) -> &Vec<RpcProviderConfig> {
+		match self.net.as_str() {
+			"testnet" => &self.sui.testnet,
+			"mainnet" => &self.sui.mainnet,
+			"localnet" => &self.sui.localnet,
+			_ => panic!("unknown net configuration: {} (expected: mainnet | testnet | localnet)", self.net),
+		}
+	}
+
+	fn rpc_providers_mut(&mut self) -> &mut Vec<RpcProviderConfig> {
+		match self.net.as_str() {
+			"testnet" => &mut self.sui.testnet,
+			"mainnet" => &mut self.sui.mainnet,
+			"localnet" => &mut self.sui.localnet,
+			_ => panic!("unknown net configuration: {} (expected: mainnet | testnet | localnet)", self.net),
+		}
+	}
+}
+
+// Used for `samplerate` and `--sample-rate`.
+pub fn parse_sample_rate(s: &str) -> Result<f64, String> {
+	match s.parse::<f64>() {
+		Ok(rate) if rate > 0.0 && rate <= 1.0 => Ok(rate),
+		_ => Err(format!("{} isn't a sample rate, must be greater than 0 and at most 1", s)),
+	}
+}
+
+// Singleton for config
+pub(crate) static APPCONFIG: OnceCell<AppConfig> = OnceCell::const_new();
+
+// Setup config singleton
+pub async fn setup_config_singleton(cfg: &AppConfig) -> &'static AppConfig {
+	APPCONFIG.get_or_init(|| async { cfg.clone() }).await
+}
+
+pub fn get_config_singleton() -> &'static AppConfig {
+	APPCONFIG.get().expect("ConfigError: config singleton could not be loaded.")
+}
+
+pub(crate) static INFLUXCLIENT: OnceCell<influxdb::Client> = OnceCell::const_new();
+
+pub async fn setup_influx_singleton() -> &'static influxdb::Client {
+	INFLUXCLIENT.get_or_init(|| async {
+		let influxconfig = get_config_singleton().influx.clone();
+		let client = Client::new(influxconfig.url, influxconfig.database).with_token(influxconfig.token);
+		return client;
+	}).await
+}
+
+pub fn get_influx_singleton() -> &'static influxdb::Client {
+	INFLUXCLIENT.get().expect("ConfigError: Influx client could not be loaded.")
+}
+
+#[derive(Clone, Debug)]
+pub struct CLevel(pub Level);
+
+impl Deref for CLevel {
+	type Target = Level;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl<'de> Deserialize<'de> for CLevel {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<CLevel, D::Error> {
+		let s: String = Deserialize::deserialize(deserializer)?;
+		Level::from_str(&s).map(CLevel).map_err(de::Error::custom)
+	}
+}
@@ -0,0 +1,113 @@
+use bson::{doc, Document};
+
+use crate::{
+	_prelude::*,
+	checkpoints::checkpoint_summaries_collection_name,
+	cli::CheckArgs,
+	mongo::{completed_checkpoints, mongo_collection_name},
+	rawchanges::raw_changes_collection_name,
+	routing::routed_collections,
+	transactions::transactions_collection_name,
+};
+
+// Entrypoint for the `check` subcommand.
+// Cross-checks what we've stored for a checkpoint range, in buckets of `--bucket-size` checkpoints, and reports every
+// bucket with a discrepancy, so gaps or corruption are noticed close to when they happen (e.g. when run from cron).
+// What can be checked depends on what's enabled:
+// - always: every checkpoint is marked as completed
+// - checkpoints + transactions: the number of stored transactions matches the checkpoint summaries
+// - rawchanges (without white- / blacklist): every changed object is present in the objects collection, or in the
+//   collection a routing rule sends it to (see routing.rs)
+// Fails if any discrepancy was found.
+pub async fn run_check(cfg: &AppConfig, args: CheckArgs) -> anyhow::Result<()> {
+	let db = cfg.mongo.client(&cfg.backfill.mongo).await?;
+	let to = match args.to {
+		Some(to) => to,
+		None => cfg.sui().await?.get_latest_checkpoint_sequence_number().await?,
+	};
+	let summaries = db.collection::<Document>(&checkpoint_summaries_collection_name(cfg));
+	let transactions = db.collection::<Document>(&transactions_collection_name(cfg));
+	let raw_changes = db.collection::<Document>(&raw_changes_collection_name(cfg));
+	let check_objects = cfg.rawchanges.enabled && !cfg.whitelist.enabled && !cfg.blacklist.enabled;
+	// an object is stored in exactly one of them, so it's missing if none of them has it
+	let mut object_collections = vec![mongo_collection_name(cfg, "")];
+	object_collections.extend(routed_collections(cfg).await);
+	let (mut lookups, mut none_stored) = (Vec::new(), Document::new());
+	for (i, collection) in object_collections.iter().enumerate() {
+		let stored = format!("stored{}", i);
+		lookups.push(doc! { "$lookup": {
+			"from": collection,
+			"localField": "_id",
+			"foreignField": "_id",
+			"as": &stored,
+		}});
+		none_stored.insert(stored, doc! { "$size": 0 });
+	}
+
+	let bucket_size = args.bucket_size.max(1);
+	let (mut num_buckets, mut num_bad) = (0usize, 0usize);
+	for start in (args.from..=to).step_by(bucket_size as usize) {
+		let end = (start + bucket_size - 1).min(to);
+		let range = doc! { "$gte": start as i64, "$lte": end as i64 };
+		let mut problems = Vec::new();
+
+		let expected = end - start + 1;
+		// every checkpoint below the stop marker counts as completed, see mongo::Checkpoint
+		let completed = completed_checkpoints(cfg, &db, start, end).await?;
+		if completed < expected {
+			problems.push(format!("{} of {} checkpoints not completed", expected - completed, expected));
+		}
+
+		if cfg.checkpoints.enabled && cfg.transactions.enabled {
+			let summed = summaries
+				.aggregate(
+					vec![
+						doc! { "$match": { "_id": range.clone() } },
+						doc! { "$group": { "_id": null, "summaries": { "$sum": 1 }, "txs": { "$sum": "$tx_count" } } },
+					],
+					None,
+				)
+				.await?
+				.try_next()
+				.await?;
+			let (num_summaries, expected_txs) = summed.map_or((0, 0), |s| {
+				(s.get_i32("summaries").unwrap_or_default() as u64, s.get_i64("txs").unwrap_or_default() as u64)
+			});
+			let stored_txs = transactions.count_documents(doc! { "checkpoint": range.clone() }, None).await?;
+			if num_summaries < expected {
+				problems.push(format!("{} of {} checkpoint summaries missing", expected - num_summaries, expected));
+			}
+			if stored_txs != expected_txs {
+				problems.push(format!("{} transactions stored, {} in checkpoint summaries", stored_txs, expected_txs));
+			}
+		}
+
+		if check_objects {
+			let mut pipeline = vec![doc! { "$match": { "cp": range.clone() } }, doc! { "$group": { "_id": "$object_id" } }];
+			pipeline.extend(lookups.iter().cloned());
+			pipeline.push(doc! { "$match": none_stored.clone() });
+			pipeline.push(doc! { "$count": "missing" });
+			let missing = raw_changes
+				.aggregate(pipeline, None)
+				.await?
+				.try_next()
+				.await?
+				.map_or(0, |m| m.get_i32("missing").unwrap_or_default());
+			if missing > 0 {
+				problems.push(format!("{} changed objects missing from the objects collections", missing));
+			}
+		}
+
+		num_buckets += 1;
+		if !problems.is_empty() {
+			num_bad += 1;
+			println!("checkpoints {} to {}: {}", start, end, problems.join(", "));
+		}
+	}
+
+	if num_bad > 0 {
+		return Err(anyhow!("found discrepancies in {} of {} checkpoint ranges", num_bad, num_buckets))
+	}
+	println!("checked checkpoints {} to {}: no discrepancies in {} ranges", args.from, to, num_buckets);
+	Ok(())
+}
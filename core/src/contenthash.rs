@@ -0,0 +1,128 @@
+use bson::{doc, Document};
+use mongodb::{options::FindOptions, Database};
+
+use crate::{
+	_prelude::*,
+	etl::ObjectItem,
+	influx::write_metric_mongo_write_error,
+	mongo::mongo_collection_name,
+	routing::routed_collections,
+};
+
+// With `contenthash.enabled`, every stored object carries a hash of its content (`content_hash`, see
+// `utils::content_hash()`). Objects are often touched by a transaction without being changed, e.g. when passed by
+// mutable reference, which gives them a new version with byte-identical content. We skip those mutations: the stored
+// object only has its `version` and `version_` moved forward (see `advance_versions()`), everything else stays as
+// the version that last changed it left it, and neither derived collections, webhooks nor plugins see them, which
+// saves most of the writes and keeps change streams free of changes that aren't any.
+// Returns the indices of the changes in `chunk` (sorted by object id and version) to skip, given their objects' hashes,
+// each with the collection its object is stored in. If we can't tell, we skip nothing, which is always safe. Objects
+// routed to other collections (see routing.rs) are looked up there.
+pub async fn unchanged(
+	cfg: &AppConfig,
+	db: &Database,
+	chunk: &[ObjectItem],
+	hashes: &[Option<String>],
+) -> HashMap<usize, String> {
+	let ids = chunk.iter().filter(|item| !item.deletion).map(|item| item.id.to_string()).collect::<HashSet<_>>();
+	if ids.is_empty() {
+		return HashMap::new()
+	}
+	let ids = ids.into_iter().collect::<Vec<_>>();
+	let mut collections = vec![mongo_collection_name(cfg, "")];
+	collections.extend(routed_collections(cfg).await);
+	let mut docs = Vec::new();
+	// an object is only ever stored in one of them
+	for collection in collections {
+		let projection = doc! { "content_hash": 1, "version_": 1, "deleted": 1, "wrapped_in_tx": 1 };
+		let found = match db
+			.collection::<Document>(&collection)
+			.find(doc! { "_id": { "$in": ids.clone() } }, FindOptions::builder().projection(projection).build())
+			.await
+		{
+			Ok(cursor) => cursor.try_collect::<Vec<_>>().await,
+			Err(err) => Err(err),
+		};
+		match found {
+			Ok(found) => docs.extend(found.into_iter().map(|d| (collection.clone(), d))),
+			Err(err) => {
+				warn!(error = ?err, "LoadWarning: Failed looking up content hashes, storing all changes.");
+				return HashMap::new()
+			}
+		}
+	}
+	// only stored objects we'd otherwise just give a newer version count: deleted and wrapped ones need their update
+	let mut current = docs
+		.iter()
+		.filter(|(_, d)| !d.get_bool("deleted").unwrap_or(false) && !d.contains_key("wrapped_in_tx"))
+		.filter_map(|(collection, d)| {
+			let hash = d.get_str("content_hash").ok()?;
+			let stored = (d.get_i64("version_").ok()?, hash.to_string(), collection.clone());
+			Some((d.get_str("_id").ok()?.to_string(), stored))
+		})
+		.collect::<HashMap<_, _>>();
+	let mut skip = HashMap::new();
+	for (i, (item, hash)) in chunk.iter().zip(hashes).enumerate() {
+		let id = item.id.to_string();
+		let Some(hash) = hash.as_ref().filter(|_| !item.deletion) else {
+			current.remove(&id);
+			continue
+		};
+		let version = item.version.value() as i64;
+		match current.get_mut(&id) {
+			Some((stored, stored_hash, collection)) if *stored < version && stored_hash == hash => {
+				skip.insert(i, collection.clone());
+				// it's moved forward, see `advance_versions()`
+				*stored = version;
+			}
+			// older than what we have, the update won't change anything either way
+			Some((stored, ..)) if *stored >= version => {}
+			// later changes of the same object in this chunk compare against this one, wherever it will be stored
+			_ => {
+				current.insert(id, (version, hash.clone(), String::new()));
+			}
+		}
+	}
+	// a change that compares against an earlier one of this chunk can only be skipped once that one is stored, so
+	// we don't skip those, which only happens for objects changed several times within a chunk
+	skip.retain(|_, collection| !collection.is_empty());
+	skip
+}
+
+// Moves the stored version of the objects of the `skipped` changes of `chunk` (see `unchanged()`) forward to theirs,
+// so a late change with a version in between, e.g. v6 arriving after v7 was skipped against v5, is ignored like any
+// other outdated change, instead of overwriting newer content. Returns false if that failed, in which case the changes
+// must be stored after all.
+pub async fn advance_versions(
+	pc: &PipelineConfig,
+	db: &Database,
+	chunk: &[ObjectItem],
+	skipped: &HashMap<usize, String>,
+) -> bool {
+	let mut groups = HashMap::<&str, Vec<Document>>::new();
+	let mut indices = skipped.keys().copied().collect::<Vec<_>>();
+	// in order, for objects skipped more than once
+	indices.sort_unstable();
+	for i in indices {
+		let item = &chunk[i];
+		let v_ = item.version.value() as i64;
+		groups.entry(skipped[&i].as_str()).or_default().push(doc! {
+			"q": { "_id": item.id.to_string(), "version_": { "$lt": v_ } },
+			"u": { "$set": { "version": item.version.to_string(), "version_": v_ } },
+			"upsert": false,
+			"multi": false,
+		});
+	}
+	for (collection, updates) in groups {
+		let mut retries_left = pc.mongo.retries;
+		while let Err(err) = db.run_command(doc! { "update": collection, "updates": updates.clone() }, None).await {
+			write_metric_mongo_write_error().await;
+			if retries_left == 0 {
+				warn!(error = ?err, "LoadWarning: Failed moving versions of unchanged objects forward, storing them.");
+				return false
+			}
+			retries_left -= 1;
+		}
+	}
+	true
+}
@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::_prelude::*;
+
+// Operator controlled pause of all extraction work. While paused, we don't query any new transactions or checkpoints,
+// so our stored progress stays where it is, but all items already in flight are still fetched and loaded.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+// Pause on SIGUSR1, resume on SIGUSR2, e.g. `kill -USR1 <pid>` before doing maintenance on MongoDB.
+pub fn spawn_pause_signal_handlers() -> anyhow::Result<()> {
+	let mut pause = signal(SignalKind::user_defined1())?;
+	let mut resume = signal(SignalKind::user_defined2())?;
+	tokio::spawn(async move {
+		loop {
+			tokio::select! {
+				Some(_) = pause.recv() => set_paused(true),
+				Some(_) = resume.recv() => set_paused(false),
+				else => break,
+			}
+		}
+	});
+	Ok(())
+}
+
+pub fn set_paused(paused: bool) {
+	if PAUSED.swap(paused, Relaxed) != paused {
+		if paused {
+			warn!("ControlWarning: Pausing extraction, in-flight items will still be completed.");
+		} else {
+			warn!("ControlWarning: Resuming extraction.");
+		}
+	}
+}
+
+pub fn is_paused() -> bool {
+	PAUSED.load(Relaxed)
+}
+
+// To be called by every extraction loop before starting new work.
+pub async fn wait_while_paused() {
+	while is_paused() {
+		tokio::time::sleep(Duration::from_millis(250)).await;
+	}
+}
+
+// Ensure Tokio threads are drained on a smooth shutdown
+pub fn ctrl_c_bool() -> Arc<AtomicBool> {
+	let stop = Arc::new(AtomicBool::new(false));
+	tokio::spawn({
+		let stop = stop.clone();
+		async move {
+			tokio::signal::ctrl_c().await.unwrap();
+			stop.store(true, Relaxed);
+		}
+	});
+	stop
+}
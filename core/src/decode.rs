@@ -0,0 +1,152 @@
+use bson::{doc, DateTime, Document};
+use futures::stream;
+use mongodb::{
+	options::{FindOptions, IndexOptions},
+	Database, IndexModel,
+};
+use sui_sdk::rpc_types::{SuiGetPastObjectRequest, SuiPastObjectResponse};
+use sui_types::base_types::{ObjectID, SequenceNumber};
+
+use crate::{
+	_prelude::*,
+	client::ClientPool,
+	control,
+	etl::full_object_data_options,
+	influx::{write_metric_ingest_error, write_metric_mongo_write_error},
+	mongo::mongo_collection_name,
+	naming,
+	routing::routed_collections,
+	utils::{object_bson_bytes, slim_object, HURACAN_VERSION},
+};
+
+// With `bcsonly.enabled`, the transform step only asks for an object's BCS, type and owner, which roughly halves
+// response sizes and the time spent deserializing them on whole-chain backfills, where most versions we fetch are
+// overwritten by newer ones anyway. Objects stored that way are marked with `content_pending`, and their parsed content
+// is filled in by this decode pool in the background: it fetches the full object data of exactly the stored version,
+// so only the versions that are still the latest ones get decoded.
+// Until then, the objects have no `content`, which means everything derived from it (balances, NFTs, kiosks, staking,
+// plugins) isn't updated for them either. Objects routed to other collections (see routing.rs) are decoded there.
+pub async fn spawn_decode_pool(cfg: &AppConfig) -> anyhow::Result<()> {
+	if !cfg.bcsonly.enabled {
+		return Ok(())
+	}
+	let cfg = cfg.clone();
+	let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+	let mut collections = vec![mongo_collection_name(&cfg, "")];
+	collections.extend(routed_collections(&cfg).await);
+	for collection in &collections {
+		db.collection::<Document>(collection)
+			.create_index(
+				IndexModel::builder()
+					.keys(doc! { "content_pending": 1 })
+					.options(Some(IndexOptions::builder().sparse(true).build()))
+					.build(),
+				None,
+			)
+			.await?;
+	}
+	let sui = cfg.sui().await?;
+	tokio::spawn(async move {
+		let batch_size = cfg.bcsonly.decodebatchsize;
+		let workers = cfg.bcsonly.decodeworkers.max(1);
+		loop {
+			let mut idle = true;
+			for collection in &collections {
+				control::wait_while_paused().await;
+				let pending = pending_versions(&db, collection, batch_size * workers).await;
+				if pending.is_empty() {
+					continue
+				}
+				idle = false;
+				stream::iter(pending.chunks(batch_size))
+					.map(|batch| decode_batch(sui.clone(), &db, collection, cfg.slimdocuments, batch))
+					.buffer_unordered(workers)
+					.collect::<Vec<_>>()
+					.await;
+			}
+			if idle {
+				tokio::time::sleep(Duration::from_millis(cfg.bcsonly.idlems)).await;
+			}
+		}
+	});
+	Ok(())
+}
+
+// Up to `limit` objects of `collection` waiting to be decoded, with their stored version.
+async fn pending_versions(db: &Database, collection: &str, limit: usize) -> Vec<(ObjectID, SequenceNumber)> {
+	let opts = FindOptions::builder().projection(doc! { "_id": 1, "version_": 1 }).limit(limit as i64).build();
+	let pending = match db.collection::<Document>(collection).find(doc! { "content_pending": true }, opts).await {
+		Ok(cursor) => cursor.try_collect::<Vec<_>>().await,
+		Err(err) => Err(err),
+	};
+	match pending {
+		Ok(pending) => pending.iter().filter_map(pending_version).collect(),
+		Err(err) => {
+			warn!(collection, error = ?err, "DecodeWarning: Failed looking up objects without content.");
+			Vec::new()
+		}
+	}
+}
+
+fn pending_version(doc: &Document) -> Option<(ObjectID, SequenceNumber)> {
+	let id = ObjectID::from_str(doc.get_str("_id").ok()?).ok()?;
+	Some((id, SequenceNumber::from(doc.get_i64("version_").ok()? as u64)))
+}
+
+async fn decode_batch(
+	mut sui: ClientPool,
+	db: &Database,
+	collection: &str,
+	slim: bool,
+	batch: &[(ObjectID, SequenceNumber)],
+) {
+	let requests =
+		batch.iter().map(|(object_id, version)| SuiGetPastObjectRequest { object_id: *object_id, version: *version });
+	let responses = match sui.try_multi_get_parsed_past_object(requests.collect(), full_object_data_options()).await {
+		Ok(responses) => responses,
+		Err(err) => {
+			warn!(error = ?err, "DecodeWarning: Cannot fetch {} object versions, will try again.", batch.len());
+			return
+		}
+	};
+	let mut updates = Vec::with_capacity(batch.len());
+	for (id, version) in batch {
+		let data = responses.iter().find_map(|res| match res {
+			SuiPastObjectResponse::VersionFound(data) if data.object_id == *id && data.version == *version => Some(data),
+			_ => None,
+		});
+		let object = data
+			.and_then(|data| object_bson_bytes(data).ok())
+			.and_then(|bytes| Document::from_reader(&mut std::io::Cursor::new(bytes)).ok())
+			.map(|mut object| {
+				if slim {
+					slim_object(&mut object);
+				}
+				naming::apply(&mut object);
+				object
+			});
+		// if the version is gone (fullnodes prune old versions) or can't be stored, there's nothing we can do anymore
+		let u = match object {
+			Some(object) => doc! {
+				"$set": { "object": object, "updated_at": DateTime::now(), "huracan_version": HURACAN_VERSION },
+				"$unset": { "content_pending": "" },
+			},
+			None => {
+				warn!(object_id = ?id, version = ?version, "DecodeWarning: Object version not available, leaving it without content.");
+				write_metric_ingest_error(id.to_string(), "content_not_decoded".to_string()).await;
+				doc! { "$unset": { "content_pending": "" } }
+			}
+		};
+		updates.push(doc! {
+			// a newer version may have been stored in the meantime, which we must not overwrite
+			"q": { "_id": id.to_string(), "version_": version.value() as i64 },
+			"u": u,
+			"upsert": false,
+			"multi": false,
+		});
+	}
+	if let Err(err) = db.run_command(doc! { "update": collection, "updates": updates, "ordered": false }, None).await {
+		write_metric_mongo_write_error().await;
+		warn!(error = ?err, "DecodeWarning: Failed storing decoded objects, will try again.");
+	}
+}
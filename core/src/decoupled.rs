@@ -0,0 +1,317 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering::Relaxed};
+
+use async_channel::Receiver as ACReceiver;
+use futures::stream;
+use mongodb::Database;
+use sui_types::base_types::TransactionDigest;
+use tokio::{pin, sync::mpsc::Sender as TSender, task::JoinHandle};
+
+use crate::{
+	_prelude::*,
+	bus::{BusConsumer, BusProducer, MessageBus},
+	client::ClientPool,
+	etl::{load_batched, transform_batched, ObjectItem, StepStatus},
+	influx::write_metric_ingest_error,
+	pipeline,
+	retries::Retries,
+};
+
+// With `decoupled: true`, the steps of a pipeline are connected through topics on a message bus (`bus`, Pulsar by
+// default) instead of in-memory channels:
+// extract -> raw topic -> transform -> enriched topic -> load
+// Each step only acks its input once the resulting output has been published (or loaded), so a crash in one step
+// doesn't lose any work buffered in the others; it's picked up again on the next run. As the steps only talk to each
+// other through the bus, they could just as well run as separate processes.
+// Inputs a step fails to process are nacked and redelivered after a backoff. With Pulsar, they're moved to a
+// dead-letter topic once they failed `pulsar.redelivery.maxredeliveries` times (see `indexer dead-letters`).
+// Transform and load run `workers.object` and `workers.mongo` consumers on a shared subscription. Messages are
+// keyed by object id, so each worker sees all versions of the objects assigned to it, in the order they were found.
+// The same goes for partitioned topics (see `pulsar.partitions`): an object's messages always go to the same partition.
+// Only the extract step knows about the chain: an item counts as done for our stored progress (see
+// `spawn_pipeline_tail()`) as soon as it has been published to the raw topic, reported via `extracted_tx`. Everything
+// downstream resumes from its subscription's position on the bus alone. The returned handle completes once transform
+// and load have drained their topics.
+// Publishing is at-least-once; anything published again after a crash is a duplicate downstream can safely load
+// again (see idempotency.rs to skip it instead). There's deliberately no exactly-once mode:
+// - Pulsar transactions would have to span our cursor in MongoDB, and our Pulsar client doesn't support them anyway
+// - Pulsar's broker-side deduplication drops messages by sequence id, which has to increase with every message of a
+//   named producer. Checkpoints are walked from newest to oldest, by several workers at once, and again in every
+//   livescan round, and polled items have no checkpoint at all, so ids derived from checkpoint and position would
+//   go backwards all the time, making the broker drop messages it has never seen.
+pub async fn spawn_stages(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	sui: ClientPool,
+	object_ids_rx: ACReceiver<(Option<TransactionDigest>, ObjectItem)>,
+	mongo: Database,
+	extracted_tx: TSender<(StepStatus, ObjectItem, Option<u64>)>,
+) -> anyhow::Result<JoinHandle<()>> {
+	match cfg.bus.as_str() {
+		#[cfg(feature = "pulsar")]
+		"pulsar" => spawn_stages_on(crate::pulsar::PulsarBus, cfg, pc, sui, object_ids_rx, mongo, extracted_tx).await,
+		#[cfg(feature = "kafka")]
+		"kafka" => {
+			spawn_stages_on(crate::kafka::KafkaBus::new(cfg), cfg, pc, sui, object_ids_rx, mongo, extracted_tx).await
+		}
+		#[cfg(feature = "redis")]
+		"redis" => {
+			let bus = crate::redisstreams::RedisBus::new(cfg)?;
+			spawn_stages_on(bus, cfg, pc, sui, object_ids_rx, mongo, extracted_tx).await
+		}
+		bus => Err(anyhow!(
+			"unsupported bus: {} (expected: pulsar | kafka | redis, each requires the feature of the same name)",
+			bus
+		)),
+	}
+}
+
+async fn spawn_stages_on<B: MessageBus>(
+	bus: B,
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	sui: ClientPool,
+	object_ids_rx: ACReceiver<(Option<TransactionDigest>, ObjectItem)>,
+	mongo: Database,
+	extracted_tx: TSender<(StepStatus, ObjectItem, Option<u64>)>,
+) -> anyhow::Result<JoinHandle<()>> {
+	// e.g. prod_testnet_objects_raw_livescan
+	let raw_topic = format!("raw_{}", pc.name);
+	let enriched_topic = format!("enriched_{}", pc.name);
+	for topic in [&raw_topic, &enriched_topic] {
+		bus.prepare_topic(topic).await?;
+	}
+	let default_num_workers = sui.configs.len();
+	let num_object_workers = pc.workers.object.unwrap_or(default_num_workers);
+	let num_mongo_workers = pc.workers.mongo.unwrap_or(default_num_workers);
+	let raw_hop = Arc::new(Hop::new(1));
+	let enriched_hop = Arc::new(Hop::new(num_object_workers));
+
+	// extract: publish everything the checkpoint / polling steps found
+	tokio::spawn({
+		let mut raw = bus.producer(&raw_topic).await?;
+		let raw_hop = raw_hop.clone();
+		let max_unconfirmed = pc.objectqueries.batchsize;
+		async move {
+			// items are only reported as extracted once the bus has confirmed them, which we wait for whenever
+			// there's nothing else to publish right now
+			let mut unconfirmed = Vec::new();
+			loop {
+				let next = if unconfirmed.is_empty() {
+					object_ids_rx.recv().await.ok()
+				} else if let Some(next) =
+					(unconfirmed.len() < max_unconfirmed).then(|| object_ids_rx.try_recv().ok()).flatten()
+				{
+					Some(next)
+				} else {
+					raw.flush().await.expect("ExtractionError: failed to publish items to raw topic!");
+					for item in unconfirmed.drain(..) {
+						extracted_tx.send((StepStatus::Ok, item, None)).await.expect("ExtractionError: pipeline tail went away!");
+					}
+					continue
+				};
+				let Some((_, item)) = next else { break };
+				publish(&mut raw, item.clone()).await.expect("ExtractionError: failed to publish item to raw topic!");
+				raw_hop.published(1);
+				unconfirmed.push(item);
+			}
+			raw_hop.upstream_done();
+		}
+	});
+
+	let mut workers = Vec::with_capacity(num_object_workers + num_mongo_workers);
+
+	// transform: fetch object data for raw items, publish them to the enriched topic
+	for _ in 0..num_object_workers {
+		workers.push(tokio::spawn({
+			let mut raw = bus.consumer(&raw_topic, &format!("{}_transform_{}", cfg.env, pc.name)).await?;
+			let mut enriched = bus.producer(&enriched_topic).await?;
+			// failed items go to the "retries" Pulsar topic, which is our dead-letter store, see retries.rs
+			let mut retries = Retries::new().await?;
+			let sui = sui.clone();
+			let raw_hop = raw_hop.clone();
+			let enriched_hop = enriched_hop.clone();
+			let pc = pc.clone();
+			async move {
+				while !raw_hop.drained() {
+					let wait = Duration::from_millis(pc.objectqueries.batchwaittimeoutms);
+					let batch = raw
+						.recv_batch(pc.objectqueries.batchsize, wait)
+						.await
+						.expect("ExtractionError: failed receiving from raw topic!");
+					if batch.is_empty() {
+						continue
+					}
+					let (items, receipts, undecodable) = deserialize_batch(batch);
+					if !undecodable.is_empty() {
+						raw_hop.consumed(raw.nack(undecodable).await.expect("ExtractionError: failed to nack raw items!"));
+					}
+					let (mut failed, mut unretried) = (None, false);
+					let transformed = transform_batched(stream::iter(vec![items]), sui.clone(), None).await;
+					pin!(transformed);
+					while let Some((status, item)) = transformed.next().await {
+						match status {
+							StepStatus::Ok => {
+								if let Err(err) = publish(&mut enriched, pipeline::enrich(item).await).await {
+									failed = Some(err);
+									break
+								}
+								enriched_hop.published(1);
+							}
+							StepStatus::Err(err) if err.is_retryable() => {
+								unretried |= !retries.send(item, &err).await;
+							}
+							StepStatus::Err(err) => {
+								warn!(object_id = ?item.id, error = %err, "ExtractionError: Unrecoverable error fetching object data, skipping.");
+								write_metric_ingest_error(item.id.to_string(), err.class().to_string()).await;
+							}
+						}
+					}
+					if failed.is_none() {
+						failed = enriched.flush().await.err();
+					}
+					// anything we've published already will be published again after redelivery, which is fine as
+					// loading is idempotent
+					if let Some(err) = failed {
+						warn!(error = ?err, "ExtractionError: Failed publishing to enriched topic, raw items will be redelivered.");
+						raw_hop.consumed(raw.nack(receipts).await.expect("ExtractionError: failed to nack raw items!"));
+					} else if unretried {
+						// without a dead-letter store, the broker redelivers them instead, see retries.rs
+						raw_hop.consumed(raw.nack(receipts).await.expect("ExtractionError: failed to nack raw items!"));
+					} else {
+						let num_received = receipts.len();
+						raw.ack(receipts).await.expect("ExtractionError: failed to ack raw items!");
+						raw_hop.consumed(num_received);
+					}
+				}
+				enriched_hop.upstream_done();
+			}
+		}));
+	}
+
+	// load: write enriched items to mongo
+	for _ in 0..num_mongo_workers {
+		workers.push(tokio::spawn({
+			let mut enriched = bus.consumer(&enriched_topic, &format!("{}_load_{}", cfg.env, pc.name)).await?;
+			// items load_batched() failed on, e.g. for lack of object data, go to the dead-letter store, just like
+			// in the tail of the in-memory pipeline
+			let mut retries = Retries::new().await?;
+			let cfg = cfg.clone();
+			let pc = pc.clone();
+			let mongo = mongo.clone();
+			let enriched_hop = enriched_hop.clone();
+			async move {
+				while !enriched_hop.drained() {
+					let wait = Duration::from_millis(pc.mongo.batchwaittimeoutms);
+					let batch = enriched
+						.recv_batch(pc.mongo.batchsize, wait)
+						.await
+						.expect("ExtractionError: failed receiving from enriched topic!");
+					if batch.is_empty() {
+						continue
+					}
+					let (items, receipts, undecodable) = deserialize_batch(batch);
+					if !undecodable.is_empty() {
+						let num_dead = enriched.nack(undecodable).await.expect("ExtractionError: failed to nack enriched items!");
+						enriched_hop.consumed(num_dead);
+					}
+					let num_received = receipts.len();
+					// load_batched() reports every item exactly once, so this never fills up while it runs
+					let (loaded_tx, mut loaded_rx) = tokio::sync::mpsc::channel(items.len().max(1));
+					load_batched(cfg.clone(), pc.clone(), stream::iter(vec![items]), mongo.clone(), loaded_tx).await;
+					let mut unretried = false;
+					while let Some((status, item, _)) = loaded_rx.recv().await {
+						match status {
+							StepStatus::Ok => {}
+							StepStatus::Err(err) if err.is_retryable() => {
+								unretried |= !retries.send(item, &err).await;
+							}
+							StepStatus::Err(err) => {
+								warn!(object_id = ?item.id, error = %err, "ExtractionError: Unrecoverable error loading object, skipping.");
+								write_metric_ingest_error(item.id.to_string(), err.class().to_string()).await;
+							}
+						}
+					}
+					if unretried {
+						// without a dead-letter store, the broker redelivers the batch instead, which is fine as
+						// loading is idempotent, see retries.rs
+						let num_dead = enriched.nack(receipts).await.expect("ExtractionError: failed to nack enriched items!");
+						enriched_hop.consumed(num_dead);
+					} else {
+						enriched.ack(receipts).await.expect("ExtractionError: failed to ack enriched items!");
+						enriched_hop.consumed(num_received);
+					}
+				}
+			}
+		}));
+	}
+
+	Ok(tokio::spawn(async move {
+		join_all(workers).await;
+	}))
+}
+
+// Lets a step know when it has seen everything its upstream step published during this run, so the pipeline shuts
+// down once drained, just like the in-memory one does once its channels are closed.
+// Leftovers from a previous run are processed as well, which can make a step stop before it has seen all items of
+// this run. These stay in their topic and will be picked up by the next run.
+struct Hop {
+	pending:           AtomicI64,
+	upstream_workers:  AtomicUsize,
+	upstream_finished: AtomicBool,
+}
+
+impl Hop {
+	fn new(upstream_workers: usize) -> Self {
+		Self {
+			pending:           AtomicI64::new(0),
+			upstream_workers:  AtomicUsize::new(upstream_workers),
+			upstream_finished: AtomicBool::new(false),
+		}
+	}
+
+	fn published(&self, n: usize) {
+		self.pending.fetch_add(n as i64, Relaxed);
+	}
+
+	fn consumed(&self, n: usize) {
+		self.pending.fetch_sub(n as i64, Relaxed);
+	}
+
+	// to be called by each upstream worker once it's done
+	fn upstream_done(&self) {
+		if self.upstream_workers.fetch_sub(1, Relaxed) == 1 {
+			self.upstream_finished.store(true, Relaxed);
+		}
+	}
+
+	fn drained(&self) -> bool {
+		self.upstream_finished.load(Relaxed) && self.pending.load(Relaxed) <= 0
+	}
+}
+
+// Publishes an item keyed by its object id.
+async fn publish<P: BusProducer>(producer: &mut P, item: ObjectItem) -> anyhow::Result<()> {
+	let key = item.id.to_string();
+	producer.send(key, serde_json::to_vec(&item)?).await
+}
+
+// Items we can't deserialize are returned separately, so they can be nacked. They might have been published by a
+// newer version, and if not, they'll end up in the dead-letter topic eventually.
+fn deserialize_batch<R>(batch: Vec<(Vec<u8>, R)>) -> (Vec<ObjectItem>, Vec<R>, Vec<R>) {
+	let mut items = Vec::with_capacity(batch.len());
+	let mut receipts = Vec::with_capacity(batch.len());
+	let mut undecodable = Vec::new();
+	for (payload, receipt) in batch {
+		match serde_json::from_slice(&payload) {
+			Ok(item) => {
+				items.push(item);
+				receipts.push(receipt);
+			}
+			Err(err) => {
+				warn!(error = ?err, "ExtractionError: Cannot deserialize item from topic, nacking it.");
+				undecodable.push(receipt);
+			}
+		}
+	}
+	(items, receipts, undecodable)
+}
@@ -0,0 +1,86 @@
+use async_trait::async_trait;
+use bson::{doc, Document};
+use mongodb::{Database, IndexModel};
+
+use crate::{
+	_prelude::*,
+	etl::ObjectItem,
+	mongo::{mongo_collection_name, replace_if_newer},
+	plugins::Plugin,
+	utils::type_params,
+};
+
+const POOL_TYPE: &str = "0xdee9::clob_v2::Pool<";
+// price levels are leaves of the pools' crit-bit trees, stored as dynamic fields of their `leaves` tables
+const LEVEL_TYPE: &str = "0x2::dynamic_field::Field<u64, 0xdee9::critbit::Leaf<0xdee9::clob_v2::TickLevel>>";
+
+// DeepBook (clob_v2) order books: `_deepbook_pools` holds the parameters of every pool, along with the ids of the
+// tables holding its bid and ask price levels, `_deepbook_levels` every price level that currently has open orders,
+// keyed by the dynamic field holding it. The bids of a pool are all levels with `table` == the pool's `bids`.
+pub struct DeepBookPlugin;
+
+#[async_trait]
+impl Plugin for DeepBookPlugin {
+	fn name(&self) -> &'static str {
+		"deepbook"
+	}
+
+	async fn setup(&self, cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+		let levels = db.collection::<Document>(&mongo_collection_name(cfg, "_deepbook_levels"));
+		levels.create_index(IndexModel::builder().keys(doc! { "table": 1, "price": 1 }).build(), None).await?;
+		Ok(())
+	}
+
+	async fn process(
+		&self,
+		cfg: &AppConfig,
+		db: &Database,
+		chunk: &[ObjectItem],
+		objects: &[Option<Document>],
+	) -> anyhow::Result<()> {
+		let pools = db.collection::<Document>(&mongo_collection_name(cfg, "_deepbook_pools"));
+		let levels = db.collection::<Document>(&mongo_collection_name(cfg, "_deepbook_levels"));
+		for (item, object) in chunk.iter().zip(objects) {
+			let id = item.id.to_string();
+			let v = item.version.value() as i64;
+			let Some(object) = object else {
+				// the level has been emptied, or the item wasn't a level at all
+				levels.delete_one(doc! { "_id": &id, "version_": { "$lt": v } }, None).await?;
+				continue
+			};
+			let ty = object.get_str("type")?;
+			let fields = object.get_document("content")?.get_document("fields")?;
+			if ty.starts_with(POOL_TYPE) {
+				let params = type_params(ty);
+				let leaves = |side: &str| -> Option<String> {
+					let tree = fields.get_document(side).ok()?.get_document("fields").ok()?;
+					let leaves = tree.get_document("leaves").ok()?.get_document("fields").ok()?;
+					Some(leaves.get_document("id").ok()?.get_str("id").ok()?.to_string())
+				};
+				let pool = doc! {
+					"_id": &id,
+					"version_": v,
+					"base": params.first().copied(),
+					"quote": params.get(1).copied(),
+					"tick_size": fields.get_str("tick_size").ok(),
+					"lot_size": fields.get_str("lot_size").ok(),
+					"taker_fee_rate": fields.get_str("taker_fee_rate").ok(),
+					"maker_rebate_rate": fields.get_str("maker_rebate_rate").ok(),
+					"bids": leaves("bids"),
+					"asks": leaves("asks"),
+				};
+				replace_if_newer(&pools, &id, v, pool).await?;
+			} else if ty == LEVEL_TYPE {
+				let Some(table) = object.get_document("owner")?.get_str("ObjectOwner").ok() else { continue };
+				let level = doc! {
+					"_id": &id,
+					"version_": v,
+					"table": table,
+					"price": fields.get_str("name")?,
+				};
+				replace_if_newer(&levels, &id, v, level).await?;
+			}
+		}
+		Ok(())
+	}
+}
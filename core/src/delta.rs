@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use bson::{doc, Bson, Document};
+
+// With `history.deltas`, versions in the history collection hold a patch against the version before them (`patch`,
+// applying to the version with id `base`) instead of a full copy of the object, except for every `keyframeevery`th
+// version, which keeps the full object as usual. Every version also records `keyframe_`, the version_ at or after
+// which the chain of patches leading up to it starts, so a version can be restored from the versions in between.
+// Patches are lists of JSON Patch style operations (`{op: "add" | "replace" | "remove", path, value}`, with RFC 6901
+// paths), in which arrays are always replaced as a whole.
+// Only depends on bson, the webserver includes it, too (see server/src/main.rs).
+
+fn escape(key: &str) -> String {
+	key.replace('~', "~0").replace('/', "~1")
+}
+
+fn unescape(token: &str) -> String {
+	token.replace("~1", "/").replace("~0", "~")
+}
+
+// The operations turning `old` into `new`.
+pub fn diff(old: &Document, new: &Document) -> Vec<Document> {
+	let mut ops = Vec::new();
+	diff_into(&mut ops, "", old, new);
+	ops
+}
+
+fn diff_into(ops: &mut Vec<Document>, prefix: &str, old: &Document, new: &Document) {
+	for (key, old_value) in old {
+		let path = format!("{}/{}", prefix, escape(key));
+		match (old_value, new.get(key)) {
+			(_, None) => ops.push(doc! { "op": "remove", "path": path }),
+			(Bson::Document(old_value), Some(Bson::Document(new_value))) => diff_into(ops, &path, old_value, new_value),
+			(old_value, Some(new_value)) if old_value != new_value => {
+				ops.push(doc! { "op": "replace", "path": path, "value": new_value.clone() })
+			}
+			_ => {}
+		}
+	}
+	for (key, new_value) in new {
+		if !old.contains_key(key) {
+			ops.push(doc! { "op": "add", "path": format!("{}/{}", prefix, escape(key)), "value": new_value.clone() });
+		}
+	}
+}
+
+// Applies `patch` to `object`, false if it doesn't fit.
+pub fn apply(object: &mut Document, patch: &[Bson]) -> bool {
+	patch.iter().all(|op| op.as_document().map_or(false, |op| apply_op(object, op)))
+}
+
+fn apply_op(object: &mut Document, op: &Document) -> bool {
+	let (Ok(kind), Ok(path)) = (op.get_str("op"), op.get_str("path")) else { return false };
+	let mut tokens = path.split('/').skip(1).map(unescape).collect::<Vec<_>>();
+	let Some(key) = tokens.pop() else { return false };
+	let mut parent = object;
+	for token in &tokens {
+		match parent.get_document_mut(token) {
+			Ok(child) => parent = child,
+			Err(_) => return false,
+		}
+	}
+	match (kind, op.get("value")) {
+		("add" | "replace", Some(value)) => {
+			parent.insert(key, value.clone());
+			true
+		}
+		("remove", _) => parent.remove(&key).is_some(),
+		_ => false,
+	}
+}
+
+// Restores the objects of all patched versions in `versions` (all of the same object, sorted by `version_`, oldest
+// first) whose chain of patches is contained in it, by inserting them as `object`. Others are left as they are.
+pub fn resolve(versions: &mut [Document]) {
+	let index = versions
+		.iter()
+		.enumerate()
+		.filter_map(|(i, v)| Some((v.get_str("_id").ok()?.to_string(), i)))
+		.collect::<HashMap<_, _>>();
+	for i in 0..versions.len() {
+		if versions[i].contains_key("object") {
+			continue
+		}
+		let (Ok(base), Ok(patch)) = (versions[i].get_str("base"), versions[i].get_array("patch")) else { continue };
+		// bases are older, so they've been restored already, if they can be
+		let Some(&base) = index.get(base).filter(|&&base| base < i) else { continue };
+		let Ok(mut object) = versions[base].get_document("object").map(Clone::clone) else { continue };
+		if apply(&mut object, patch) {
+			versions[i].insert("object", object);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_delta_roundtrip() {
+		let old = doc! {
+			"type": "0xdee9::clob_v2::Pool",
+			"owner": { "Shared": { "initial_shared_version": 5 } },
+			"content": { "fields": { "bids": [1, 2], "asks": [3], "a/b": "x", "fee": "10" } },
+		};
+		let new = doc! {
+			"type": "0xdee9::clob_v2::Pool",
+			"owner": { "Shared": { "initial_shared_version": 5 } },
+			"content": { "fields": { "bids": [1, 2, 4], "asks": [3], "a/b": "y", "paused": true } },
+		};
+		let patch = diff(&old, &new);
+		assert_eq!(patch.len(), 4);
+		assert!(patch.contains(&doc! { "op": "replace", "path": "/content/fields/a~1b", "value": "y" }));
+		assert!(patch.contains(&doc! { "op": "remove", "path": "/content/fields/fee" }));
+		let mut restored = old.clone();
+		assert!(apply(&mut restored, &patch.into_iter().map(Bson::Document).collect::<Vec<_>>()));
+		assert_eq!(restored.get_document("content").unwrap().get_document("fields").unwrap().get("paused"), Some(&Bson::Boolean(true)));
+		assert!(diff(&restored, &new).is_empty());
+		assert!(diff(&new, &new).is_empty());
+		// patches that don't fit
+		assert!(!apply(&mut doc! {}, &[Bson::Document(doc! { "op": "remove", "path": "/content/fields/fee" })]));
+	}
+
+	#[test]
+	fn test_delta_resolve() {
+		let object = |n: i32| doc! { "content": { "fields": { "n": n } } };
+		let patch = |from: i32, to: i32| diff(&object(from), &object(to));
+		let mut versions = vec![
+			doc! { "_id": "0x5:1", "version_": 1i64, "keyframe_": 1i64, "object": object(1) },
+			doc! { "_id": "0x5:2", "version_": 2i64, "keyframe_": 1i64, "base": "0x5:1", "patch": patch(1, 2) },
+			doc! { "_id": "0x5:3", "version_": 3i64, "keyframe_": 1i64, "base": "0x5:2", "patch": patch(2, 3) },
+			// its base is missing
+			doc! { "_id": "0x5:5", "version_": 5i64, "keyframe_": 1i64, "base": "0x5:4", "patch": patch(4, 5) },
+		];
+		resolve(&mut versions);
+		assert_eq!(versions[1].get_document("object").unwrap(), &object(2));
+		assert_eq!(versions[2].get_document("object").unwrap(), &object(3));
+		assert!(!versions[3].contains_key("object"));
+	}
+}
@@ -0,0 +1,69 @@
+use async_trait::async_trait;
+use bson::{doc, Document};
+use mongodb::{Database, IndexModel};
+
+use crate::{
+	_prelude::*,
+	etl::ObjectItem,
+	mongo::{mongo_collection_name, replace_if_newer},
+	plugins::Plugin,
+	utils::{price_from_sqrt_price_x64, type_params},
+};
+
+// Cetus CLMM pools, the largest DEX on Sui by liquidity.
+const CETUS_POOL_TYPE: &str = "0x1eabed72c53feb3805120a081dc15963c204dc8d091542592abaf7a35689b2fb::pool::Pool<";
+
+// DEX pools: `_dex_pools` holds the current reserves and price of every known pool. Prices are in units of coin B per
+// coin A, both in their smallest unit, so they still need to be adjusted for the coins' decimals.
+pub struct DexPoolsPlugin;
+
+#[async_trait]
+impl Plugin for DexPoolsPlugin {
+	fn name(&self) -> &'static str {
+		"dexpools"
+	}
+
+	async fn setup(&self, cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+		let pools = db.collection::<Document>(&mongo_collection_name(cfg, "_dex_pools"));
+		pools.create_index(IndexModel::builder().keys(doc! { "coin_a": 1, "coin_b": 1 }).build(), None).await?;
+		Ok(())
+	}
+
+	async fn process(
+		&self,
+		cfg: &AppConfig,
+		db: &Database,
+		chunk: &[ObjectItem],
+		objects: &[Option<Document>],
+	) -> anyhow::Result<()> {
+		let pools = db.collection::<Document>(&mongo_collection_name(cfg, "_dex_pools"));
+		for (item, object) in chunk.iter().zip(objects) {
+			// pools are shared objects, which can't be deleted
+			let Some(object) = object else { continue };
+			let ty = object.get_str("type")?;
+			if !ty.starts_with(CETUS_POOL_TYPE) {
+				continue
+			}
+			let params = type_params(ty);
+			let fields = object.get_document("content")?.get_document("fields")?;
+			let sqrt_price = fields.get_str("current_sqrt_price")?.parse::<u128>()?;
+			let v = item.version.value() as i64;
+			let id = item.id.to_string();
+			let pool = doc! {
+				"_id": &id,
+				"version_": v,
+				"protocol": "cetus",
+				"coin_a": params.first().copied(),
+				"coin_b": params.get(1).copied(),
+				// Sui encodes large numbers as strings, so do we
+				"reserve_a": fields.get_str("coin_a")?,
+				"reserve_b": fields.get_str("coin_b")?,
+				"liquidity": fields.get_str("liquidity")?,
+				"fee_rate": fields.get_str("fee_rate")?,
+				"price": price_from_sqrt_price_x64(sqrt_price),
+			};
+			replace_if_newer(&pools, &id, v, pool).await?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,53 @@
+use crate::_prelude::*;
+
+// Periodic Tokio runtime stats, with the `diagnostics` feature and `log.runtimestatsms`, to tell a stalled pipeline
+// (e.g. a worker thread blocked by synchronous code, or tasks piling up) from a slow RPC provider or MongoDB in
+// production. The runtime only collects these when built with `RUSTFLAGS="--cfg tokio_unstable"`.
+#[cfg(tokio_unstable)]
+pub fn spawn_runtime_stats(cfg: &AppConfig) {
+	let Some(interval_ms) = cfg.log.runtimestatsms else { return };
+	tokio::spawn(async move {
+		let metrics = tokio::runtime::Handle::current().metrics();
+		let workers = metrics.num_workers();
+		let mut last_polls = (0..workers).map(|w| metrics.worker_poll_count(w)).collect::<Vec<_>>();
+		let mut last_busy = (0..workers).map(|w| metrics.worker_total_busy_duration(w)).collect::<Vec<_>>();
+		loop {
+			tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+			let mut polls = 0;
+			let mut busiest = Duration::ZERO;
+			for w in 0..workers {
+				let (p, busy) = (metrics.worker_poll_count(w), metrics.worker_total_busy_duration(w));
+				polls += p - last_polls[w];
+				busiest = busiest.max(busy - last_busy[w]);
+				(last_polls[w], last_busy[w]) = (p, busy);
+			}
+			info!(
+				"RuntimeInfo: {} tasks / {} polls / {} queued / {} of {} blocking threads busy / busiest worker {}% busy",
+				metrics.active_tasks_count(),
+				polls,
+				metrics.injection_queue_depth(),
+				metrics.num_blocking_threads() - metrics.num_idle_blocking_threads(),
+				metrics.num_blocking_threads(),
+				busiest.as_millis() as u64 * 100 / interval_ms.max(1),
+			);
+			// a worker that never got to park during a whole interval is most likely blocked
+			if busiest.as_millis() as u64 >= interval_ms * 95 / 100 {
+				warn!("RuntimeWarning: A worker thread was busy for the whole last {}ms, it may be blocked.", interval_ms);
+			}
+		}
+	});
+}
+
+#[cfg(not(tokio_unstable))]
+pub fn spawn_runtime_stats(cfg: &AppConfig) {
+	if cfg.log.runtimestatsms.is_some() {
+		warn!("RuntimeWarning: log.runtimestatsms needs a build with RUSTFLAGS=\"--cfg tokio_unstable\", ignoring it.");
+	}
+}
+
+// Setup tracing with tokio-console enabled.
+// See: https://tokio.rs/tokio/topics/tracing-next-steps
+pub fn setup_console_tracing() -> anyhow::Result<()> {
+	console_subscriber::init();
+	Ok(())
+}
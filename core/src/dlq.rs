@@ -0,0 +1,170 @@
+use futures::stream;
+use pulsar::{Consumer, SubType, TokioExecutor};
+use tokio::pin;
+
+use crate::{
+	_prelude::*,
+	cli::{DeadLettersArgs, DecoupledTopic, ReplayDlqArgs},
+	commands::spawn_sinks,
+	etl::{load_batched, transform_batched, ObjectItem, StepStatus},
+	pulsar::{dead_letter_topic, keyed_message, make_consumer, make_producer, recv_batch},
+};
+
+// If no new dead-letter item shows up for this long, we assume we've seen all of them.
+const IDLE_TIMEOUT_MS: u64 = 5000;
+
+// Entrypoint for the `replay-dlq` subcommand.
+// Our dead-letter store is the "retries" Pulsar topic, which receives every item we failed to fetch object data for.
+// Items are re-injected in batches: those without object data go through the transform step again, items that
+// already carry object data (or are deletions) go straight to the load step.
+// An item is acked (marked as resolved) once it has been loaded successfully, if it turned out to be filtered out
+// during transform, or if it failed with an error that retrying won't fix. Items failing with a retryable error, and
+// items outside of the requested time range or error class (see retries.rs), are left unacked, so they stay in the
+// dead-letter store and will be delivered again on the next run.
+pub async fn run_replay_dlq(cfg: &AppConfig, args: ReplayDlqArgs) -> anyhow::Result<()> {
+	spawn_sinks(cfg).await?;
+	let pc = cfg.pipeline(args.pipeline).clone();
+	let sui = cfg.sui().await?;
+	let db = cfg.mongo.client(&pc.mongo).await?;
+	let mut consumer: Consumer<ObjectItem, TokioExecutor> =
+		make_consumer("retries", &format!("{}_replay_dlq", cfg.env), SubType::Exclusive).await?;
+
+	let (mut resolved, mut failed, mut dropped, mut skipped) = (0usize, 0usize, 0usize, 0usize);
+	loop {
+		// collect the next batch
+		let mut batch = Vec::with_capacity(pc.objectqueries.batchsize);
+		while batch.len() < pc.objectqueries.batchsize {
+			let msg = match timeout(Duration::from_millis(IDLE_TIMEOUT_MS), consumer.try_next()).await {
+				Ok(Ok(Some(msg))) => msg,
+				Ok(Ok(None)) | Err(_) => break,
+				Ok(Err(err)) => return Err(err.into()),
+			};
+			let item = match msg.deserialize() {
+				Ok(item) => item,
+				Err(err) => {
+					warn!(error = ?err, "DlqError: Cannot deserialize dead-letter item, leaving it in place.");
+					skipped += 1;
+					continue
+				}
+			};
+			let error_class =
+				msg.payload.metadata.properties.iter().find(|p| p.key == "error_class").map(|p| p.value.as_str());
+			if args.since.map_or(false, |since| item.ts_first_seen < since)
+				|| args.until.map_or(false, |until| item.ts_first_seen > until)
+				|| args.error_class.as_deref().map_or(false, |class| error_class != Some(class))
+			{
+				skipped += 1;
+				continue
+			}
+			batch.push((msg, item));
+		}
+		if batch.is_empty() {
+			break
+		}
+
+		let (mut to_load, to_transform): (Vec<_>, Vec<_>) =
+			batch.iter().map(|(_, item)| item.clone()).partition(|item| item.deletion || !item.bytes.is_empty());
+		let (mut failed_ids, mut dropped_ids) = (HashSet::new(), HashSet::new());
+		if !to_transform.is_empty() {
+			let transformed = transform_batched(stream::iter(vec![to_transform]), sui.clone(), None).await;
+			pin!(transformed);
+			while let Some((status, item)) = transformed.next().await {
+				match status {
+					StepStatus::Ok => to_load.push(item),
+					StepStatus::Err(err) if err.is_retryable() => {
+						failed_ids.insert(item.id);
+					}
+					StepStatus::Err(err) => {
+						warn!(object_id = ?item.id, error = %err, "DlqWarning: Unrecoverable error, dropping dead-letter item.");
+						dropped_ids.insert(item.id);
+					}
+				}
+			}
+		}
+		if !to_load.is_empty() {
+			// load_batched() reports every item back to the pipeline tail, so we need enough room for all of them
+			let (last_tx, mut last_rx) = tokio::sync::mpsc::channel(to_load.len());
+			load_batched(cfg.clone(), pc.clone(), stream::iter(vec![to_load]), db.clone(), last_tx).await;
+			// items without usable object data are reported as failed, and stay in the dead-letter store
+			while let Ok((status, item, _)) = last_rx.try_recv() {
+				if matches!(status, StepStatus::Err(err) if err.is_retryable()) {
+					failed_ids.insert(item.id);
+				}
+			}
+		}
+
+		for (msg, item) in batch {
+			if failed_ids.contains(&item.id) {
+				failed += 1;
+				continue
+			}
+			consumer
+				.ack(&msg)
+				.await
+				.with_context(|| format!("failed to ack dead-letter item for object {}", item.id))?;
+			if dropped_ids.contains(&item.id) {
+				dropped += 1;
+			} else {
+				resolved += 1;
+			}
+		}
+		info!(
+			"DlqInfo: {} resolved / {} still failing / {} dropped / {} skipped so far",
+			resolved, failed, dropped, skipped
+		);
+	}
+
+	println!(
+		"replayed dead-letter items: {} resolved / {} still failing / {} dropped / {} skipped",
+		resolved, failed, dropped, skipped
+	);
+	Ok(())
+}
+
+// Entrypoint for the `dead-letters` subcommand.
+// Dead letters are messages of the raw / enriched topics that failed too often in decoupled mode (see
+// decoupled.rs). By default we just print them, leaving them in place. With `--replay`, they're published to their
+// original topic again, with their original key, and removed from the dead-letter topic.
+pub async fn run_dead_letters(cfg: &AppConfig, args: DeadLettersArgs) -> anyhow::Result<()> {
+	let pc = cfg.pipeline(args.pipeline);
+	let topic = match args.topic {
+		DecoupledTopic::Raw => format!("raw_{}", pc.name),
+		DecoupledTopic::Enriched => format!("enriched_{}", pc.name),
+	};
+	let mut consumer: Consumer<Vec<u8>, TokioExecutor> =
+		make_consumer(&dead_letter_topic(&topic), &format!("{}_dead_letters", cfg.env), SubType::Exclusive).await?;
+	let mut producer = if args.replay { Some(make_producer(&topic).await?) } else { None };
+
+	let (mut seen, mut replayed) = (0usize, 0usize);
+	loop {
+		let batch = recv_batch(&mut consumer, 100, Duration::from_millis(IDLE_TIMEOUT_MS)).await?;
+		if batch.is_empty() {
+			break
+		}
+		for msg in batch {
+			seen += 1;
+			let meta = &msg.payload.metadata;
+			let attempts = meta.properties.iter().find(|p| p.key == "attempts").map_or("?", |p| p.value.as_str());
+			let item = serde_json::from_slice::<ObjectItem>(&msg.payload.data);
+			match &item {
+				Ok(item) => println!(
+					"{} version {} checkpoint {} deletion {} ({} attempts)",
+					item.id, item.version, item.cp, item.deletion, attempts
+				),
+				Err(err) => println!("undecodable message with key {:?}: {} ({} attempts)", meta.partition_key, err, attempts),
+			}
+			if let (Some(producer), Ok(item)) = (&mut producer, &item) {
+				producer.send(keyed_message(msg.payload.data.clone(), item.id.to_string())?).await?.await?;
+				consumer.ack(&msg).await.with_context(|| format!("failed to ack dead letter for object {}", item.id))?;
+				replayed += 1;
+			}
+		}
+	}
+
+	if args.replay {
+		println!("replayed {} of {} dead letters to {}", replayed, seen, topic);
+	} else {
+		println!("{} dead letters in {}", seen, dead_letter_topic(&topic));
+	}
+	Ok(())
+}
@@ -0,0 +1,77 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{options::UpdateOptions, Collection, Database};
+use sha2::{Digest, Sha256};
+use sui_sdk::rpc_types::Checkpoint as SuiCheckpoint;
+
+use crate::{_prelude::*, mongo::mongo_collection_name};
+
+// With `checkpoints.enabled`, every epoch's last checkpoint also yields a summary of that epoch, stored in a separate
+// collection, e.g. prod_testnet_objects_epochs, keyed by epoch.
+// The last checkpoint knows the epoch's totals, but where the epoch started is only known from the end of the
+// previous one. As we scan checkpoints in any order, each epoch summary completes its neighbours (or is completed by
+// them), whichever is stored last.
+pub fn epochs_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_epochs")
+}
+
+// Hash over the validators and their stake, to easily tell whether the validator set changed between epochs.
+fn committee_hash(committee: &[(impl fmt::Display, u64)]) -> String {
+	let mut members = committee.iter().map(|(name, stake)| format!("{}:{}", name, stake)).collect::<Vec<_>>();
+	members.sort();
+	hex::encode(Sha256::digest(members.join("\n").as_bytes()))
+}
+
+pub async fn store_epoch_end(cfg: &AppConfig, db: &Database, cp: &SuiCheckpoint) -> anyhow::Result<()> {
+	let Some(end) = &cp.end_of_epoch_data else { return Ok(()) };
+	let coll = db.collection::<Document>(&epochs_collection_name(cfg));
+	let gas = &cp.epoch_rolling_gas_cost_summary;
+	let epoch = cp.epoch as i64;
+	set(&coll, epoch, doc! {
+		"end_checkpoint": cp.sequence_number as i64,
+		"end_ts": DateTime::from_millis(cp.timestamp_ms as i64),
+		"network_total_transactions": cp.network_total_transactions as i64,
+		"gas": {
+			"computation_cost": gas.computation_cost as i64,
+			"storage_cost": gas.storage_cost as i64,
+			"storage_rebate": gas.storage_rebate as i64,
+			"non_refundable_storage_fee": gas.non_refundable_storage_fee as i64,
+		},
+		// storage costs go into the storage fund, rebates are paid out of it
+		"storage_fund_change": gas.storage_cost as i64 - gas.storage_rebate as i64,
+		"next_validator_set_hash": committee_hash(&end.next_epoch_committee),
+		"next_protocol_version": end.next_epoch_protocol_version.as_u64() as i64,
+	})
+	.await?;
+
+	if epoch == 0 {
+		set(&coll, 0, doc! { "start_checkpoint": 0i64, "total_transactions": cp.network_total_transactions as i64 })
+			.await?;
+	} else if let Some(previous) = coll.find_one(doc! { "_id": epoch - 1 }, None).await? {
+		link(&coll, &previous, epoch, cp.network_total_transactions as i64).await?;
+	}
+	let next = coll.find_one(doc! { "_id": epoch + 1, "network_total_transactions": { "$exists": true } }, None).await?;
+	if let Some(next) = next {
+		let this = coll.find_one(doc! { "_id": epoch }, None).await?.ok_or_else(|| anyhow!("epoch {} vanished", epoch))?;
+		link(&coll, &this, epoch + 1, next.get_i64("network_total_transactions")?).await?;
+	}
+	Ok(())
+}
+
+// Completes epoch `epoch` using the end of the epoch before it.
+async fn link(coll: &Collection<Document>, previous: &Document, epoch: i64, total_at_end: i64) -> anyhow::Result<()> {
+	let mut update = doc! {
+		"start_checkpoint": previous.get_i64("end_checkpoint")? + 1,
+		"start_ts": previous.get_datetime("end_ts")?,
+		"total_transactions": total_at_end - previous.get_i64("network_total_transactions")?,
+	};
+	if let Ok(hash) = previous.get_str("next_validator_set_hash") {
+		update.insert("validator_set_hash", hash);
+	}
+	set(coll, epoch, update).await
+}
+
+async fn set(coll: &Collection<Document>, epoch: i64, fields: Document) -> anyhow::Result<()> {
+	coll.update_one(doc! { "_id": epoch }, doc! { "$set": fields }, UpdateOptions::builder().upsert(true).build())
+		.await?;
+	Ok(())
+}
@@ -0,0 +1,64 @@
+use std::fmt::{self, Display, Formatter};
+
+use jsonrpsee::core::Error as RpcError;
+use sui_sdk::error::Error as SuiError;
+
+// Classifies pipeline failures, so that retry, dead-letter and alerting logic can decide what to do with them
+// without having to parse log strings.
+#[derive(Clone, Debug)]
+pub enum EtlError {
+	// timeouts, rate limits, connection issues: likely to succeed if we try again later
+	RpcTransient(String),
+	// the RPC node understood our request, but rejected it or returned an error for it
+	RpcPermanent(String),
+	// we got a response, but couldn't make sense of it
+	DecodeFailure(String),
+	// MongoDB can't be written to right now
+	SinkUnavailable(String),
+	// something that should never happen, e.g. a response not matching its request
+	DataInvariantViolation(String),
+	// an object change reached the load step without its object data, which needs to be fetched again
+	MissingObjectData(String),
+}
+
+impl EtlError {
+	pub fn from_rpc(err: &SuiError) -> Self {
+		match err {
+			SuiError::RpcError(RpcError::Transport(_) | RpcError::RequestTimeout | RpcError::RestartNeeded(_)) => {
+				Self::RpcTransient(format!("{err:?}"))
+			}
+			_ => Self::RpcPermanent(format!("{err:?}")),
+		}
+	}
+
+	// Whether the failed item should be retried later. Everything else won't get any better by retrying.
+	pub fn is_retryable(&self) -> bool {
+		matches!(self, Self::RpcTransient(_) | Self::SinkUnavailable(_) | Self::MissingObjectData(_))
+	}
+
+	pub fn class(&self) -> &'static str {
+		match self {
+			Self::RpcTransient(_) => "rpc_transient",
+			Self::RpcPermanent(_) => "rpc_permanent",
+			Self::DecodeFailure(_) => "decode_failure",
+			Self::SinkUnavailable(_) => "sink_unavailable",
+			Self::DataInvariantViolation(_) => "data_invariant_violation",
+			Self::MissingObjectData(_) => "missing_object_data",
+		}
+	}
+}
+
+impl Display for EtlError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::RpcTransient(msg)
+			| Self::RpcPermanent(msg)
+			| Self::DecodeFailure(msg)
+			| Self::SinkUnavailable(msg)
+			| Self::DataInvariantViolation(msg)
+			| Self::MissingObjectData(msg) => write!(f, "{}: {}", self.class(), msg),
+		}
+	}
+}
+
+impl std::error::Error for EtlError {}
@@ -1,24 +1,24 @@
 use std::{
-	collections::{btree_map::OccupiedError, BTreeMap},
+	collections::{btree_map::Entry, BTreeMap},
 	fmt::{Display, Formatter},
+	hash::{Hash, Hasher},
 	io::Cursor,
-	sync::atomic::{AtomicU16, Ordering::Relaxed},
+	sync::atomic::{AtomicU16, AtomicU64, Ordering::Relaxed},
 	vec::IntoIter,
-	iter::zip,
 };
 use anyhow::Result;
 use async_channel::{Receiver as ACReceiver, Sender as ACSender};
 use async_stream::stream;
-use bson::{doc, Document};
+use bson::{doc, Bson, Document};
 use chrono::Utc;
 use futures::Stream;
 use futures_batch::ChunksTimeoutStreamExt;
 use influxdb::InfluxDbWriteable;
 use mongodb::{Database, options::FindOneOptions};
-use pulsar::{Pulsar, TokioExecutor};
 use rocksdb::{DBWithThreadMode, SingleThreaded};
 use sui_sdk::rpc_types::{
-	SuiObjectDataOptions, SuiTransactionBlockResponseOptions, SuiTransactionBlockResponseQuery, TransactionFilter,
+	SuiObjectDataOptions, SuiTransactionBlockResponse, SuiTransactionBlockResponseOptions,
+	SuiTransactionBlockResponseQuery, TransactionFilter,
 };
 use sui_types::{
 	base_types::{ObjectID, SequenceNumber, TransactionDigest},
@@ -32,25 +32,52 @@ use tokio::{
 
 use crate::{
 	_prelude::*,
+	activity, alerts,
+	alerts::Stage,
+	balances, checkpoints,
+	changefeed,
+	chunksizing::{adaptive_chunks, ChunkSizing},
+	cli::WatchArgs,
 	client,
 	client::{ClientPool, parse_get_object_response},
-	conf::{AppConfig, PipelineConfig},
-	ctrl_c_bool, mongo,
+	conf::{AppConfig, PipelineConfig, APPCONFIG},
+	control::{self, ctrl_c_bool},
+	decoupled,
+	contenthash,
+	errors::EtlError, filters, gas, history, idempotency, ids, inflight, kiosk, mongo, movecalls, naming, nft, owners, packages,
+	plugins,
 	mongo::{Checkpoint, mongo_checkpoint},
-	utils::make_descending_ranges
+	pipeline,
+	rawchanges,
+	readback,
+	reporting::{report_stage_error, ErrorContext},
+	retries::Retries,
+	routing,
+	staking, statsd, transactions, transfers, typestats,
+	utils,
+	utils::{bound_descending_ranges, content_hash, make_descending_ranges, object_doc_matches, object_update, slim_object},
+	webhooks, wraps,
 };
-use crate::conf::get_influx_singleton;
-use crate::influx::{get_influx_timestamp_as_milliseconds, InsertObject, ModifiedObject, write_metric_rpc_error, write_metric_rpc_request, write_metric_mongo_write_error, write_metric_checkpoints_behind, write_metric_backfill_init, write_metric_current_checkpoint, write_metric_create_checkpoint, write_metric_final_checkpoint, write_metric_pause_livescan, write_metric_start_livescan, UnchangedObject, write_metric_extraction_latency};
+use crate::conf::get_config_singleton;
+use crate::influx::{get_influx_timestamp_as_milliseconds, write_influx, InsertObject, ModifiedObject, write_metric_rpc_error, write_metric_rpc_request, write_metric_ingest_error, write_metric_mongo_write_error, write_metric_checkpoints_behind, write_metric_backfill_init, write_metric_current_checkpoint, write_metric_create_checkpoint, write_metric_final_checkpoint, write_metric_pause_livescan, write_metric_start_livescan, UnchangedObject, write_metric_extraction_latency, write_metric_backpressure};
 
 
 // sui now allows a max of 1000 objects to be queried for at once (used to be 50), at least on the
 // endpoints we're using (try_multi_get_parsed_past_object, query_transaction_blocks)
-const SUI_QUERY_MAX_RESULT_LIMIT: usize = 1000;
+pub const SUI_QUERY_MAX_RESULT_LIMIT: usize = 1000;
+// max. number of digests per sui_multiGetTransactionBlocks() request
+const SUI_MULTI_GET_LIMIT: usize = 50;
+// With `finalizedonly`, this many polled transaction blocks are held back at most while waiting for their checkpoint.
+// Anything beyond is left to the checkpoint scan.
+const MAX_PENDING_FINALITY: usize = 1000;
 
 // Our internal representation of a Sui object change. The `bytes` property is left empty before we fetch the full object data.
 // This is the final output from the checkpoint/transaction block crawl. It is published to the object stream to queue an RPC lookup of the full object data.
 // After the object data lookup, `bytes` is populated and this struct is queued for CRUD into downstream systems.
-#[derive(Clone, Debug, Serialize, Deserialize, PulsarMessage)]
+// On the wire (Pulsar / Kafka topics, recorded bench input), it's encoded as `ObjectItemWire`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "pulsar", derive(PulsarMessage))]
+#[serde(into = "ObjectItemWire", try_from = "ObjectItemWire")]
 pub struct ObjectItem {
 	pub cp:            CheckpointSequenceNumber,
 	pub deletion:      bool,
@@ -62,6 +89,88 @@ pub struct ObjectItem {
 	pub bytes:         Vec<u8>,
 }
 
+// A change of object 0x5 at checkpoint 7, for unit tests.
+#[cfg(test)]
+pub(crate) fn test_item(version: u64, deletion: bool) -> ObjectItem {
+	ObjectItem {
+		cp:            7,
+		deletion,
+		id:            ObjectID::from_hex_literal("0x5").unwrap(),
+		version:       SequenceNumber::from_u64(version),
+		ts_sui:        None,
+		ts_first_seen: 0,
+		ingested_via:  IngestRoute::Livescan,
+		bytes:         Vec::new(),
+	}
+}
+
+// Schema version of `ObjectItemWire`. Adding a field with a default is backward compatible and doesn't need a new
+// version, as older versions just ignore fields they don't know. Anything else (removing, renaming or changing
+// fields) does, and must keep reading all previous versions, so during a rolling upgrade, consumers can be upgraded
+// first, then producers, without draining any topics. Messages with a version we don't know yet fail to deserialize,
+// which makes them end up in the dead-letter topic in decoupled mode, from where they can be replayed after the
+// upgrade.
+pub const OBJECT_ITEM_SCHEMA: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct ObjectItemWire {
+	// messages from before we versioned the schema don't have this, but have the same layout as version 1
+	#[serde(default)]
+	schema:        u32,
+	cp:            CheckpointSequenceNumber,
+	deletion:      bool,
+	id:            ObjectID,
+	version:       SequenceNumber,
+	ts_sui:        Option<u64>,
+	ts_first_seen: u64,
+	ingested_via:  IngestRoute,
+	bytes:         Vec<u8>,
+	// see `AppConfig::network()`, empty in messages from before we tagged them
+	#[serde(default)]
+	network:       String,
+}
+
+impl From<ObjectItem> for ObjectItemWire {
+	fn from(item: ObjectItem) -> Self {
+		Self {
+			schema:        OBJECT_ITEM_SCHEMA,
+			cp:            item.cp,
+			deletion:      item.deletion,
+			id:            item.id,
+			version:       item.version,
+			ts_sui:        item.ts_sui,
+			ts_first_seen: item.ts_first_seen,
+			ingested_via:  item.ingested_via,
+			bytes:         item.bytes,
+			network:       APPCONFIG.get().map(|cfg| cfg.network().to_string()).unwrap_or_default(),
+		}
+	}
+}
+
+impl TryFrom<ObjectItemWire> for ObjectItem {
+	type Error = String;
+
+	fn try_from(wire: ObjectItemWire) -> Result<Self, Self::Error> {
+		// never load objects of another network, e.g. from a topic shared by mistake
+		if let Some(cfg) = APPCONFIG.get().filter(|cfg| !wire.network.is_empty() && wire.network != cfg.network()) {
+			return Err(format!("ObjectItem from network {}, but we're indexing {}", wire.network, cfg.network()))
+		}
+		match wire.schema {
+			0 | 1 => Ok(Self {
+				cp:            wire.cp,
+				deletion:      wire.deletion,
+				id:            wire.id,
+				version:       wire.version,
+				ts_sui:        wire.ts_sui,
+				ts_first_seen: wire.ts_first_seen,
+				ingested_via:  wire.ingested_via,
+				bytes:         wire.bytes,
+			}),
+			v => Err(format!("unsupported ObjectItem schema version {} (supported: up to {})", v, OBJECT_ITEM_SCHEMA)),
+		}
+	}
+}
+
 // We track the last pipeline an ObjectItem has been through.
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum IngestRoute {
@@ -73,37 +182,150 @@ pub enum IngestRoute {
 	Backfill,
 }
 
-// Ensure each data extraction step is successful. If a step is Err, it will be placed in the retry pipeline.
+// Ensure each data extraction step is successful. If a step is Err with a retryable error, it will be placed in the
+// retry pipeline.
 #[derive(Debug)]
 pub enum StepStatus {
 	Ok,
-	Err,
+	Err(EtlError),
 }
 
 impl Display for StepStatus {
 	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
 		match self {
 			Self::Ok => f.write_str("Ok"),
-			Self::Err => f.write_str("Err"),
+			Self::Err(err) => write!(f, "Err({})", err),
 		}
 	}
 }
 
+// How often each pipeline reports its backpressure stats.
+const BACKPRESSURE_REPORT_INTERVAL_MS: u64 = 10_000;
+
+// Backpressure stats of a pipeline: besides the number of items buffered in the channels between steps, we track
+// how long each step spent waiting for room in the channel to the next step. Whichever step makes the previous one
+// wait is the bottleneck.
+#[derive(Clone, Default)]
+pub struct Backpressure {
+	extract_blocked_us:   Arc<AtomicU64>,
+	transform_blocked_us: Arc<AtomicU64>,
+}
+
+impl Backpressure {
+	// Runs `f` (usually a channel send), adding the time it took to `counter`.
+	async fn timed<F: std::future::Future>(counter: &AtomicU64, f: F) -> F::Output {
+		let start = Instant::now();
+		let res = f.await;
+		counter.fetch_add(start.elapsed().as_micros() as u64, Relaxed);
+		res
+	}
+}
+
 // This is the entrypoint when environment variable BACKFILL_ONLY = true. This allows us to begin a highly parallel backfill starting at a specific checkpoint.
 pub async fn run_backfill_only(cfg: &AppConfig, start_checkpoint: Option<u64>) -> Result<()> {
 	let sui = cfg.sui().await?;
-	let (_, handle) = spawn_backfill_pipeline(&cfg, &cfg.backfill, sui, start_checkpoint).await?;
+	let (_, handle) = spawn_backfill_pipeline(&cfg, &cfg.backfill, sui, start_checkpoint, None).await?;
 	handle.await?;
 	Ok(())
 }
 
+// Applies `extract.start` (or its CLI override), before any pipeline is spawned. Our progress is the set of completed
+// checkpoints, plus stop markers: no backfill ever goes below the highest one. So every mode but resume replaces the
+// stop markers, keeping the completed checkpoints:
+// - resume: nothing changes, we continue from our stored progress. Without any, that's the same as genesis.
+// - latest: the current chain tip becomes the stop marker, so every older checkpoint is considered completed.
+// - genesis: no stop marker, so backfills go all the way down to checkpoint 0.
+// - checkpoint / digest: the checkpoint before the given one (or the one containing the given transaction) becomes
+//   the stop marker.
+// As this happens on every start, anything but resume is meant for one-off runs, e.g. via `--start`. With latest,
+// everything happening while we're not running would be skipped.
+pub async fn apply_start(cfg: &AppConfig) -> Result<()> {
+	// CLI overrides haven't been validated by `AppConfig::new()`
+	cfg.extract.validate()?;
+	let mongo = cfg.mongo.client(&cfg.livescan.mongo).await?;
+	let coll = mongo.collection::<Document>(&mongo::mongo_collection_name(cfg, "_checkpoints"));
+	let stop = match cfg.extract.start.as_str() {
+		"resume" => {
+			if coll.find_one(None, None).await?.is_none() {
+				warn!("IngestWarning: No stored progress to resume from, indexing everything since genesis.");
+			}
+			return Ok(())
+		}
+		"latest" => {
+			if cfg.backfillonly {
+				return Err(anyhow!("extract.start: latest cannot be combined with backfillonly"))
+			}
+			Some(cfg.sui().await?.get_latest_checkpoint_sequence_number().await? as u64)
+		}
+		"genesis" => None,
+		"checkpoint" => cfg.extract.checkpoint.unwrap_or_default().checked_sub(1),
+		"digest" => {
+			let digest = cfg.extract.digest.as_deref().unwrap_or_default();
+			checkpoint_of_transaction(cfg, digest).await?.checked_sub(1)
+		}
+		_ => unreachable!("validated above"),
+	};
+	coll.delete_many(doc! { "stop": true }, None).await?;
+	match stop {
+		Some(cp) => {
+			mongo::mongo_checkpoint_stop(cfg, &mongo, cp).await?;
+			warn!("IngestWarning: Starting after checkpoint {}, all older checkpoints are considered completed.", cp);
+		}
+		None => warn!("IngestWarning: Starting from genesis, backfilling every checkpoint not completed yet."),
+	}
+	Ok(())
+}
+
+// The checkpoint containing a transaction, given its digest.
+pub(crate) async fn checkpoint_of_transaction(cfg: &AppConfig, digest: &str) -> Result<u64> {
+	let digest =
+		TransactionDigest::from_str(digest).map_err(|e| anyhow!("invalid transaction digest {}: {}", digest, e))?;
+	let mut sui = cfg.sui().await?;
+	let tx = sui.get_transaction_with_options(digest, SuiTransactionBlockResponseOptions::new()).await?;
+	tx.checkpoint.ok_or_else(|| anyhow!("transaction {} is not part of a checkpoint yet", digest))
+}
+
+// This is the entrypoint for the `watch` subcommand. We poll for new transaction blocks, fetch their full object data
+// and pretty-print every object matching the given filters to stdout. Nothing is written to MongoDB or Pulsar.
+pub async fn run_watch(cfg: &AppConfig, args: WatchArgs) -> Result<()> {
+	info!("ExtractionInfo: Initializing run_watch().");
+	let sui = cfg.sui().await?;
+	// owners are stored in their canonical form, so we need to normalize whatever we got passed in
+	let owner =
+		args.owner.map(|owner| ids::normalize_id(&owner).ok_or_else(|| anyhow!("invalid --owner address"))).transpose()?;
+
+	let (items, _observed_cps) = spawn_checkpoint_poll(cfg, sui.clone(), Arc::new(AtomicU16::new(0))).await;
+	// deletions don't come with any object data we could match against, so we skip them
+	let items = items.filter_map(|(_, item)| async move { (!item.deletion).then_some(item) }).chunks_timeout(
+		cfg.livescan.objectqueries.batchsize,
+		Duration::from_millis(cfg.livescan.objectqueries.batchwaittimeoutms),
+	);
+	let stream = transform_batched(items, sui, None).await;
+	pin!(stream);
+	while let Some((status, item)) = stream.next().await {
+		if let StepStatus::Err(err) = status {
+			warn!(object_id = ?item.id, error = %err, "ExtractionError: Failed fetching object data, skipping.");
+			continue
+		}
+		let object = Document::from_reader(&mut Cursor::new(&item.bytes))?;
+		if !object_doc_matches(&object, args.type_.as_deref(), owner.as_deref()) {
+			continue
+		}
+		println!("{}", serde_json::to_string_pretty(&Bson::Document(object).into_relaxed_extjson())?);
+	}
+	Ok(())
+}
+
 // This is the default operation mode and will initialize a livescan with the most recent Sui checkpoint.
 // If our indexer is behind by "backfillthreshold", it will also initialize a separate backfill pipeline.
-pub async fn run(cfg: &AppConfig) -> Result<()> {
+// If `exit_at_tip` is set, we return as soon as our completed checkpoints are at most that many checkpoints behind
+// the latest one, otherwise we keep running indefinitely.
+pub async fn run(cfg: &AppConfig, exit_at_tip: Option<u64>) -> Result<()> {
 	info!("ExtractionInfo: Initializing run().");
 	let mut sui = cfg.sui().await?;
 	let stop = ctrl_c_bool();
 	let pause_livescan = Arc::new(AtomicU16::new(0));
+	let (tip_reached_tx, tip_reached_rx) = tokio::sync::oneshot::channel();
 
 	// Initialize livescan.
 	let (mut poll_livescan_items, _poll_observed_cps) = spawn_checkpoint_poll(cfg, sui.clone(), pause_livescan.clone()).await;
@@ -115,8 +337,10 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 	// Purpose of poll_livescan_items stream:
 	// 1) Pass on as normal input to livescan pipeline immediately.
 	// 2) Pass all seen transaction digests on to another stream.
+	let backpressure = Backpressure::default();
 	tokio::spawn({
 		let livescan_items_tx = livescan_items_tx.clone();
+		let backpressure = backpressure.clone();
 		async move {
 			while let Some(it) = poll_livescan_items.next().await {
 				// doing this first because we can copy the digest and then move the full value
@@ -126,7 +350,7 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 					}
 				}
 				// copy over to normal livescan pipeline input channel
-				if livescan_items_tx.send(it).await.is_err() {
+				if Backpressure::timed(&backpressure.extract_blocked_us, livescan_items_tx.send(it)).await.is_err() {
 					break
 				}
 			}
@@ -134,9 +358,14 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 	});
 
 	// rest of the livescan pipeline
-	let (livescan_cp_control_tx, livescan_handle) =
-		spawn_pipeline_tail(cfg.clone(), cfg.livescan.clone(), sui.clone(),  livescan_items_rx.clone())
-			.await?;
+	let (livescan_cp_control_tx, livescan_handle) = spawn_pipeline_tail(
+		cfg.clone(),
+		cfg.livescan.clone(),
+		sui.clone(),
+		livescan_items_rx.clone(),
+		backpressure.clone(),
+	)
+	.await?;
 
 	// observe checkpoints flow:
 	// if we fell behind too far, we focus on backfilling until caught up:
@@ -145,6 +374,7 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 	tokio::spawn({
 		let stop = stop.clone();
 		let cfg = cfg.clone();
+		let backpressure = backpressure.clone();
 
 		// load latest completed checkpoint
 		// if this is too far back
@@ -184,6 +414,7 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 				if stop.load(Relaxed) {
 					break
 				}
+				control::wait_while_paused().await;
 
 				let wait_ms = cfg.pollintervalms.saturating_sub(last_poll.elapsed().as_millis() as u64);
 				if wait_ms > 0 {
@@ -213,6 +444,14 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 				let behind_cp = latest_cp - last_completed_cp.unwrap_or(0) as u64;
 				info!("ExtractionInfo: Currently behind by {} checkpoints.", behind_cp);
 				write_metric_checkpoints_behind(behind_cp).await;
+				alerts::record_checkpoints_behind(behind_cp);
+				// completed checkpoints are only stored once all of their items have been loaded, so at this point
+				// everything up to the tip (minus the allowed lag) has been fully processed
+				if exit_at_tip.map_or(false, |max_lag| behind_cp <= max_lag) {
+					info!("ExtractionInfo: Reached chain tip (behind by {} checkpoints), exiting.", behind_cp);
+					tip_reached_tx.send(()).ok();
+					break
+				}
 				// Do not initialize a backfill pipeline if livescanonly is enabled.
 				if behind_cp > cfg.backfillthreshold as u64 && cfg.livescanonly == false {
 					warn!("IngestWarning: Initializing backfill pipeline.");
@@ -224,7 +463,7 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 					}
 					// run backfill
 					let (checkpointcompleted_rx, handle) =
-						spawn_backfill_pipeline(&cfg, &cfg.backfill, sui.clone(), None)
+						spawn_backfill_pipeline(&cfg, &cfg.backfill, sui.clone(), None, None)
 							.await
 							.unwrap();
 					// We continue low-latency work as soon as the first checkpoint crawl of the backfill completes,
@@ -278,11 +517,12 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 							// when the entry was made, so even if we should have bugs leading to mismatching
 							// and non-removal of entries, we can still remove them on an age basis
 							// and thus prevent memory leaks
-							match txns_already_processed.try_insert(tx, cp_offset_marker) {
-								Ok(_) => {
+							match txns_already_processed.entry(tx) {
+								Entry::Vacant(e) => {
 									// it was a new entry
+									e.insert(cp_offset_marker);
 								},
-								Err(OccupiedError { entry: e, .. }) => {
+								Entry::Occupied(e) => {
 									let v = e.get();
 									if *v > 0 {
 										// duplicate processing from polling side, probably a bug somewhere we need to fix!
@@ -303,7 +543,7 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 					}
 				}
 				// gc: stop remembering txs added more than 120 checkpoints ago
-				txns_already_processed.drain_filter(|_, v| latest_cp as i64 - v.abs() > 120);
+				txns_already_processed.retain(|_, v| latest_cp as i64 - v.abs() <= 120);
 
 				// spawn the livescan
 				let (mut scan_items, mut livescan_cp_control_rx) =
@@ -318,15 +558,16 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 					// if it's a new tx, then we want to check if we need to skip all items of this tx
 					if let Some(tx) = tx {
 						// keep track of "already processed" txs, figure out if we want to skip this tx
-						// first, see not on try_insert() above
+						// first, see note on entry() above
 						// then, we're using a negative value here, so we can discern between entries
 						// made from the polling side (positive) vs livescan side (negative)
-						match txns_already_processed.try_insert(tx, -cp_offset_marker) {
-							Ok(_) => {
+						match txns_already_processed.entry(tx) {
+							Entry::Vacant(e) => {
 								// tx was not seen yet
+								e.insert(-cp_offset_marker);
 								skip = false;
 							}
-							Err(OccupiedError { entry: e, .. }) => {
+							Entry::Occupied(e) => {
 								let v = e.get();
 								if *v < 0 {
 									// duplicate processing from livescan side, probably a bug somewhere we need to fix!
@@ -365,7 +606,8 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 
 					// if we're not currently skipping, then we also need to forward the item to the pipeline
 					if !skip {
-						if livescan_items_tx.send((tx, item)).await.is_err() {
+						let send = livescan_items_tx.send((tx, item));
+						if Backpressure::timed(&backpressure.extract_blocked_us, send).await.is_err() {
 							break
 						}
 					}
@@ -390,14 +632,17 @@ pub async fn run(cfg: &AppConfig) -> Result<()> {
 		}
 	});
 
-	// keep running for as long as our low-latency pipeline is running
-	livescan_handle.await?;
+	// keep running for as long as our low-latency pipeline is running, or until we've reached the tip
+	tokio::select! {
+		res = livescan_handle => { res?; }
+		Ok(()) = tip_reached_rx => {}
+	}
 
 	Ok(())
 }
 
 // Crawl the entire history of checkpoints on the Sui blockchain.
-async fn spawn_checkpoint_poll(
+pub(crate) async fn spawn_checkpoint_poll(
 	cfg: &AppConfig,
 	sui: ClientPool,
 	pause: Arc<AtomicU16>,
@@ -438,6 +683,8 @@ async fn spawn_livescan(
 			None,
 			items_tx.clone(),
 			cp_control_tx.clone(),
+			// items are forwarded to the livescan pipeline by run(), which keeps track of backpressure there
+			Backpressure::default(),
 		));
 	}
 
@@ -449,6 +696,7 @@ async fn spawn_pipeline_tail(
 	pc: PipelineConfig,
 	sui: ClientPool,
 	object_ids_rx: ACReceiver<(Option<TransactionDigest>, ObjectItem)>,
+	backpressure: Backpressure,
 ) -> Result<(TSender<(CheckpointSequenceNumber, u32)>, JoinHandle<u64>)> {
 	info!("ExtractionInfo: Spawning pipeline tail.");
 	let mongo = cfg.mongo.client(&pc.mongo).await?;
@@ -457,59 +705,110 @@ async fn spawn_pipeline_tail(
 	let num_mongo_workers = pc.workers.mongo.unwrap_or(default_num_workers);
 	info!("ExtractionInfo: workers: object: {}; mongo: {}", num_object_workers, num_mongo_workers);
 
-	// mostly we want to buffer up to mongo batch size items smoothly, assuming writes to mongo from a single writer will be fast enough
-	let (mongo_tx, mongo_rx) =
-		async_channel::bounded(pc.mongo.batchsize * pc.queuebuffers.mongoinfactor * num_mongo_workers);
+	let (last_tx, mut last_rx) = tokio::sync::mpsc::channel(pc.queuebuffers.last);
 
-	// Initialize object workers which read object changes from the checkpoint step, and fetch full object data via RPC.
-	{
-		for _ in 0..num_object_workers {
-			tokio::spawn({
-				let sui = sui.clone();
-				let mut retries = crate::pulsar::make_producer("retries").await?;
-				let batch_size = pc.objectqueries.batchsize;
-				let batch_wait_timeout = pc.objectqueries.batchwaittimeoutms;
-				let object_ids_rx = object_ids_rx.clone();
-				let mongo_tx = mongo_tx.clone();
-
-				async move {
-					let object_ids_rx = object_ids_rx
-						.map(|(_, item)| item)
-						.chunks_timeout(batch_size, Duration::from_millis(batch_wait_timeout));
-					let stream = transform_batched(object_ids_rx, sui).await;
-					let stream = stream! {
-						for await (status, item) in stream {
-							if let StepStatus::Err = status {
-								retries.send(item).await.expect("ExtractionError: failed to send retry message to pulsar!");
-							} else {
-								yield item;
+	// in decoupled mode, items are reported to us once extracted instead of once loaded, see decoupled.rs
+	let mut downstream = None;
+	if cfg.decoupled {
+		downstream = Some(decoupled::spawn_stages(&cfg, &pc, sui, object_ids_rx, mongo.clone(), last_tx).await?);
+	} else {
+		// With `workers.ordered`, every object is always handled by the same object worker and the same mongo worker,
+		// so its changes can't overtake each other on their way to the load step. Otherwise, all workers of a step
+		// share a single channel, and whichever worker is free takes the next items.
+		let (object_rxs, (mongo_txs, mongo_rxs)) = if pc.workers.ordered {
+			let capacity = pc.mongo.batchsize * pc.queuebuffers.mongoinfactor;
+			(
+				keyed_receivers(object_ids_rx.clone(), num_object_workers, pc.objectqueries.batchsize),
+				(0..num_mongo_workers).map(|_| async_channel::bounded(capacity)).unzip::<_, _, Vec<_>, Vec<_>>(),
+			)
+		} else {
+			// mostly we want to buffer up to mongo batch size items smoothly, assuming writes to mongo from a single writer will be fast enough
+			let (mongo_tx, mongo_rx) =
+				async_channel::bounded(pc.mongo.batchsize * pc.queuebuffers.mongoinfactor * num_mongo_workers);
+			(vec![object_ids_rx.clone()], (vec![mongo_tx], vec![mongo_rx]))
+		};
+
+		spawn_backpressure_report(pc.name.clone(), object_ids_rx.clone(), mongo_rxs.clone(), backpressure.clone());
+
+		// Initialize object workers which read object changes from the checkpoint step, and fetch full object data via RPC.
+		{
+			// shared by all object workers, as they share the same RPC providers
+			let sizing = pc.objectqueries.adaptive.then(|| Arc::new(ChunkSizing::new(&pc)));
+			for i in 0..num_object_workers {
+				tokio::spawn({
+					let sui = sui.clone();
+					let mut retries = Retries::new().await?;
+					let batch_size = pc.objectqueries.batchsize;
+					let batch_wait_timeout = pc.objectqueries.batchwaittimeoutms;
+					let object_ids_rx = object_rxs[i % object_rxs.len()].clone();
+					let mongo_txs = mongo_txs.clone();
+					let backpressure = backpressure.clone();
+					let raw_changes = cfg.rawchanges.enabled.then(|| Arc::new((cfg.clone(), pc.clone(), mongo.clone())));
+					let sizing = sizing.clone();
+					let inflight_cfg = cfg.inflight.clone();
+
+					async move {
+						let chunks = match &sizing {
+							Some(sizing) => adaptive_chunks(object_ids_rx, sizing.clone()).boxed(),
+							None => object_ids_rx.chunks_timeout(batch_size, Duration::from_millis(batch_wait_timeout)).boxed(),
+						};
+						let object_ids_rx = chunks.then(move |chunk| {
+							let raw_changes = raw_changes.clone();
+							async move {
+								// stored before fetching any object data, so the changes can be replayed later
+								if let Some((cfg, pc, mongo)) = raw_changes.as_deref() {
+									rawchanges::store_raw_changes(cfg, pc, mongo, &chunk).await;
+								}
+								chunk.into_iter().map(|(_, item)| item).collect::<Vec<_>>()
+							}
+						});
+						let stream = transform_batched(object_ids_rx, sui, sizing).await;
+						let stream = stream! {
+							for await (status, item) in stream {
+								match status {
+									StepStatus::Ok => yield pipeline::enrich(item).await,
+									// these never reach the pipeline tail, so their checkpoint doesn't complete either way
+									StepStatus::Err(err) if err.is_retryable() => {
+										retries.send(item, &err).await;
+									}
+									StepStatus::Err(err) => {
+										warn!(object_id = ?item.id, error = %err, "ExtractionError: Unrecoverable error fetching object data, skipping.");
+										write_metric_ingest_error(item.id.to_string(), err.class().to_string()).await;
+									}
+								}
 							}
+						};
+						// convert stream to channel
+						pin!(stream);
+						while let Some(it) = stream.next().await {
+							let mongo_tx = &mongo_txs[worker_for(&it.id, mongo_txs.len())];
+							let send = async {
+								inflight::acquire(&inflight_cfg, it.bytes.len()).await;
+								mongo_tx.send(it).await
+							};
+							Backpressure::timed(&backpressure.transform_blocked_us, send)
+								.await
+								.expect("ExtractionInfo: passing items from object data stream to mongo tokio channel");
 						}
-					};
-					// convert stream to channel
-					pin!(stream);
-					while let Some(it) = stream.next().await {
-						mongo_tx.send(it).await.expect("ExtractionInfo: passing items from object data stream to mongo tokio channel");
 					}
-				}
-			});
+				});
+			}
+			drop(object_ids_rx);
+			drop(object_rxs);
+			drop(mongo_txs);
 		}
-		drop(object_ids_rx);
-		drop(mongo_tx);
-	}
 
-	let (last_tx, mut last_rx) = tokio::sync::mpsc::channel(pc.queuebuffers.last);
-
-	// step 3: mongo workers
-	{
-		for _ in 0..num_mongo_workers {
-			let mongo_rx = mongo_rx.clone();
-			let mongo_rx =
-				mongo_rx.chunks_timeout(pc.mongo.batchsize, Duration::from_millis(pc.mongo.batchwaittimeoutms));
-			tokio::spawn(load_batched(cfg.clone(), pc.clone(), mongo_rx, mongo.clone(), last_tx.clone()));
+		// step 3: mongo workers
+		{
+			for i in 0..num_mongo_workers {
+				let mongo_rx = mongo_rxs[i % mongo_rxs.len()].clone();
+				let mongo_rx =
+					mongo_rx.chunks_timeout(pc.mongo.batchsize, Duration::from_millis(pc.mongo.batchwaittimeoutms));
+				tokio::spawn(load_batched(cfg.clone(), pc.clone(), mongo_rx, mongo.clone(), last_tx.clone()));
+			}
+			drop(mongo_rxs);
+			drop(last_tx);
 		}
-		drop(mongo_rx);
-		drop(last_tx);
 	}
 
 	// for the control channel, we want to add some blocking behavior in case the task acting
@@ -522,13 +821,18 @@ async fn spawn_pipeline_tail(
 		let pc = pc.clone();
 		async move {
 			// finally: check completions, issue retries
-			let mut retries = crate::pulsar::make_producer("retries").await.unwrap();
+			let mut retries = Retries::new().await.unwrap();
 			let mut completions_left = HashMap::new();
 			let mut max_cp_completed = 0u64;
 			let mut last_latency = 0;
+			// in decoupled mode, items come from the bus instead of our transform workers, see inflight.rs
+			let in_flight_budget = downstream.is_none();
 			loop {
 				let (cp, v) = tokio::select! {
 					Some((status, item, completed_at)) = last_rx.recv() => {
+						if in_flight_budget {
+							inflight::release(item.bytes.len());
+						}
 						if let (Some(ts_sui), Some(completed)) = (item.ts_sui, completed_at) {
 							let latency = completed.checked_sub(item.ts_first_seen).unwrap_or(0);
 							// we don't want to log the same value more than once consecutively
@@ -548,8 +852,12 @@ async fn spawn_pipeline_tail(
 							// ignore, not really a checkpoint
 							continue;
 						}
-						if let StepStatus::Err = status {
-							retries.send(item).await.expect("ExtractionError: failed to send retry message to pulsar!");
+						if let StepStatus::Err(err) = &status {
+							// without a dead-letter store, the checkpoint mustn't complete, so it's processed again
+							// after a restart
+							if err.is_retryable() && !retries.send(item, err).await {
+								continue
+							}
 						}
 						(cp, completions_left.entry(cp).and_modify(|n| *n -= 1).or_insert(-1i64))
 					},
@@ -565,20 +873,90 @@ async fn spawn_pipeline_tail(
 					max_cp_completed = max_cp_completed.max(cp);
 				}
 			}
+			// not needed for our progress, but we don't want to exit with items still in flight downstream
+			if let Some(downstream) = downstream {
+				downstream.await.expect("ExtractionError: decoupled transform / load workers failed!");
+			}
 			max_cp_completed
 		}
 	});
 
 	Ok((cp_control_tx, handle))
 }
+// The worker out of `n` that handles all changes of object `id`, see `workers.ordered`.
+fn worker_for(id: &ObjectID, n: usize) -> usize {
+	if n <= 1 {
+		return 0
+	}
+	let mut hasher = std::collections::hash_map::DefaultHasher::new();
+	id.hash(&mut hasher);
+	(hasher.finish() % n as u64) as usize
+}
+
+// Splits `rx` into `n` receivers, routing every item to the one of its object, see `worker_for()`.
+fn keyed_receivers<T: Send + 'static>(
+	rx: ACReceiver<(T, ObjectItem)>,
+	n: usize,
+	capacity: usize,
+) -> Vec<ACReceiver<(T, ObjectItem)>> {
+	let (txs, rxs): (Vec<_>, Vec<_>) = (0..n).map(|_| async_channel::bounded(capacity)).unzip();
+	tokio::spawn(async move {
+		while let Ok(msg) = rx.recv().await {
+			if txs[worker_for(&msg.1.id, n)].send(msg).await.is_err() {
+				break
+			}
+		}
+	});
+	rxs
+}
+
+// Regularly logs + reports how many items are buffered between the pipeline steps, and how long each step was blocked
+// on sending to the next one since the last report. Stops once the pipeline has been fully drained.
+fn spawn_backpressure_report(
+	pipeline: String,
+	object_ids_rx: ACReceiver<(Option<TransactionDigest>, ObjectItem)>,
+	mongo_rxs: Vec<ACReceiver<ObjectItem>>,
+	backpressure: Backpressure,
+) {
+	tokio::spawn(async move {
+		let fmt_capacity = |c: Option<usize>| c.map_or("unbounded".to_string(), |c| c.to_string());
+		loop {
+			tokio::time::sleep(Duration::from_millis(BACKPRESSURE_REPORT_INTERVAL_MS)).await;
+			let extract_blocked_ms = backpressure.extract_blocked_us.swap(0, Relaxed) / 1000;
+			let transform_blocked_ms = backpressure.transform_blocked_us.swap(0, Relaxed) / 1000;
+			let mongo_len = mongo_rxs.iter().map(|rx| rx.len()).sum::<usize>();
+			let mongo_capacity = mongo_rxs.iter().map(|rx| rx.capacity()).sum::<Option<usize>>();
+			let (inflight_items, inflight_bytes) = inflight::current();
+			info!(
+				"[{}] backpressure: extract -> transform: {}/{} buffered, blocked {}ms // transform -> load: {}/{} buffered, blocked {}ms // in flight: {} objects, {} KB",
+				pipeline,
+				object_ids_rx.len(),
+				fmt_capacity(object_ids_rx.capacity()),
+				extract_blocked_ms,
+				mongo_len,
+				fmt_capacity(mongo_capacity),
+				transform_blocked_ms,
+				inflight_items,
+				inflight_bytes / 1024,
+			);
+			write_metric_backpressure(pipeline.clone(), "extract".into(), object_ids_rx.len() as u64, extract_blocked_ms).await;
+			write_metric_backpressure(pipeline.clone(), "transform".into(), mongo_len as u64, transform_blocked_ms).await;
+			if object_ids_rx.is_closed() && mongo_rxs.iter().all(|rx| rx.is_closed()) && mongo_len == 0 {
+				break
+			}
+		}
+	});
+}
+
 // The backfill pipeline crawls several checkpoints concurrently. Although this is faster for backfilling, it can overwhelm downstream systems with too many CRUD operations. It can also cause some delay for ingesting the latest checkpoint data.
 // Each backfill pipeline creates its own RocksDB instance, which is used to prevent ingesting the same data points repeatedly across multiple threads.
-#[allow(unused)]
-async fn spawn_backfill_pipeline(
+// If `lowest_checkpoint` is set, we don't go further back than that.
+pub(crate) async fn spawn_backfill_pipeline(
 	cfg: &AppConfig,
 	pc: &PipelineConfig,
 	mut sui: ClientPool,
 	start_checkpoint: Option<u64>,
+	lowest_checkpoint: Option<u64>,
 ) -> Result<(tokio::sync::oneshot::Receiver<()>, JoinHandle<u64>)> {
 	info!("ExtractionInfo: Spawning backfill pipeline.");
 	write_metric_backfill_init(start_checkpoint.unwrap_or(0)).await;
@@ -624,7 +1002,7 @@ async fn spawn_backfill_pipeline(
 		if stop_at > 0 {
 			cpids.sort_unstable();
 			// chop off all checkpoints lower than stop_at
-			cpids.drain_filter(|cp| *cp < stop_at);
+			cpids.retain(|cp| *cp >= stop_at);
 			let mut ranges = make_descending_ranges(cpids);
 			// add where to stop as the last range item
 			ranges.push((stop_at, 0));
@@ -633,13 +1011,18 @@ async fn spawn_backfill_pipeline(
 			make_descending_ranges(cpids)
 		}
 	};
+	let completed_checkpoint_ranges = match lowest_checkpoint {
+		Some(lowest) => bound_descending_ranges(completed_checkpoint_ranges, lowest),
+		None => completed_checkpoint_ranges,
+	};
 
 	// MPMC channel, as an easy way to balance incoming work from checkpoint workers into multiple object workers.
 	info!("ExtractionInfo: Initializing Tokio channel for object workers with bound limit {}", pc.queuebuffers.checkpointout);
 	let (object_ids_tx, object_ids_rx) = async_channel::bounded(pc.queuebuffers.checkpointout);
 
+	let backpressure = Backpressure::default();
 	let (cp_control_tx, handle) =
-		spawn_pipeline_tail(cfg.clone(), pc.clone(), sui.clone(), object_ids_rx).await?;
+		spawn_pipeline_tail(cfg.clone(), pc.clone(), sui.clone(), object_ids_rx, backpressure.clone()).await?;
 
 	info!("Initializing {} number of backfill workers.", num_checkpoint_workers);
 	let (checkpointfinished_tx, checkpointfinished_rx) = tokio::sync::oneshot::channel();
@@ -658,6 +1041,7 @@ async fn spawn_backfill_pipeline(
 				Some(db.clone()),
 				object_ids_tx.clone(),
 				cp_control_tx.clone(),
+				backpressure.clone(),
 			)));
 		}
 		drop(object_ids_tx);
@@ -751,7 +1135,7 @@ async fn do_walk(
 						// might not actually have any changes in this block or will be skipping it
 						cursor = Some(block.digest);
 						// skip this block?
-						if block_cp > cp {
+						if block_cp > cp || !sampled(get_config_singleton(), &block) {
 							continue
 						}
 						if let Some(changes) = block.object_changes {
@@ -894,9 +1278,23 @@ async fn do_scan(
 	db: Option<Arc<DBWithThreadMode<SingleThreaded>>>,
 	object_ids_tx: ACSender<(Option<TransactionDigest>, ObjectItem)>,
 	cp_control_tx: TSender<(CheckpointSequenceNumber, u32)>,
+	backpressure: Backpressure,
 ) {
 	info!("ExtractionInfo: Initializing do_scan()");
 	let stop = ctrl_c_bool();
+	let cfg = get_config_singleton();
+	// for wrapped objects, and full transaction blocks, checkpoint summaries and published packages, if enabled
+	let mongo = cfg.mongo.client(&pc.mongo).await.unwrap();
+	let mut tx_options = SuiTransactionBlockResponseOptions::new().with_object_changes();
+	if cfg.transactions.enabled || cfg.movecalls.enabled {
+		tx_options = tx_options.with_input().with_effects();
+	}
+	if cfg.transfers.enabled {
+		tx_options = tx_options.with_input().with_balance_changes();
+	}
+	if cfg.activity.enabled {
+		tx_options = tx_options.with_input();
+	}
 	let mut completed_iter = completed_checkpoint_ranges.iter();
 	let mut completed_range = completed_iter.next();
 	let mut iter = (1..=checkpoint_max as usize - partition).rev().step_by(step_size).into_iter();
@@ -907,6 +1305,7 @@ async fn do_scan(
 		if stop.load(Relaxed) {
 			break
 		}
+		control::wait_while_paused().await;
 		// check if we've already completed this checkpoint:
 		// ranges are sorted from highest to lowest, so we can iterate them in tandem with the
 		// checkpoint sequence itself
@@ -924,7 +1323,10 @@ async fn do_scan(
 					}
 					// match! advance by whatever number of steps we need to end this iteration
 					// at checkpoint `start`, or right before, considering our step size
-					iter.advance_by(((cp - *start) as usize).div_floor(step_size)).ok();
+					let steps = (cp - *start) as usize / step_size;
+					if steps > 0 {
+						iter.nth(steps - 1);
+					}
 					// we also already know that we need to compare with the next range item
 					completed_range = completed_iter.next();
 					continue 'cp
@@ -941,7 +1343,7 @@ async fn do_scan(
 		// start fetching all tx blocks for this checkpoint
 		let q = SuiTransactionBlockResponseQuery::new(
 			Some(TransactionFilter::Checkpoint(cp as CheckpointSequenceNumber)),
-			Some(SuiTransactionBlockResponseOptions::new().with_object_changes()),
+			Some(tx_options.clone()),
 		);
 		let mut cursor = None;
 		let mut retries_left = pc.checkpointretries;
@@ -951,15 +1353,51 @@ async fn do_scan(
 			let call_start_ts = Utc::now().timestamp_millis() as u64;
 			let page = sui.query_transaction_blocks(q.clone(), cursor, Some(SUI_QUERY_MAX_RESULT_LIMIT), true).await;
 			match page {
-				Ok(page) => {
+				Ok(mut page) => {
 					retries_left = pc.checkpointretries;
+					page.data.retain(|block| sampled(cfg, block));
+					// stored before sending the checkpoint's control message, so a completed checkpoint always has
+					// all of its transactions
+					if cfg.transactions.enabled {
+						let stored = transactions::store_transactions(cfg, &pc, &mongo, &page.data).await;
+						if cfg.gas.enabled {
+							gas::update_gas_rollups(cfg, &mongo, &stored).await;
+						}
+					}
+					if cfg.movecalls.enabled {
+						movecalls::store_move_calls(cfg, &pc, &mongo, &page.data).await;
+					}
+					if cfg.transfers.enabled {
+						transfers::store_transfers(cfg, &pc, &mongo, &page.data).await;
+					}
+					if cfg.activity.enabled {
+						activity::store_activity(cfg, &pc, &mongo, &page.data).await;
+					}
+					// wrapped objects don't go through the rest of the pipeline, as there's nothing to fetch for them
+					wraps::mark_wrapped(cfg, &pc, &mongo, &page.data).await;
 					for block in page.data {
+						if cfg.packages.enabled {
+							packages::store_published(cfg, &pc, &mut sui, &mongo, cp, &block).await;
+						}
 						if let Some(changes) = block.object_changes {
 							let mut tx_digest_once = Some(block.digest);
 							for change in changes {
+								if !filters::keep(&change) {
+									continue
+								}
 								let Some((object_id, version, deleted)) = client::parse_change(change) else {
                                     continue;
                                 };
+								let item = ObjectItem {
+									cp: cp as CheckpointSequenceNumber,
+									deletion: deleted,
+									id: object_id,
+									version,
+									ts_sui: block.timestamp_ms,
+									ts_first_seen: call_start_ts,
+									ingested_via: ingest_route,
+									bytes: Default::default(),
+								};
 								if let Some(db) = &db {
 									let k = object_id.as_slice();
 									// known?
@@ -975,21 +1413,8 @@ async fn do_scan(
 								}
 								num_objects += 1;
 								// send to step 2
-								let send_res = object_ids_tx
-									.send((
-										tx_digest_once.take(),
-										ObjectItem {
-											cp: cp as CheckpointSequenceNumber,
-											deletion: deleted,
-											id: object_id,
-											version,
-											ts_sui: block.timestamp_ms,
-											ts_first_seen: call_start_ts,
-											ingested_via: ingest_route,
-											bytes: Default::default(),
-										},
-									))
-									.await;
+								let send = object_ids_tx.send((tx_digest_once.take(), item));
+								let send_res = Backpressure::timed(&backpressure.extract_blocked_us, send).await;
 								if send_res.is_err() {
 									// channel closed, consumers stopped
 									break 'cp
@@ -999,6 +1424,9 @@ async fn do_scan(
 					}
 					if !page.has_next_page {
 						// we're done with this cp
+						if cfg.checkpoints.enabled {
+							checkpoints::store_checkpoint_summary(cfg, &pc, &mut sui, &mongo, cp).await;
+						}
 						// send control message about number of expected object tasks from this cp
 						cp_control_tx.send((cp as CheckpointSequenceNumber, num_objects)).await.unwrap();
 						break
@@ -1016,6 +1444,11 @@ async fn do_scan(
 						break
 					}
 					error!(error = ?err, "ExtractionError: There was an error reading object changes... retrying (retry #{}) after short timeout", retries_left);
+					report_stage_error(Stage::Extract, &EtlError::from_rpc(&err), ErrorContext {
+						checkpoint: Some(cp),
+						endpoint: Some(sui.current_provider()),
+						..Default::default()
+					});
 					retries_left -= 1;
 					tokio::time::sleep(Duration::from_millis(pc.checkpointretrytimeoutms)).await;
 				}
@@ -1044,11 +1477,14 @@ async fn do_poll(
 	let mut desc = true;
 	let mut checkpoints = HashSet::with_capacity(64);
 	let mut last_poll = Instant::now().checked_sub(Duration::from_millis(cfg.pollintervalms)).unwrap();
+	// with `finalizedonly`: blocks we've seen, but which weren't part of a checkpoint yet
+	let mut pending = Vec::new();
 
 	loop {
 		if stop.load(Relaxed) {
 			break
 		}
+		control::wait_while_paused().await;
 		// XXX not sure yet if pausing this way is silly or smart, but it should work at least
 		loop {
 			let pause = pause.load(Relaxed);
@@ -1067,7 +1503,7 @@ async fn do_poll(
 		// for latency tracking, to make it a little easier, we're adding half of the time spent waiting since the last poll
 		// since that's the average of when a tx will have been added to the chain in the meantime
 		let latency_first_seen_ms =
-			Utc::now().timestamp_millis() as u64 + last_poll.elapsed().as_millis().div_ceil(2) as u64;
+			Utc::now().timestamp_millis() as u64 + (last_poll.elapsed().as_millis() + 1) as u64 / 2;
 		match sui.query_transaction_blocks(q.clone(), cursor, Some(SUI_QUERY_MAX_RESULT_LIMIT), desc).await {
 			Ok(mut page) => {
 				write_metric_rpc_request("query_transaction_blocks".to_string()).await;
@@ -1086,36 +1522,52 @@ async fn do_poll(
 					desc = false;
 				}
 				cursor = Some(page.data.last().unwrap().digest);
+				page.data.retain(|block| sampled(&cfg, block));
+
+				let blocks = if cfg.finalizedonly {
+					// blocks held back earlier go first, as they're older
+					let mut blocks = release_finalized(&mut sui, &mut pending).await;
+					for block in page.data {
+						if block.checkpoint.is_some() {
+							blocks.push(block);
+						} else {
+							pending.push(block.digest);
+						}
+					}
+					if pending.len() > MAX_PENDING_FINALITY {
+						let dropped = pending.drain(..pending.len() - MAX_PENDING_FINALITY).count();
+						warn!("ExtractionWarning: Too many blocks waiting for their checkpoint, leaving {} of them to the checkpoint scan.", dropped);
+					}
+					blocks
+				} else {
+					page.data
+				};
 
 				checkpoints.clear();
-				for block in page.data {
+				for block in blocks {
 					// if we found a new (to this iteration) checkpoint, we want to let the checkpoints-based
 					// processor know immediately
 					// we also skip those items here, so we don't need to coordinate with it
-					if let Some(cp) = block.checkpoint && checkpoints.insert(cp) {
+					if let Some(cp) = block.checkpoint.filter(|cp| checkpoints.insert(*cp)) {
                         observed_checkpoints_tx.send(cp).ok();
                         continue;
                     }
 					let mut tx_digest_once = Some(block.digest);
 					let Some(changes) = block.object_changes else { continue; };
-					for (id, version, deletion) in changes.into_iter().filter_map(client::parse_change) {
-						if items
-							.send((
-								tx_digest_once.take(),
-								ObjectItem {
-									cp: 0,
-									deletion,
-									id,
-									version,
-									ts_sui: block.timestamp_ms,
-									ts_first_seen: latency_first_seen_ms,
-									ingested_via: IngestRoute::Poll,
-									bytes: Default::default(),
-								},
-							))
-							.await
-							.is_err()
-						{
+					let changes =
+						changes.into_iter().filter(|change| filters::keep(change)).filter_map(client::parse_change);
+					for (id, version, deletion) in changes {
+						let item = ObjectItem {
+							cp: 0,
+							deletion,
+							id,
+							version,
+							ts_sui: block.timestamp_ms,
+							ts_first_seen: latency_first_seen_ms,
+							ingested_via: IngestRoute::Poll,
+							bytes: Default::default(),
+						};
+						if items.send((tx_digest_once.take(), item)).await.is_err() {
 							// channel closed, stop processing
 							return
 						}
@@ -1125,6 +1577,10 @@ async fn do_poll(
 			Err(err) => {
 				let timeout_ms = 100;
 				warn!(error = ?err, "ExtractionError: Error polling tx blocks; retry #{} after {}ms timeout", retry_count, timeout_ms);
+				report_stage_error(Stage::Extract, &EtlError::from_rpc(&err), ErrorContext {
+					endpoint: Some(sui.current_provider()),
+					..Default::default()
+				});
 				retry_count += 1;
 				tokio::time::sleep(Duration::from_millis(timeout_ms)).await;
 			}
@@ -1132,61 +1588,144 @@ async fn do_poll(
 	}
 }
 
-async fn transform_batched<'a, S: Stream<Item = Vec<ObjectItem>> + 'a>(
-	stream: S,
-	mut sui: ClientPool,
-) -> impl Stream<Item = (StepStatus, ObjectItem)> + 'a {
-	let query_opts = SuiObjectDataOptions {
+// Returns those of the `pending` blocks that have been included in a checkpoint by now, which makes them final, and
+// keeps the rest pending. Blocks we fail to look up stay pending, and are tried again with the next poll.
+async fn release_finalized(
+	sui: &mut ClientPool,
+	pending: &mut Vec<TransactionDigest>,
+) -> Vec<SuiTransactionBlockResponse> {
+	let mut released = Vec::new();
+	let mut still_pending = Vec::new();
+	for digests in pending.chunks(SUI_MULTI_GET_LIMIT) {
+		let opts = SuiTransactionBlockResponseOptions::new().with_object_changes();
+		match sui.multi_get_transactions_with_options(digests.to_vec(), opts).await {
+			Ok(blocks) => {
+				for block in blocks {
+					if block.checkpoint.is_some() {
+						released.push(block);
+					} else {
+						still_pending.push(block.digest);
+					}
+				}
+			}
+			Err(err) => {
+				warn!(error = ?err, "ExtractionError: Cannot check whether {} polled blocks are final yet", digests.len());
+				write_metric_rpc_error("multi_get_transactions_with_options".to_string()).await;
+				still_pending.extend_from_slice(digests);
+			}
+		}
+	}
+	*pending = still_pending;
+	released
+}
+
+// Everything we store about an object.
+// What the transform step asks for, see decode.rs for `bcsonly`.
+pub fn object_data_options() -> SuiObjectDataOptions {
+	if get_config_singleton().bcsonly.enabled {
+		return SuiObjectDataOptions {
+			show_type:                 true,
+			show_owner:                true,
+			show_previous_transaction: true,
+			show_display:              false,
+			show_content:              false,
+			show_bcs:                  true,
+			show_storage_rebate:       true,
+		}
+	}
+	full_object_data_options()
+}
+
+pub(crate) fn full_object_data_options() -> SuiObjectDataOptions {
+	SuiObjectDataOptions {
 		show_type:                 true,
 		show_owner:                true,
 		show_previous_transaction: true,
-		show_display:              false,
+		// only needed to recognize NFTs
+		show_display:              get_config_singleton().nft.enabled,
 		show_content:              true,
 		show_bcs:                  true,
 		show_storage_rebate:       true,
-	};
+	}
+}
+
+pub(crate) async fn transform_batched<'a, S: Stream<Item = Vec<ObjectItem>> + 'a>(
+	stream: S,
+	mut sui: ClientPool,
+	sizing: Option<Arc<ChunkSizing>>,
+) -> impl Stream<Item = (StepStatus, ObjectItem)> + 'a {
+	let cfg = get_config_singleton();
+	let query_opts = object_data_options();
 
 	stream! {
 		for await mut chunk in stream {
 			// skip loading objects for 'delete' type changes, as we're just going to delete them from our working set anyway
-			for item in chunk.drain_filter(|o| o.deletion) {
+			let (deletions, rest): (Vec<_>, Vec<_>) = chunk.into_iter().partition(|o| o.deletion);
+			chunk = rest;
+			for item in deletions {
 				yield (StepStatus::Ok, item);
 			}
 			let obj_ids = chunk.iter().map(|item| item.id).collect::<Vec<_>>();
-			match sui.multi_get_object_with_options(obj_ids, query_opts.clone()).await {
+			let call_start = Instant::now();
+			let res = sui.multi_get_object_with_options(obj_ids, query_opts.clone()).await;
+			let elapsed_ms = call_start.elapsed().as_millis() as u64;
+			warn_if_slow("rpc_multi_get", cfg.log.slowrpcms, elapsed_ms, &chunk);
+			if let Some(sizing) = &sizing {
+				sizing.record(elapsed_ms, res.is_ok());
+			}
+			// objects we didn't get a usable response for in the multi-get are fetched one by one
+			let individually = match res {
 				Err(err) => {
 					warn!(error = format!("{err:?}"), "cannot fetch object data for one or more objects, retrying them individually");
 					write_metric_rpc_error("multi_get_object_with_options".to_string()).await;
-					// try one by one
-					// TODO this should be super easy to do in parallel, firing off the reqs on some tokio thread pool executor
+					chunk
+				},
+				Ok(objs) => {
+					// match responses to requests by object id instead of relying on them being in the same order,
+					// the same object may also be requested more than once per chunk
+					let mut responses = HashMap::with_capacity(objs.len());
+					for res in objs {
+						if let Ok(object_id) = res.object_id() {
+							responses.insert(object_id, res);
+						}
+					}
+					let mut unmatched = Vec::new();
 					for mut item in chunk {
-						match sui.get_object_with_options(item.id, query_opts.clone()).await {
-							Err(err) => {
-								error!(object_id = ?item.id, error = format!("{err:?}"), "individual fetch also failed");
-								write_metric_rpc_error("get_object_with_options".to_string()).await;
-								yield (StepStatus::Err, item);
-							},
-							Ok(res) => {
-								// TODO send them off in batches
-								if let Some((version, bytes)) = parse_get_object_response(&item.id, res).await {
-									item.version = version;
-									item.bytes = bytes;
-									yield (StepStatus::Ok, item);
-								}
-							}
+						let Some(res) = responses.get(&item.id) else {
+							unmatched.push(item);
+							continue
+						};
+						// TODO if we can't get object info, do we really want to skip indexing this change? or is there something more productive we can do?
+						// TODO send them off in batches
+						if let Some((version, bytes)) = parse_get_object_response(&item.id, res.clone()).await {
+							item.version = version;
+							item.bytes = bytes;
+							yield (StepStatus::Ok, item);
 						}
 					}
-				},
-				Ok(objs) => {
-					// XXX: relying on a possible Sui API implementation detail
-					// the sui endpoint is implemented such that the response items are in the same
-					// order as the input items, so we don't have to search or otherwise match them
-					if objs.len() != chunk.len() {
+					if !unmatched.is_empty() {
+						warn!(num_objects = unmatched.len(), "multi-get response is missing some of the requested objects, retrying them individually");
 						write_metric_rpc_error("unexpected_payload".to_string()).await;
-						panic!("sui.multi_get_object_with_options() mismatch between input and result len!");
 					}
-					for (mut item, res) in zip(chunk, objs) {
-						// TODO if we can't get object info, do we really want to skip indexing this change? or is there something more productive we can do?
+					unmatched
+				}
+			};
+			// TODO this should be super easy to do in parallel, firing off the reqs on some tokio thread pool executor
+			for mut item in individually {
+				match sui.get_object_with_options(item.id, query_opts.clone()).await {
+					Err(err) => {
+						error!(object_id = ?item.id, error = format!("{err:?}"), "individual fetch also failed");
+						write_metric_rpc_error("get_object_with_options".to_string()).await;
+						let err = EtlError::from_rpc(&err);
+						report_stage_error(Stage::Transform, &err, ErrorContext {
+							object_id: Some(item.id),
+							checkpoint: (item.cp > 0).then_some(item.cp),
+							endpoint: Some(sui.current_provider()),
+							..Default::default()
+						});
+						yield (StepStatus::Err(err), item);
+					},
+					Ok(res) => {
 						// TODO send them off in batches
 						if let Some((version, bytes)) = parse_get_object_response(&item.id, res).await {
 							item.version = version;
@@ -1200,7 +1739,21 @@ async fn transform_batched<'a, S: Stream<Item = Vec<ObjectItem>> + 'a>(
 	}
 }
 
-async fn load_batched<'a, S: Stream<Item = Vec<ObjectItem>> + 'a>(
+// With `samplerate`, only a fixed fraction of transaction blocks is indexed, always the same ones for the same rate.
+fn sampled(cfg: &AppConfig, block: &SuiTransactionBlockResponse) -> bool {
+	cfg.samplerate.map_or(true, |rate| utils::sampled(block.digest.inner(), rate))
+}
+
+// Logs a structured warning listing all involved objects if an operation took longer than its configured threshold.
+fn warn_if_slow(op: &str, threshold_ms: Option<u64>, elapsed_ms: u64, items: &[ObjectItem]) {
+	let Some(threshold_ms) = threshold_ms else { return };
+	if elapsed_ms > threshold_ms {
+		let object_ids = items.iter().map(|item| item.id.to_string()).collect::<Vec<_>>();
+		warn!(op, elapsed_ms, threshold_ms, object_ids = ?object_ids, "SlowOpWarning: {} took {}ms for {} objects", op, elapsed_ms, items.len());
+	}
+}
+
+pub(crate) async fn load_batched<'a, S: Stream<Item = Vec<ObjectItem>> + 'a>(
 	cfg: AppConfig,
 	pc: PipelineConfig,
 	stream: S,
@@ -1209,73 +1762,130 @@ async fn load_batched<'a, S: Stream<Item = Vec<ObjectItem>> + 'a>(
 ) {
 	// e.g. prod_testnet_objects
 	let collection = mongo::mongo_collection_name(&cfg, "");
-	let influx_client = get_influx_singleton();
 
 	pin!(stream);
-	while let Some(chunk) = stream.next().await {
+	while let Some(mut chunk) = stream.next().await {
+		// the transform step yields deletions first, and individual retries after the rest of their batch, so we bring
+		// every object's changes back into version order (updates are applied in order), leaving the rest as it is
+		chunk.sort_by_key(|item| (item.id, item.version));
+		// changes we've fully applied before only need to be reported as done, see idempotency.rs
+		if cfg.idempotency.enabled {
+			let (fresh, applied) = idempotency::split_applied(&cfg, &db, chunk).await;
+			if !applied.is_empty() {
+				info!("|> mongo: skipping {} already applied changes", applied.len());
+			}
+			for item in applied {
+				last_tx.send((StepStatus::Ok, item, None)).await.unwrap();
+			}
+			chunk = fresh;
+		}
+		// items without (decodable) object data, e.g. because fetching it was skipped upstream, can't be stored, so
+		// they're reported as failed, which sends them to the dead-letter store to be fetched again
+		let (mut loadable, mut objects) = (Vec::with_capacity(chunk.len()), Vec::with_capacity(chunk.len()));
+		for item in chunk {
+			if item.deletion {
+				loadable.push(item);
+				objects.push(Document::new());
+				continue
+			}
+			match Document::from_reader(&mut Cursor::new(&item.bytes)) {
+				Ok(object) => {
+					loadable.push(item);
+					objects.push(object);
+				}
+				Err(err) => {
+					let err = EtlError::MissingObjectData(format!("{err:?}"));
+					warn!(object_id = ?item.id, error = %err, "LoadWarning: No object data to store, sending item to dead-letter store.");
+					report_stage_error(Stage::Load, &err, ErrorContext {
+						object_id: Some(item.id),
+						checkpoint: (item.cp > 0).then_some(item.cp),
+						..Default::default()
+					});
+					last_tx.send((StepStatus::Err(err), item, None)).await.unwrap();
+				}
+			}
+		}
+		let mut chunk = loadable;
+		// every object is decoded only once, here: `objects` is what we store, and `fetched` (if it differs) is the
+		// object data as we got it, which the derived collections, webhooks, topic routes and plugins read
+		let mut fetched = None;
+		if cfg.slimdocuments || naming::renames() {
+			fetched = Some(objects.clone());
+			for object in &mut objects {
+				if cfg.slimdocuments {
+					slim_object(object);
+				}
+				naming::apply(object);
+			}
+		}
+		let mut hashes = vec![None; chunk.len()];
+		if cfg.contenthash.enabled {
+			hashes = objects.iter().map(content_hash).collect();
+			// mutations that didn't change anything are done as they are, see contenthash.rs
+			let mut unchanged = contenthash::unchanged(&cfg, &db, &chunk, &hashes).await;
+			// they still move the stored version forward, and if we can't do that we store them after all
+			if !unchanged.is_empty() && !contenthash::advance_versions(&pc, &db, &chunk, &unchanged).await {
+				unchanged.clear();
+			}
+			if !unchanged.is_empty() {
+				info!("|> mongo: skipping {} changes without changed content", unchanged.len());
+				statsd::count("unchanged_content", unchanged.len() as i64, &[]);
+				let (mut kept, mut kept_objects, mut kept_hashes) = (Vec::new(), Vec::new(), Vec::new());
+				for (i, ((item, object), hash)) in chunk.into_iter().zip(objects).zip(hashes).enumerate() {
+					if unchanged.contains_key(&i) {
+						last_tx.send((StepStatus::Ok, item, None)).await.unwrap();
+					} else {
+						kept.push(item);
+						kept_objects.push(object);
+						kept_hashes.push(hash);
+					}
+				}
+				(chunk, objects, hashes) = (kept, kept_objects, kept_hashes);
+				if let Some(fetched) = &mut fetched {
+					*fetched = std::mem::take(fetched)
+						.into_iter()
+						.enumerate()
+						.filter_map(|(i, object)| (!unchanged.contains_key(&i)).then_some(object))
+						.collect();
+				}
+			}
+		}
+		if chunk.is_empty() {
+			continue
+		}
+		// the objects collection, or the one a routing rule sends the object to, see routing.rs
+		let mut collections = Vec::with_capacity(chunk.len());
+		for (item, object) in chunk.iter().zip(&objects) {
+			let routed = routing::collection_for(&cfg, (!item.deletion).then_some(object)).await;
+			collections.push(routed.unwrap_or_else(|| collection.clone()));
+		}
 		let mut retries_left = pc.mongo.retries;
 		loop {
 			// for now mongo's rust driver doesn't offer a way to directly do bulk updates / batching
 			// there's a high-level API only for inserting many, but not for updating or deleting many,
 			// and neither for mixing all of those easily
 			// but what it does provide is the generic run_command() method,
-			let updates = chunk.iter().map(|item| {
-                let v = item.version.to_string();
-                let v_ = u64::from_str_radix(&v[2..], 16).unwrap();
-                // FIXME our value range here is u64, but I can't figure out how to get a BSON repr of a u64?!
-                let v_ = v_ as i64;
-                if item.deletion {
-                    // we're assuming each object id will ever exist only once, so when deleting
-                    // we don't check for previous versions
-                    // we execute the delete, whenever it may come in, and it's final
-                    doc! {
-						"q": doc! { "_id": item.id.to_string() },
-						"u": doc! {
-							"$set": {
-								"_id": item.id.to_string(),
-								"version": v,
-								"version_": v_,
-								"deleted": true,
-							},
-						},
-						"upsert": true,
-						"multi": false,
-					}
-                } else {
-                    // we will only upsert and object if this current version is higher than any previously stored one
-                    // (if the object has already been deleted, we still allow setting any other fields, including
-                    // any previously valid full object state... probably not needed, but also not incorrect)
-                    let mut c = Cursor::new(&item.bytes);
-                    doc! {
-						"q": doc! { "_id": item.id.to_string() },
-						// use an aggregation pipeline in our update, so that we can conditionally update
-						// the version and object only if the previous version was lower than our current one
-						"u": vec![doc! {
-							"$set": {
-								"_id": item.id.to_string(),
-								// version_ must be added first, so that it's available in the next items in the pipeline
-								// it has a more complex condition, so it's also added if the field doesn't exist yet
-								// afterwards, the other fields can rely on it being present
-								"version_": {"$cond": { "if": { "$or": [ { "$lt": [ "$version_", v_ ] }, { "$lte": [ "$version", None::<i32> ] } ] }, "then": v_, "else": "$version_" }},
-								"version": {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": v.clone(), "else": "$version" }},
-								"object": {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": Document::from_reader(&mut c).unwrap(), "else": "$object" }},
-							},
-						}],
-						"upsert": true,
-						"multi": false,
-					}
-                }
-            }).collect::<Vec<_>>();
+			let updates = chunk
+				.iter()
+				.zip(&objects)
+				.zip(&hashes)
+				.map(|((item, object), hash)| {
+					object_update(
+						&item.id.to_string(),
+						&item.version.to_string(),
+						item.deletion,
+						object,
+						hash.as_deref(),
+						cfg.network(),
+					)
+				})
+				.collect::<Vec<_>>();
 			let n = updates.len();
-			let res = db
-				.run_command(
-					doc! {
-						"update": &collection,
-						"updates": updates,
-					},
-					None,
-				)
-				.await;
+			let write_start = Instant::now();
+			let res = routing::run_updates(&cfg, &db, &chunk, updates, &collections).await;
+			#[cfg(feature = "chaos")]
+			let res = crate::chaos::maybe_fail_mongo(res);
+			warn_if_slow("mongo_write", cfg.log.slowmongoms, write_start.elapsed().as_millis() as u64, &chunk);
 			match res {
 				Ok(res) => {
 					// res: {n: i32, upserted: [{index: i32, _id: String}, ...], nModified: i32, writeErrors: [{index: i32, code: i32}, ...]}
@@ -1286,6 +1896,76 @@ async fn load_batched<'a, S: Stream<Item = Vec<ObjectItem>> + 'a>(
 							res.get_array("writeErrors").unwrap()
 						);
 					}
+					if cfg.readback.enabled {
+						readback::verify(&cfg, &db, &chunk, &objects, &hashes, &collections).await;
+					}
+
+					// every derived collection has its own writer, so a slow one doesn't hold up the others, but we
+					// wait for all of them before taking on the next chunk, so each of them still sees every object's
+					// changes in order
+					let fetched = fetched.as_deref().unwrap_or(&objects);
+					tokio::join!(
+						async {
+							if cfg.history.enabled {
+								history::store_versions(&cfg, &pc, &db, &chunk, &objects).await;
+							}
+						},
+						async {
+							if cfg.balances.enabled {
+								balances::update_balances(&cfg, &pc, &db, &chunk, fetched).await;
+							}
+						},
+						async {
+							if cfg.nft.enabled {
+								nft::update_nfts(&cfg, &db, &chunk, fetched).await;
+							}
+						},
+						async {
+							if cfg.owners.enabled {
+								owners::update_owners(&cfg, &pc, &db, &chunk, fetched).await;
+							}
+						},
+						async {
+							if cfg.typestats.enabled {
+								typestats::update_type_stats(&cfg, &db, &chunk, fetched).await;
+							}
+						},
+						async {
+							if cfg.kiosk.enabled {
+								kiosk::update_kiosks(&cfg, &db, &chunk, fetched).await;
+							}
+						},
+						async {
+							if cfg.staking.enabled {
+								staking::update_stakes(&cfg, &db, &chunk, fetched).await;
+							}
+						},
+						plugins::process(&cfg, &db, &chunk, fetched),
+					);
+					webhooks::notify(&chunk, fetched).await;
+					if cfg.changefeed.enabled {
+						let inserted = res
+							.get_array("upserted")
+							.map(|upserted| {
+								upserted
+									.iter()
+									.filter_map(|u| u.as_document()?.get_i32("index").ok())
+									.map(|i| i as usize)
+									.collect()
+							})
+							.unwrap_or_default();
+						changefeed::publish(&cfg, &chunk, &collections, &inserted).await;
+					}
+					routing::publish(&cfg, &chunk, fetched).await;
+					// only once everything derived from these changes is stored, too
+					if cfg.idempotency.enabled {
+						idempotency::mark_applied(&cfg, &pc, &db, &chunk).await;
+					}
+
+					if let Some(first_seen) = chunk.iter().map(|item| item.ts_first_seen).min() {
+						let pass_ms = (Utc::now().timestamp_millis() as u64).saturating_sub(first_seen);
+						warn_if_slow("chunk_pass", cfg.log.slowchunkms, pass_ms, &chunk);
+					}
 
 					let completed_at = pc.tracklatency.then(|| Utc::now().timestamp_millis() as u64);
 					// TODO send whole batch at once
@@ -1316,22 +1996,23 @@ async fn load_batched<'a, S: Stream<Item = Vec<ObjectItem>> + 'a>(
                             count: unchanged as i32,
 						}.into_query("missing_object"),
 					);
-					let write_result = influx_client.query(influx_items).await;
-					match write_result {
-						Ok(string) => debug!(string),
-						Err(error) => warn!("Could not write to influx: {}", error),
-					}
+					write_influx(influx_items).await;
+					statsd::count("inserted_object", inserted as i64, &[]);
+					statsd::count("modified_object", modified as i64, &[]);
+					statsd::count("missing_object", unchanged as i64, &[]);
 					break;
 				}
 				Err(err) => {
 					// the whole thing failed; retry a few times, then assume it's a bug
 					// Report to InfluxDB
 					write_metric_mongo_write_error().await;
+					report_stage_error(Stage::Load, &EtlError::SinkUnavailable(format!("{err:?}")), Default::default());
 					if retries_left == 0 {
 						panic!("final attempt to run mongo batch failed: {:?}", err);
 					}
 					warn!("error running mongo batch, will retry {} more times: {:?}", retries_left, err);
 					retries_left -= 1;
+					mongo::write_backoff(pc.mongo.retries - retries_left).await;
 				}
 			}
 		}
@@ -0,0 +1,223 @@
+use std::{collections::BTreeSet, sync::RwLock};
+
+use bson::Document;
+use mongodb::Database;
+use sui_sdk::rpc_types::ObjectChange as SuiObjectChange;
+use sui_types::{
+	base_types::{ObjectID, SequenceNumber},
+	object::Owner,
+};
+use tokio::sync::OnceCell;
+
+use crate::{
+	_prelude::*,
+	conf::FiltersConfig,
+	ids,
+	mongo::mongo_collection_name,
+	pipeline,
+	types::{TypeAliases, TypePattern},
+};
+
+// Filters decide which of the changes found by the extract step we index at all. They're evaluated before any object
+// data is fetched, so a change that is filtered out never costs an RPC call, and doesn't count towards its checkpoint.
+// Our built-in filters are configured under `filters`, more can be added when composing a pipeline (see pipeline.rs).
+// A change is indexed if every filter keeps it.
+// Unlike `whitelist` / `blacklist`, which are applied to the fetched object, filters only see what the change tells
+// us: an object that changes hands is judged by its new owner, so objects leaving a watched owner are never updated
+// again.
+pub trait ObjectFilter: Send + Sync + 'static {
+	fn keep(&self, change: &Change) -> bool;
+}
+
+impl<F: Fn(&Change) -> bool + Send + Sync + 'static> ObjectFilter for F {
+	fn keep(&self, change: &Change) -> bool {
+		self(change)
+	}
+}
+
+// What we know about an object change before fetching its object data.
+#[derive(Clone, Debug)]
+pub struct Change {
+	pub id:          ObjectID,
+	pub version:     SequenceNumber,
+	pub deletion:    bool,
+	// as in stored objects, e.g. `0x2::coin::Coin<0x2::sui::SUI>`
+	pub object_type: String,
+	// the package defining the (outermost) type
+	pub package:     ObjectID,
+	// the owning address or object in its canonical form (see ids.rs), None for deletions, shared and immutable objects
+	pub owner:       Option<String>,
+	// the sender of the transaction block, in its canonical form
+	pub sender:      String,
+}
+
+impl Change {
+	// Only for the kinds of changes we index, see `client::parse_change()`.
+	fn from_object_change(change: &SuiObjectChange) -> Option<Self> {
+		use sui_sdk::rpc_types::ObjectChange::*;
+		let (id, version, deletion, object_type, owner, sender) = match change {
+			Created { object_id, version, object_type, owner, sender, .. }
+			| Mutated { object_id, version, object_type, owner, sender, .. } => {
+				let owner = match owner {
+					Owner::AddressOwner(address) | Owner::ObjectOwner(address) => Some(address.to_string()),
+					_ => None,
+				};
+				(*object_id, *version, false, object_type, owner, sender)
+			}
+			Deleted { object_id, version, object_type, sender, .. } => {
+				(*object_id, *version, true, object_type, None, sender)
+			}
+			_ => return None,
+		};
+		Some(Change {
+			id,
+			version,
+			deletion,
+			object_type: object_type.to_string(),
+			package: ObjectID::from(object_type.address),
+			owner,
+			sender: sender.to_string(),
+		})
+	}
+}
+
+// `filters.types` / `filters.excludetypes`
+pub struct TypeFilter {
+	include: Vec<TypePattern>,
+	exclude: Vec<TypePattern>,
+}
+
+impl ObjectFilter for TypeFilter {
+	fn keep(&self, change: &Change) -> bool {
+		(self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(&change.object_type)))
+			&& !self.exclude.iter().any(|pattern| pattern.matches(&change.object_type))
+	}
+}
+
+// `filters.owners`. We don't know who owned a deleted object, so deletions are always kept.
+pub struct OwnerWatchlist {
+	owners: HashSet<String>,
+}
+
+impl ObjectFilter for OwnerWatchlist {
+	fn keep(&self, change: &Change) -> bool {
+		change.deletion || change.owner.as_ref().map_or(false, |owner| self.owners.contains(owner))
+	}
+}
+
+// `filters.watchlist`: changes made by or owned by one of the watched addresses. Like `OwnerWatchlist`, deletions are
+// always kept, as we don't know who owned the object.
+pub struct AddressWatchlist {
+	addresses: HashSet<String>,
+}
+
+impl ObjectFilter for AddressWatchlist {
+	fn keep(&self, change: &Change) -> bool {
+		let watched = |address: &String| {
+			self.addresses.contains(address) || WATCHED.read().unwrap().contains(address.as_str())
+		};
+		change.deletion || watched(&change.sender) || change.owner.as_ref().map_or(false, watched)
+	}
+}
+
+// The addresses in the watchlist collection, as of its last refresh, see `spawn_watchlist_refresh()`.
+static WATCHED: RwLock<BTreeSet<String>> = RwLock::new(BTreeSet::new());
+
+// With `filters.watchlist.collection`, watched addresses can be added and removed at runtime, as documents like
+// `{_id: "0x…"}` in the `_watchlist` collection. It's loaded before the pipeline starts, and then reloaded every
+// `refreshms`. Changes we skipped while an address wasn't watched yet aren't indexed retroactively.
+pub(crate) async fn spawn_watchlist_refresh(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	if !cfg.filters.watchlist.collection {
+		return Ok(())
+	}
+	let collection = db.collection::<Document>(&mongo_collection_name(cfg, "_watchlist"));
+	refresh_watchlist(&collection).await.context("cannot load the watchlist collection")?;
+	let refresh = Duration::from_millis(cfg.filters.watchlist.refreshms);
+	tokio::spawn(async move {
+		loop {
+			tokio::time::sleep(refresh).await;
+			if let Err(err) = refresh_watchlist(&collection).await {
+				warn!(error = ?err, "FilterWarning: Failed reloading the watchlist collection, keeping the previous one.");
+			}
+		}
+	});
+	Ok(())
+}
+
+async fn refresh_watchlist(collection: &mongodb::Collection<Document>) -> anyhow::Result<()> {
+	let mut addresses = BTreeSet::new();
+	let mut cursor = collection.find(None, None).await?;
+	while let Some(doc) = cursor.try_next().await? {
+		match doc.get_str("_id").ok().and_then(ids::normalize_id) {
+			Some(address) => {
+				addresses.insert(address);
+			}
+			None => warn!(id = ?doc.get("_id"), "FilterWarning: Ignoring invalid address in the watchlist collection."),
+		}
+	}
+	*WATCHED.write().unwrap() = addresses;
+	Ok(())
+}
+
+// `filters.packages`, compared by the packages they stand for, see `TypeAliases`
+pub struct PackageFilter {
+	packages: HashSet<String>,
+	aliases:  TypeAliases,
+}
+
+impl ObjectFilter for PackageFilter {
+	fn keep(&self, change: &Change) -> bool {
+		self.aliases.resolve(&change.package.to_string()).map_or(false, |package| self.packages.contains(&package))
+	}
+}
+
+// The built-in filters, as configured.
+static FILTERS: OnceCell<Vec<Box<dyn ObjectFilter>>> = OnceCell::const_new();
+
+pub fn setup_filters(cfg: &FiltersConfig, aliases: &TypeAliases) -> anyhow::Result<()> {
+	let mut filters: Vec<Box<dyn ObjectFilter>> = Vec::new();
+	if !cfg.types.is_empty() || !cfg.excludetypes.is_empty() {
+		let patterns = |patterns: &[String]| patterns.iter().map(|p| TypePattern::new(p, aliases)).collect();
+		filters.push(Box::new(TypeFilter { include: patterns(&cfg.types), exclude: patterns(&cfg.excludetypes) }));
+	}
+	if !cfg.owners.is_empty() {
+		// owners are compared in their canonical form, so we need to normalize whatever we got configured
+		let owners = cfg
+			.owners
+			.iter()
+			.map(|owner| ids::normalize_id(owner).ok_or_else(|| anyhow!("invalid address in filters.owners: {}", owner)))
+			.collect::<Result<_, _>>()?;
+		filters.push(Box::new(OwnerWatchlist { owners }));
+	}
+	if !cfg.watchlist.addresses.is_empty() || cfg.watchlist.collection {
+		let addresses = cfg
+			.watchlist
+			.addresses
+			.iter()
+			.map(|address| {
+				ids::normalize_id(address).ok_or_else(|| anyhow!("invalid address in filters.watchlist: {}", address))
+			})
+			.collect::<Result<_, _>>()?;
+		filters.push(Box::new(AddressWatchlist { addresses }));
+	}
+	if !cfg.packages.is_empty() {
+		let packages = cfg
+			.packages
+			.iter()
+			.map(|package| aliases.resolve(package).ok_or_else(|| anyhow!("invalid package id in filters.packages: {}", package)))
+			.collect::<Result<_, _>>()?;
+		filters.push(Box::new(PackageFilter { packages, aliases: aliases.clone() }));
+	}
+	FILTERS.set(filters).map_err(|_| anyhow!("filters have already been set up"))
+}
+
+// To be called by the extract step for every change, before sending it on.
+pub(crate) fn keep(change: &SuiObjectChange) -> bool {
+	let builtin = FILTERS.get().map_or(&[][..], |filters| filters.as_slice());
+	let custom = pipeline::filters();
+	if builtin.is_empty() && custom.is_empty() {
+		return true
+	}
+	let Some(change) = Change::from_object_change(change) else { return true };
+	builtin.iter().chain(custom).all(|filter| filter.keep(&change))
+}
@@ -0,0 +1,69 @@
+use bson::{doc, Document};
+use chrono::NaiveDateTime;
+use mongodb::{options::UpdateOptions, Database, IndexModel};
+use sui_sdk::rpc_types::{SuiTransactionBlockEffectsAPI, SuiTransactionBlockResponse};
+
+use crate::{_prelude::*, mongo::mongo_collection_name, transactions::move_calls};
+
+// With `gas.enabled`, gas costs of transactions are rolled up per called package and day (UTC) in a separate
+// collection, e.g. prod_testnet_objects_gas_daily, keyed by `<package>:<day>`. The breakdown per transaction is part
+// of the `effects` stored in the transactions collection.
+// Rollups are increments, so we only count transactions the transactions collection didn't contain yet, which is why
+// this requires `transactions.enabled`. A transaction calling several packages counts fully towards each of them.
+pub fn gas_daily_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_gas_daily")
+}
+
+pub async fn ensure_gas_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&gas_daily_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "package": 1, "day": -1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "day": -1, "net_gas": -1 }).build(), None).await?;
+	Ok(())
+}
+
+// Adds the gas costs of `blocks`, which have just been stored for the first time, to their rollups. Failing to do so
+// is not fatal for the rest of the pipeline, but leaves the affected rollups short.
+pub async fn update_gas_rollups(cfg: &AppConfig, db: &Database, blocks: &[&SuiTransactionBlockResponse]) {
+	let coll = db.collection::<Document>(&gas_daily_collection_name(cfg));
+	// package:day -> increments
+	let mut rollups = HashMap::<(String, String), [i64; 5]>::new();
+	for block in blocks {
+		let (Some(effects), Some(ts)) = (&block.effects, block.timestamp_ms) else { continue };
+		let Some(day) = NaiveDateTime::from_timestamp_millis(ts as i64).map(|t| t.format("%Y-%m-%d").to_string()) else {
+			continue
+		};
+		let cost = effects.gas_cost_summary();
+		let mut packages = move_calls(block).into_iter().map(|call| call.package.to_string()).collect::<Vec<_>>();
+		packages.sort();
+		packages.dedup();
+		for package in packages {
+			let sums = rollups.entry((package, day.clone())).or_default();
+			sums[0] += 1;
+			sums[1] += cost.computation_cost as i64;
+			sums[2] += cost.storage_cost as i64;
+			sums[3] += cost.storage_rebate as i64;
+			sums[4] += cost.net_gas_usage();
+		}
+	}
+	for ((package, day), [txs, computation, storage, rebate, net]) in rollups {
+		let res = coll
+			.update_one(
+				doc! { "_id": format!("{}:{}", package, day) },
+				doc! {
+					"$inc": {
+						"txs": txs,
+						"computation_cost": computation,
+						"storage_cost": storage,
+						"storage_rebate": rebate,
+						"net_gas": net,
+					},
+					"$setOnInsert": { "package": &package, "day": &day },
+				},
+				UpdateOptions::builder().upsert(true).build(),
+			)
+			.await;
+		if let Err(err) = res {
+			warn!(package = %package, day = %day, error = ?err, "GasWarning: Failed updating gas rollup.");
+		}
+	}
+}
@@ -0,0 +1,330 @@
+use bson::{doc, Bson, DateTime, Document};
+use mongodb::{
+	options::{AggregateOptions, FindOptions, IndexOptions},
+	Collection, Database, IndexModel,
+};
+
+use crate::{
+	_prelude::*,
+	cli::CompactArgs,
+	delta,
+	etl::ObjectItem,
+	influx::write_metric_mongo_write_error,
+	mongo::{mongo_collection_name, write_backoff},
+	utils::HURACAN_VERSION,
+};
+
+// With `history.enabled`, we keep every version of an object we've seen in a separate collection next to the
+// main one (which only keeps the latest version), e.g. prod_testnet_objects_history.
+// Documents are keyed by object id + version, so storing the same version again is a no-op.
+// With `history.deltas`, most versions only hold a patch against the version before them, see delta.rs. Anything
+// reading versions must restore their objects with `object_at()`.
+pub fn history_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_history")
+}
+
+pub async fn ensure_history_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&history_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "object_id": 1, "version_": -1 }).build(), None).await?;
+	coll.create_index(
+		IndexModel::builder()
+			.keys(doc! { "ts": 1 })
+			.options(Some(IndexOptions::builder().sparse(true).build()))
+			.build(),
+		None,
+	)
+	.await?;
+	if cfg.history.deltas {
+		coll.create_index(
+			IndexModel::builder()
+				.keys(doc! { "base": 1 })
+				.options(Some(IndexOptions::builder().sparse(true).build()))
+				.build(),
+			None,
+		)
+		.await?;
+	}
+	Ok(())
+}
+
+// `object` is the object as stored in the objects collection.
+fn history_version(item: &ObjectItem, object: &Document, network: &str) -> Document {
+	let v = item.version.to_string();
+	let v_ = u64::from_str_radix(&v[2..], 16).unwrap() as i64;
+	let mut version = doc! {
+		"_id": format!("{}:{}", item.id, v_),
+		"object_id": item.id.to_string(),
+		"version": v,
+		"version_": v_,
+		"cp": item.cp as i64,
+		"deleted": item.deletion,
+		"ingested_at": DateTime::now(),
+		"huracan_version": HURACAN_VERSION,
+		"network": network,
+	};
+	if let Some(ts) = item.ts_sui {
+		version.insert("ts", DateTime::from_millis(ts as i64));
+	}
+	if !item.deletion {
+		version.insert("object", object.clone());
+	}
+	version
+}
+
+fn history_update(version: Document) -> Document {
+	doc! {
+		"q": doc! { "_id": version.get_str("_id").unwrap() },
+		"u": doc! { "$setOnInsert": version },
+		"upsert": true,
+		"multi": false,
+	}
+}
+
+// Stores all versions in `chunk` in the history collection, with `objects` as stored in the objects collection.
+// Failing to do so is not fatal for the rest of the pipeline, as the main collection has already been updated at
+// this point.
+pub async fn store_versions(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	db: &Database,
+	chunk: &[ObjectItem],
+	objects: &[Document],
+) {
+	let collection = history_collection_name(cfg);
+	let mut versions = chunk
+		.iter()
+		.zip(objects)
+		.map(|(item, object)| history_version(item, object, cfg.network()))
+		.collect::<Vec<_>>();
+	if cfg.history.deltas {
+		encode_deltas(cfg, db, &mut versions).await;
+	}
+	let updates = versions.into_iter().map(history_update).collect::<Vec<_>>();
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		match db.run_command(doc! { "update": &collection, "updates": updates.clone(), "ordered": false }, None).await {
+			Ok(_) => break,
+			Err(err) => {
+				write_metric_mongo_write_error().await;
+				if retries_left == 0 {
+					error!(error = ?err, "failed to store {} object versions in history collection", updates.len());
+					break
+				}
+				warn!("error storing object versions in history collection, will retry {} more times: {:?}", retries_left, err);
+				retries_left -= 1;
+				write_backoff(pc.mongo.retries - retries_left).await;
+			}
+		}
+	}
+}
+
+// The latest stored version of an object, restored.
+struct Latest {
+	id:       String,
+	version:  i64,
+	keyframe: i64,
+	// patches since the keyframe
+	depth:    u64,
+	object:   Document,
+}
+
+// Replaces the objects of `versions` (sorted by object id and version) with patches against the version before them,
+// other than for keyframes. If we can't tell which versions we have stored already, every version becomes a keyframe,
+// which is always safe.
+async fn encode_deltas(cfg: &AppConfig, db: &Database, versions: &mut [Document]) {
+	let coll = db.collection::<Document>(&history_collection_name(cfg));
+	let ids = versions.iter().filter_map(|v| v.get_str("object_id").ok().map(String::from)).collect::<HashSet<_>>();
+	let mut latest = match latest_versions(&coll, ids).await {
+		Ok(latest) => latest,
+		Err(err) => {
+			warn!(error = ?err, "LoadWarning: Failed looking up the latest stored versions, storing full objects.");
+			HashMap::new()
+		}
+	};
+	for version in versions.iter_mut() {
+		let (Ok(object_id), Ok(v_)) = (version.get_str("object_id").map(String::from), version.get_i64("version_"))
+		else {
+			continue
+		};
+		let Ok(object) = version.get_document("object").map(Clone::clone) else {
+			// deletions end the chain
+			latest.remove(&object_id);
+			continue
+		};
+		let id = version.get_str("_id").unwrap().to_string();
+		match latest.get(&object_id) {
+			// older than what we have, e.g. retried after later versions were stored, so it starts a chain of its own
+			Some(prev) if prev.version >= v_ => {
+				version.insert("keyframe_", v_);
+			}
+			Some(prev) if prev.depth + 1 < cfg.history.keyframeevery => {
+				let (keyframe, depth) = (prev.keyframe, prev.depth + 1);
+				version.remove("object");
+				version.insert("patch", delta::diff(&prev.object, &object));
+				version.insert("base", prev.id.clone());
+				version.insert("keyframe_", keyframe);
+				latest.insert(object_id, Latest { id, version: v_, keyframe, depth, object });
+			}
+			_ => {
+				version.insert("keyframe_", v_);
+				latest.insert(object_id, Latest { id, version: v_, keyframe: v_, depth: 0, object });
+			}
+		}
+	}
+}
+
+async fn latest_versions(
+	coll: &Collection<Document>,
+	ids: HashSet<String>,
+) -> anyhow::Result<HashMap<String, Latest>> {
+	if ids.is_empty() {
+		return Ok(HashMap::new())
+	}
+	let pipeline = vec![
+		doc! { "$match": { "object_id": { "$in": ids.into_iter().collect::<Vec<_>>() } } },
+		doc! { "$sort": { "object_id": 1, "version_": -1 } },
+		doc! { "$group": {
+			"_id": "$object_id",
+			"version_": { "$first": "$version_" },
+			// versions stored without deltas are keyframes
+			"keyframe_": { "$first": { "$ifNull": [ "$keyframe_", "$version_" ] } },
+			"deleted": { "$first": "$deleted" },
+		}},
+		doc! { "$match": { "deleted": { "$ne": true } } },
+	];
+	let heads = coll.aggregate(pipeline, None).await?.try_collect::<Vec<_>>().await?;
+	if heads.is_empty() {
+		return Ok(HashMap::new())
+	}
+	let ranges = heads
+		.iter()
+		.map(|head| {
+			Ok(doc! {
+				"object_id": head.get_str("_id")?,
+				"version_": { "$gte": head.get_i64("keyframe_")?, "$lte": head.get_i64("version_")? },
+			})
+		})
+		.collect::<anyhow::Result<Vec<_>>>()?;
+	let opts = FindOptions::builder().sort(doc! { "object_id": 1, "version_": 1 }).build();
+	let found = coll.find(doc! { "$or": ranges }, opts).await?.try_collect::<Vec<_>>().await?;
+	let mut by_object = HashMap::<String, Vec<Document>>::new();
+	for version in found {
+		by_object.entry(version.get_str("object_id")?.to_string()).or_default().push(version);
+	}
+	let mut latest = HashMap::new();
+	for (object_id, mut versions) in by_object {
+		delta::resolve(&mut versions);
+		let Some(head) = versions.last() else { continue };
+		// can't be restored, e.g. if a version in between is missing, so the next version will be a keyframe
+		let Ok(object) = head.get_document("object") else { continue };
+		latest.insert(object_id, Latest {
+			id:       head.get_str("_id")?.to_string(),
+			version:  head.get_i64("version_")?,
+			keyframe: head.get_i64("keyframe_").unwrap_or(head.get_i64("version_")?),
+			depth:    versions.len() as u64 - 1,
+			object:   object.clone(),
+		});
+	}
+	Ok(latest)
+}
+
+// The object of a stored version, restored from its patches if needed. None for deletions.
+pub(crate) async fn object_at(coll: &Collection<Document>, version: &Document) -> anyhow::Result<Option<Document>> {
+	if let Ok(object) = version.get_document("object") {
+		return Ok(Some(object.clone()))
+	}
+	if !version.contains_key("patch") {
+		return Ok(None)
+	}
+	let (object_id, v_) = (version.get_str("object_id")?, version.get_i64("version_")?);
+	let filter = doc! { "object_id": object_id, "version_": { "$gte": version.get_i64("keyframe_")?, "$lte": v_ } };
+	let opts = FindOptions::builder().sort(doc! { "version_": 1 }).build();
+	let mut versions = coll.find(filter, opts).await?.try_collect::<Vec<_>>().await?;
+	delta::resolve(&mut versions);
+	match versions.last().filter(|v| v.get_i64("version_").ok() == Some(v_)).map(|v| v.get_document("object")) {
+		Some(Ok(object)) => Ok(Some(object.clone())),
+		_ => Err(anyhow!("cannot restore version {} of {} from its patches", v_, object_id)),
+	}
+}
+
+// Before removing versions, all versions patched against one of them get their full object back, so they can still
+// be restored.
+pub(crate) async fn materialize_dependents(coll: &Collection<Document>, ids: &[Bson]) -> anyhow::Result<u64> {
+	let mut dependents = coll.find(doc! { "base": { "$in": ids }, "_id": { "$nin": ids } }, None).await?;
+	let mut n = 0;
+	while let Some(version) = dependents.try_next().await? {
+		let Some(object) = object_at(coll, &version).await? else { continue };
+		coll.update_one(
+			doc! { "_id": version.get_str("_id")? },
+			doc! {
+				"$set": { "object": object, "keyframe_": version.get_i64("version_")? },
+				"$unset": { "patch": "", "base": "" },
+			},
+			None,
+		)
+		.await?;
+		n += 1;
+	}
+	Ok(n)
+}
+
+// Entrypoint for the `compact` subcommand.
+// For every object, versions older than the retention window are collapsed into periodic keyframes: within each
+// keyframe period, only the latest version is kept. Newer versions are left untouched.
+pub async fn run_compact(cfg: &AppConfig, args: CompactArgs) -> anyhow::Result<()> {
+	if !cfg.history.enabled {
+		return Err(anyhow!("compact only works with history.enabled, as there is nothing to compact otherwise"))
+	}
+	let db = cfg.mongo.client(&cfg.backfill.mongo).await?;
+	let coll = db.collection::<Document>(&history_collection_name(cfg));
+	let period_ms = args.keyframe_hours as i64 * 60 * 60 * 1000;
+	let cutoff = DateTime::from_millis(
+		DateTime::now().timestamp_millis() - args.retention_days as i64 * 24 * 60 * 60 * 1000,
+	);
+	info!("CompactInfo: Compacting versions older than {} into keyframes every {}h.", cutoff, args.keyframe_hours);
+
+	let pipeline = vec![
+		doc! { "$match": { "ts": { "$lt": cutoff } } },
+		doc! { "$sort": { "object_id": 1, "version_": -1 } },
+		doc! { "$group": {
+			"_id": {
+				"object_id": "$object_id",
+				"period": { "$subtract": [ { "$toLong": "$ts" }, { "$mod": [ { "$toLong": "$ts" }, period_ms ] } ] },
+			},
+			"ids": { "$push": "$_id" },
+		}},
+		// periods with a single version are already compacted
+		doc! { "$match": { "ids.1": { "$exists": true } } },
+	];
+	let mut groups = coll.aggregate(pipeline, AggregateOptions::builder().allow_disk_use(true).build()).await?;
+
+	let (mut num_removed, mut to_remove) = (0u64, Vec::with_capacity(args.batch_size));
+	while let Some(group) = groups.try_next().await? {
+		// ids are sorted by version desc, so we keep the first (= latest) one as keyframe
+		to_remove.extend(group.get_array("ids")?.iter().skip(1).cloned());
+		if to_remove.len() >= args.batch_size {
+			num_removed += remove(cfg, &coll, &mut to_remove, args.dry_run).await?;
+		}
+	}
+	num_removed += remove(cfg, &coll, &mut to_remove, args.dry_run).await?;
+
+	println!("{} {} intermediate object versions", if args.dry_run { "would remove" } else { "removed" }, num_removed);
+	Ok(())
+}
+
+async fn remove(
+	cfg: &AppConfig,
+	coll: &Collection<Document>,
+	ids: &mut Vec<Bson>,
+	dry_run: bool,
+) -> anyhow::Result<u64> {
+	let n = ids.len() as u64;
+	if n > 0 && !dry_run {
+		if cfg.history.deltas {
+			materialize_dependents(coll, ids).await?;
+		}
+		coll.delete_many(doc! { "_id": { "$in": std::mem::take(ids) } }, None).await?;
+	}
+	ids.clear();
+	Ok(n)
+}
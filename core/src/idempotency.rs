@@ -0,0 +1,109 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{
+	options::{FindOptions, IndexOptions},
+	Database, IndexModel,
+};
+
+use crate::{_prelude::*, etl::ObjectItem, influx::write_metric_mongo_write_error, mongo::mongo_collection_name};
+
+// With `idempotency.enabled`, we remember every change the load step has fully applied (including all derived
+// collections), in a separate collection, e.g. prod_testnet_objects_applied, keyed by object id and version, which
+// identifies the transaction that produced it, too. Changes delivered again, e.g. when resuming after a crash or by
+// the bus in decoupled mode, are then skipped instead of running their updates again.
+// Keys expire after `retentiondays` (if > 0), as redeliveries only happen for recent changes.
+pub fn applied_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_applied")
+}
+
+pub async fn ensure_applied_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	if cfg.idempotency.retentiondays == 0 {
+		return Ok(())
+	}
+	let coll = db.collection::<Document>(&applied_collection_name(cfg));
+	let ttl = Duration::from_secs(cfg.idempotency.retentiondays * 24 * 60 * 60);
+	coll.create_index(
+		IndexModel::builder().keys(doc! { "ts": 1 }).options(IndexOptions::builder().expire_after(ttl).build()).build(),
+		None,
+	)
+	.await?;
+	Ok(())
+}
+
+fn applied_key(item: &ObjectItem) -> String {
+	format!("{}:{}", item.id, item.version.value())
+}
+
+// How many keys we delete at once in `forget_applied()`.
+const FORGET_BATCH_SIZE: usize = 10_000;
+
+// Removes the given changes, as (object id, version), from the applied ones, so they're applied again once they're
+// delivered again, e.g. after `rewind --invalidate` removed them. Returns the number of keys removed.
+pub async fn forget_applied(
+	cfg: &AppConfig,
+	db: &Database,
+	changes: impl IntoIterator<Item = (String, i64)>,
+) -> anyhow::Result<u64> {
+	let coll = db.collection::<Document>(&applied_collection_name(cfg));
+	let keys = changes.into_iter().map(|(id, version)| format!("{}:{}", id, version)).collect::<Vec<_>>();
+	let mut deleted = 0;
+	for keys in keys.chunks(FORGET_BATCH_SIZE) {
+		deleted += coll.delete_many(doc! { "_id": { "$in": keys.to_vec() } }, None).await?.deleted_count;
+	}
+	Ok(deleted)
+}
+
+// Splits `chunk` into changes not applied yet and changes that were. If we can't tell, we treat everything as not
+// applied yet, which is safe, as applying a change twice doesn't change the end result, it's just wasted work.
+pub async fn split_applied(cfg: &AppConfig, db: &Database, chunk: Vec<ObjectItem>) -> (Vec<ObjectItem>, Vec<ObjectItem>) {
+	let coll = db.collection::<Document>(&applied_collection_name(cfg));
+	let keys = chunk.iter().map(applied_key).collect::<Vec<_>>();
+	let found = match coll
+		.find(doc! { "_id": { "$in": keys.clone() } }, FindOptions::builder().projection(doc! { "_id": 1 }).build())
+		.await
+	{
+		Ok(cursor) => cursor.try_collect::<Vec<_>>().await,
+		Err(err) => Err(err),
+	};
+	let applied = match found {
+		Ok(docs) => docs.iter().filter_map(|d| d.get_str("_id").ok().map(str::to_string)).collect::<HashSet<_>>(),
+		Err(err) => {
+			warn!(error = ?err, "LoadWarning: Failed looking up applied changes, applying all of them.");
+			return (chunk, Vec::new())
+		}
+	};
+	let (applied, fresh): (Vec<_>, Vec<_>) = chunk.into_iter().zip(keys).partition(|(_, key)| applied.contains(key));
+	(fresh.into_iter().map(|(item, _)| item).collect(), applied.into_iter().map(|(item, _)| item).collect())
+}
+
+// Failing to do so is not fatal, the changes will just be applied again if they're delivered again.
+pub async fn mark_applied(cfg: &AppConfig, pc: &PipelineConfig, db: &Database, chunk: &[ObjectItem]) {
+	let collection = applied_collection_name(cfg);
+	let now = DateTime::now();
+	let updates = chunk
+		.iter()
+		.map(|item| {
+			let key = applied_key(item);
+			doc! {
+				"q": { "_id": &key },
+				"u": { "$setOnInsert": { "_id": &key, "ts": now } },
+				"upsert": true,
+				"multi": false,
+			}
+		})
+		.collect::<Vec<_>>();
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		match db.run_command(doc! { "update": &collection, "updates": updates.clone(), "ordered": false }, None).await {
+			Ok(_) => break,
+			Err(err) => {
+				write_metric_mongo_write_error().await;
+				if retries_left == 0 {
+					error!(error = ?err, "failed to mark {} changes as applied", updates.len());
+					break
+				}
+				warn!("error marking changes as applied, will retry {} more times: {:?}", retries_left, err);
+				retries_left -= 1;
+			}
+		}
+	}
+}
@@ -0,0 +1,46 @@
+// Sui addresses and object ids are 32 bytes, but they're written in many forms: `0x2`, full-length with leading zeros,
+// upper case hex, without `0x`, ... Stored documents and queries only ever use one canonical form: full-length,
+// `0x`-prefixed lower case hex, e.g. `0x0000000000000000000000000000000000000000000000000000000000000002`, so that
+// lookups and joins between collections don't depend on where an id came from.
+// Dependency free, the webserver includes it, too, to normalize query inputs (see server/src/main.rs).
+
+// bytes of an address or object id
+pub const ID_LENGTH: usize = 32;
+
+// Canonical form of an address or object id in any of its forms, None if it isn't one.
+pub fn normalize_id(id: &str) -> Option<String> {
+	let hex = id.trim();
+	let hex = hex.strip_prefix("0x").or_else(|| hex.strip_prefix("0X")).unwrap_or(hex);
+	if hex.is_empty() || hex.len() > ID_LENGTH * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return None
+	}
+	Some(format!("0x{:0>width$}", hex.to_ascii_lowercase(), width = ID_LENGTH * 2))
+}
+
+// For query inputs: the canonical form if `id` is an address or object id, else `id` as it is, which then just won't
+// match anything.
+pub fn canonical_id(id: &str) -> String {
+	normalize_id(id).unwrap_or_else(|| id.to_string())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_normalize_id() {
+		let two = "0x0000000000000000000000000000000000000000000000000000000000000002";
+		assert_eq!(normalize_id("0x2").as_deref(), Some(two));
+		assert_eq!(normalize_id("2").as_deref(), Some(two));
+		assert_eq!(normalize_id(" 0X0002 ").as_deref(), Some(two));
+		assert_eq!(normalize_id(two).as_deref(), Some(two));
+		assert_eq!(
+			normalize_id("0xDEE9").as_deref(),
+			Some("0x000000000000000000000000000000000000000000000000000000000000dee9")
+		);
+		assert_eq!(normalize_id("0x"), None);
+		assert_eq!(normalize_id("0xg"), None);
+		assert_eq!(normalize_id(&format!("0x1{}", &two[2..])), None);
+		assert_eq!(canonical_id("shared"), "shared");
+	}
+}
@@ -0,0 +1,37 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+use crate::{_prelude::*, conf::InFlightConfig};
+
+// Cap on the objects between the transform and the load step of the in-memory pipeline, across all pipelines, by count
+// (`inflight.maxitems`) and estimated size (`inflight.maxbytes`, the size of their BSON). Channel capacities only
+// bound the number of items per channel, so with large objects and a slow MongoDB, gigabytes of object data could
+// pile up in between. Transform workers wait for room before handing an item to the load step, the pipeline tail
+// makes room again once the item has been loaded (or failed). 0 means no limit.
+static ITEMS: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub async fn acquire(cfg: &InFlightConfig, bytes: usize) {
+	let bytes = bytes as u64;
+	loop {
+		let (items, in_flight) = (ITEMS.load(Relaxed), BYTES.load(Relaxed));
+		// a single item larger than the whole budget still has to get through at some point
+		if items == 0
+			|| ((cfg.maxitems == 0 || items < cfg.maxitems) && (cfg.maxbytes == 0 || in_flight + bytes <= cfg.maxbytes))
+		{
+			break
+		}
+		tokio::time::sleep(Duration::from_millis(10)).await;
+	}
+	ITEMS.fetch_add(1, Relaxed);
+	BYTES.fetch_add(bytes, Relaxed);
+}
+
+pub fn release(bytes: usize) {
+	ITEMS.fetch_sub(1, Relaxed);
+	BYTES.fetch_sub(bytes as u64, Relaxed);
+}
+
+// (items, bytes)
+pub fn current() -> (u64, u64) {
+	(ITEMS.load(Relaxed), BYTES.load(Relaxed))
+}
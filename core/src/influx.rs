@@ -1,7 +1,8 @@
-use influxdb::{InfluxDbWriteable, Timestamp};
+use influxdb::{InfluxDbWriteable, Query, Timestamp};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::{info, warn, debug};
-use crate::conf::get_influx_singleton;
+use crate::conf::{get_config_singleton, get_influx_singleton};
+use crate::statsd;
 
 // Please see tag vs field docs before adding additional structs:
 // https://docs.influxdata.com/influxdb/v2.7/write-data/best-practices/schema-design/#use-tags-and-fields
@@ -40,16 +41,12 @@ pub struct MongoWriteError {
 }
 
 pub async fn write_metric_mongo_write_error() {
-    let influx_client = get_influx_singleton();
+    statsd::count("mongo_write_error", 1, &[]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = MongoWriteError {
         time,
     }.into_query("mongo_write_error",);
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 // Finished processing a new checkpoint.
@@ -60,17 +57,13 @@ pub struct CreateCheckpoint {
 }
 
 pub async fn write_metric_create_checkpoint(checkpoint_id: u64) {
-    let influx_client = get_influx_singleton();
+    statsd::gauge("create_checkpoint", checkpoint_id, &[]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = CreateCheckpoint {
         time,
         checkpoint_id,
     }.into_query("create_checkpoint",);
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 // Hit an error writing checkpoint data.
@@ -81,17 +74,13 @@ pub struct CheckpointError {
 }
 
 pub async fn write_metric_checkpoint_error(checkpoint_id: u64) {
-    let influx_client = get_influx_singleton();
+    statsd::count("checkpoint_error", 1, &[]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = CheckpointError {
         time,
         checkpoint_id,
     }.into_query("checkpoint_error",);
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 // Hit some kind of error during ingest.
@@ -104,18 +93,14 @@ pub struct IngestError {
 }
 
 pub async fn write_metric_ingest_error(object_id: String, error_type: String) {
-    let influx_client = get_influx_singleton();
+    statsd::count("ingest_error", 1, &[("error_type", &error_type)]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = IngestError {
         time,
         object_id,
         error_type,
     }.into_query("ingest_error");
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 // Hit an RPC error.
@@ -127,17 +112,13 @@ pub struct RPCError {
 }
 
 pub async fn write_metric_rpc_error(rpc_method: String) {
-    let influx_client = get_influx_singleton();
+    statsd::count("rpc_error", 1, &[("rpc_method", &rpc_method)]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = RPCError {
         time,
         rpc_method,
     }.into_query("rpc_error");
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 // Sent an RPC request.
@@ -148,17 +129,13 @@ pub struct RPCRequest {
 }
 
 pub async fn write_metric_rpc_request(rpc_method: String) {
-    let influx_client = get_influx_singleton();
+    statsd::count("rpc_request", 1, &[("rpc_method", &rpc_method)]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = RPCRequest {
         time: time,
         rpc_method: rpc_method,
     }.into_query("rpc_request");
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 #[derive(InfluxDbWriteable)]
@@ -168,17 +145,13 @@ pub struct CheckpointsBehind {
 }
 
 pub async fn write_metric_checkpoints_behind(behind_by: u64) {
-    let influx_client = get_influx_singleton();
+    statsd::gauge("checkpoints_behind", behind_by, &[]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = CheckpointsBehind {
         time,
         behind_by,
     }.into_query("checkpoints_behind");
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 #[derive(InfluxDbWriteable)]
@@ -188,17 +161,13 @@ pub struct CurrentCheckpoint {
 }
 
 pub async fn write_metric_current_checkpoint(checkpoint_id: u64) {
-    let influx_client = get_influx_singleton();
+    statsd::gauge("current_checkpoint", checkpoint_id, &[]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = CurrentCheckpoint {
         time,
         checkpoint_id,
     }.into_query("current_checkpoint");
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 // This is the final checkpoint handled by do_scan() and indexing will stop when this is published.
@@ -209,17 +178,13 @@ pub struct FinalCheckpoint {
 }
 
 pub async fn write_metric_final_checkpoint(checkpoint_id: u64) {
-    let influx_client = get_influx_singleton();
+    statsd::gauge("final_checkpoint", checkpoint_id, &[]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = FinalCheckpoint {
         time,
         checkpoint_id,
     }.into_query("final_checkpoint");
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 
@@ -230,17 +195,13 @@ pub struct BackfillInit {
 }
 
 pub async fn write_metric_backfill_init(start_checkpoint: u64) {
-    let influx_client = get_influx_singleton();
+    statsd::gauge("backfill_init", start_checkpoint, &[]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = BackfillInit {
         time,
         start_checkpoint,
     }.into_query("backfill_init");
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 #[derive(InfluxDbWriteable)]
@@ -250,17 +211,13 @@ pub struct PauseLivescan{
 }
 
 pub async fn write_metric_pause_livescan(behind_by: u64) {
-    let influx_client = get_influx_singleton();
+    statsd::gauge("pause_livescan", behind_by, &[]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = PauseLivescan {
         time,
         behind_by,
     }.into_query("pause_livescan");
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 #[derive(InfluxDbWriteable)]
@@ -269,16 +226,12 @@ pub struct StartLivescan{
 }
 
 pub async fn write_metric_start_livescan() {
-    let influx_client = get_influx_singleton();
+    statsd::count("start_livescan", 1, &[]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = StartLivescan {
         time,
     }.into_query("start_livescan");
-    let write_result = influx_client.query(influx_item).await;
-    match write_result {
-        Ok(string) => debug!(string),
-        Err(error) => warn!("Could not write to influx: {}", error),
-    }
+    write_influx(influx_item).await;
 }
 
 #[derive(InfluxDbWriteable)]
@@ -289,14 +242,48 @@ pub struct ExtractionLatency {
 }
 
 pub async fn write_metric_extraction_latency(source: String, latency_ms: i32) {
-    let influx_client = get_influx_singleton();
+    statsd::timing("extraction_latency", latency_ms.max(0) as u64, &[("source", &source)]);
     let time = get_influx_timestamp_as_milliseconds().await;
     let influx_item = ExtractionLatency {
         time,
         latency_ms,
         source,
     }.into_query("extraction_latency");
-    let write_result = influx_client.query(influx_item).await;
+    write_influx(influx_item).await;
+}
+
+// Items buffered in the channel after a pipeline step, and how long that step was blocked on sending to the
+// next one since the last report.
+#[derive(InfluxDbWriteable)]
+pub struct Backpressure {
+    pub(crate) time: Timestamp,
+    #[influxdb(tag)] pub(crate) pipeline: String,
+    #[influxdb(tag)] pub(crate) step: String,
+    pub(crate) buffered: u64,
+    pub(crate) blocked_ms: u64,
+}
+
+pub async fn write_metric_backpressure(pipeline: String, step: String, buffered: u64, blocked_ms: u64) {
+    let tags = [("pipeline", pipeline.as_str()), ("step", step.as_str())];
+    statsd::gauge("backpressure.buffered", buffered, &tags);
+    statsd::gauge("backpressure.blocked_ms", blocked_ms, &tags);
+    let time = get_influx_timestamp_as_milliseconds().await;
+    let influx_item = Backpressure {
+        time,
+        pipeline,
+        step,
+        buffered,
+        blocked_ms,
+    }.into_query("backpressure");
+    write_influx(influx_item).await;
+}
+
+// Writes to InfluxDB, unless it has been disabled in favor of StatsD.
+pub(crate) async fn write_influx<Q: Query>(query: Q) {
+    if !get_config_singleton().metrics.influx {
+        return
+    }
+    let write_result = get_influx_singleton().query(query).await;
     match write_result {
         Ok(string) => debug!(string),
         Err(error) => warn!("Could not write to influx: {}", error),
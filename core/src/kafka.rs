@@ -0,0 +1,195 @@
+use std::collections::BTreeSet;
+
+use async_trait::async_trait;
+use rdkafka::{
+	consumer::{CommitMode, Consumer, StreamConsumer},
+	message::OwnedMessage,
+	producer::{FutureProducer, FutureRecord},
+	ClientConfig, Message, Offset, TopicPartitionList,
+};
+
+use crate::{
+	_prelude::*,
+	bus::{BusConsumer, BusProducer, MessageBus},
+	conf::KafkaConfig,
+};
+
+// Kafka as transport for the decoupled pipeline (requires the `kafka` feature).
+// Producers use the message key for partitioning, and consumers of the same subscription form a consumer group,
+// so all messages for the same object are handled by the same consumer, in order.
+pub struct KafkaBus {
+	cfg:        KafkaConfig,
+	// e.g. prod_testnet_objects_
+	topic_base: String,
+}
+
+impl KafkaBus {
+	pub fn new(cfg: &AppConfig) -> Self {
+		Self {
+			cfg:        cfg.kafka.clone(),
+			topic_base: format!("{}_{}_{}_", cfg.env, cfg.net, cfg.mongo.collectionbase),
+		}
+	}
+
+	fn client_config(&self) -> ClientConfig {
+		let mut config = ClientConfig::new();
+		config.set("bootstrap.servers", &self.cfg.brokers);
+		for (k, v) in &self.cfg.options {
+			config.set(k, v);
+		}
+		config
+	}
+}
+
+#[async_trait]
+impl MessageBus for KafkaBus {
+	type Producer = KafkaProducer;
+	type Consumer = KafkaConsumer;
+
+	// we leave topic creation + partitioning to the cluster's defaults or its operators
+	async fn prepare_topic(&self, _topic: &str) -> anyhow::Result<()> {
+		Ok(())
+	}
+
+	async fn producer(&self, topic: &str) -> anyhow::Result<KafkaProducer> {
+		let producer = self.client_config().set("enable.idempotence", "true").create()?;
+		Ok(KafkaProducer { producer, topic: format!("{}{}", self.topic_base, topic) })
+	}
+
+	async fn consumer(&self, topic: &str, subscription: &str) -> anyhow::Result<KafkaConsumer> {
+		let consumer: StreamConsumer = self
+			.client_config()
+			.set("group.id", subscription)
+			.set("enable.auto.commit", "false")
+			.set("auto.offset.reset", "earliest")
+			.create()?;
+		consumer.subscribe(&[&format!("{}{}", self.topic_base, topic)])?;
+		Ok(KafkaConsumer {
+			consumer,
+			dead_letter: self.client_config().set("enable.idempotence", "true").create()?,
+			max_redeliveries: self.cfg.maxredeliveries,
+			nacked: HashMap::new(),
+			nacks: HashMap::new(),
+			num_dead: 0,
+		})
+	}
+}
+
+pub struct KafkaProducer {
+	producer: FutureProducer,
+	topic:    String,
+}
+
+#[async_trait]
+impl BusProducer for KafkaProducer {
+	async fn send(&mut self, key: String, payload: Vec<u8>) -> anyhow::Result<()> {
+		self.producer
+			.send(FutureRecord::to(&self.topic).key(&key).payload(&payload), Duration::from_secs(30))
+			.await
+			.map_err(|(err, _)| anyhow!(err))?;
+		Ok(())
+	}
+}
+
+// Kafka only knows about committed offsets per partition, so a partition's offset is never committed past a message
+// that was nacked and hasn't succeeded since. Messages nacked more than `kafka.maxredeliveries` times are moved to the
+// dead-letter topic when they're delivered again, e.g. prod_testnet_objects_raw_livescan_dlt, and count as acked.
+// Nack counts are only kept in memory: after a restart, messages get `kafka.maxredeliveries` more attempts.
+pub struct KafkaConsumer {
+	consumer:         StreamConsumer,
+	dead_letter:      FutureProducer,
+	max_redeliveries: u32,
+	// offsets nacked and not acked since, per topic and partition
+	nacked:           HashMap<(String, i32), BTreeSet<i64>>,
+	// nacks per message
+	nacks:            HashMap<(String, i32, i64), u32>,
+	// moved to the dead-letter topic since the last `nack()`
+	num_dead:         usize,
+}
+
+impl KafkaConsumer {
+	async fn move_to_dead_letter(&mut self, msg: &OwnedMessage) -> anyhow::Result<()> {
+		let topic = format!("{}_dlt", msg.topic());
+		let mut record = FutureRecord::to(&topic).payload(msg.payload().unwrap_or_default());
+		if let Some(key) = msg.key() {
+			record = record.key(key);
+		}
+		self.dead_letter.send(record, Duration::from_secs(30)).await.map_err(|(err, _)| anyhow!(err))?;
+		warn!(topic = msg.topic(), "DlqWarning: Message failed too often, moved it to the dead-letter topic.");
+		self.num_dead += 1;
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl BusConsumer for KafkaConsumer {
+	// topic, partition, offset
+	type Receipt = (String, i32, i64);
+
+	async fn recv_batch(&mut self, max: usize, wait: Duration) -> anyhow::Result<Vec<(Vec<u8>, Self::Receipt)>> {
+		let deadline = Instant::now() + wait;
+		let mut batch = Vec::with_capacity(max);
+		while batch.len() < max {
+			let msg = match tokio::time::timeout_at(deadline, self.consumer.recv()).await {
+				Ok(Ok(msg)) => msg.detach(),
+				Ok(Err(err)) => return Err(err.into()),
+				Err(_) => break,
+			};
+			let receipt = (msg.topic().to_string(), msg.partition(), msg.offset());
+			if self.nacks.get(&receipt).map_or(false, |nacks| *nacks > self.max_redeliveries) {
+				self.move_to_dead_letter(&msg).await?;
+				self.ack(vec![receipt]).await?;
+				continue
+			}
+			batch.push((msg.payload().unwrap_or_default().to_vec(), receipt));
+		}
+		Ok(batch)
+	}
+
+	// We commit the offset after the highest one we've seen for each partition, or the lowest one still nacked.
+	async fn ack(&mut self, receipts: Vec<Self::Receipt>) -> anyhow::Result<()> {
+		let mut offsets = HashMap::new();
+		for receipt in receipts {
+			self.nacks.remove(&receipt);
+			let (topic, partition, offset) = receipt;
+			let key = (topic, partition);
+			if let Some(nacked) = self.nacked.get_mut(&key) {
+				nacked.remove(&offset);
+				if nacked.is_empty() {
+					self.nacked.remove(&key);
+				}
+			}
+			let max = offsets.entry(key).or_insert(offset);
+			*max = (*max).max(offset);
+		}
+		let mut tpl = TopicPartitionList::new();
+		for ((topic, partition), offset) in offsets {
+			// the committed offset is the next one to be consumed
+			let mut next = offset + 1;
+			if let Some(nacked) = self.nacked.get(&(topic.clone(), partition)).and_then(|nacked| nacked.first()) {
+				next = next.min(*nacked);
+			}
+			tpl.add_partition_offset(&topic, partition, Offset::Offset(next))?;
+		}
+		self.consumer.commit(&tpl, CommitMode::Async)?;
+		Ok(())
+	}
+
+	// There are no per-message negative acks in Kafka, so we rewind each partition to the earliest nacked message,
+	// which redelivers everything after it, too. Messages are given up on when they're delivered again, so we return
+	// how many were moved to the dead-letter topic since the last call instead.
+	async fn nack(&mut self, receipts: Vec<Self::Receipt>) -> anyhow::Result<usize> {
+		let mut offsets = HashMap::new();
+		for receipt in receipts {
+			*self.nacks.entry(receipt.clone()).or_default() += 1;
+			let (topic, partition, offset) = receipt;
+			self.nacked.entry((topic.clone(), partition)).or_default().insert(offset);
+			let min = offsets.entry((topic, partition)).or_insert(offset);
+			*min = (*min).min(offset);
+		}
+		for ((topic, partition), offset) in offsets {
+			self.consumer.seek(&topic, partition, Offset::Offset(offset), Duration::from_secs(10))?;
+		}
+		Ok(std::mem::take(&mut self.num_dead))
+	}
+}
@@ -0,0 +1,120 @@
+use bson::{doc, Document};
+use mongodb::{
+	options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions},
+	Collection, Database,
+};
+
+use crate::{
+	_prelude::*,
+	etl::ObjectItem,
+	mongo::mongo_collection_name,
+	routing::routed_collections,
+	utils::{kiosk_item, KioskObject},
+};
+
+// With `kiosk.enabled`, we resolve the effective owner of items placed in a kiosk: such an item is owned by a dynamic
+// field of the (shared) kiosk, so its owner says nothing about who actually owns it. Instead, that's whoever holds
+// the kiosk's `KioskOwnerCap`, or, if the cap isn't owned by an address (e.g. wrapped into a personal kiosk cap), the
+// `owner` field of the kiosk itself.
+// For every kiosk, we keep track of both in `_kiosks`, and for every dynamic field holding an item, which kiosk and
+// item it belongs to in `_kiosk_items`. The effective owner is then stored in the item's document in the main
+// collection (or the one it's routed to, see routing.rs) as `kiosk` and `kiosk_owner`, and updated whenever one of
+// them changes.
+fn kiosks_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_kiosks")
+}
+
+fn kiosk_items_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_kiosk_items")
+}
+
+struct Collections {
+	// the objects collection first, then all routed collections
+	objects: Vec<Collection<Document>>,
+	kiosks:  Collection<Document>,
+	items:   Collection<Document>,
+}
+
+// Updates kiosks and the effective owners of their items for all items in `chunk`, which has just been loaded, with
+// `objects` as fetched. Failing to do so is not fatal for the rest of the pipeline, the affected items will be updated
+// with their kiosk's next change.
+pub async fn update_kiosks(cfg: &AppConfig, db: &Database, chunk: &[ObjectItem], objects: &[Document]) {
+	let mut objects = vec![db.collection(&mongo_collection_name(cfg, ""))];
+	objects.extend(routed_collections(cfg).await.iter().map(|name| db.collection(name)));
+	let c = Collections {
+		objects,
+		kiosks:  db.collection(&kiosks_collection_name(cfg)),
+		items:   db.collection(&kiosk_items_collection_name(cfg)),
+	};
+	for (item, object) in chunk.iter().zip(objects) {
+		if let Err(err) = update_item(&c, item, object).await {
+			warn!(object_id = ?item.id, error = ?err, "KioskWarning: Failed updating kiosk ownership.");
+		}
+	}
+}
+
+async fn update_item(c: &Collections, item: &ObjectItem, object: &Document) -> anyhow::Result<()> {
+	let id = item.id.to_string();
+	if item.deletion {
+		// an item has been taken out of its kiosk
+		if let Some(placed) = c.items.find_one_and_delete(doc! { "_id": &id }, None).await? {
+			let (item_id, kiosk) = (placed.get_str("item")?, placed.get_str("kiosk")?);
+			let unset = doc! { "$unset": { "kiosk": "", "kiosk_owner": "" } };
+			for objects in &c.objects {
+				objects.update_one(doc! { "_id": item_id, "kiosk": kiosk }, unset.clone(), None).await?;
+			}
+		}
+		return Ok(())
+	}
+	if let Some((kiosk, item_id)) = kiosk_item(object) {
+		c.items.update_one(doc! { "_id": &id }, doc! { "$set": { "kiosk": &kiosk, "item": &item_id } }, upsert()).await?;
+		let owner = match c.kiosks.find_one(doc! { "_id": &kiosk }, None).await? {
+			Some(k) => effective_owner(&k),
+			None => None,
+		};
+		set_on_item(c, &item_id, doc! { "$set": { "kiosk": &kiosk, "kiosk_owner": owner } }).await?;
+		return Ok(())
+	}
+	let (kiosk, field, owner) = match KioskObject::from_doc(object) {
+		Some(KioskObject::Kiosk { owner }) => (id, "owner", owner),
+		Some(KioskObject::OwnerCap { kiosk, holder }) => (kiosk, "cap_owner", holder),
+		None => return Ok(()),
+	};
+	// kiosk and cap change independently, so each of them only moves forward in its own version
+	let v = item.version.value() as i64;
+	let version_field = format!("{}_version_", field);
+	let newer = doc! { "$lt": [ { "$ifNull": [ format!("${}", version_field), -1i64 ] }, v ] };
+	let update = vec![doc! { "$set": {
+		field: { "$cond": { "if": newer, "then": owner, "else": format!("${}", field) } },
+		&version_field: { "$max": [ format!("${}", version_field), v ] },
+	}}];
+	let opts = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+	if let Some(k) = c.kiosks.find_one_and_update(doc! { "_id": &kiosk }, update, opts).await? {
+		let set = doc! { "$set": { "kiosk_owner": effective_owner(&k) } };
+		for objects in &c.objects {
+			objects.update_many(doc! { "kiosk": &kiosk }, set.clone(), None).await?;
+		}
+	}
+	Ok(())
+}
+
+// Updates the item's document wherever it's stored. If it hasn't been loaded yet, it's created in the objects
+// collection, as we can't tell where it will be routed to without its type. Items routed elsewhere and loaded only
+// after being placed in a kiosk therefore only get their kiosk fields with the next change to their placement.
+async fn set_on_item(c: &Collections, item_id: &str, update: Document) -> anyhow::Result<()> {
+	for objects in &c.objects[1..] {
+		if objects.update_one(doc! { "_id": item_id }, update.clone(), None).await?.matched_count > 0 {
+			return Ok(())
+		}
+	}
+	c.objects[0].update_one(doc! { "_id": item_id }, update, upsert()).await?;
+	Ok(())
+}
+
+fn effective_owner(kiosk: &Document) -> Option<String> {
+	kiosk.get_str("cap_owner").or_else(|_| kiosk.get_str("owner")).ok().map(String::from)
+}
+
+fn upsert() -> UpdateOptions {
+	UpdateOptions::builder().upsert(true).build()
+}
@@ -0,0 +1,124 @@
+//! The indexer's extract / transform / load pipeline as a library, so other services can embed it instead of running
+//! the `indexer` binary (which is a thin wrapper around `commands::run()`).
+//!
+//! The public API:
+//! - `conf`: `AppConfig`, loaded from config.yaml + environment via `AppConfig::new()`, and every config section.
+//!   Credentials kept in files or secret managers are filled in by `AppConfig::load_secrets()`.
+//! - `init()`: sets up the global state the pipeline relies on (config, metrics, error reporting, signal handlers).
+//!   Call it once, after `AppConfig::load_secrets()` and before anything else.
+//! - `commands::run()`: runs any of our subcommands (`cli::Command`), e.g. `Command::Run` for the full pipeline
+//!   including index setup, migrations and the instance lock.
+//! - `etl`: the pipeline itself (`etl::run()`, `etl::run_backfill_only()`, `etl::run_watch()`) and the items flowing
+//!   through it (`ObjectItem`, `StepStatus`).
+//! - `filters::ObjectFilter`: decides which changes are indexed, before fetching their object data.
+//! - `pipeline::Pipeline`: composes the same pipeline `etl::run()` runs, with custom filters, enrichers and sinks.
+//! - `plugins::Plugin`: hooks seeing every loaded chunk of objects. The built-in ones are enabled by name via
+//!   `plugins.enabled`.
+//! - `arrow` (with the `arrow` feature): loaded objects as Arrow RecordBatches with a stable schema.
+//! - `types`: parsing, canonical forms and patterns of Move types.
+//! - `ids`: the canonical form of addresses and object ids we store and query them in.
+//! - `bus::MessageBus`: the transport between steps in decoupled mode.
+
+#[macro_use]
+extern crate serde;
+
+mod _prelude;
+mod activity;
+mod alerts;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+mod balances;
+mod bench;
+pub mod bus;
+mod changefeed;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod checkpoints;
+mod chunksizing;
+pub mod cli;
+mod client;
+mod contenthash;
+pub mod commands;
+pub mod conf;
+mod consistency;
+mod control;
+mod decode;
+mod decoupled;
+mod delta;
+#[cfg(feature = "deepbook")]
+mod deepbook;
+#[cfg(feature = "dexpools")]
+mod dexpools;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "pulsar")]
+mod dlq;
+mod epochs;
+mod errors;
+pub mod etl;
+pub mod filters;
+mod gas;
+mod history;
+mod idempotency;
+pub mod ids;
+mod inflight;
+mod influx;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod kiosk;
+mod migrations;
+mod mongo;
+mod movecalls;
+mod naming;
+mod nft;
+mod owners;
+mod packages;
+#[cfg(feature = "parquet")]
+mod parquet;
+pub mod pipeline;
+pub mod plugins;
+#[cfg(feature = "pulsar")]
+mod pulsar;
+mod rawchanges;
+mod readback;
+#[cfg(feature = "redis")]
+mod redisstreams;
+mod reporting;
+mod retries;
+mod rewind;
+mod routing;
+mod rpcauth;
+pub mod rpcfixtures;
+mod secrets;
+mod selfcheck;
+mod staking;
+mod statsd;
+mod transactions;
+mod transfers;
+pub mod types;
+mod typestats;
+mod utils;
+mod webhooks;
+mod workqueue;
+mod wraps;
+
+use sentry::ClientInitGuard;
+
+use crate::{
+	_prelude::*,
+	conf::{setup_config_singleton, setup_influx_singleton},
+};
+
+// Keep the returned guard alive for as long as errors should be reported to Sentry.
+pub async fn init(cfg: &AppConfig) -> anyhow::Result<Option<ClientInitGuard>> {
+	setup_config_singleton(cfg).await;
+	let sentry = reporting::init_sentry(cfg);
+	setup_influx_singleton().await;
+	filters::setup_filters(&cfg.filters, &cfg.types.aliases()?)?;
+	naming::setup_naming(&cfg.naming)?;
+	statsd::setup_statsd_singleton().await.context("cannot setup statsd")?;
+	control::spawn_pause_signal_handlers().context("cannot setup pause/resume signal handlers")?;
+	#[cfg(feature = "diagnostics")]
+	diagnostics::spawn_runtime_stats(cfg);
+	Ok(sentry)
+}
@@ -0,0 +1,112 @@
+use bson::{doc, DateTime, Document};
+use futures::{future::BoxFuture, TryStreamExt};
+use mongodb::{
+	options::{FindOptions, UpdateOptions},
+	Database, IndexModel,
+};
+
+use crate::{_prelude::*, mongo::mongo_collection_name, utils::type_components};
+
+// Changes to the shape of what we've already stored (renamed fields, new indexes, different keys, ...) are applied to
+// existing deployments by migrations, run in order on startup (after acquiring the instance lock), instead of
+// requiring a full re-index. How far a deployment has been migrated is kept in the `_huracan_meta` collection, in one
+// document per collection base, e.g. { _id: "prod_testnet_objects", schema_version: 1 }.
+// A migration must work on empty collections (new deployments run all of them), and should be safe to run again in
+// case we crash before recording it. Migrations are never removed or reordered, only appended.
+type Migration = for<'a> fn(&'a AppConfig, &'a Database) -> BoxFuture<'a, anyhow::Result<()>>;
+
+const MIGRATIONS: &[(&str, Migration)] = &[
+	("baseline", baseline),
+	("type_components", type_components_fields),
+	("updated_at_index", updated_at_index),
+];
+
+pub(crate) const META_COLLECTION: &str = "_huracan_meta";
+
+// the schema as of introducing migrations, nothing to change
+fn baseline<'a>(_cfg: &'a AppConfig, _db: &'a Database) -> BoxFuture<'a, anyhow::Result<()>> {
+	Box::pin(async { Ok(()) })
+}
+
+// objects get their type's components as top-level fields (see `utils::type_components()`), indexed for queries by
+// package, module, struct or type parameter
+fn type_components_fields<'a>(cfg: &'a AppConfig, db: &'a Database) -> BoxFuture<'a, anyhow::Result<()>> {
+	Box::pin(async move {
+		let name = mongo_collection_name(cfg, "");
+		let coll = db.collection::<Document>(&name);
+		coll.create_index(
+			IndexModel::builder().keys(doc! { "type_package": 1, "type_module": 1, "type_struct": 1 }).build(),
+			None,
+		)
+		.await?;
+		coll.create_index(IndexModel::builder().keys(doc! { "type_params": 1 }).build(), None).await?;
+		// objects stored before only get them when they change again, unless we add them here
+		let mut cursor = coll
+			.find(
+				doc! { "object.type": { "$exists": true }, "type_package": { "$exists": false } },
+				FindOptions::builder().projection(doc! { "object.type": 1 }).batch_size(1000).build(),
+			)
+			.await?;
+		let mut updates = Vec::new();
+		let mut updated = 0;
+		while let Some(doc) = cursor.try_next().await? {
+			let components = doc.get_document("object").map(type_components).unwrap_or_default();
+			if components.is_empty() {
+				continue
+			}
+			updates.push(doc! { "q": { "_id": doc.get("_id").cloned() }, "u": { "$set": components }, "multi": false });
+			if updates.len() == 1000 {
+				updated += updates.len();
+				db.run_command(doc! { "update": &name, "updates": std::mem::take(&mut updates), "ordered": false }, None)
+					.await?;
+			}
+		}
+		if !updates.is_empty() {
+			updated += updates.len();
+			db.run_command(doc! { "update": &name, "updates": updates, "ordered": false }, None).await?;
+		}
+		info!("MigrationInfo: Added type components to {} stored objects.", updated);
+		Ok(())
+	})
+}
+
+// objects carry `ingested_at` / `updated_at` (see `utils::object_update()`), indexed for incremental exports of
+// everything updated since some point in time. Objects stored before only get them when they change again.
+fn updated_at_index<'a>(cfg: &'a AppConfig, db: &'a Database) -> BoxFuture<'a, anyhow::Result<()>> {
+	Box::pin(async move {
+		let coll = db.collection::<Document>(&mongo_collection_name(cfg, ""));
+		coll.create_index(IndexModel::builder().keys(doc! { "updated_at": 1 }).build(), None).await?;
+		Ok(())
+	})
+}
+
+pub async fn run_migrations(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let meta = db.collection::<Document>(META_COLLECTION);
+	let base = mongo_collection_name(cfg, "");
+	let current = meta
+		.find_one(doc! { "_id": &base }, None)
+		.await?
+		.and_then(|m| m.get_i64("schema_version").ok())
+		.unwrap_or(0) as usize;
+	if current > MIGRATIONS.len() {
+		return Err(anyhow!(
+			"{} is at schema version {}, but this build only knows up to {}, refusing to run an older indexer on it",
+			base,
+			current,
+			MIGRATIONS.len()
+		))
+	}
+
+	for (i, (name, migration)) in MIGRATIONS.iter().enumerate().skip(current) {
+		let version = i + 1;
+		info!("MigrationInfo: Migrating {} to schema version {} ({}).", base, version, name);
+		migration(cfg, db).await.with_context(|| format!("migration {} ({}) failed", version, name))?;
+		meta.update_one(
+			doc! { "_id": &base },
+			doc! { "$set": { "schema_version": version as i64, "migration": *name, "migrated_at": DateTime::now() } },
+			UpdateOptions::builder().upsert(true).build(),
+		)
+		.await?;
+	}
+	Ok(())
+}
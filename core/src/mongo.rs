@@ -0,0 +1,227 @@
+use bson::{doc, oid::ObjectId, DateTime, Document};
+use influxdb::InfluxDbWriteable;
+use mongodb::{
+	error::{CommandError, ErrorKind, WriteError, WriteFailure},
+	options::{FindOneAndUpdateOptions, FindOneOptions, ReplaceOptions},
+	Collection, Database,
+};
+use sui_types::messages_checkpoint::CheckpointSequenceNumber;
+use tokio::task::JoinHandle;
+
+use crate::_prelude::*;
+use crate::influx::{write_metric_checkpoint_error, write_metric_create_checkpoint, write_metric_mongo_write_error};
+
+
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+	// TODO mongo u64 issue
+	pub _id:  u64,
+	// marks the oldest checkpoint we need to look at, with the assurance that every checkpoint
+	// older than this one has already been completed, even if we may not store that info otherwise
+	pub stop: Option<bool>,
+}
+
+pub fn mongo_collection_name(cfg: &AppConfig, suffix: &str) -> String {
+	format!("{}_{}_{}{}", cfg.env, cfg.net, cfg.mongo.collectionbase, suffix)
+}
+
+pub async fn mongo_checkpoint(cfg: &AppConfig, pc: &PipelineConfig, db: &Database, cp: CheckpointSequenceNumber) {
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		if let Err(err) = db
+			.run_command(
+				doc! {
+					// e.g. prod_testnet_objects_checkpoints
+					"update": mongo_collection_name(&cfg, "_checkpoints"),
+					"updates": vec![
+						doc! {
+							// FIXME how do we store a u64 in mongo? this will be an issue when the chain
+							//		 has been running for long enough!
+							"q": doc! { "_id": cp as i64 },
+							"u": doc! { "_id": cp as i64 },
+							"upsert": true,
+						}
+					]
+				},
+				None,
+			)
+			.await
+		{
+			warn!("failed saving checkpoint to mongo: {:?}", err);
+			write_metric_mongo_write_error().await;
+			write_metric_checkpoint_error(cp as u64).await;
+			if retries_left > 0 {
+				retries_left -= 1;
+				continue
+			}
+			error!(error = ?err, "checkpoint {} fully completed, but could not save checkpoint status to mongo!", cp);
+		}
+		// At this point, we have successfully saved the checkpoint to MongoDB.
+		write_metric_create_checkpoint(cp as u64).await;
+		break
+	}
+}
+
+// Marks `cp` as the oldest checkpoint we need to look at: every older checkpoint will be treated as completed.
+pub async fn mongo_checkpoint_stop(cfg: &AppConfig, db: &Database, cp: CheckpointSequenceNumber) -> anyhow::Result<()> {
+	db.run_command(
+		doc! {
+			"update": mongo_collection_name(&cfg, "_checkpoints"),
+			"updates": vec![
+				doc! {
+					"q": doc! { "_id": cp as i64 },
+					"u": doc! { "_id": cp as i64, "stop": true },
+					"upsert": true,
+				}
+			]
+		},
+		None,
+	)
+	.await?;
+	Ok(())
+}
+
+// How many of the checkpoints `start` to `end` (inclusive) are completed, counting all those below the stop marker.
+pub(crate) async fn completed_checkpoints(cfg: &AppConfig, db: &Database, start: u64, end: u64) -> anyhow::Result<u64> {
+	let checkpoints = db.collection::<Document>(&mongo_collection_name(cfg, "_checkpoints"));
+	let stop = checkpoints
+		.find_one(doc! { "stop": true }, FindOneOptions::builder().sort(doc! { "_id": -1 }).build())
+		.await?
+		.and_then(|cp| cp.get_i64("_id").ok())
+		.map_or(0, |cp| cp as u64);
+	let below_stop = stop.clamp(start, end + 1) - start;
+	let marked = checkpoints
+		.count_documents(doc! { "_id": { "$gte": stop.max(start) as i64, "$lte": end as i64 } }, None)
+		.await?;
+	Ok(below_stop + marked)
+}
+
+// Lease ensuring only a single indexer instance writes to our collections at any time.
+// It's renewed in the background for as long as this is alive; if we ever lose it, we exit immediately.
+pub struct InstanceLock {
+	coll:      Collection<Document>,
+	id:        String,
+	heartbeat: JoinHandle<()>,
+}
+
+impl InstanceLock {
+	// With `standby`, we wait for the lock instead of failing if another instance holds it. As the holder renews its
+	// lease every leasems / 3, we take over at most leasems + leasems / 3 after it stopped doing so.
+	pub async fn acquire(cfg: &AppConfig, db: &Database, standby: bool) -> anyhow::Result<Self> {
+		// e.g. prod_testnet_objects_locks
+		let coll = db.collection::<Document>(&mongo_collection_name(cfg, "_locks"));
+		let id = ObjectId::new().to_hex();
+		let lease = Duration::from_millis(cfg.lock.leasems);
+		if !renew_lock(&coll, &id, lease).await? {
+			let holder = coll.find_one(doc! { "_id": "indexer" }, None).await?;
+			if !standby {
+				return Err(anyhow!(
+					"another indexer instance holds the lock for {}: {:?}",
+					mongo_collection_name(cfg, "_locks"),
+					holder
+				))
+			}
+			info!(instance = %id, holder = ?holder, "standing by until the instance lock becomes available");
+			loop {
+				tokio::time::sleep(lease / 3).await;
+				match renew_lock(&coll, &id, lease).await {
+					Ok(true) => break,
+					Ok(false) => {}
+					Err(err) => warn!(instance = %id, error = ?err, "failed checking instance lock, will retry"),
+				}
+			}
+		}
+		info!(instance = %id, "acquired instance lock");
+
+		let heartbeat = tokio::spawn({
+			let (coll, id) = (coll.clone(), id.clone());
+			async move {
+				let mut last_renewed = Instant::now();
+				loop {
+					tokio::time::sleep(lease / 3).await;
+					match renew_lock(&coll, &id, lease).await {
+						Ok(true) => last_renewed = Instant::now(),
+						Ok(false) => {
+							error!(instance = %id, "instance lock was taken over by another instance, exiting!");
+							std::process::exit(1);
+						}
+						Err(err) => {
+							if last_renewed.elapsed() >= lease {
+								error!(instance = %id, error = ?err, "failed renewing instance lock before it expired, exiting!");
+								std::process::exit(1);
+							}
+							warn!(instance = %id, error = ?err, "failed renewing instance lock, will retry");
+						}
+					}
+				}
+			}
+		});
+
+		Ok(Self { coll, id, heartbeat })
+	}
+
+	// Gives up the lease, so another instance can take over immediately instead of waiting for it to expire.
+	pub async fn release(self) -> anyhow::Result<()> {
+		self.heartbeat.abort();
+		self.coll.delete_one(doc! { "_id": "indexer", "owner": &self.id }, None).await?;
+		info!(instance = %self.id, "released instance lock");
+		Ok(())
+	}
+}
+
+// Returns false if the lock is currently held by another instance.
+async fn renew_lock(coll: &Collection<Document>, id: &str, lease: Duration) -> anyhow::Result<bool> {
+	let now = DateTime::now();
+	let res = coll
+		.find_one_and_update(
+			// either we already hold the lock, or the previous holder's lease has expired
+			doc! { "_id": "indexer", "$or": [ { "owner": id }, { "expires_at": { "$lt": now } } ] },
+			doc! { "$set": {
+				"owner": id,
+				"expires_at": DateTime::from_millis(now.timestamp_millis() + lease.as_millis() as i64),
+			}},
+			FindOneAndUpdateOptions::builder().upsert(true).build(),
+		)
+		.await;
+	match res {
+		Ok(_) => Ok(true),
+		// the filter didn't match, so the upsert tried to insert a second lock document
+		Err(err) if is_duplicate_key_error(&err) => Ok(false),
+		Err(err) => Err(err.into()),
+	}
+}
+
+// Replaces (or inserts) the document with id `id`, unless it has already been stored with a version >= `v`. Expects
+// the version in a `version_` field.
+pub(crate) async fn replace_if_newer(
+	coll: &Collection<Document>,
+	id: &str,
+	v: i64,
+	replacement: Document,
+) -> anyhow::Result<()> {
+	let filter = doc! { "_id": id, "version_": { "$lt": v } };
+	match coll.replace_one(filter, replacement, ReplaceOptions::builder().upsert(true).build()).await {
+		Ok(_) => Ok(()),
+		// the filter didn't match, because a newer version is already stored
+		Err(err) if is_duplicate_key_error(&err) => Ok(()),
+		Err(err) => Err(err.into()),
+	}
+}
+
+pub(crate) fn is_duplicate_key_error(err: &mongodb::error::Error) -> bool {
+	match err.kind.as_ref() {
+		ErrorKind::Command(CommandError { code: 11000, .. }) => true,
+		ErrorKind::Write(WriteFailure::WriteError(WriteError { code: 11000, .. })) => true,
+		_ => false,
+	}
+}
+
+const WRITE_BACKOFF_MS: u64 = 100;
+const WRITE_MAX_BACKOFF_MS: u64 = 10_000;
+
+// Waits before retrying a failed write for the `attempt`th time (starting at 1), backing off exponentially, so a
+// struggling MongoDB isn't hammered with retries, and a failover has time to complete before we run out of them.
+pub(crate) async fn write_backoff(attempt: usize) {
+	let backoff = (WRITE_BACKOFF_MS << (attempt - 1).min(16)).min(WRITE_MAX_BACKOFF_MS);
+	tokio::time::sleep(Duration::from_millis(backoff)).await;
+}
@@ -0,0 +1,108 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{Database, IndexModel};
+use sui_sdk::rpc_types::{
+	SuiExecutionStatus, SuiTransactionBlockDataAPI, SuiTransactionBlockEffectsAPI, SuiTransactionBlockResponse,
+};
+
+use crate::{
+	_prelude::*,
+	influx::write_metric_mongo_write_error,
+	mongo::mongo_collection_name,
+	transactions::move_calls,
+};
+
+// With `movecalls.enabled`, the checkpoint scan stores one document per Move call in a separate collection, e.g.
+// prod_testnet_objects_move_calls, keyed by transaction digest and the call's index within the transaction block.
+// Gas can't be attributed to individual calls, so every call carries the gas used by its whole transaction block,
+// plus the number of calls sharing it.
+pub fn move_calls_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_move_calls")
+}
+
+pub async fn ensure_move_calls_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&move_calls_collection_name(cfg));
+	coll.create_index(
+		IndexModel::builder().keys(doc! { "package": 1, "module": 1, "function": 1, "ts": -1 }).build(),
+		None,
+	)
+	.await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "sender": 1, "ts": -1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "ts": -1 }).build(), None).await?;
+	Ok(())
+}
+
+fn move_call_documents(block: &SuiTransactionBlockResponse) -> Vec<Document> {
+	let calls = move_calls(block);
+	let num_calls = calls.len() as i64;
+	let mut common = doc! { "tx": block.digest.to_string(), "calls_in_tx": num_calls };
+	if let Some(cp) = block.checkpoint {
+		common.insert("checkpoint", cp as i64);
+	}
+	if let Some(ts) = block.timestamp_ms {
+		common.insert("ts", DateTime::from_millis(ts as i64));
+	}
+	if let Some(data) = block.transaction.as_ref().map(|t| &t.data) {
+		common.insert("sender", data.sender().to_string());
+		common.insert("gas_price", data.gas_data().price as i64);
+	}
+	if let Some(effects) = &block.effects {
+		let cost = effects.gas_cost_summary();
+		common.insert("gas_used", cost.net_gas_usage());
+		common.insert("success", matches!(effects.status(), SuiExecutionStatus::Success));
+	}
+	calls
+		.into_iter()
+		.enumerate()
+		.map(|(i, call)| {
+			let mut d = doc! {
+				"_id": format!("{}:{}", block.digest, i),
+				"package": call.package.to_string(),
+				"module": call.module.to_string(),
+				"function": call.function.to_string(),
+			};
+			d.extend(common.clone());
+			d
+		})
+		.collect()
+}
+
+// Stores all Move calls of a page of the checkpoint scan. Like transactions, calls are immutable, so storing the same
+// one again is a no-op.
+pub async fn store_move_calls(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	db: &Database,
+	blocks: &[SuiTransactionBlockResponse],
+) {
+	let updates = blocks
+		.iter()
+		.flat_map(move_call_documents)
+		.map(|call| {
+			doc! {
+				"q": doc! { "_id": call.get_str("_id").unwrap() },
+				"u": doc! { "$setOnInsert": call },
+				"upsert": true,
+				"multi": false,
+			}
+		})
+		.collect::<Vec<_>>();
+	if updates.is_empty() {
+		return
+	}
+	let collection = move_calls_collection_name(cfg);
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		match db.run_command(doc! { "update": &collection, "updates": updates.clone(), "ordered": false }, None).await {
+			Ok(_) => break,
+			Err(err) => {
+				write_metric_mongo_write_error().await;
+				if retries_left == 0 {
+					error!(error = ?err, "failed to store {} Move calls in move calls collection", updates.len());
+					break
+				}
+				warn!("error storing Move calls in move calls collection, will retry {} more times: {:?}", retries_left, err);
+				retries_left -= 1;
+			}
+		}
+	}
+}
@@ -0,0 +1,50 @@
+use bson::{Bson, Document};
+use tokio::sync::OnceCell;
+
+use crate::{_prelude::*, conf::NamingConfig, utils::rename_sdk_keys};
+
+// Stored objects and packages are serialized from sui-sdk types, so their keys are whatever sui-sdk's serde
+// representation says, and change whenever it does. With `naming.style` set to `camel` or `snake`, the load step
+// renames the keys it knows about (see `utils::rename_sdk_keys()`) to stable names in that style instead, e.g.
+// `module_name`, `struct_name` and `type_params` for struct types. The default, `sdk`, stores keys as they come.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Style {
+	Sdk,
+	Camel,
+	Snake,
+}
+
+static STYLE: OnceCell<Style> = OnceCell::const_new();
+
+pub fn setup_naming(cfg: &NamingConfig) -> anyhow::Result<()> {
+	let style = match cfg.style.as_str() {
+		"" | "sdk" => Style::Sdk,
+		"camel" => Style::Camel,
+		"snake" => Style::Snake,
+		other => return Err(anyhow!("unknown naming.style '{}', expected one of: sdk, camel, snake", other)),
+	};
+	STYLE.set(style).map_err(|_| anyhow!("naming has already been set up"))
+}
+
+// Whether `apply()` changes anything at all.
+pub(crate) fn renames() -> bool {
+	matches!(STYLE.get(), Some(Style::Camel | Style::Snake))
+}
+
+// To be called for every object document right before it's written.
+pub(crate) fn apply(doc: &mut Document) {
+	match STYLE.get() {
+		Some(Style::Camel) => rename_sdk_keys(doc, false),
+		Some(Style::Snake) => rename_sdk_keys(doc, true),
+		_ => {}
+	}
+}
+
+// Same for a package's normalized modules, keyed by module name.
+pub(crate) fn apply_modules(modules: &mut Document) {
+	for (_, module) in modules.iter_mut() {
+		if let Bson::Document(module) = module {
+			apply(module);
+		}
+	}
+}
@@ -0,0 +1,105 @@
+use bson::{doc, Bson, Document};
+use mongodb::{
+	options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions},
+	Collection, Database, IndexModel,
+};
+
+use crate::{
+	_prelude::*,
+	etl::ObjectItem,
+	mongo::{is_duplicate_key_error, mongo_collection_name},
+};
+
+// With `nft.enabled`, we fetch the Display fields of every object, and treat every object that has any as an NFT.
+// NFTs are grouped into collections by their full type: `_nft_items` holds the display fields and current owner of
+// every NFT, `_nft_collections` the number of NFTs per collection, e.g. prod_testnet_objects_nft_items.
+pub fn nft_items_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_nft_items")
+}
+
+pub fn nft_collections_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_nft_collections")
+}
+
+pub async fn ensure_nft_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let items = db.collection::<Document>(&nft_items_collection_name(cfg));
+	items.create_index(IndexModel::builder().keys(doc! { "collection": 1, "_id": 1 }).build(), None).await?;
+	items.create_index(IndexModel::builder().keys(doc! { "owner": 1, "collection": 1 }).build(), None).await?;
+	Ok(())
+}
+
+// Display fields of a stored object document, if it has any.
+fn display(object: &Document) -> Option<&Document> {
+	object.get_document("display").ok()?.get_document("data").ok().filter(|data| !data.is_empty())
+}
+
+// Updates NFT items and collection counts for all items in `chunk`, which has just been loaded, with `objects` as
+// fetched. Failing to do so is not fatal for the rest of the pipeline, the affected NFTs will be updated with their
+// next change.
+pub async fn update_nfts(cfg: &AppConfig, db: &Database, chunk: &[ObjectItem], objects: &[Document]) {
+	let items = db.collection::<Document>(&nft_items_collection_name(cfg));
+	let collections = db.collection::<Document>(&nft_collections_collection_name(cfg));
+	for (item, object) in chunk.iter().zip(objects) {
+		if let Err(err) = update_item(&items, &collections, item, object).await {
+			warn!(object_id = ?item.id, error = ?err, "NftWarning: Failed updating NFT.");
+		}
+	}
+}
+
+async fn update_item(
+	items: &Collection<Document>,
+	collections: &Collection<Document>,
+	item: &ObjectItem,
+	object: &Document,
+) -> anyhow::Result<()> {
+	let v = item.version.value() as i64;
+	// only move forward, so replayed or out of order versions don't count twice
+	let filter = doc! { "_id": item.id.to_string(), "version_": { "$lt": v } };
+	let (update, upsert, collection) = if item.deletion {
+		// if we don't know the object, it wasn't an NFT (or we've never seen it), so there's nothing to undo
+		(doc! { "$set": { "version_": v, "deleted": true } }, false, None)
+	} else {
+		let Some(display) = display(object) else { return Ok(()) };
+		let collection = object.get_str("type")?.to_string();
+		let owner = object
+			.get_document("owner")
+			.ok()
+			.and_then(|o| o.get_str("AddressOwner").or_else(|_| o.get_str("ObjectOwner")).ok())
+			.map_or(Bson::Null, |owner| Bson::String(owner.to_string()));
+		let update = doc! { "$set": {
+			"version_": v,
+			"collection": &collection,
+			"owner": owner,
+			"display": display.clone(),
+			"deleted": false,
+		}};
+		(update, true, Some(collection))
+	};
+	let opts = FindOneAndUpdateOptions::builder().upsert(upsert).return_document(ReturnDocument::Before).build();
+	let previous = match items.find_one_and_update(filter, update, opts).await {
+		Ok(previous) => previous,
+		// the NFT is already stored with a newer version
+		Err(err) if is_duplicate_key_error(&err) => return Ok(()),
+		Err(err) => return Err(err.into()),
+	};
+	let existed = previous.as_ref().map_or(false, |p| !p.get_bool("deleted").unwrap_or(false));
+	match (existed, collection) {
+		// newly minted (or unwrapped) NFT
+		(false, Some(collection)) => count(collections, &collection, 1).await?,
+		// burned (or wrapped) NFT
+		(true, None) => count(collections, previous.unwrap().get_str("collection")?, -1).await?,
+		_ => {}
+	}
+	Ok(())
+}
+
+async fn count(collections: &Collection<Document>, collection: &str, n: i64) -> anyhow::Result<()> {
+	collections
+		.update_one(
+			doc! { "_id": collection },
+			doc! { "$inc": { "items": n } },
+			UpdateOptions::builder().upsert(true).build(),
+		)
+		.await?;
+	Ok(())
+}
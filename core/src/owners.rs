@@ -0,0 +1,77 @@
+use bson::{doc, Bson, Document};
+use mongodb::{Database, IndexModel};
+
+use crate::{_prelude::*, etl::ObjectItem, influx::write_metric_mongo_write_error, mongo::mongo_collection_name};
+
+// With `owners.enabled`, the load step maintains the current owner of every object in a separate collection, e.g.
+// prod_testnet_objects_owner_index, so listing everything an address owns is a single indexed query.
+// Documents are keyed by object id and only ever move forward in version, so replayed or out of order changes don't
+// reassign objects to previous owners. Objects that are deleted, shared or immutable have no owner.
+pub fn owner_index_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_owner_index")
+}
+
+pub async fn ensure_owner_index_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&owner_index_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "owner": 1, "_id": 1 }).build(), None).await?;
+	Ok(())
+}
+
+fn owner_update(item: &ObjectItem, object: &Document) -> Document {
+	let v_ = item.version.value() as i64;
+	let owner = if item.deletion {
+		None
+	} else {
+		object
+			.get_document("owner")
+			.ok()
+			.and_then(|o| o.get_str("AddressOwner").or_else(|_| o.get_str("ObjectOwner")).ok().map(String::from))
+	};
+	doc! {
+		// if a newer version is already stored, the filter doesn't match and the upsert fails with a duplicate key
+		"q": doc! { "_id": item.id.to_string(), "version_": { "$lt": v_ } },
+		"u": doc! { "$set": { "owner": owner.map_or(Bson::Null, Bson::String), "version_": v_ } },
+		"upsert": true,
+		"multi": false,
+	}
+}
+
+// Updates the owners of all objects in `chunk`, which has just been loaded, with `objects` as fetched. Failing to do
+// so is not fatal for the rest of the pipeline, the affected objects will be updated with their next change.
+pub async fn update_owners(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	db: &Database,
+	chunk: &[ObjectItem],
+	objects: &[Document],
+) {
+	let collection = owner_index_collection_name(cfg);
+	let updates = chunk.iter().zip(objects).map(|(item, object)| owner_update(item, object)).collect::<Vec<_>>();
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		match db.run_command(doc! { "update": &collection, "updates": updates.clone(), "ordered": false }, None).await {
+			Ok(res) => {
+				// duplicate keys just mean we've already seen a newer version
+				let failed = res
+					.get_array("writeErrors")
+					.map(|errors| {
+						errors.iter().filter(|e| e.as_document().and_then(|e| e.get_i32("code").ok()) != Some(11000)).count()
+					})
+					.unwrap_or(0);
+				if failed > 0 {
+					warn!("OwnerWarning: Failed updating owners of {} objects: {:?}", failed, res.get_array("writeErrors"));
+				}
+				break
+			}
+			Err(err) => {
+				write_metric_mongo_write_error().await;
+				if retries_left == 0 {
+					error!(error = ?err, "failed to update owners of {} objects in owner index", updates.len());
+					break
+				}
+				warn!("error updating owner index, will retry {} more times: {:?}", retries_left, err);
+				retries_left -= 1;
+			}
+		}
+	}
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use bson::{doc, DateTime, Document};
+use mongodb::{
+	options::{FindOptions, ReplaceOptions},
+	Collection, Database, IndexModel,
+};
+use sui_sdk::rpc_types::{ObjectChange, SuiTransactionBlockResponse};
+use sui_types::{base_types::ObjectID, messages_checkpoint::CheckpointSequenceNumber, SUI_FRAMEWORK_ADDRESS};
+
+use crate::{
+	_prelude::*,
+	client::ClientPool,
+	influx::write_metric_mongo_write_error,
+	mongo::mongo_collection_name,
+	naming,
+};
+
+// With `packages.enabled`, the checkpoint scan stores every package it sees being published or upgraded in a separate
+// collection, e.g. prod_testnet_objects_packages, keyed by package id, with the normalized form of all of its modules
+// (structs with their fields and abilities, function signatures), as returned by the RPC.
+// Every version of a package is a separate object with its own id. All versions share the same upgrade cap though,
+// which we use to link them: every package records the `original_id` (version 1), `previous_id` and `latest_id` of its
+// lineage, as far as we know them. Types keep the id of the package version that introduced them, so `latest_id` is
+// what a type tag's package has to be mapped to in order to call its current code.
+pub fn packages_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_packages")
+}
+
+pub async fn ensure_packages_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&packages_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "upgrade_cap": 1, "version": 1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "module_names": 1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "original_id": 1 }).build(), None).await?;
+	Ok(())
+}
+
+// Stores all packages published by `block`. Failing to do so is not fatal for the rest of the pipeline, the package
+// will just be missing from the registry until its checkpoint is processed again.
+pub async fn store_published(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	sui: &mut ClientPool,
+	db: &Database,
+	cp: CheckpointSequenceNumber,
+	block: &SuiTransactionBlockResponse,
+) {
+	let Some(changes) = &block.object_changes else { return };
+	// publishing creates an upgrade cap, upgrading mutates it
+	let upgrade_cap = changes.iter().find_map(|change| match change {
+		ObjectChange::Created { object_type, object_id, .. } | ObjectChange::Mutated { object_type, object_id, .. }
+			if object_type.address == SUI_FRAMEWORK_ADDRESS
+				&& object_type.module.as_str() == "package"
+				&& object_type.name.as_str() == "UpgradeCap" =>
+		{
+			Some(*object_id)
+		}
+		_ => None,
+	});
+	for change in changes {
+		let ObjectChange::Published { package_id, version, .. } = change else { continue };
+		let package = match package_document(sui, *package_id).await {
+			Ok(mut package) => {
+				package.insert("version", version.value() as i64);
+				package.insert("upgrade_cap", upgrade_cap.map(|cap| cap.to_string()));
+				package.insert("tx", block.digest.to_string());
+				package.insert("checkpoint", cp as i64);
+				package.insert("network", cfg.network());
+				if let Some(ts) = block.timestamp_ms {
+					package.insert("ts", DateTime::from_millis(ts as i64));
+				}
+				package
+			}
+			Err(err) => {
+				warn!(package_id = ?package_id, error = ?err, "ExtractionError: Failed fetching package modules, not storing package.");
+				continue
+			}
+		};
+		let coll = db.collection::<Document>(&packages_collection_name(cfg));
+		let mut retries_left = pc.mongo.retries;
+		loop {
+			let res = coll
+				.replace_one(
+					doc! { "_id": package_id.to_string() },
+					package.clone(),
+					ReplaceOptions::builder().upsert(true).build(),
+				)
+				.await;
+			match res {
+				Ok(_) => break,
+				Err(err) => {
+					write_metric_mongo_write_error().await;
+					if retries_left == 0 {
+						error!(error = ?err, "failed to store package {}", package_id);
+						break
+					}
+					warn!("error storing package {}, will retry {} more times: {:?}", package_id, retries_left, err);
+					retries_left -= 1;
+				}
+			}
+		}
+		// packages without an upgrade cap can't be upgraded, so they're their own lineage
+		let res = match upgrade_cap {
+			Some(cap) => link_lineage(&coll, &cap.to_string()).await,
+			None => coll
+				.update_one(
+					doc! { "_id": package_id.to_string() },
+					doc! { "$set": { "original_id": package_id.to_string(), "latest_id": package_id.to_string() } },
+					None,
+				)
+				.await
+				.map(|_| ())
+				.map_err(Into::into),
+		};
+		if let Err(err) = res {
+			warn!(package_id = ?package_id, error = ?err, "ExtractionError: Failed linking package to its upgrade lineage.");
+		}
+	}
+}
+
+// (Re-)links all known versions of the package upgraded via `upgrade_cap`. The checkpoint scan mostly walks from new to
+// old, so we usually see upgrades before the packages they upgrade, and can't just link each package as it comes in.
+// Versions of a lineage are consecutive, so a missing version leaves the `previous_id` of its successor unset.
+async fn link_lineage(coll: &Collection<Document>, upgrade_cap: &str) -> anyhow::Result<()> {
+	let versions = coll
+		.find(
+			doc! { "upgrade_cap": upgrade_cap },
+			FindOptions::builder().projection(doc! { "version": 1 }).sort(doc! { "version": 1 }).build(),
+		)
+		.await?
+		.try_collect::<Vec<_>>()
+		.await?
+		.into_iter()
+		.map(|p| Ok((p.get_i64("version")?, p.get_str("_id")?.to_string())))
+		.collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+	let original = versions.get(&1).cloned();
+	let Some((_, latest)) = versions.iter().next_back() else { return Ok(()) };
+	for (version, id) in &versions {
+		let previous = versions.get(&(version - 1)).cloned();
+		coll.update_one(
+			doc! { "_id": id },
+			doc! { "$set": { "original_id": original.clone(), "previous_id": previous, "latest_id": latest } },
+			None,
+		)
+		.await?;
+	}
+	Ok(())
+}
+
+async fn package_document(sui: &mut ClientPool, package_id: ObjectID) -> anyhow::Result<Document> {
+	let modules = sui.get_normalized_move_modules_by_package(package_id).await?;
+	let mut normalized = bson::to_document(&modules)?;
+	naming::apply_modules(&mut normalized);
+	Ok(doc! {
+		"_id": package_id.to_string(),
+		"module_names": modules.keys().cloned().collect::<Vec<_>>(),
+		"modules": normalized,
+	})
+}
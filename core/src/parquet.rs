@@ -0,0 +1,60 @@
+use std::{
+	fs::File,
+	path::PathBuf,
+	sync::atomic::{AtomicU64, Ordering::Relaxed},
+};
+
+use async_trait::async_trait;
+use bson::Document;
+use chrono::Utc;
+use mongodb::Database;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+
+use crate::{_prelude::*, arrow::record_batch, etl::ObjectItem, plugins::Plugin};
+
+// Writes every loaded chunk as a Parquet file to `parquet.dir`, in the schema of arrow.rs, e.g. for analytics on a data
+// lake instead of MongoDB. Files are named by the time they were written and never changed afterwards, so they can be
+// picked up by anything watching the directory. Like all plugins, replayed chunks are written again.
+pub struct ParquetSink;
+
+// tells files written within the same millisecond apart
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+#[async_trait]
+impl Plugin for ParquetSink {
+	fn name(&self) -> &'static str {
+		"parquet"
+	}
+
+	async fn setup(&self, cfg: &AppConfig, _db: &Database) -> anyhow::Result<()> {
+		std::fs::create_dir_all(&cfg.parquet.dir).with_context(|| format!("cannot create {}", cfg.parquet.dir))?;
+		Ok(())
+	}
+
+	async fn process(
+		&self,
+		cfg: &AppConfig,
+		_db: &Database,
+		chunk: &[ObjectItem],
+		objects: &[Option<Document>],
+	) -> anyhow::Result<()> {
+		if chunk.is_empty() {
+			return Ok(())
+		}
+		let batch = record_batch(chunk, objects, cfg.network())?;
+		let name = format!("{}_{:06}.parquet", Utc::now().format("%Y%m%dT%H%M%S%3f"), SEQ.fetch_add(1, Relaxed) % 1_000_000);
+		let path = PathBuf::from(&cfg.parquet.dir).join(name);
+		tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+			// written under a temporary name first, so readers never see partial files
+			let tmp = path.with_extension("parquet.tmp");
+			let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+			let mut writer = ArrowWriter::try_new(File::create(&tmp)?, batch.schema(), Some(props))?;
+			writer.write(&batch)?;
+			writer.close()?;
+			std::fs::rename(&tmp, &path)?;
+			Ok(())
+		})
+		.await??;
+		Ok(())
+	}
+}
@@ -0,0 +1,146 @@
+use std::io::Cursor;
+
+use async_trait::async_trait;
+use bson::Document;
+use tokio::sync::OnceCell;
+
+use crate::{
+	_prelude::*,
+	commands::prepare_run,
+	conf::get_config_singleton,
+	etl::{self, ObjectItem},
+	filters::ObjectFilter,
+	plugins::Plugin,
+	utils::object_bson_bytes,
+};
+
+// Composes the same streams `indexer run` wires up, with custom stages inserted, for services embedding the pipeline:
+//
+//   Pipeline::builder().source(Source::Live { exit_at_tip: None }).filter(..).enrich(..).sink(..).build().run().await
+//
+// - filters see every change as soon as it has been extracted, next to the ones configured under `filters` (see
+//   filters.rs). Changes any of them rejects are dropped before their object data is fetched.
+// - enrichers see the object data of every change after it has been fetched and before it's loaded, in the order
+//   they were added, and may modify it.
+// - sinks see every loaded chunk, see `Plugin`. They're set up and run next to the plugins in `plugins.enabled`.
+// Like plugins, custom stages are global, so there can only be one composed pipeline per process. Call
+// `huracan_core::init()` before running it.
+pub struct Pipeline {
+	source:    Source,
+	filters:   Vec<Box<dyn ObjectFilter>>,
+	enrichers: Vec<Box<dyn Enricher>>,
+	sinks:     Vec<Box<dyn Plugin>>,
+}
+
+// Where changes come from.
+#[derive(Clone, Debug)]
+pub enum Source {
+	// The latest checkpoints via livescan and polling, plus a backfill whenever we fall behind by more than
+	// `backfillthreshold`, as `indexer run`. With `exit_at_tip`, we stop once we're at most that many checkpoints
+	// behind the latest one.
+	Live { exit_at_tip: Option<u64> },
+	// A single backfill down from `start_checkpoint` (or the latest one) to where `extract.start` says, as
+	// `backfillonly`.
+	Backfill { start_checkpoint: Option<u64> },
+}
+
+impl Default for Source {
+	fn default() -> Self {
+		Source::Live { exit_at_tip: None }
+	}
+}
+
+#[async_trait]
+pub trait Enricher: Send + Sync + 'static {
+	// Not called for deletions, as there's no object data. Errors are logged, and the object is loaded as it was.
+	async fn enrich(&self, item: &ObjectItem, object: &mut Document) -> anyhow::Result<()>;
+}
+
+#[derive(Default)]
+pub struct PipelineBuilder {
+	source:    Source,
+	filters:   Vec<Box<dyn ObjectFilter>>,
+	enrichers: Vec<Box<dyn Enricher>>,
+	sinks:     Vec<Box<dyn Plugin>>,
+}
+
+impl Pipeline {
+	pub fn builder() -> PipelineBuilder {
+		PipelineBuilder::default()
+	}
+
+	pub async fn run(self) -> anyhow::Result<()> {
+		let cfg = get_config_singleton();
+		STAGES
+			.set(Stages { filters: self.filters, enrichers: self.enrichers })
+			.map_err(|_| anyhow!("a pipeline has already been run in this process"))?;
+		let lock = prepare_run(cfg, false, false, self.sinks).await?;
+		etl::apply_start(cfg).await?;
+		match self.source {
+			Source::Live { exit_at_tip } => etl::run(cfg, exit_at_tip).await?,
+			Source::Backfill { start_checkpoint } => etl::run_backfill_only(cfg, start_checkpoint).await?,
+		}
+		if let Some(lock) = lock {
+			lock.release().await?;
+		}
+		Ok(())
+	}
+}
+
+impl PipelineBuilder {
+	pub fn source(mut self, source: Source) -> Self {
+		self.source = source;
+		self
+	}
+
+	pub fn filter(mut self, filter: impl ObjectFilter) -> Self {
+		self.filters.push(Box::new(filter));
+		self
+	}
+
+	pub fn enrich(mut self, enricher: impl Enricher) -> Self {
+		self.enrichers.push(Box::new(enricher));
+		self
+	}
+
+	pub fn sink(mut self, sink: impl Plugin) -> Self {
+		self.sinks.push(Box::new(sink));
+		self
+	}
+
+	pub fn build(self) -> Pipeline {
+		Pipeline { source: self.source, filters: self.filters, enrichers: self.enrichers, sinks: self.sinks }
+	}
+}
+
+struct Stages {
+	filters:   Vec<Box<dyn ObjectFilter>>,
+	enrichers: Vec<Box<dyn Enricher>>,
+}
+
+// Only set by `Pipeline::run()`, so our own commands never run any custom stages.
+static STAGES: OnceCell<Stages> = OnceCell::const_new();
+
+// See `filters::keep()`.
+pub(crate) fn filters() -> &'static [Box<dyn ObjectFilter>] {
+	STAGES.get().map_or(&[], |stages| stages.filters.as_slice())
+}
+
+// To be called by the transform step for every object it fetched, before handing it to the load step.
+pub(crate) async fn enrich(mut item: ObjectItem) -> ObjectItem {
+	let Some(stages) = STAGES.get() else { return item };
+	if stages.enrichers.is_empty() || item.deletion {
+		return item
+	}
+	let Ok(mut object) = Document::from_reader(&mut Cursor::new(&item.bytes)) else { return item };
+	for enricher in &stages.enrichers {
+		if let Err(err) = enricher.enrich(&item, &mut object).await {
+			warn!(object_id = ?item.id, error = ?err, "PipelineWarning: Failed enriching object.");
+		}
+	}
+	match object_bson_bytes(&object) {
+		Ok(bytes) => item.bytes = bytes,
+		Err(err) => warn!(object_id = ?item.id, error = ?err, "PipelineWarning: Cannot store enriched object as BSON."),
+	}
+	item
+}
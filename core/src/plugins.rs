@@ -0,0 +1,75 @@
+use async_trait::async_trait;
+use bson::Document;
+use mongodb::Database;
+use tokio::sync::OnceCell;
+
+use crate::{_prelude::*, etl::ObjectItem};
+
+// A protocol specific indexer, deriving its own state from the objects we load. Plugins see every loaded chunk,
+// after it has been written to the main collection, and write to their own collections only.
+#[async_trait]
+pub trait Plugin: Send + Sync + 'static {
+	// as used in the `plugins.enabled` config
+	fn name(&self) -> &'static str;
+	// Called once on startup.
+	async fn setup(&self, cfg: &AppConfig, db: &Database) -> anyhow::Result<()>;
+	// `objects` holds the object data of each item, if it isn't a deletion. Errors are logged, but otherwise
+	// ignored, so plugins should handle replayed and out of order items gracefully.
+	async fn process(
+		&self,
+		cfg: &AppConfig,
+		db: &Database,
+		chunk: &[ObjectItem],
+		objects: &[Option<Document>],
+	) -> anyhow::Result<()>;
+}
+
+// All compiled-in plugins, each behind the feature of the same name.
+fn registry() -> Vec<Box<dyn Plugin>> {
+	#[allow(unused_mut)]
+	let mut plugins: Vec<Box<dyn Plugin>> = Vec::new();
+	#[cfg(feature = "deepbook")]
+	plugins.push(Box::new(crate::deepbook::DeepBookPlugin));
+	#[cfg(feature = "dexpools")]
+	plugins.push(Box::new(crate::dexpools::DexPoolsPlugin));
+	#[cfg(feature = "parquet")]
+	plugins.push(Box::new(crate::parquet::ParquetSink));
+	plugins
+}
+
+// The enabled plugins. Only set up by `setup_plugins()`, so commands that don't call it never run any.
+static PLUGINS: OnceCell<Vec<Box<dyn Plugin>>> = OnceCell::const_new();
+
+// `custom` plugins (the sinks of a composed pipeline, see pipeline.rs) are always enabled.
+pub async fn setup_plugins(cfg: &AppConfig, db: &Database, custom: Vec<Box<dyn Plugin>>) -> anyhow::Result<()> {
+	let mut plugins = registry();
+	for name in &cfg.plugins.enabled {
+		if !plugins.iter().any(|p| p.name() == name) {
+			return Err(anyhow!("unknown plugin {:?} in plugins.enabled (or built without its feature)", name))
+		}
+	}
+	plugins.retain(|p| cfg.plugins.enabled.iter().any(|name| name == p.name()));
+	plugins.extend(custom);
+	for plugin in &plugins {
+		plugin.setup(cfg, db).await.with_context(|| format!("failed setting up plugin {}", plugin.name()))?;
+	}
+	PLUGINS.set(plugins).map_err(|_| anyhow!("plugins have already been set up"))
+}
+
+// To be called for every chunk after it has been loaded successfully, with `objects` as fetched.
+pub async fn process(cfg: &AppConfig, db: &Database, chunk: &[ObjectItem], objects: &[Document]) {
+	let Some(plugins) = PLUGINS.get() else { return };
+	if plugins.is_empty() {
+		return
+	}
+	let objects = chunk
+		.iter()
+		.zip(objects)
+		.map(|(item, object)| (!item.deletion).then(|| object.clone()))
+		.collect::<Vec<_>>();
+	for plugin in plugins {
+		if let Err(err) = plugin.process(cfg, db, chunk, &objects).await {
+			warn!(plugin = plugin.name(), error = ?err, "PluginWarning: Failed processing chunk.");
+		}
+	}
+}
@@ -0,0 +1,313 @@
+use async_trait::async_trait;
+use std::collections::VecDeque;
+
+use pulsar::{
+	authentication::oauth2::{OAuth2Authentication, OAuth2Params},
+	compression::{Compression, CompressionLz4, CompressionZstd},
+	consumer::{InitialPosition, Message},
+	producer::{self, ProducerOptions, SendFuture},
+	Authentication, Consumer, ConsumerOptions, DeserializeMessage, OperationRetryOptions, Producer, Pulsar,
+	PulsarBuilder, SerializeMessage, SubType, TokioExecutor,
+};
+use tokio::sync::OnceCell;
+
+use crate::_prelude::*;
+use crate::bus::{BusConsumer, BusProducer, MessageBus};
+use crate::conf::get_config_singleton;
+
+// e.g. {persistent://public/default/}{prod}_{testnet}_{objects}_{retries}
+// braces added for clarity of discerning between the different parts
+fn topic_name(cfg: &AppConfig, topic_suffix: &str) -> String {
+	format!("{}{}_{}_{}_{}", cfg.pulsar.topicbase, cfg.env, cfg.net, cfg.mongo.collectionbase, topic_suffix)
+}
+
+pub async fn make_producer(topic_suffix: &str ) -> anyhow::Result<Producer<TokioExecutor>> {
+	let client = get_pulsar_singleton();
+	let cfg = get_config_singleton();
+	Ok(client
+		.producer()
+		.with_topic(&topic_name(cfg, topic_suffix))
+		.with_options(producer_options(cfg)?)
+		.build()
+		.await?)
+}
+
+// see `pulsar.producer`
+fn producer_options(cfg: &AppConfig) -> anyhow::Result<ProducerOptions> {
+	let pc = &cfg.pulsar.producer;
+	let compression = match pc.compression.as_str() {
+		"none" => None,
+		"lz4" => Some(Compression::Lz4(CompressionLz4::default())),
+		"zstd" => Some(Compression::Zstd(CompressionZstd::default())),
+		other => return Err(anyhow!("unsupported pulsar.producer.compression: {} (expected: none | lz4 | zstd)", other)),
+	};
+	Ok(ProducerOptions {
+		batch_size: pc.batchsize,
+		batch_byte_size: pc.batchbytes,
+		batch_timeout: pc.batchtimeoutms.map(Duration::from_millis),
+		compression,
+		..Default::default()
+	})
+}
+
+// Consumer for one of our topics. New subscriptions start at the earliest message still retained,
+// so we also get to see everything that was published before the subscription was first created.
+// With SubType::KeyShared, messages are spread over all consumers of the subscription by key (see `keyed_message()`),
+// so all messages for the same object are delivered to the same consumer, in order.
+pub async fn make_consumer<T: DeserializeMessage>(
+	topic_suffix: &str,
+	subscription: &str,
+	sub_type: SubType,
+) -> anyhow::Result<Consumer<T, TokioExecutor>> {
+	let client = get_pulsar_singleton();
+	let cfg = get_config_singleton();
+	Ok(client
+		.consumer()
+		.with_topic(topic_name(cfg, topic_suffix))
+		.with_subscription_type(sub_type)
+		.with_subscription(subscription)
+		.with_options(ConsumerOptions { initial_position: InitialPosition::Earliest, ..Default::default() })
+		.build()
+		.await?)
+}
+
+// Partitioned topics can't be created through the binary protocol, so we just check that the topic has been set up
+// with the expected number of partitions, e.g. via
+// `pulsar-admin topics create-partitioned-topic persistent://public/default/prod_testnet_objects_raw_livescan -p 8`
+// Keyed messages are routed to a partition by their key, so all versions of an object end up in the same partition.
+pub async fn check_partitions(topic_suffix: &str) -> anyhow::Result<()> {
+	let cfg = get_config_singleton();
+	let topic = topic_name(cfg, topic_suffix);
+	let partitions = get_pulsar_singleton()
+		.lookup_partitioned_topic_number(&topic)
+		.await
+		.with_context(|| format!("cannot look up partitions of topic {}", topic))?;
+	if partitions != cfg.pulsar.partitions {
+		return Err(anyhow!(
+			"topic {} has {} partitions, but pulsar.partitions is {}",
+			topic,
+			partitions,
+			cfg.pulsar.partitions
+		))
+	}
+	Ok(())
+}
+
+// Serializes an item into a message keyed by `key`, e.g. an object id.
+pub fn keyed_message<T: SerializeMessage>(item: T, key: String) -> anyhow::Result<producer::Message> {
+	let mut msg = T::serialize_message(item)?;
+	msg.partition_key = Some(key);
+	Ok(msg)
+}
+
+// Receives up to `max` messages, for at most `wait`. Returns an empty batch if nothing arrived in the meantime.
+pub async fn recv_batch<T: DeserializeMessage>(
+	consumer: &mut Consumer<T, TokioExecutor>,
+	max: usize,
+	wait: Duration,
+) -> anyhow::Result<Vec<Message<T>>> {
+	let deadline = Instant::now() + wait;
+	let mut batch = Vec::with_capacity(max);
+	while batch.len() < max {
+		match tokio::time::timeout_at(deadline, consumer.try_next()).await {
+			Ok(Ok(Some(msg))) => batch.push(msg),
+			Ok(Ok(None)) | Err(_) => break,
+			Ok(Err(err)) => return Err(err.into()),
+		}
+	}
+	Ok(batch)
+}
+
+// Pulsar as transport for the decoupled pipeline. Consumers use a Key_Shared subscription.
+pub struct PulsarBus;
+
+#[async_trait]
+impl MessageBus for PulsarBus {
+	type Producer = PulsarProducer;
+	type Consumer = PulsarConsumer;
+
+	async fn prepare_topic(&self, topic: &str) -> anyhow::Result<()> {
+		check_partitions(topic).await
+	}
+
+	async fn producer(&self, topic: &str) -> anyhow::Result<Self::Producer> {
+		Ok(PulsarProducer {
+			producer:    make_producer(topic).await?,
+			pending:     VecDeque::new(),
+			max_pending: get_config_singleton().pulsar.producer.maxpending.max(1),
+		})
+	}
+
+	async fn consumer(&self, topic: &str, subscription: &str) -> anyhow::Result<Self::Consumer> {
+		Ok(PulsarConsumer {
+			consumer:    make_consumer(topic, subscription, SubType::KeyShared).await?,
+			dead_letter: make_producer(&dead_letter_topic(topic)).await?,
+			attempts:    HashMap::new(),
+			delayed:     Vec::new(),
+		})
+	}
+}
+
+// Keeps up to `pulsar.producer.maxpending` messages in flight, so batching can actually fill up batches.
+pub struct PulsarProducer {
+	producer:    Producer<TokioExecutor>,
+	pending:     VecDeque<SendFuture>,
+	max_pending: usize,
+}
+
+#[async_trait]
+impl BusProducer for PulsarProducer {
+	async fn send(&mut self, key: String, payload: Vec<u8>) -> anyhow::Result<()> {
+		if self.pending.len() >= self.max_pending {
+			self.pending.pop_front().unwrap().await?;
+		}
+		self.pending.push_back(self.producer.send(keyed_message(payload, key)?).await?);
+		Ok(())
+	}
+
+	async fn flush(&mut self) -> anyhow::Result<()> {
+		while let Some(receipt) = self.pending.pop_front() {
+			receipt.await?;
+		}
+		Ok(())
+	}
+}
+
+// e.g. raw_livescan_dlt
+pub fn dead_letter_topic(topic_suffix: &str) -> String {
+	format!("{}_dlt", topic_suffix)
+}
+
+// ledger id, entry id, partition, batch index
+type MessageKey = (u64, u64, Option<i32>, Option<i32>);
+
+fn message_key(msg: &Message<Vec<u8>>) -> MessageKey {
+	let id = &msg.message_id.id;
+	(id.ledger_id, id.entry_id, id.partition, id.batch_index)
+}
+
+// Nacked messages are redelivered after an exponential backoff (see `pulsar.redelivery`). Once a message has been
+// redelivered `maxredeliveries` times, it's moved to the dead-letter topic of its topic, so it can't hold up
+// the rest of the messages with the same key forever.
+// Attempts are counted per consumer, which is accurate as long as Key_Shared keeps redelivering a message to the
+// same consumer, i.e. as long as the set of consumers doesn't change.
+pub struct PulsarConsumer {
+	consumer:    Consumer<Vec<u8>, TokioExecutor>,
+	dead_letter: Producer<TokioExecutor>,
+	attempts:    HashMap<MessageKey, u32>,
+	// nacked messages, waiting for their backoff to pass
+	delayed:     Vec<(Instant, Message<Vec<u8>>)>,
+}
+
+impl PulsarConsumer {
+	async fn nack_due(&mut self) -> anyhow::Result<()> {
+		let now = Instant::now();
+		let (due, delayed): (Vec<_>, Vec<_>) =
+			std::mem::take(&mut self.delayed).into_iter().partition(|(due, _)| *due <= now);
+		self.delayed = delayed;
+		for (_, msg) in due {
+			self.consumer.nack(&msg).await?;
+		}
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl BusConsumer for PulsarConsumer {
+	type Receipt = Message<Vec<u8>>;
+
+	async fn recv_batch(&mut self, max: usize, wait: Duration) -> anyhow::Result<Vec<(Vec<u8>, Self::Receipt)>> {
+		self.nack_due().await?;
+		Ok(recv_batch(&mut self.consumer, max, wait)
+			.await?
+			.into_iter()
+			.map(|msg| (msg.payload.data.clone(), msg))
+			.collect())
+	}
+
+	async fn ack(&mut self, receipts: Vec<Self::Receipt>) -> anyhow::Result<()> {
+		for msg in receipts {
+			self.attempts.remove(&message_key(&msg));
+			self.consumer.ack(&msg).await?;
+		}
+		Ok(())
+	}
+
+	async fn nack(&mut self, receipts: Vec<Self::Receipt>) -> anyhow::Result<usize> {
+		let cfg = &get_config_singleton().pulsar.redelivery;
+		let mut num_dead = 0;
+		for msg in receipts {
+			let key = message_key(&msg);
+			let attempts = self.attempts.entry(key).or_insert(0);
+			*attempts += 1;
+			if *attempts <= cfg.maxredeliveries {
+				let backoff = cfg.backoffms.saturating_mul(1 << (*attempts - 1).min(16)).min(cfg.maxbackoffms);
+				self.delayed.push((Instant::now() + Duration::from_millis(backoff), msg));
+				continue
+			}
+			let mut dead = producer::Message {
+				payload: msg.payload.data.clone(),
+				partition_key: msg.payload.metadata.partition_key.clone(),
+				..Default::default()
+			};
+			dead.properties.insert("attempts".to_string(), attempts.to_string());
+			dead.properties.insert("topic".to_string(), msg.topic.clone());
+			self.dead_letter.send(dead).await?.await?;
+			self.attempts.remove(&key);
+			self.consumer.ack(&msg).await?;
+			warn!(topic = msg.topic, "DlqWarning: Message failed too often, moved it to the dead-letter topic.");
+			num_dead += 1;
+		}
+		Ok(num_dead)
+	}
+}
+
+pub async fn make_transaction_producer(topic_suffix: &str ) -> anyhow::Result<Producer<TokioExecutor>> {
+	let client = get_pulsar_singleton();
+	let cfg = get_config_singleton();
+	Ok(client
+        .producer()
+        // e.g. {persistent://public/default/}{prod}_{testnet}_{your-variable}
+        // braces added for clarity of discerning between the different parts
+        .with_topic(&format!("{}{}_{}_{}_", cfg.pulsar.topicbase, cfg.env, cfg.net, topic_suffix))
+        .build()
+        .await?)
+}
+
+pub async fn create(cfg: &AppConfig) -> anyhow::Result<Pulsar<TokioExecutor>> {
+	Ok(builder(cfg).build().await?)
+}
+
+// Authenticates with `pulsar.token` if set, with OAuth2 client credentials otherwise.
+fn builder(cfg: &AppConfig) -> PulsarBuilder<TokioExecutor> {
+	let builder =
+		Pulsar::builder(&cfg.pulsar.url, TokioExecutor).with_operation_retry_options(operation_retry_options(cfg));
+	match &cfg.pulsar.token {
+		Some(token) => builder.with_auth(Authentication { name: "token".into(), data: token.clone().into_bytes() }),
+		None => builder.with_auth_provider(OAuth2Authentication::client_credentials(OAuth2Params {
+			issuer_url:      cfg.pulsar.issuer.clone(),
+			credentials_url: cfg.pulsar.credentials.to_string().clone(),
+			audience:        Some(cfg.pulsar.audience.clone()),
+			scope:           None,
+		})),
+	}
+}
+
+fn operation_retry_options(cfg: &AppConfig) -> OperationRetryOptions {
+	OperationRetryOptions {
+		operation_timeout: Duration::from_millis(cfg.pulsar.producer.sendtimeoutms),
+		..Default::default()
+	}
+}
+
+pub(crate) static PULSARCLIENT: OnceCell<Pulsar<TokioExecutor>> = OnceCell::const_new();
+
+// Setup config singleton
+pub async fn setup_pulsar_singleton() -> &'static Pulsar<TokioExecutor> {
+	let cfg = get_config_singleton();
+	PULSARCLIENT.get_or_init(|| async { builder(cfg).build().await.unwrap() }).await
+}
+
+pub fn get_pulsar_singleton() -> &'static Pulsar<TokioExecutor> {
+	PULSARCLIENT.get().expect("ConfigError: Pulsar Client singleton could not be loaded.")
+}
@@ -0,0 +1,200 @@
+use bson::{doc, DateTime, Document};
+use futures::stream;
+use mongodb::{options::FindOptions, Database, IndexModel};
+use sui_sdk::rpc_types::{SuiGetPastObjectRequest, SuiObjectResponse, SuiPastObjectResponse};
+use sui_types::base_types::{ObjectID, SequenceNumber, TransactionDigest};
+
+use crate::{
+	_prelude::*,
+	cli::ReplayArgs,
+	client::{parse_get_object_response, ClientPool},
+	commands::spawn_sinks,
+	etl::{load_batched, object_data_options, IngestRoute, ObjectItem, StepStatus},
+	influx::write_metric_mongo_write_error,
+	mongo::mongo_collection_name,
+};
+
+// With `rawchanges.enabled`, every object change found by the extract step is stored as is, before its object data is
+// fetched, in a separate collection, e.g. prod_testnet_objects_raw_changes, keyed by object id and version.
+// The `replay` subcommand runs transform and load again for a checkpoint range from there, so a fix to either of them
+// can be applied to already indexed data without scanning checkpoints again. Object data is fetched at exactly the
+// recorded version, so replaying the same range always yields the same result, as long as the fullnode still has
+// these versions (fullnodes prune old object versions after a while).
+pub fn raw_changes_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_raw_changes")
+}
+
+pub async fn ensure_raw_changes_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&raw_changes_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "cp": 1, "version_": 1 }).build(), None).await?;
+	Ok(())
+}
+
+fn raw_change_update(digest: &Option<TransactionDigest>, item: &ObjectItem) -> Document {
+	let v_ = item.version.value() as i64;
+	let mut change = doc! {
+		"_id": format!("{}:{}", item.id, v_),
+		"object_id": item.id.to_string(),
+		"version_": v_,
+		"deletion": item.deletion,
+		"cp": item.cp as i64,
+		"ingested_via": bson::to_bson(&item.ingested_via).unwrap(),
+	};
+	// only the first change of each transaction block comes with its digest
+	if let Some(digest) = digest {
+		change.insert("tx", digest.to_string());
+	}
+	if let Some(ts) = item.ts_sui {
+		change.insert("ts", DateTime::from_millis(ts as i64));
+	}
+	doc! {
+		"q": doc! { "_id": change.get_str("_id").unwrap() },
+		"u": doc! { "$setOnInsert": change },
+		"upsert": true,
+		"multi": false,
+	}
+}
+
+// Stores a batch of extracted changes. Failing to do so is not fatal for the rest of the pipeline, the changes just
+// can't be replayed later.
+pub async fn store_raw_changes(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	db: &Database,
+	changes: &[(Option<TransactionDigest>, ObjectItem)],
+) {
+	if changes.is_empty() {
+		return
+	}
+	let collection = raw_changes_collection_name(cfg);
+	let updates = changes.iter().map(|(digest, item)| raw_change_update(digest, item)).collect::<Vec<_>>();
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		match db.run_command(doc! { "update": &collection, "updates": updates.clone(), "ordered": false }, None).await {
+			Ok(_) => break,
+			Err(err) => {
+				write_metric_mongo_write_error().await;
+				if retries_left == 0 {
+					error!(error = ?err, "failed to store {} raw changes", updates.len());
+					break
+				}
+				warn!("error storing raw changes, will retry {} more times: {:?}", retries_left, err);
+				retries_left -= 1;
+			}
+		}
+	}
+}
+
+fn raw_change_item(change: &Document) -> anyhow::Result<ObjectItem> {
+	Ok(ObjectItem {
+		cp:            change.get_i64("cp")? as u64,
+		deletion:      change.get_bool("deletion")?,
+		id:            ObjectID::from_str(change.get_str("object_id")?)?,
+		version:       SequenceNumber::from_u64(change.get_i64("version_")? as u64),
+		ts_sui:        change.get_datetime("ts").ok().map(|ts| ts.timestamp_millis() as u64),
+		ts_first_seen: DateTime::now().timestamp_millis() as u64,
+		ingested_via:  change.get("ingested_via").cloned().map_or(Ok(IngestRoute::Backfill), bson::from_bson)?,
+		bytes:         Vec::new(),
+	})
+}
+
+// Entrypoint for the `replay` subcommand.
+// Changes are replayed in batches ordered by checkpoint and version. Deletions go straight to the load step, all other
+// changes get their object data fetched at their recorded version first.
+pub async fn run_replay(cfg: &AppConfig, args: ReplayArgs) -> anyhow::Result<()> {
+	if !cfg.rawchanges.enabled {
+		return Err(anyhow!("replay only works with rawchanges.enabled, as there are no recorded changes otherwise"))
+	}
+	spawn_sinks(cfg).await?;
+	let pc = cfg.pipeline(args.pipeline).clone();
+	// replaying means applying these changes again on purpose
+	let mut cfg = cfg.clone();
+	cfg.idempotency.enabled = false;
+	let cfg = &cfg;
+	let mut sui = cfg.sui().await?;
+	let db = cfg.mongo.client(&pc.mongo).await?;
+	let coll = db.collection::<Document>(&raw_changes_collection_name(cfg));
+	let filter = doc! { "cp": { "$gte": args.from as i64, "$lte": args.to as i64 } };
+	let mut changes = coll.find(filter, FindOptions::builder().sort(doc! { "cp": 1, "version_": 1 }).build()).await?;
+
+	let (mut replayed, mut failed) = (0usize, 0usize);
+	let mut batch = Vec::with_capacity(pc.objectqueries.batchsize);
+	loop {
+		let change = changes.try_next().await?;
+		if let Some(change) = &change {
+			batch.push(raw_change_item(change)?);
+			if batch.len() < pc.objectqueries.batchsize {
+				continue
+			}
+		}
+		if !batch.is_empty() {
+			let (to_load, num_failed) = fetch_versions(&mut sui, std::mem::take(&mut batch)).await;
+			failed += num_failed;
+			replayed += to_load.len();
+			if !to_load.is_empty() {
+				// load_batched() reports every item back to the pipeline tail, so we need enough room for all of them
+				let (last_tx, mut last_rx) = tokio::sync::mpsc::channel(to_load.len());
+				load_batched(cfg.clone(), pc.clone(), stream::iter(vec![to_load]), db.clone(), last_tx).await;
+				while let Ok((status, _, _)) = last_rx.try_recv() {
+					if let StepStatus::Err(_) = status {
+						replayed -= 1;
+						failed += 1;
+					}
+				}
+			}
+			info!("ReplayInfo: {} replayed / {} failed so far", replayed, failed);
+		}
+		if change.is_none() {
+			break
+		}
+	}
+
+	println!("replayed checkpoints {} to {}: {} changes replayed / {} failed", args.from, args.to, replayed, failed);
+	Ok(())
+}
+
+// Fetches the object data of all changes in `batch` at their exact version, returns the loadable items and the number
+// of those we couldn't get data for.
+async fn fetch_versions(sui: &mut ClientPool, batch: Vec<ObjectItem>) -> (Vec<ObjectItem>, usize) {
+	let (mut to_load, to_fetch): (Vec<_>, Vec<_>) = batch.into_iter().partition(|item| item.deletion);
+	if to_fetch.is_empty() {
+		return (to_load, 0)
+	}
+	let requests = to_fetch
+		.iter()
+		.map(|item| SuiGetPastObjectRequest { object_id: item.id, version: item.version })
+		.collect::<Vec<_>>();
+	let responses = match sui.try_multi_get_parsed_past_object(requests, object_data_options()).await {
+		Ok(responses) => responses,
+		Err(err) => {
+			warn!(error = ?err, "ReplayWarning: Cannot fetch {} object versions, skipping them.", to_fetch.len());
+			return (to_load, to_fetch.len())
+		}
+	};
+	let mut failed = 0;
+	for mut item in to_fetch {
+		// match responses to requests by object id and version, see transform_batched()
+		let data = responses.iter().find_map(|res| match res {
+			SuiPastObjectResponse::VersionFound(data) if data.object_id == item.id && data.version == item.version => {
+				Some(data.clone())
+			}
+			_ => None,
+		});
+		let parsed = match data {
+			Some(data) => parse_get_object_response(&item.id, SuiObjectResponse::new_with_data(data)).await,
+			None => None,
+		};
+		match parsed {
+			Some((version, bytes)) => {
+				item.version = version;
+				item.bytes = bytes;
+				to_load.push(item);
+			}
+			None => {
+				warn!(object_id = ?item.id, version = ?item.version, "ReplayWarning: Object version not available or filtered out, skipping it.");
+				failed += 1;
+			}
+		}
+	}
+	(to_load, failed)
+}
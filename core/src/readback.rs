@@ -0,0 +1,178 @@
+use bson::{doc, Document};
+use mongodb::Database;
+
+use crate::{_prelude::*, etl::ObjectItem, statsd};
+
+// With `readback.enabled`, a few of the documents of every chunk written by the load step are read back right away and
+// compared to what we sent, to notice serialization issues (e.g. values that don't survive the round trip) or writes
+// that didn't end up where we think they did (e.g. with a too weak write concern, or a misconfigured router) early,
+// in new deployments or after upgrades. It costs a read per collection written to per chunk, so it's off by default.
+// Mismatches are only logged and counted (`readback_mismatch`), loading goes on as usual.
+// `chunk`, `objects` (as sent, empty for deletions), `hashes` and `collections` are as passed to
+// `utils::object_update()` and `routing::run_updates()`.
+pub async fn verify(
+	cfg: &AppConfig,
+	db: &Database,
+	chunk: &[ObjectItem],
+	objects: &[Document],
+	hashes: &[Option<String>],
+	collections: &[String],
+) {
+	let mut sampled = HashMap::<&str, Vec<usize>>::new();
+	for i in sample(chunk.len(), cfg.readback.samples) {
+		sampled.entry(collections[i].as_str()).or_default().push(i);
+	}
+	let mut mismatches = 0;
+	for (collection, indices) in sampled {
+		let ids = indices.iter().map(|&i| chunk[i].id.to_string()).collect::<Vec<_>>();
+		let found = match db.collection::<Document>(collection).find(doc! { "_id": { "$in": ids } }, None).await {
+			Ok(cursor) => cursor.try_collect::<Vec<_>>().await,
+			Err(err) => Err(err),
+		};
+		let stored = match found {
+			Ok(docs) => docs
+				.into_iter()
+				.filter_map(|d| Some((d.get_str("_id").ok()?.to_string(), d)))
+				.collect::<HashMap<_, _>>(),
+			Err(err) => {
+				warn!(collection, error = ?err, "ReadBackWarning: Failed reading back written objects.");
+				continue
+			}
+		};
+		for i in indices {
+			let item = &chunk[i];
+			if let Err(problem) =
+				compare(item, &objects[i], hashes[i].as_deref(), cfg.network(), stored.get(&item.id.to_string()))
+			{
+				mismatches += 1;
+				error!(
+					object_id = ?item.id,
+					version = item.version.value(),
+					collection,
+					"ReadBackError: Stored object doesn't match what we wrote: {}.",
+					problem
+				);
+			}
+		}
+	}
+	if mismatches > 0 {
+		statsd::count("readback_mismatch", mismatches, &[]);
+	}
+}
+
+// `n` indices spread evenly over a chunk of `len` items. Chunks are sorted by object id, so that's as good as random.
+pub(crate) fn sample(len: usize, n: usize) -> impl Iterator<Item = usize> {
+	let n = n.min(len);
+	(0..n).map(move |j| j * len / n)
+}
+
+// Whether `stored` is what writing `item` should have left behind, or what's wrong with it.
+pub(crate) fn compare(
+	item: &ObjectItem,
+	sent: &Document,
+	hash: Option<&str>,
+	network: &str,
+	stored: Option<&Document>,
+) -> Result<(), String> {
+	let stored = stored.ok_or("not found")?;
+	let version = stored.get_i64("version_").map_err(|_| "no version_")?;
+	let ours = item.version.value() as i64;
+	if item.deletion {
+		// deletions are final, whatever else comes in later
+		if !stored.get_bool("deleted").unwrap_or(false) {
+			return Err("not marked as deleted".into())
+		}
+		return Ok(())
+	}
+	// a newer version has been stored in the meantime (or before, and ours was ignored), nothing to compare
+	if version > ours {
+		return Ok(())
+	}
+	if version < ours {
+		return Err(format!("stored version {} is older than ours", version))
+	}
+	let stored_network = stored.get_str("network").ok();
+	if stored_network != Some(network) {
+		return Err(format!("network {:?} instead of {}", stored_network, network))
+	}
+	if hash.is_some() && stored.get_str("content_hash").ok() != hash {
+		return Err("content_hash differs".into())
+	}
+	let mut object = stored.get_document("object").map_err(|_| "no object")?.clone();
+	let mut sent = sent.clone();
+	// objects stored without content get it once decoded, see decode.rs
+	if !sent.contains_key("content") {
+		object.remove("content");
+		sent.remove("content");
+	}
+	if object != sent {
+		let differing = sent
+			.iter()
+			.filter(|(key, value)| object.get(key.as_str()) != Some(*value))
+			.map(|(key, _)| key.as_str())
+			.chain(object.keys().filter(|key| !sent.contains_key(key.as_str())).map(String::as_str))
+			.collect::<Vec<_>>();
+		return Err(format!("object differs in {}", differing.join(", ")))
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::etl::test_item;
+
+	#[test]
+	fn test_readback_sample() {
+		assert_eq!(sample(10, 3).collect::<Vec<_>>(), vec![0, 3, 6]);
+		// never more than there are, and every index only once
+		assert_eq!(sample(2, 5).collect::<Vec<_>>(), vec![0, 1]);
+		assert_eq!(sample(0, 5).count(), 0);
+		assert_eq!(sample(7, 0).count(), 0);
+	}
+
+	#[test]
+	fn test_readback_compare() {
+		let sent = doc! { "type": "0x2::coin::Coin<0x2::sui::SUI>", "content": { "fields": { "balance": "10" } } };
+		let stored = |version: i64, object: Document| {
+			doc! { "_id": "0x5", "version_": version, "network": "testnet", "content_hash": "h", "object": object }
+		};
+
+		assert_eq!(compare(&test_item(3, false), &sent, Some("h"), "testnet", Some(&stored(3, sent.clone()))), Ok(()));
+		assert_eq!(compare(&test_item(3, false), &sent, None, "testnet", None), Err("not found".to_string()));
+		// a newer version has been stored since, whatever it holds
+		assert_eq!(
+			compare(&test_item(3, false), &sent, Some("h"), "testnet", Some(&stored(4, Document::new()))),
+			Ok(())
+		);
+		assert!(compare(&test_item(3, false), &sent, None, "testnet", Some(&stored(2, sent.clone())))
+			.unwrap_err()
+			.contains("older"));
+		assert!(compare(&test_item(3, false), &sent, None, "mainnet", Some(&stored(3, sent.clone())))
+			.unwrap_err()
+			.contains("network"));
+		assert_eq!(
+			compare(&test_item(3, false), &sent, Some("other"), "testnet", Some(&stored(3, sent.clone()))),
+			Err("content_hash differs".to_string())
+		);
+		let changed = doc! { "type": "0x2::coin::Coin<0x2::sui::SUI>", "content": { "fields": { "balance": "11" } } };
+		assert_eq!(
+			compare(&test_item(3, false), &sent, None, "testnet", Some(&stored(3, changed))),
+			Err("object differs in content".to_string())
+		);
+
+		// objects stored without content get it once decoded, so it doesn't count either way
+		let pending = doc! { "type": "0x2::coin::Coin<0x2::sui::SUI>" };
+		assert_eq!(compare(&test_item(3, false), &pending, None, "testnet", Some(&stored(3, sent.clone()))), Ok(()));
+		assert_eq!(compare(&test_item(3, false), &pending, None, "testnet", Some(&stored(3, pending.clone()))), Ok(()));
+
+		// deletions only need to be marked as such, at whatever version
+		let mut deleted = stored(9, Document::new());
+		assert_eq!(
+			compare(&test_item(3, true), &Document::new(), None, "testnet", Some(&deleted)),
+			Err("not marked as deleted".to_string())
+		);
+		deleted.insert("deleted", true);
+		assert_eq!(compare(&test_item(3, true), &Document::new(), None, "testnet", Some(&deleted)), Ok(()));
+	}
+}
@@ -0,0 +1,188 @@
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+use async_trait::async_trait;
+use redis::{
+	aio::MultiplexedConnection,
+	streams::{StreamClaimReply, StreamId, StreamMaxlen, StreamRangeReply, StreamReadOptions, StreamReadReply},
+	AsyncCommands, Client,
+};
+
+use crate::{
+	_prelude::*,
+	bus::{BusConsumer, BusProducer, MessageBus},
+	conf::RedisConfig,
+};
+
+// distinguishes the consumers of this process within a consumer group
+static NEXT_CONSUMER: AtomicUsize = AtomicUsize::new(0);
+
+// Redis Streams as transport for the decoupled pipeline (requires the `redis` feature), for small deployments that
+// don't want to run Pulsar or Kafka. Every topic is a stream, every subscription a consumer group on it.
+// Consumer groups spread entries over their consumers regardless of key, so unlike with Pulsar and Kafka, versions of
+// the same object may be processed out of order. That's fine for the main collection, as loading never replaces a
+// newer version with an older one.
+pub struct RedisBus {
+	cfg:         RedisConfig,
+	client:      Client,
+	// e.g. prod_testnet_objects_
+	stream_base: String,
+}
+
+impl RedisBus {
+	pub fn new(cfg: &AppConfig) -> anyhow::Result<Self> {
+		Ok(Self {
+			cfg:         cfg.redis.clone(),
+			client:      Client::open(cfg.redis.url.as_str())?,
+			stream_base: format!("{}_{}_{}_", cfg.env, cfg.net, cfg.mongo.collectionbase),
+		})
+	}
+}
+
+#[async_trait]
+impl MessageBus for RedisBus {
+	type Producer = RedisProducer;
+	type Consumer = RedisConsumer;
+
+	// streams are created on first use
+	async fn prepare_topic(&self, _topic: &str) -> anyhow::Result<()> {
+		Ok(())
+	}
+
+	async fn producer(&self, topic: &str) -> anyhow::Result<RedisProducer> {
+		Ok(RedisProducer {
+			con:    self.client.get_multiplexed_tokio_connection().await?,
+			stream: format!("{}{}", self.stream_base, topic),
+			maxlen: self.cfg.maxlen,
+		})
+	}
+
+	async fn consumer(&self, topic: &str, subscription: &str) -> anyhow::Result<RedisConsumer> {
+		let mut con = self.client.get_multiplexed_tokio_connection().await?;
+		let stream = format!("{}{}", self.stream_base, topic);
+		// new groups start at the beginning of the stream, like our Pulsar subscriptions
+		let created: redis::RedisResult<()> = con.xgroup_create_mkstream(&stream, subscription, "0").await;
+		if let Err(err) = created {
+			if err.code() != Some("BUSYGROUP") {
+				return Err(err.into())
+			}
+		}
+		Ok(RedisConsumer {
+			con,
+			dead_letter: format!("{}_dlt", stream),
+			stream,
+			group: subscription.to_string(),
+			consumer: format!("{}-{}", std::process::id(), NEXT_CONSUMER.fetch_add(1, Relaxed)),
+			cfg: self.cfg.clone(),
+		})
+	}
+}
+
+pub struct RedisProducer {
+	con:    MultiplexedConnection,
+	stream: String,
+	maxlen: Option<usize>,
+}
+
+#[async_trait]
+impl BusProducer for RedisProducer {
+	async fn send(&mut self, key: String, payload: Vec<u8>) -> anyhow::Result<()> {
+		let fields = [("k", key.as_bytes()), ("p", payload.as_slice())];
+		let _: String = match self.maxlen {
+			Some(maxlen) => self.con.xadd_maxlen(&self.stream, StreamMaxlen::Approx(maxlen), "*", &fields).await?,
+			None => self.con.xadd(&self.stream, "*", &fields).await?,
+		};
+		Ok(())
+	}
+}
+
+// Entries that aren't acked stay pending in their consumer group. Once they've been idle for `redis.claimidlems`,
+// any consumer of the group claims them (which covers nacked entries as well as those of crashed consumers).
+// After `redis.maxredeliveries` failed deliveries, they're moved to the dead-letter stream instead,
+// e.g. prod_testnet_objects_raw_livescan_dlt.
+pub struct RedisConsumer {
+	con:         MultiplexedConnection,
+	stream:      String,
+	dead_letter: String,
+	group:       String,
+	consumer:    String,
+	cfg:         RedisConfig,
+}
+
+impl RedisConsumer {
+	// claims entries other consumers (or we ourselves) failed to process in time
+	async fn claim_idle(&mut self, max: usize) -> anyhow::Result<Vec<StreamId>> {
+		// id, consumer, idle ms, deliveries
+		let idle: Vec<(String, String, u64, u64)> = redis::cmd("XPENDING")
+			.arg(&self.stream)
+			.arg(&self.group)
+			.arg("IDLE")
+			.arg(self.cfg.claimidlems)
+			.arg("-")
+			.arg("+")
+			.arg(max)
+			.query_async(&mut self.con)
+			.await?;
+		if idle.is_empty() {
+			return Ok(Vec::new())
+		}
+		let ids = idle.into_iter().map(|(id, ..)| id).collect::<Vec<_>>();
+		let claimed: StreamClaimReply =
+			self.con.xclaim(&self.stream, &self.group, &self.consumer, self.cfg.claimidlems, &ids).await?;
+		Ok(claimed.ids)
+	}
+}
+
+#[async_trait]
+impl BusConsumer for RedisConsumer {
+	// entry id
+	type Receipt = String;
+
+	async fn recv_batch(&mut self, max: usize, wait: Duration) -> anyhow::Result<Vec<(Vec<u8>, Self::Receipt)>> {
+		let mut entries = self.claim_idle(max).await?;
+		if entries.len() < max {
+			let opts = StreamReadOptions::default()
+				.group(&self.group, &self.consumer)
+				.count(max - entries.len())
+				// 0 would block forever
+				.block((wait.as_millis() as usize).max(1));
+			let read: Option<StreamReadReply> = self.con.xread_options(&[&self.stream], &[">"], &opts).await?;
+			entries.extend(read.into_iter().flat_map(|r| r.keys).flat_map(|k| k.ids));
+		}
+		Ok(entries.into_iter().map(|entry| (entry.get::<Vec<u8>>("p").unwrap_or_default(), entry.id)).collect())
+	}
+
+	async fn ack(&mut self, receipts: Vec<Self::Receipt>) -> anyhow::Result<()> {
+		if !receipts.is_empty() {
+			let _: usize = self.con.xack(&self.stream, &self.group, &receipts).await?;
+		}
+		Ok(())
+	}
+
+	// Nacked entries are just left pending, to be claimed again once idle, unless they've failed too often already.
+	async fn nack(&mut self, receipts: Vec<Self::Receipt>) -> anyhow::Result<usize> {
+		let mut num_dead = 0;
+		for id in receipts {
+			let pending: Vec<(String, String, u64, u64)> = redis::cmd("XPENDING")
+				.arg(&self.stream)
+				.arg(&self.group)
+				.arg(&id)
+				.arg(&id)
+				.arg(1)
+				.query_async(&mut self.con)
+				.await?;
+			if pending.first().map_or(true, |(_, _, _, deliveries)| *deliveries <= self.cfg.maxredeliveries as u64) {
+				continue
+			}
+			let entry: StreamRangeReply = self.con.xrange(&self.stream, &id, &id).await?;
+			if let Some(entry) = entry.ids.first() {
+				let key = entry.get::<Vec<u8>>("k").unwrap_or_default();
+				let payload = entry.get::<Vec<u8>>("p").unwrap_or_default();
+				let _: String = self.con.xadd(&self.dead_letter, "*", &[("k", key), ("p", payload)]).await?;
+			}
+			let _: usize = self.con.xack(&self.stream, &self.group, &[&id]).await?;
+			warn!(stream = self.stream, "DlqWarning: Entry failed too often, moved it to the dead-letter stream.");
+			num_dead += 1;
+		}
+		Ok(num_dead)
+	}
+}
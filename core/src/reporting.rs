@@ -0,0 +1,53 @@
+use sentry::{ClientInitGuard, ClientOptions, Level};
+use sui_types::base_types::{ObjectID, TransactionDigest};
+
+use crate::{_prelude::*, alerts, alerts::Stage, errors::EtlError};
+
+// Whatever we know about the item or request that failed, attached to the reported error.
+#[derive(Default)]
+pub struct ErrorContext<'a> {
+	pub object_id:  Option<ObjectID>,
+	pub digest:     Option<TransactionDigest>,
+	pub checkpoint: Option<u64>,
+	// name of the RPC provider the failed request went to
+	pub endpoint:   Option<&'a str>,
+}
+
+// Sends errors to Sentry if `sentry.dsn` is configured. Reporting stays active for as long as the returned guard is
+// kept alive, dropping it flushes any pending events.
+pub fn init_sentry(cfg: &AppConfig) -> Option<ClientInitGuard> {
+	let dsn = cfg.sentry.dsn.as_ref()?;
+	Some(sentry::init((dsn.as_str(), ClientOptions {
+		release: sentry::release_name!(),
+		environment: Some(cfg.env.clone().into()),
+		sample_rate: cfg.sentry.samplerate,
+		..Default::default()
+	})))
+}
+
+// To be called whenever a pipeline stage fails to process an item or request. Counts towards error-rate alerts, and
+// is reported to Sentry, if enabled.
+pub fn report_stage_error(stage: Stage, err: &EtlError, ctx: ErrorContext) {
+	alerts::record_error(stage);
+	sentry::with_scope(
+		|scope| {
+			scope.set_tag("stage", stage.name());
+			scope.set_tag("class", err.class());
+			let cfg = crate::conf::get_config_singleton();
+			scope.set_tag("net", &cfg.net);
+			if let Some(object_id) = ctx.object_id {
+				scope.set_extra("object_id", object_id.to_string().into());
+			}
+			if let Some(digest) = ctx.digest {
+				scope.set_extra("digest", digest.to_string().into());
+			}
+			if let Some(cp) = ctx.checkpoint {
+				scope.set_extra("checkpoint", cp.into());
+			}
+			if let Some(endpoint) = ctx.endpoint {
+				scope.set_tag("endpoint", endpoint);
+			}
+		},
+		|| sentry::capture_message(&format!("{} error: {}", stage.name(), err), Level::Error),
+	);
+}
@@ -0,0 +1,40 @@
+#[cfg(feature = "pulsar")]
+use pulsar::{Producer, TokioExecutor};
+
+use crate::{errors::EtlError, etl::ObjectItem};
+
+// Where items go that we failed to fetch object data for with a retryable error: the "retries" Pulsar topic, our
+// dead-letter store (see dlq.rs). The class of the error is kept as the `error_class` message property, so
+// `replay-dlq --error-class` can pick items by what went wrong. Builds without the `pulsar` feature have no
+// dead-letter store, so these items are logged and counted as ingest errors, and `send()` returns false: callers must
+// then make sure they're processed again, by not completing their checkpoint, or by nacking them.
+pub(crate) struct Retries {
+	#[cfg(feature = "pulsar")]
+	producer: Producer<TokioExecutor>,
+}
+
+impl Retries {
+	pub(crate) async fn new() -> anyhow::Result<Self> {
+		Ok(Self {
+			#[cfg(feature = "pulsar")]
+			producer: crate::pulsar::make_producer("retries").await?,
+		})
+	}
+
+	// Returns whether the item was stored to be retried later.
+	#[cfg(feature = "pulsar")]
+	pub(crate) async fn send(&mut self, item: ObjectItem, err: &EtlError) -> bool {
+		use pulsar::SerializeMessage;
+		let mut msg = ObjectItem::serialize_message(item).expect("ExtractionError: failed to serialize retry message!");
+		msg.properties.insert("error_class".to_string(), err.class().to_string());
+		self.producer.send(msg).await.expect("ExtractionError: failed to send retry message to pulsar!");
+		true
+	}
+
+	#[cfg(not(feature = "pulsar"))]
+	pub(crate) async fn send(&mut self, item: ObjectItem, err: &EtlError) -> bool {
+		tracing::warn!(object_id = ?item.id, error = %err, "ExtractionError: Failed processing object, and there's no dead-letter store to retry it from.");
+		crate::influx::write_metric_ingest_error(item.id.to_string(), "no_dead_letter_store".to_string()).await;
+		false
+	}
+}
@@ -0,0 +1,104 @@
+use bson::{doc, Document};
+use mongodb::options::{FindOneOptions, FindOptions};
+
+use crate::{
+	_prelude::*,
+	cli::RewindArgs,
+	etl::checkpoint_of_transaction,
+	history::{history_collection_name, materialize_dependents, object_at},
+	idempotency::forget_applied,
+	mongo::{mongo_checkpoint_stop, mongo_collection_name},
+	routing::{collection_for, routed_collections},
+	utils::{content_hash, object_update},
+};
+
+// Entrypoint for the `rewind` subcommand.
+// Our cursor is the set of completed checkpoints, so rewinding to checkpoint N means forgetting about every completed
+// checkpoint > N, which makes the next run re-process all of them. If we had a stop marker above N, we move it down
+// to N, so the re-processing doesn't turn into a full backfill down to genesis.
+// With `--invalidate` (requires history.enabled) we also remove every object version from checkpoints > N, and reset
+// the affected objects to their latest version at or before N (or remove them if they didn't exist yet).
+// Versions ingested via polling don't know their checkpoint and are therefore left untouched.
+// The removed versions are forgotten by the idempotency guard as well, so they're loaded again on re-processing.
+pub async fn run_rewind(cfg: &AppConfig, args: RewindArgs) -> anyhow::Result<()> {
+	let cp = match (args.checkpoint, &args.digest) {
+		(Some(cp), None) => cp,
+		// we want to re-process the checkpoint containing this transaction, too
+		(None, Some(digest)) => checkpoint_of_transaction(cfg, digest).await?.saturating_sub(1),
+		_ => return Err(anyhow!("exactly one of --checkpoint or --digest is required")),
+	};
+	if args.invalidate && !cfg.history.enabled {
+		return Err(anyhow!("--invalidate requires history.enabled, as we can't know which objects changed after checkpoint {} otherwise", cp))
+	}
+
+	let db = cfg.mongo.client(&cfg.backfill.mongo).await?;
+	let checkpoints = db.collection::<Document>(&mongo_collection_name(cfg, "_checkpoints"));
+	let had_stop_above = checkpoints.find_one(doc! { "_id": { "$gt": cp as i64 }, "stop": true }, None).await?.is_some();
+	let res = checkpoints.delete_many(doc! { "_id": { "$gt": cp as i64 } }, None).await?;
+	if had_stop_above {
+		mongo_checkpoint_stop(cfg, &db, cp).await?;
+	}
+	println!("rewound to checkpoint {}: forgot about {} completed checkpoints", cp, res.deleted_count);
+
+	if args.invalidate {
+		// the objects collection first, then all routed collections, see routing.rs
+		let mut collections = vec![mongo_collection_name(cfg, "")];
+		collections.extend(routed_collections(cfg).await);
+		let history = db.collection::<Document>(&history_collection_name(cfg));
+		let newer = doc! { "cp": { "$gt": cp as i64 } };
+		let affected = history.distinct("object_id", newer.clone(), None).await?;
+		for id in &affected {
+			let id = id.as_str().ok_or_else(|| anyhow!("unexpected object_id in history: {:?}", id))?;
+			let previous = history
+				.find_one(
+					doc! { "object_id": id, "cp": { "$lte": cp as i64, "$gt": 0 } },
+					FindOneOptions::builder().sort(doc! { "version_": -1 }).build(),
+				)
+				.await?;
+			// the object is removed from all collections, and its restored version is stored in the one it's routed to,
+			// as a fresh document built the same way the load step builds them, see `utils::object_update()`. A wrap
+			// mark can only be from after checkpoint `cp` (a wrapped object has no newer versions until it's unwrapped,
+			// which removes the mark), so it goes away, too.
+			for collection in &collections {
+				db.collection::<Document>(collection).delete_one(doc! { "_id": id }, None).await?;
+			}
+			if let Some(v) = previous {
+				let deletion = v.get_bool("deleted").unwrap_or(false);
+				let object = if deletion {
+					Document::new()
+				} else {
+					object_at(&history, &v).await?.ok_or_else(|| anyhow!("no object data for {}", id))?
+				};
+				let hash = if cfg.contenthash.enabled && !deletion { content_hash(&object) } else { None };
+				let routed = if deletion { None } else { collection_for(cfg, Some(&object)).await };
+				let collection = routed.unwrap_or_else(|| collections[0].clone());
+				let update =
+					object_update(id, v.get_str("version")?, deletion, &object, hash.as_deref(), cfg.network());
+				db.run_command(doc! { "update": collection, "updates": [update] }, None).await?;
+			}
+		}
+		if cfg.history.deltas {
+			let removed = history.distinct("_id", newer.clone(), None).await?;
+			materialize_dependents(&history, &removed).await?;
+		}
+		// the removed versions would otherwise be skipped as already applied when their checkpoints are processed
+		// again, see idempotency.rs
+		let removed = history
+			.find(newer.clone(), FindOptions::builder().projection(doc! { "object_id": 1, "version_": 1 }).build())
+			.await?
+			.try_collect::<Vec<_>>()
+			.await?
+			.into_iter()
+			.filter_map(|v| Some((v.get_str("object_id").ok()?.to_string(), v.get_i64("version_").ok()?)));
+		let forgotten = forget_applied(cfg, &db, removed).await?;
+		let res = history.delete_many(newer, None).await?;
+		println!(
+			"invalidated {} object versions of {} objects ({} marked as applied)",
+			res.deleted_count,
+			affected.len(),
+			forgotten
+		);
+	}
+
+	Ok(())
+}
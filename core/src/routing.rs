@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+
+use bson::{doc, Bson, Document};
+use futures::future::try_join_all;
+use mongodb::Database;
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, OnceCell};
+
+use crate::{
+	_prelude::*,
+	bus::spawn_publisher,
+	conf::{RouteRule, WebhookRule},
+	etl::ObjectItem,
+	mongo::mongo_collection_name,
+	types::{glob_matches, TypeAliases, TypePattern},
+};
+
+// `routing.rules` decide where objects go besides (or, for collections, instead of) the objects collection: each rule
+// matches on type, package and owner, and sends matching objects to a collection, a topic on `bus` and / or a webhook.
+// - collection: the first matching rule's collection (a suffix, e.g. `deepbook` for prod_testnet_objects_deepbook)
+//   stores the object instead of the objects collection. Only type and package can be matched on, so an object always
+//   stays in the same collection. Everything derived from it is stored as usual.
+// - topic: every matching rule's topic receives the object, as JSON keyed by object id.
+// - webhook: every matching rule's URL receives the object, just like with `webhooks.rules`.
+// Deletions don't carry object data, so they only match rules without any pattern. They're stored in the objects
+// collection as usual, and also applied to every routed collection, without creating objects there.
+// `webhooks.rules` match the same way.
+pub struct Matcher {
+	ty:      Option<TypePattern>,
+	// canonical full-length id, see `TypeAliases`
+	package: Option<String>,
+	owner:   Option<String>,
+	aliases: TypeAliases,
+}
+
+impl Matcher {
+	pub fn new(ty: Option<&str>, package: Option<&str>, owner: Option<&str>, aliases: &TypeAliases) -> anyhow::Result<Self> {
+		let package = package
+			.map(|package| aliases.resolve(package).ok_or_else(|| anyhow!("invalid package id in routing rule: {}", package)))
+			.transpose()?;
+		Ok(Self {
+			ty: ty.map(|pattern| TypePattern::new(pattern, aliases)),
+			package,
+			owner: owner.map(String::from),
+			aliases: aliases.clone(),
+		})
+	}
+
+	fn from_rule(rule: &RouteRule, aliases: &TypeAliases) -> anyhow::Result<Self> {
+		Self::new(rule.type_.as_deref(), rule.package.as_deref(), rule.owner.as_deref(), aliases)
+	}
+
+	pub fn matches(&self, object: Option<&Document>) -> bool {
+		if self.ty.is_none() && self.package.is_none() && self.owner.is_none() {
+			return true
+		}
+		let Some(object) = object else { return false };
+		let ty = object.get_str("type").ok();
+		if let Some(pattern) = &self.ty {
+			if !ty.map_or(false, |ty| pattern.matches(ty)) {
+				return false
+			}
+		}
+		if let Some(package) = &self.package {
+			let object_package = ty.and_then(|ty| ty.split("::").next()).and_then(|p| self.aliases.resolve(p));
+			if object_package.as_ref() != Some(package) {
+				return false
+			}
+		}
+		if let Some(pattern) = &self.owner {
+			let owner = object
+				.get_document("owner")
+				.ok()
+				.and_then(|o| o.get_str("AddressOwner").or_else(|_| o.get_str("ObjectOwner")).ok());
+			if !owner.map_or(false, |owner| glob_matches(pattern, owner)) {
+				return false
+			}
+		}
+		true
+	}
+}
+
+// What topics and webhooks receive for an object change.
+pub(crate) fn snapshot(item: &ObjectItem, object: Option<&Document>, network: &str) -> Value {
+	json!({
+		"id": item.id.to_string(),
+		"version": item.version.value(),
+		"checkpoint": item.cp,
+		"deleted": item.deletion,
+		"network": network,
+		"object": object.map(|o| Bson::Document(o.clone()).into_relaxed_extjson()),
+	})
+}
+
+// Rules with a collection, and the full name of their collection.
+static COLLECTIONS: OnceCell<Vec<(Matcher, String)>> = OnceCell::const_new();
+
+async fn collection_routes(cfg: &AppConfig) -> &'static [(Matcher, String)] {
+	COLLECTIONS
+		.get_or_init(|| async {
+			// already validated by `init()`
+			let aliases = cfg.types.aliases().unwrap_or_default();
+			cfg.routing
+				.rules
+				.iter()
+				.filter_map(|rule| {
+					let collection = rule.collection.as_ref()?;
+					let matcher = Matcher::from_rule(rule, &aliases).expect("invalid routing rule");
+					Some((matcher, mongo_collection_name(cfg, &format!("_{}", collection))))
+				})
+				.collect()
+		})
+		.await
+}
+
+// The collections of all collection routes.
+pub(crate) async fn routed_collections(cfg: &AppConfig) -> Vec<String> {
+	collection_routes(cfg).await.iter().map(|(_, collection)| collection.clone()).collect()
+}
+
+// The collection to store an object in, None for the objects collection. Deletions always go to the objects
+// collection.
+pub(crate) async fn collection_for(cfg: &AppConfig, object: Option<&Document>) -> Option<String> {
+	let object = object?;
+	let routes = collection_routes(cfg).await;
+	routes.iter().find(|(matcher, _)| matcher.matches(Some(object))).map(|(_, collection)| collection.clone())
+}
+
+// Rules with a topic, and the queue of their topic's publisher. Only set up by `spawn_topic_routes()`.
+static TOPICS: OnceCell<Vec<(Matcher, mpsc::Sender<(String, Vec<u8>)>)>> = OnceCell::const_new();
+
+pub async fn spawn_topic_routes(cfg: &AppConfig) -> anyhow::Result<()> {
+	let aliases = cfg.types.aliases()?;
+	let mut publishers = HashMap::new();
+	let mut routes = Vec::new();
+	for rule in &cfg.routing.rules {
+		// also validates all other rules, before they're needed
+		let matcher = Matcher::from_rule(rule, &aliases)?;
+		let Some(topic) = &rule.topic else { continue };
+		// rules sharing a topic share its publisher
+		let tx = match publishers.get(topic) {
+			Some(tx) => tx.clone(),
+			None => {
+				let tx = spawn_publisher(cfg, topic, cfg.routing.queuesize).await?;
+				publishers.insert(topic.clone(), tx.clone());
+				tx
+			}
+		};
+		routes.push((matcher, tx));
+	}
+	if routes.is_empty() {
+		return Ok(())
+	}
+	TOPICS.set(routes).map_err(|_| anyhow!("topic routes have already been set up"))
+}
+
+// To be called for every item after it has been loaded successfully, with `objects` as fetched, publishes it to the
+// topics of all matching rules.
+pub async fn publish(cfg: &AppConfig, items: &[ObjectItem], objects: &[Document]) {
+	let Some(routes) = TOPICS.get() else { return };
+	for (item, object) in items.iter().zip(objects) {
+		let object = (!item.deletion).then_some(object);
+		let payload = serde_json::to_vec(&snapshot(item, object, cfg.network())).unwrap();
+		for (_, tx) in routes.iter().filter(|(matcher, _)| matcher.matches(object)) {
+			if tx.send((item.id.to_string(), payload.clone())).await.is_err() {
+				warn!(object_id = ?item.id, "RoutingWarning: Topic publisher stopped, dropping change.");
+			}
+		}
+	}
+}
+
+// Rules with a webhook, as webhook rules without patterns of their own, and their matchers.
+pub(crate) fn webhook_routes(cfg: &AppConfig) -> anyhow::Result<Vec<(WebhookRule, Matcher)>> {
+	let aliases = cfg.types.aliases()?;
+	cfg.routing
+		.rules
+		.iter()
+		.filter_map(|rule| {
+			let url = rule.webhook.clone()?;
+			let webhook = WebhookRule { url, type_: None, owner: None, secret: rule.secret.clone() };
+			Some(Matcher::from_rule(rule, &aliases).map(|matcher| (webhook, matcher)))
+		})
+		.collect()
+}
+
+// Runs `updates` (one per item of `items`, see `utils::object_update()`) against the collection each of them is routed
+// to, `collections`, and deletions against every routed collection, too. The commands for different collections run
+// concurrently, so a slow collection doesn't hold up the others. Returns the combined result, as if it was a single
+// update command, with the indices in `upserted` referring to `updates`.
+pub(crate) async fn run_updates(
+	cfg: &AppConfig,
+	db: &Database,
+	items: &[ObjectItem],
+	updates: Vec<Document>,
+	collections: &[String],
+) -> mongodb::error::Result<Document> {
+	// they only update objects that are there already, so they're not part of the result
+	let deletions = items
+		.iter()
+		.zip(&updates)
+		.filter(|(item, _)| item.deletion)
+		.map(|(_, update)| {
+			let mut update = update.clone();
+			update.insert("upsert", false);
+			update
+		})
+		.collect::<Vec<_>>();
+	let mut groups = BTreeMap::<&str, (Vec<usize>, Vec<Document>)>::new();
+	for (i, (update, collection)) in updates.into_iter().zip(collections).enumerate() {
+		let (indices, updates) = groups.entry(collection.as_str()).or_default();
+		indices.push(i);
+		updates.push(update);
+	}
+	let routed = if deletions.is_empty() { Vec::new() } else { routed_collections(cfg).await };
+	let deleting = try_join_all(
+		routed.iter().map(|collection| db.run_command(doc! { "update": collection, "updates": deletions.clone() }, None)),
+	);
+	let updating = try_join_all(groups.into_iter().map(|(collection, (indices, updates))| async move {
+		let res = db.run_command(doc! { "update": collection, "updates": updates }, None).await?;
+		Ok::<_, mongodb::error::Error>((indices, res))
+	}));
+	let (results, _) = tokio::try_join!(updating, deleting)?;
+	let (mut n, mut modified, mut upserted, mut write_errors) = (0, 0, Vec::new(), Vec::new());
+	for (indices, res) in results {
+		n += res.get_i32("n").unwrap_or_default();
+		modified += res.get_i32("nModified").unwrap_or_default();
+		for u in res.get_array("upserted").into_iter().flatten() {
+			let Some(mut u) = u.as_document().cloned() else { continue };
+			if let Ok(index) = u.get_i32("index") {
+				u.insert("index", indices[index as usize] as i32);
+			}
+			upserted.push(u);
+		}
+		write_errors.extend(res.get_array("writeErrors").into_iter().flatten().cloned());
+	}
+	Ok(doc! { "n": n, "nModified": modified, "upserted": upserted, "writeErrors": write_errors })
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_routing_matcher() {
+		let none = TypeAliases::default();
+		let aliases = TypeAliases::new(&[("0xb".to_string(), "0xa".to_string())].into_iter().collect()).unwrap();
+		let object = |ty: &str, owner: Document| doc! { "type": ty, "owner": owner };
+		let coin = object("0x2::coin::Coin<0x2::sui::SUI>", doc! { "AddressOwner": "0x00ab" });
+		let pool = object("0xa::pool::Pool<0x2::sui::SUI>", doc! { "ObjectOwner": "0x00cd" });
+		let shared = object("0xa::pool::Pool<0x2::sui::SUI>", doc! { "Shared": { "initial_shared_version": 1 } });
+
+		// type
+		let coins = Matcher::new(Some("0x2::coin::Coin<*>"), None, None, &none).unwrap();
+		assert!(coins.matches(Some(&coin)) && !coins.matches(Some(&pool)));
+		// package, given as the id of a later version of the package
+		let package = Matcher::new(None, Some("0xb"), None, &aliases).unwrap();
+		assert!(package.matches(Some(&pool)) && package.matches(Some(&shared)) && !package.matches(Some(&coin)));
+		// without the alias, 0xb is a package of its own
+		assert!(!Matcher::new(None, Some("0xb"), None, &none).unwrap().matches(Some(&pool)));
+		assert!(Matcher::new(None, Some("0xz"), None, &none).is_err());
+		// owner glob, on address and object owners, never on shared objects
+		let owner = Matcher::new(None, None, Some("0x00*"), &none).unwrap();
+		assert!(owner.matches(Some(&coin)) && owner.matches(Some(&pool)) && !owner.matches(Some(&shared)));
+		assert!(!Matcher::new(None, None, Some("0x00ab"), &none).unwrap().matches(Some(&pool)));
+		// all of them need to match
+		let both = Matcher::new(Some("0xa::pool::*"), None, Some("0x00ab"), &none).unwrap();
+		assert!(!both.matches(Some(&pool)) && !both.matches(Some(&coin)));
+
+		// deletions (no object) only match rules without any pattern
+		assert!(Matcher::new(None, None, None, &none).unwrap().matches(None));
+		assert!(Matcher::new(None, None, None, &none).unwrap().matches(Some(&coin)));
+		for matcher in [coins, package, owner, both] {
+			assert!(!matcher.matches(None));
+		}
+	}
+}
@@ -0,0 +1,114 @@
+use std::{collections::BTreeMap, net::SocketAddr};
+
+use hyper::{
+	body::to_bytes,
+	header::{HeaderName, HeaderValue, CONTENT_LENGTH, HOST, TRANSFER_ENCODING},
+	service::{make_service_fn, service_fn},
+	Body, HeaderMap, Request, Response, Server, StatusCode,
+};
+
+use crate::{
+	_prelude::*,
+	conf::{BasicAuthConfig, RpcProviderConfig, TlsConfig},
+};
+
+// Most providers only serve authenticated requests, via an API key in a header or basic auth, and some networks need
+// their own TLS settings (see `sui.<net>` in the config). sui-sdk, at the version we're pinned to, can't send any
+// headers of our own, nor be configured for TLS, so we reach those providers through a forwarder on localhost instead,
+// which passes every request on with the provider's credentials added, over a connection with its TLS settings.
+// There's one forwarder per provider, shared by all clients of it.
+static FORWARDERS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+// The URL to connect to for `provider`: a forwarder's if it needs authentication or TLS settings, its own otherwise.
+pub(crate) fn url(provider: &RpcProviderConfig) -> anyhow::Result<String> {
+	if !provider.forwarded() {
+		return Ok(provider.url.clone())
+	}
+	let key = format!("{}@{}", provider.name, provider.url);
+	if let Some(url) = FORWARDERS.lock().unwrap().get(&key) {
+		return Ok(url.clone())
+	}
+	let url =
+		spawn_forwarder(provider).with_context(|| format!("cannot set up authentication for {}", provider.name))?;
+	Ok(FORWARDERS.lock().unwrap().entry(key).or_insert(url).clone())
+}
+
+fn spawn_forwarder(provider: &RpcProviderConfig) -> anyhow::Result<String> {
+	let mut headers = HeaderMap::new();
+	for (name, value) in &provider.headers {
+		headers.insert(HeaderName::from_str(name)?, HeaderValue::from_str(value)?);
+	}
+	let client = client(provider.tls.as_ref())?;
+	let upstream = Arc::new((provider.url.clone(), headers, provider.basicauth.clone()));
+	let make_service = make_service_fn(move |_| {
+		let (client, upstream) = (client.clone(), upstream.clone());
+		async move { Ok::<_, hyper::Error>(service_fn(move |req| forward(client.clone(), upstream.clone(), req))) }
+	});
+	let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?.serve(make_service);
+	let url = format!("http://{}", server.local_addr());
+	let name = provider.name.clone();
+	tokio::spawn(async move {
+		if let Err(err) = server.await {
+			error!(provider = name, error = ?err, "RpcError: Authenticating forwarder stopped.");
+		}
+	});
+	Ok(url)
+}
+
+fn client(tls: Option<&TlsConfig>) -> anyhow::Result<reqwest::Client> {
+	let Some(tls) = tls else { return Ok(reqwest::Client::new()) };
+	let mut builder = reqwest::Client::builder().use_rustls_tls().danger_accept_invalid_certs(tls.allowinvalidcerts);
+	if let Some(path) = &tls.cafile {
+		let pem = std::fs::read(path).with_context(|| format!("cannot read {}", path))?;
+		for cert in reqwest::Certificate::from_pem_bundle(&pem)? {
+			builder = builder.add_root_certificate(cert);
+		}
+	}
+	if let Some(path) = &tls.certfile {
+		let pem = std::fs::read(path).with_context(|| format!("cannot read {}", path))?;
+		builder = builder.identity(reqwest::Identity::from_pem(&pem)?);
+	}
+	Ok(builder.build()?)
+}
+
+// the provider's URL, headers and basic auth credentials
+type Upstream = (String, HeaderMap, Option<BasicAuthConfig>);
+
+async fn forward(
+	client: reqwest::Client,
+	upstream: Arc<Upstream>,
+	req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+	let (url, auth_headers, basic_auth) = upstream.as_ref();
+	let mut headers = req.headers().clone();
+	headers.remove(HOST);
+	headers.remove(CONTENT_LENGTH);
+	headers.extend(auth_headers.clone());
+	let body = to_bytes(req.into_body()).await?;
+	let mut request = client.post(url).headers(headers).body(body);
+	if let Some(auth) = basic_auth {
+		request = request.basic_auth(&auth.username, Some(&auth.password));
+	}
+	// errors talking to the provider are passed on as such, so the client sees them like any other transport error
+	let response = match request.send().await {
+		Ok(response) => response,
+		Err(err) => return Ok(bad_gateway(err)),
+	};
+	let mut forwarded = Response::builder().status(response.status());
+	for (name, value) in response.headers() {
+		// we pass on the body as a whole
+		if name != CONTENT_LENGTH && name != TRANSFER_ENCODING {
+			forwarded = forwarded.header(name, value);
+		}
+	}
+	match response.bytes().await {
+		Ok(body) => Ok(forwarded.body(Body::from(body)).unwrap()),
+		Err(err) => Ok(bad_gateway(err)),
+	}
+}
+
+fn bad_gateway(err: reqwest::Error) -> Response<Body> {
+	let mut response = Response::new(Body::from(err.to_string()));
+	*response.status_mut() = StatusCode::BAD_GATEWAY;
+	response
+}
@@ -0,0 +1,56 @@
+use serde::{de::DeserializeOwned, Serialize};
+use sui_sdk::error::{Error as SuiRpcError, SuiRpcResult};
+
+use crate::{_prelude::*, conf::APPCONFIG, utils::fixture_file_name};
+
+// Recorded read API responses, to run the pipeline against a fixed set of inputs without any RPC provider, e.g. for
+// reproducing issues or testing extraction, transform fallbacks and load batching end-to-end against a throwaway
+// MongoDB. With `rpcfixtures.mode: record`, every successful read API call of the ClientPool is written to
+// `rpcfixtures.dir` as a JSON file, named after the method and a hash of its arguments. With `mode: replay`, the
+// ClientPool answers every read API call from these files instead, and calls that weren't recorded fail.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+	Off,
+	Record,
+	Replay,
+}
+
+pub fn mode() -> Mode {
+	match APPCONFIG.get().map(|cfg| cfg.rpcfixtures.mode.as_str()) {
+		Some("record") => Mode::Record,
+		Some("replay") => Mode::Replay,
+		_ => Mode::Off,
+	}
+}
+
+// Name of the file a call of `method` with `args` is recorded to / replayed from, with `args` given as a tuple of all
+// arguments of the ClientPool method, e.g. `&(&object_id, &options)`. For naming hand-written fixtures, see core/tests.
+pub fn file_name(method: &str, args: &impl std::fmt::Debug) -> String {
+	fixture_file_name(method, &format!("{:?}", args))
+}
+
+fn fixture_path(method: &str, args: &str) -> std::path::PathBuf {
+	let dir = &APPCONFIG.get().expect("fixtures are only used with a config").rpcfixtures.dir;
+	std::path::Path::new(dir).join(fixture_file_name(method, args))
+}
+
+// Failing to record is not fatal, the response is still returned to the caller.
+pub fn record<T: Serialize>(method: &str, args: &str, res: &SuiRpcResult<T>) {
+	let Ok(res) = res else { return };
+	let path = fixture_path(method, args);
+	let written = serde_json::to_vec_pretty(res).map_err(anyhow::Error::from).and_then(|json| {
+		std::fs::create_dir_all(path.parent().unwrap())?;
+		Ok(std::fs::write(&path, json)?)
+	});
+	if let Err(err) = written {
+		warn!(error = ?err, "FixturesWarning: Failed recording {} response to {}.", method, path.display());
+	}
+}
+
+pub fn replay<T: DeserializeOwned>(method: &str, args: &str) -> SuiRpcResult<T> {
+	let path = fixture_path(method, args);
+	let json = std::fs::read(&path)
+		.map_err(|e| SuiRpcError::DataError(format!("no recorded {} response at {}: {}", method, path.display(), e)))?;
+	serde_json::from_slice(&json)
+		.map_err(|e| SuiRpcError::DataError(format!("invalid recorded {} response at {}: {}", method, path.display(), e)))
+}
@@ -0,0 +1,122 @@
+use serde_json::Value;
+
+use crate::{_prelude::*, conf::VaultConfig};
+
+// Credentials don't have to live in config.yaml or plain APP_ env vars. Each of them can be read from a file instead
+// (`mongo.urifile`, `influx.tokenfile`, `pulsar.tokenfile`, e.g. mounted Kubernetes / Docker secrets), or be given as
+// a reference to a secret manager, looked up once on startup:
+// - `vault:<path>#<key>`: `key` of the secret at `path` in HashiCorp Vault, e.g. `vault:secret/data/huracan#mongouri`
+//   (KV v1 and v2 both work), see `secrets.vault`.
+// - `awssm:<secret id>[#<key>]`: a secret from AWS Secrets Manager, as is or, with `key`, one key of its JSON value.
+//   Needs the `aws` feature, and the usual AWS credentials and region (env vars, profile or instance role).
+// References are supported for `mongo.uri`, `influx.token`, `pulsar.token`, `sentry.dsn`, and the headers and basic
+// auth passwords of RPC providers.
+pub(crate) async fn load_secrets(cfg: &mut AppConfig) -> anyhow::Result<()> {
+	if let Some(path) = &cfg.mongo.urifile {
+		cfg.mongo.uri = read_file(path).context("cannot read mongo.urifile")?;
+	}
+	if let Some(path) = &cfg.influx.tokenfile {
+		cfg.influx.token = read_file(path).context("cannot read influx.tokenfile")?;
+	}
+	if let Some(path) = &cfg.pulsar.tokenfile {
+		cfg.pulsar.token = Some(read_file(path).context("cannot read pulsar.tokenfile")?);
+	}
+
+	let vault = cfg.secrets.vault.clone();
+	cfg.mongo.uri = resolve(&vault, &cfg.mongo.uri).await.context("cannot resolve mongo.uri")?;
+	cfg.influx.token = resolve(&vault, &cfg.influx.token).await.context("cannot resolve influx.token")?;
+	if let Some(token) = &cfg.pulsar.token {
+		cfg.pulsar.token = Some(resolve(&vault, token).await.context("cannot resolve pulsar.token")?);
+	}
+	if let Some(dsn) = &cfg.sentry.dsn {
+		cfg.sentry.dsn = Some(resolve(&vault, dsn).await.context("cannot resolve sentry.dsn")?);
+	}
+	for provider in cfg.sui.testnet.iter_mut().chain(&mut cfg.sui.mainnet).chain(&mut cfg.sui.localnet) {
+		let name = provider.name.clone();
+		let context = || format!("cannot resolve credentials of RPC provider {}", name);
+		for value in provider.headers.values_mut() {
+			*value = resolve(&vault, value).await.with_context(context)?;
+		}
+		if let Some(auth) = &mut provider.basicauth {
+			auth.password = resolve(&vault, &auth.password).await.with_context(context)?;
+		}
+	}
+	Ok(())
+}
+
+// Secret files usually end with a newline, which is never part of the secret.
+fn read_file(path: &str) -> anyhow::Result<String> {
+	let content = std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path))?;
+	Ok(content.trim_end_matches(|c| c == '\n' || c == '\r').to_string())
+}
+
+// Values that aren't references are returned as they are.
+async fn resolve(vault: &VaultConfig, value: &str) -> anyhow::Result<String> {
+	if let Some(reference) = value.strip_prefix("vault:") {
+		let (path, key) =
+			reference.split_once('#').ok_or_else(|| anyhow!("expected vault:<path>#<key>, got {}", value))?;
+		return vault_secret(vault, path, key).await
+	}
+	if let Some(reference) = value.strip_prefix("awssm:") {
+		let (id, key) = match reference.split_once('#') {
+			Some((id, key)) => (id, Some(key)),
+			None => (reference, None),
+		};
+		return aws_secret(id, key).await
+	}
+	Ok(value.to_string())
+}
+
+async fn vault_secret(cfg: &VaultConfig, path: &str, key: &str) -> anyhow::Result<String> {
+	let addr = cfg
+		.addr
+		.clone()
+		.or_else(|| std::env::var("VAULT_ADDR").ok())
+		.ok_or_else(|| anyhow!("secrets.vault.addr (or VAULT_ADDR) is needed for vault: references"))?;
+	let token = match (&cfg.token, &cfg.tokenfile) {
+		(Some(token), _) => token.clone(),
+		(None, Some(path)) => read_file(path).context("cannot read secrets.vault.tokenfile")?,
+		(None, None) => std::env::var("VAULT_TOKEN")
+			.map_err(|_| anyhow!("secrets.vault.token (or tokenfile, or VAULT_TOKEN) is needed for vault: references"))?,
+	};
+	let url = format!("{}/v1/{}", addr.trim_end_matches('/'), path.trim_start_matches('/'));
+	let response: Value = reqwest::Client::new()
+		.get(&url)
+		.header("X-Vault-Token", token)
+		.send()
+		.await?
+		.error_for_status()
+		.with_context(|| format!("cannot read {} from vault", path))?
+		.json()
+		.await?;
+	// KV v2 wraps the secret's data together with its metadata, KV v1 returns the data as is
+	let data = &response["data"];
+	let data = if data["metadata"].is_object() { &data["data"] } else { data };
+	data[key]
+		.as_str()
+		.map(|secret| secret.to_string())
+		.ok_or_else(|| anyhow!("vault secret {} has no string value for {}", path, key))
+}
+
+#[cfg(feature = "aws")]
+async fn aws_secret(id: &str, key: Option<&str>) -> anyhow::Result<String> {
+	let client = aws_sdk_secretsmanager::Client::new(&aws_config::load_from_env().await);
+	let output = client
+		.get_secret_value()
+		.secret_id(id)
+		.send()
+		.await
+		.with_context(|| format!("cannot get {} from AWS Secrets Manager", id))?;
+	let secret = output.secret_string().ok_or_else(|| anyhow!("AWS secret {} has no string value", id))?;
+	let Some(key) = key else { return Ok(secret.to_string()) };
+	let value: Value = serde_json::from_str(secret).with_context(|| format!("AWS secret {} isn't JSON", id))?;
+	value[key]
+		.as_str()
+		.map(|secret| secret.to_string())
+		.ok_or_else(|| anyhow!("AWS secret {} has no string value for {}", id, key))
+}
+
+#[cfg(not(feature = "aws"))]
+async fn aws_secret(id: &str, _key: Option<&str>) -> anyhow::Result<String> {
+	Err(anyhow!("cannot get {} from AWS Secrets Manager, as this build doesn't include the aws feature", id))
+}
@@ -0,0 +1,119 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{options::UpdateOptions, Database};
+
+use crate::{_prelude::*, client::ClientPool, migrations::META_COLLECTION, mongo::mongo_collection_name};
+
+// Chain identifiers (first 4 bytes of the genesis checkpoint digest) of the public networks.
+// Testnet gets a new one whenever it's wiped, so a mismatch there is only a warning.
+const MAINNET_CHAIN_ID: &str = "35834a8a";
+const TESTNET_CHAIN_ID: &str = "4c78adac";
+
+// Verifies that every configured RPC provider serves the configured network, and that we can write to MongoDB,
+// before any of the pipeline is started. Fails with the specific cause instead of erroring deep inside a stream.
+pub async fn run_self_check(cfg: &AppConfig) -> anyhow::Result<()> {
+	let mut summary = Vec::new();
+
+	for provider in cfg.rpc_providers() {
+		let mut sui = ClientPool::new(vec![provider.clone()])
+			.await
+			.with_context(|| format!("SelfCheck: cannot connect to RPC provider {} ({})", provider.name, provider.url))?;
+		let chain_id = sui
+			.get_chain_identifier()
+			.await
+			.with_context(|| format!("SelfCheck: cannot query chain identifier from RPC provider {}", provider.name))?;
+		check_chain_id(cfg, &format!("RPC provider {}", provider.name), &chain_id)?;
+		let start = Instant::now();
+		let latest = sui
+			.get_latest_checkpoint_sequence_number()
+			.await
+			.with_context(|| format!("SelfCheck: cannot query latest checkpoint from RPC provider {}", provider.name))?;
+		summary.push(format!(
+			"rpc {}: chain {}, latest checkpoint {}, {}ms",
+			provider.name,
+			chain_id,
+			latest,
+			start.elapsed().as_millis()
+		));
+	}
+
+	// insert + delete a marker document, to verify we're authenticated and allowed to write
+	let db = cfg.mongo.client(&cfg.livescan.mongo).await.context("SelfCheck: invalid MongoDB configuration")?;
+	let coll = db.collection::<bson::Document>(&mongo_collection_name(cfg, "_selfcheck"));
+	let start = Instant::now();
+	let res = coll
+		.insert_one(doc! { "ts": bson::DateTime::now() }, None)
+		.await
+		.context("SelfCheck: cannot write to MongoDB, check credentials and write permissions")?;
+	coll.delete_one(doc! { "_id": res.inserted_id }, None)
+		.await
+		.context("SelfCheck: cannot delete from MongoDB, check write permissions")?;
+	summary.push(format!("mongo {}: writable, {}ms", cfg.mongo.db, start.elapsed().as_millis()));
+
+	for line in summary {
+		println!("self-check: {}", line);
+	}
+	Ok(())
+}
+
+// `source` names who told us `chain_id`, for error messages.
+fn check_chain_id(cfg: &AppConfig, source: &str, chain_id: &str) -> anyhow::Result<()> {
+	if let Some(expected) = &cfg.chainid {
+		if chain_id != expected {
+			return Err(anyhow!(
+				"SelfCheck: {} serves chain {}, which doesn't match configured chainid {}",
+				source,
+				chain_id,
+				expected
+			))
+		}
+		return Ok(())
+	}
+	match (cfg.net.as_str(), chain_id) {
+		("mainnet", MAINNET_CHAIN_ID) | ("testnet", TESTNET_CHAIN_ID) => {}
+		("mainnet", _) | (_, MAINNET_CHAIN_ID) => {
+			return Err(anyhow!(
+				"SelfCheck: {} serves chain {}, which doesn't match configured net {}",
+				source,
+				chain_id,
+				cfg.net
+			))
+		}
+		_ => warn!(
+			"SelfCheck: unknown chain {} for net {} at {}, make sure it's the right one",
+			chain_id, cfg.net, source
+		),
+	}
+	Ok(())
+}
+
+// Unlike the self-check, run on every start: the chain our RPC providers serve must be the configured one, and the
+// one our collections have been indexed from so far (recorded in the `_huracan_meta` collection on first start), so
+// that a misconfigured deployment can't mix objects of different networks in one database.
+pub async fn verify_network(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let chain_id = cfg.sui().await?.get_chain_identifier().await.context("cannot query chain identifier")?;
+	check_chain_id(cfg, "our RPC providers", &chain_id)?;
+	let meta = db.collection::<Document>(META_COLLECTION);
+	let base = mongo_collection_name(cfg, "");
+	let indexed = meta
+		.find_one(doc! { "_id": &base }, None)
+		.await?
+		.and_then(|m| m.get_str("chain_id").ok().map(String::from));
+	match indexed {
+		Some(indexed) if indexed != chain_id => Err(anyhow!(
+			"{} has been indexed from chain {}, but our RPC providers serve chain {}, refusing to mix them",
+			base,
+			indexed,
+			chain_id
+		)),
+		Some(_) => Ok(()),
+		None => {
+			meta.update_one(
+				doc! { "_id": &base },
+				doc! { "$set": { "chain_id": &chain_id, "network": cfg.network(), "chain_recorded_at": DateTime::now() } },
+				UpdateOptions::builder().upsert(true).build(),
+			)
+			.await?;
+			Ok(())
+		}
+	}
+}
@@ -0,0 +1,121 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{options::ReplaceOptions, Database, IndexModel};
+
+use crate::{
+	_prelude::*,
+	etl::ObjectItem,
+	mongo::{mongo_collection_name, replace_if_newer},
+};
+
+const STAKED_SUI_TYPE: &str = "0x3::staking_pool::StakedSui";
+
+// With `staking.enabled`, the load step keeps every active stake (`StakedSui` object) in a separate collection,
+// e.g. prod_testnet_objects_staking, and a background task snapshots the active validator set whenever a new epoch
+// starts into `_validators`, keyed by epoch + validator address. Validator snapshots are only taken for epochs
+// that start while we're running, as the RPC only serves the latest system state.
+pub fn staking_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_staking")
+}
+
+pub fn validators_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_validators")
+}
+
+pub async fn ensure_staking_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let staking = db.collection::<Document>(&staking_collection_name(cfg));
+	staking.create_index(IndexModel::builder().keys(doc! { "owner": 1 }).build(), None).await?;
+	staking.create_index(IndexModel::builder().keys(doc! { "pool_id": 1 }).build(), None).await?;
+	let validators = db.collection::<Document>(&validators_collection_name(cfg));
+	validators.create_index(IndexModel::builder().keys(doc! { "address": 1, "epoch": -1 }).build(), None).await?;
+	Ok(())
+}
+
+// Updates stakes for all items in `chunk`, which has just been loaded, with `objects` as fetched. Failing to do so is
+// not fatal for the rest of the pipeline, the affected stakes will be updated with their next change.
+pub async fn update_stakes(cfg: &AppConfig, db: &Database, chunk: &[ObjectItem], objects: &[Document]) {
+	let staking = db.collection::<Document>(&staking_collection_name(cfg));
+	for (item, object) in chunk.iter().zip(objects) {
+		let id = item.id.to_string();
+		let v = item.version.value() as i64;
+		let res = if item.deletion {
+			// withdrawn (or it wasn't a stake at all)
+			let filter = doc! { "_id": &id, "version_": { "$lt": v } };
+			staking.delete_one(filter, None).await.map(|_| ()).map_err(Into::into)
+		} else {
+			match stake_document(object) {
+				Some(mut stake) => {
+					stake.insert("_id", id.clone());
+					stake.insert("version_", v);
+					replace_if_newer(&staking, &id, v, stake).await
+				}
+				None => Ok(()),
+			}
+		};
+		if let Err(err) = res {
+			warn!(object_id = ?item.id, error = ?err, "StakingWarning: Failed updating stake.");
+		}
+	}
+}
+
+fn stake_document(object: &Document) -> Option<Document> {
+	if object.get_str("type").ok()? != STAKED_SUI_TYPE {
+		return None
+	}
+	let fields = object.get_document("content").ok()?.get_document("fields").ok()?;
+	let owner = object.get_document("owner").ok().and_then(|o| o.get_str("AddressOwner").ok().map(String::from));
+	Some(doc! {
+		"owner": owner,
+		"pool_id": fields.get_str("pool_id").ok()?,
+		"stake_activation_epoch": fields.get_str("stake_activation_epoch").ok()?,
+		// Sui encodes large numbers as strings, so do we
+		"principal": fields.get_str("principal").ok()?,
+	})
+}
+
+// Checks for a new epoch every `staking.epochcheckms`, and stores the validator set once it sees one.
+pub fn spawn_validator_snapshots(cfg: &AppConfig) {
+	if !cfg.staking.enabled {
+		return
+	}
+	let cfg = cfg.clone();
+	tokio::spawn(async move {
+		let mut last_epoch = None;
+		loop {
+			match snapshot_validators(&cfg, last_epoch).await {
+				Ok(epoch) => last_epoch = Some(epoch),
+				Err(err) => warn!(error = ?err, "StakingWarning: Failed taking validator snapshot."),
+			}
+			tokio::time::sleep(Duration::from_millis(cfg.staking.epochcheckms)).await;
+		}
+	});
+}
+
+// Returns the current epoch.
+async fn snapshot_validators(cfg: &AppConfig, last_epoch: Option<u64>) -> anyhow::Result<u64> {
+	let state = cfg.sui().await?.get_latest_sui_system_state().await?;
+	if last_epoch == Some(state.epoch) {
+		return Ok(state.epoch)
+	}
+	let db = cfg.mongo.client(&cfg.livescan.mongo).await?;
+	let validators = db.collection::<Document>(&validators_collection_name(cfg));
+	for v in &state.active_validators {
+		let address = v.sui_address.to_string();
+		let snapshot = doc! {
+			"_id": format!("{}:{}", state.epoch, address),
+			"epoch": state.epoch as i64,
+			"epoch_start": DateTime::from_millis(state.epoch_start_timestamp_ms as i64),
+			"address": &address,
+			"name": &v.name,
+			"staking_pool_id": v.staking_pool_id.to_string(),
+			"stake": v.staking_pool_sui_balance.to_string(),
+			"next_epoch_stake": v.next_epoch_stake.to_string(),
+			"voting_power": v.voting_power as i64,
+			"commission_rate": v.commission_rate as i64,
+			"gas_price": v.gas_price as i64,
+		};
+		let filter = doc! { "_id": snapshot.get_str("_id")? };
+		validators.replace_one(filter, snapshot.clone(), ReplaceOptions::builder().upsert(true).build()).await?;
+	}
+	info!("StakingInfo: Stored {} validators for epoch {}.", state.active_validators.len(), state.epoch);
+	Ok(state.epoch)
+}
@@ -0,0 +1,56 @@
+use std::net::UdpSocket;
+
+use tokio::sync::OnceCell;
+
+use crate::{_prelude::*, conf::get_config_singleton};
+
+// Pushes metrics to a StatsD (or DogStatsD) agent over UDP, for operators that don't use InfluxDB.
+// Sending is fire-and-forget: if the agent is unreachable or the socket buffer is full, metrics are dropped.
+pub struct StatsdClient {
+	socket:    UdpSocket,
+	prefix:    String,
+	dogstatsd: bool,
+}
+
+pub(crate) static STATSDCLIENT: OnceCell<Option<StatsdClient>> = OnceCell::const_new();
+
+pub async fn setup_statsd_singleton() -> anyhow::Result<()> {
+	let cfg = &get_config_singleton().metrics;
+	let client = match &cfg.statsd {
+		Some(addr) => {
+			let socket = UdpSocket::bind("0.0.0.0:0")?;
+			socket.connect(addr).with_context(|| format!("cannot resolve statsd address {}", addr))?;
+			socket.set_nonblocking(true)?;
+			Some(StatsdClient { socket, prefix: cfg.statsdprefix.clone(), dogstatsd: cfg.dogstatsd })
+		}
+		None => None,
+	};
+	_ = STATSDCLIENT.set(client);
+	Ok(())
+}
+
+pub fn count(name: &str, value: i64, tags: &[(&str, &str)]) {
+	send(name, &value.to_string(), "c", tags);
+}
+
+pub fn gauge(name: &str, value: u64, tags: &[(&str, &str)]) {
+	send(name, &value.to_string(), "g", tags);
+}
+
+pub fn timing(name: &str, ms: u64, tags: &[(&str, &str)]) {
+	send(name, &ms.to_string(), "ms", tags);
+}
+
+fn send(name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) {
+	let Some(Some(client)) = STATSDCLIENT.get() else { return };
+	// e.g. "indexer.rpc_error:1|c|#rpc_method:query_transaction_blocks"
+	let mut line = format!("{}.{}:{}|{}", client.prefix, name, value, kind);
+	// tags are a DogStatsD extension, plain StatsD would choke on them
+	if client.dogstatsd && !tags.is_empty() {
+		line.push_str("|#");
+		line.push_str(&tags.iter().map(|(k, v)| format!("{}:{}", k, v)).collect::<Vec<_>>().join(","));
+	}
+	if let Err(err) = client.socket.send(line.as_bytes()) {
+		debug!("Could not send to statsd: {}", err);
+	}
+}
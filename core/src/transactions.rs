@@ -0,0 +1,145 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{Database, IndexModel};
+use sui_sdk::rpc_types::{
+	SuiCommand, SuiExecutionStatus, SuiProgrammableMoveCall, SuiTransactionBlockDataAPI,
+	SuiTransactionBlockEffectsAPI, SuiTransactionBlockKind, SuiTransactionBlockResponse,
+};
+
+use crate::{_prelude::*, influx::write_metric_mongo_write_error, mongo::mongo_collection_name};
+
+// With `transactions.enabled`, the checkpoint scan additionally stores a summary of every transaction block it sees
+// in a separate collection, e.g. prod_testnet_objects_transactions, keyed by transaction digest.
+// Transactions of a checkpoint are stored before the checkpoint can be marked as completed, so they follow the same
+// cursor as the objects.
+pub fn transactions_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_transactions")
+}
+
+pub async fn ensure_transactions_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&transactions_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "sender": 1, "checkpoint": -1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "checkpoint": -1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "move_calls.package": 1, "checkpoint": -1 }).build(), None)
+		.await?;
+	Ok(())
+}
+
+// All Move calls of a programmable transaction block, in order. Requires the block to be queried with its input.
+pub fn move_calls(block: &SuiTransactionBlockResponse) -> Vec<&SuiProgrammableMoveCall> {
+	match block.transaction.as_ref().map(|t| t.data.transaction()) {
+		Some(SuiTransactionBlockKind::ProgrammableTransaction(ptb)) => ptb
+			.commands
+			.iter()
+			.filter_map(|command| match command {
+				SuiCommand::MoveCall(call) => Some(call.as_ref()),
+				_ => None,
+			})
+			.collect(),
+		_ => Vec::new(),
+	}
+}
+
+fn transaction_document(block: &SuiTransactionBlockResponse) -> Document {
+	let mut tx = doc! { "_id": block.digest.to_string() };
+	if let Some(cp) = block.checkpoint {
+		tx.insert("checkpoint", cp as i64);
+	}
+	if let Some(ts) = block.timestamp_ms {
+		tx.insert("ts", DateTime::from_millis(ts as i64));
+	}
+	if let Some(data) = block.transaction.as_ref().map(|t| &t.data) {
+		let gas = data.gas_data();
+		tx.insert("sender", data.sender().to_string());
+		tx.insert("gas", doc! {
+			"owner": gas.owner.to_string(),
+			"price": gas.price as i64,
+			"budget": gas.budget as i64,
+		});
+		let move_calls = move_calls(block)
+			.into_iter()
+			.map(|call| {
+				doc! {
+					"package": call.package.to_string(),
+					"module": call.module.to_string(),
+					"function": call.function.to_string(),
+				}
+			})
+			.collect::<Vec<_>>();
+		tx.insert("move_calls", move_calls);
+	}
+	if let Some(effects) = &block.effects {
+		let (status, error) = match effects.status() {
+			SuiExecutionStatus::Success => ("success", None),
+			SuiExecutionStatus::Failure { error } => ("failure", Some(error.clone())),
+		};
+		let cost = effects.gas_cost_summary();
+		let mut summary = doc! {
+			"status": status,
+			"computation_cost": cost.computation_cost as i64,
+			"storage_cost": cost.storage_cost as i64,
+			"storage_rebate": cost.storage_rebate as i64,
+			"created": effects.created().len() as i64,
+			"mutated": effects.mutated().len() as i64,
+			"deleted": effects.deleted().len() as i64,
+		};
+		if let Some(error) = error {
+			summary.insert("error", error);
+		}
+		tx.insert("status", status);
+		tx.insert("effects", summary);
+	}
+	tx
+}
+
+// Stores all transaction blocks of a page of the checkpoint scan. Transactions are immutable, so storing the same one
+// again is a no-op. Returns the blocks that weren't stored before.
+pub async fn store_transactions<'a>(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	db: &Database,
+	blocks: &'a [SuiTransactionBlockResponse],
+) -> Vec<&'a SuiTransactionBlockResponse> {
+	if blocks.is_empty() {
+		return Vec::new()
+	}
+	let collection = transactions_collection_name(cfg);
+	let updates = blocks
+		.iter()
+		.map(|block| {
+			let tx = transaction_document(block);
+			doc! {
+				"q": doc! { "_id": tx.get_str("_id").unwrap() },
+				"u": doc! { "$setOnInsert": tx },
+				"upsert": true,
+				"multi": false,
+			}
+		})
+		.collect::<Vec<_>>();
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		match db.run_command(doc! { "update": &collection, "updates": updates.clone(), "ordered": false }, None).await {
+			// res: {n: i32, upserted: [{index: i32, _id: String}, ...], ...}
+			Ok(res) => {
+				return res
+					.get_array("upserted")
+					.map(|upserted| {
+						upserted
+							.iter()
+							.filter_map(|u| u.as_document()?.get_i32("index").ok())
+							.filter_map(|i| blocks.get(i as usize))
+							.collect()
+					})
+					.unwrap_or_default()
+			}
+			Err(err) => {
+				write_metric_mongo_write_error().await;
+				if retries_left == 0 {
+					error!(error = ?err, "failed to store {} transactions in transactions collection", updates.len());
+					return Vec::new()
+				}
+				warn!("error storing transactions in transactions collection, will retry {} more times: {:?}", retries_left, err);
+				retries_left -= 1;
+			}
+		}
+	}
+}
@@ -0,0 +1,102 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{Database, IndexModel};
+use sui_sdk::rpc_types::{SuiTransactionBlockDataAPI, SuiTransactionBlockResponse};
+use sui_types::object::Owner;
+
+use crate::{_prelude::*, influx::write_metric_mongo_write_error, mongo::mongo_collection_name, utils::transfer_legs};
+
+// With `transfers.enabled`, the checkpoint scan derives a ledger of token transfers from the balance changes of every
+// transaction block, and stores it in a separate collection, e.g. prod_testnet_objects_transfers, keyed by transaction
+// digest and the transfer's index within the transaction block.
+// Any coin object changing hands shows up as a balance change of both owners, so this covers coin movements as well.
+// Amounts are stored as i64, so they're only correct up to 2^63 - 1, like balances.
+pub fn transfers_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_transfers")
+}
+
+pub async fn ensure_transfers_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&transfers_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "from": 1, "ts": -1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "to": 1, "ts": -1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "coin_type": 1, "ts": -1 }).build(), None).await?;
+	Ok(())
+}
+
+fn owner_address(owner: &Owner) -> Option<String> {
+	match owner {
+		Owner::AddressOwner(address) | Owner::ObjectOwner(address) => Some(address.to_string()),
+		_ => None,
+	}
+}
+
+fn transfer_documents(block: &SuiTransactionBlockResponse) -> Vec<Document> {
+	let Some(changes) = &block.balance_changes else { return Vec::new() };
+	let Some(sender) = block.transaction.as_ref().map(|t| t.data.sender().to_string()) else { return Vec::new() };
+	let changes = changes
+		.iter()
+		.filter_map(|c| Some((owner_address(&c.owner)?, c.coin_type.to_string(), c.amount)))
+		.collect::<Vec<_>>();
+	transfer_legs(&sender, &changes)
+		.into_iter()
+		.enumerate()
+		.map(|(i, (from, to, coin_type, amount))| {
+			let mut transfer = doc! {
+				"_id": format!("{}:{}", block.digest, i),
+				"tx": block.digest.to_string(),
+				"from": from,
+				"to": to,
+				"coin_type": coin_type,
+				"amount": amount.min(i64::MAX as u128) as i64,
+				"sender": sender.clone(),
+			};
+			if let Some(cp) = block.checkpoint {
+				transfer.insert("checkpoint", cp as i64);
+			}
+			if let Some(ts) = block.timestamp_ms {
+				transfer.insert("ts", DateTime::from_millis(ts as i64));
+			}
+			transfer
+		})
+		.collect()
+}
+
+// Stores all transfers of a page of the checkpoint scan. Balance changes of a transaction never change, so storing the
+// same transfer again is a no-op.
+pub async fn store_transfers(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	db: &Database,
+	blocks: &[SuiTransactionBlockResponse],
+) {
+	let updates = blocks
+		.iter()
+		.flat_map(transfer_documents)
+		.map(|transfer| {
+			doc! {
+				"q": doc! { "_id": transfer.get_str("_id").unwrap() },
+				"u": doc! { "$setOnInsert": transfer },
+				"upsert": true,
+				"multi": false,
+			}
+		})
+		.collect::<Vec<_>>();
+	if updates.is_empty() {
+		return
+	}
+	let collection = transfers_collection_name(cfg);
+	let mut retries_left = pc.mongo.retries;
+	loop {
+		match db.run_command(doc! { "update": &collection, "updates": updates.clone(), "ordered": false }, None).await {
+			Ok(_) => break,
+			Err(err) => {
+				write_metric_mongo_write_error().await;
+				if retries_left == 0 {
+					error!(error = ?err, "failed to store {} transfers in transfers collection", updates.len());
+					break
+				}
+				warn!("error storing transfers in transfers collection, will retry {} more times: {:?}", retries_left, err);
+				retries_left -= 1;
+			}
+		}
+	}
+}
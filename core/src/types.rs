@@ -0,0 +1,251 @@
+use std::{collections::HashMap, fmt};
+
+use crate::ids::normalize_id;
+
+// Move type tags as they appear in object types, e.g. `0x2::coin::Coin<0x2::sui::SUI>`, and patterns matching them.
+// The same type can be written in many ways: with short or full-length addresses, in upper or lower case, with or
+// without spaces between type parameters, and, as structs keep the id of the package version that first defined
+// them, with the id of any later version of their package. Everything comparing types (filters, webhook rules, the
+// webserver's queries) goes through here, so all of these compare equal.
+// Only depends on ids.rs, the webserver includes both, too (see server/src/main.rs).
+
+// The canonical form of addresses in types is the short one sui-sdk uses for object types, e.g. `0x2`, unlike ids on
+// their own (see ids.rs), so that canonical types are what we store in `object.type`.
+fn short_address(id: &str) -> String {
+	let hex = id.trim_start_matches("0x").trim_start_matches('0');
+	if hex.is_empty() { "0x0".to_string() } else { format!("0x{}", hex) }
+}
+
+const PRIMITIVES: &[&str] = &["bool", "u8", "u16", "u32", "u64", "u128", "u256", "address", "signer"];
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TypeTag {
+	Primitive(String),
+	Vector(Box<TypeTag>),
+	// `package` in its canonical full-length form, see ids.rs
+	Struct { package: String, module: String, name: String, params: Vec<TypeTag> },
+}
+
+impl TypeTag {
+	pub fn parse(ty: &str) -> Option<Self> {
+		let (tag, rest) = Self::parse_prefix(ty)?;
+		rest.trim().is_empty().then_some(tag)
+	}
+
+	// Parses the type at the start of `s`, returns it with whatever follows it.
+	fn parse_prefix(s: &str) -> Option<(Self, &str)> {
+		let s = s.trim_start();
+		let end = s.find(|c| c == '<' || c == ',' || c == '>').unwrap_or(s.len());
+		let (head, mut rest) = (s[..end].trim(), &s[end..]);
+		let mut params = Vec::new();
+		if let Some(mut inner) = rest.strip_prefix('<') {
+			loop {
+				let (param, after) = Self::parse_prefix(inner)?;
+				params.push(param);
+				let after = after.trim_start();
+				if let Some(after) = after.strip_prefix(',') {
+					inner = after;
+				} else {
+					rest = after.strip_prefix('>')?;
+					break
+				}
+			}
+		}
+		let tag = match head.split("::").collect::<Vec<_>>()[..] {
+			["vector"] if params.len() == 1 => Self::Vector(Box::new(params.pop().unwrap())),
+			[primitive] if PRIMITIVES.contains(&primitive) && params.is_empty() => Self::Primitive(primitive.to_string()),
+			[package, module, name] if !module.is_empty() && !name.is_empty() => Self::Struct {
+				package: normalize_id(package)?,
+				module: module.to_string(),
+				name: name.to_string(),
+				params,
+			},
+			_ => return None,
+		};
+		Some((tag, rest))
+	}
+
+	// The package defining the outermost struct, None for primitives and vectors.
+	pub fn package(&self) -> Option<&str> {
+		match self {
+			Self::Struct { package, .. } => Some(package),
+			_ => None,
+		}
+	}
+
+	// Replaces the package ids of all structs in this type with the ids they're aliases of.
+	pub fn resolve(&mut self, aliases: &TypeAliases) {
+		match self {
+			Self::Primitive(_) => {}
+			Self::Vector(inner) => inner.resolve(aliases),
+			Self::Struct { package, params, .. } => {
+				if let Some(original) = aliases.0.get(package.as_str()) {
+					*package = original.clone();
+				}
+				params.iter_mut().for_each(|param| param.resolve(aliases));
+			}
+		}
+	}
+}
+
+// The canonical form: short addresses, and type parameters separated by `, `.
+impl fmt::Display for TypeTag {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Primitive(primitive) => f.write_str(primitive),
+			Self::Vector(inner) => write!(f, "vector<{}>", inner),
+			Self::Struct { package, module, name, params } => {
+				write!(f, "{}::{}::{}", short_address(package), module, name)?;
+				if !params.is_empty() {
+					let params = params.iter().map(|param| param.to_string()).collect::<Vec<_>>();
+					write!(f, "<{}>", params.join(", "))?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+// Canonical form of a full type, None if it isn't one.
+pub fn canonical_type(ty: &str) -> Option<String> {
+	TypeTag::parse(ty).map(|tag| tag.to_string())
+}
+
+// Package ids to treat as another package's, e.g. later versions of an upgraded package as its original id, so types
+// and patterns can use the id of any of its versions. Keyed by canonical full-length ids.
+#[derive(Clone, Debug, Default)]
+pub struct TypeAliases(HashMap<String, String>);
+
+impl TypeAliases {
+	// `aliases` maps package ids (in any form) to the package id they stand for.
+	pub fn new(aliases: &HashMap<String, String>) -> Result<Self, String> {
+		let id = |id: &String| normalize_id(id).ok_or_else(|| format!("invalid package id {}", id));
+		aliases.iter().map(|(alias, original)| Ok((id(alias)?, id(original)?))).collect::<Result<_, _>>().map(Self)
+	}
+
+	// The package id `package` stands for, in its canonical full-length form.
+	pub fn resolve(&self, package: &str) -> Option<String> {
+		let package = normalize_id(package)?;
+		Some(self.0.get(&package).cloned().unwrap_or(package))
+	}
+}
+
+// Rewrites every address in a type or pattern into the canonical short form of the package it stands for, and
+// whitespace into the canonical `, ` between type parameters, leaving everything else (such as `*`) as it is.
+fn normalize_addresses(s: &str, aliases: &TypeAliases) -> String {
+	let mut out = String::with_capacity(s.len());
+	let mut rest = s;
+	while let Some(c) = rest.chars().next() {
+		let starts_token = out.chars().last().map_or(true, |prev| !(prev.is_ascii_alphanumeric() || prev == '_'));
+		if starts_token && (rest.starts_with("0x") || rest.starts_with("0X")) {
+			let end = rest[2..].find(|c: char| !c.is_ascii_hexdigit()).map_or(rest.len(), |i| i + 2);
+			match aliases.resolve(&rest[..end]) {
+				Some(package) => out.push_str(&short_address(&package)),
+				None => out.push_str(&rest[..end]),
+			}
+			rest = &rest[end..];
+		} else {
+			match c {
+				',' => out.push_str(", "),
+				c if c.is_whitespace() => {}
+				c => out.push(c),
+			}
+			rest = &rest[c.len_utf8()..];
+		}
+	}
+	out
+}
+
+// A pattern for full types, in which `*` stands for any (possibly empty) sequence of characters, e.g.
+// `0xdee9::clob_v2::*` for all types of a module, or `0x2::coin::Coin<*>` for coins of any type. Addresses may be
+// given in any form, including aliases (see `TypeAliases`).
+#[derive(Clone, Debug)]
+pub struct TypePattern {
+	pattern: String,
+	aliases: TypeAliases,
+}
+
+impl TypePattern {
+	pub fn new(pattern: &str, aliases: &TypeAliases) -> Self {
+		Self { pattern: normalize_addresses(pattern, aliases), aliases: aliases.clone() }
+	}
+
+	pub fn matches(&self, ty: &str) -> bool {
+		glob_matches(&self.pattern, &normalize_addresses(ty, &self.aliases))
+	}
+
+	// The pattern in canonical form, with `*` left as it is.
+	pub fn as_str(&self) -> &str {
+		&self.pattern
+	}
+}
+
+// Matches `s` against a pattern in which `*` stands for any (possibly empty) sequence of characters.
+pub fn glob_matches(pattern: &str, s: &str) -> bool {
+	let mut parts = pattern.split('*');
+	// split always yields at least one part
+	let first = parts.next().unwrap();
+	let Some(mut rest) = s.strip_prefix(first) else { return false };
+	let mut parts = parts.collect::<Vec<_>>();
+	let Some(last) = parts.pop() else { return rest.is_empty() };
+	for part in parts {
+		match rest.find(part) {
+			Some(i) => rest = &rest[i + part.len()..],
+			None => return false,
+		}
+	}
+	rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_glob_matches() {
+		assert!(glob_matches("0x2::coin::Coin<*>", "0x2::coin::Coin<0x2::sui::SUI>"));
+		assert!(!glob_matches("0x2::coin::Coin<*>", "0x2::coin::CoinMetadata<0x2::sui::SUI>"));
+		assert!(glob_matches("*", ""));
+		assert!(glob_matches("0xabc", "0xabc"));
+		assert!(!glob_matches("0xabc", "0xabcd"));
+		assert!(glob_matches("0x*::pool::*", "0xdee9::pool::Pool"));
+		assert!(glob_matches("a*ba", "aba"));
+		assert!(!glob_matches("ab*ba", "aba"));
+	}
+
+	#[test]
+	fn test_canonical_type() {
+		let long = "0x0000000000000000000000000000000000000000000000000000000000000002";
+		assert_eq!(
+			canonical_type(&format!("{long}::coin::Coin<{long}::sui::SUI>")).as_deref(),
+			Some("0x2::coin::Coin<0x2::sui::SUI>")
+		);
+		assert_eq!(
+			canonical_type("0xDEE9::clob_v2::Pool<0x2::sui::SUI,vector<u8>>").as_deref(),
+			Some("0xdee9::clob_v2::Pool<0x2::sui::SUI, vector<u8>>")
+		);
+		assert_eq!(canonical_type("u64").as_deref(), Some("u64"));
+		assert_eq!(canonical_type("0x2::coin"), None);
+		assert_eq!(canonical_type("0x2::coin::Coin<0x2::sui::SUI"), None);
+		assert_eq!(canonical_type("0x2::coin::Coin<0x2::sui::SUI>>"), None);
+	}
+
+	#[test]
+	fn test_type_pattern() {
+		let none = TypeAliases::default();
+		assert!(TypePattern::new("0xdee9::clob_v2::*", &none).matches("0xdee9::clob_v2::Pool<0x2::sui::SUI, u64>"));
+		assert!(TypePattern::new("0x2::coin::Coin<*>", &none).matches("0x2::coin::Coin<0x2::sui::SUI>"));
+		assert!(!TypePattern::new("0x2::coin::Coin<*>", &none).matches("0x2::coin::CoinMetadata<0x2::sui::SUI>"));
+		let long = TypePattern::new("0x0000000000000000000000000000000000000000000000000000000000000002::coin::*", &none);
+		assert!(long.matches("0x2::coin::Coin<0x2::sui::SUI>"));
+		assert_eq!(TypePattern::new("0xDEE9::m::P<0x2::s::S,*>", &none).as_str(), "0xdee9::m::P<0x2::s::S, *>");
+
+		// the struct keeps the id of the version first defining it, patterns may use the latest one
+		let aliases = [("0xb".to_string(), "0xa".to_string())].into_iter().collect();
+		let aliases = TypeAliases::new(&aliases).unwrap();
+		assert!(TypePattern::new("0xb::pool::Pool<*>", &aliases).matches("0xa::pool::Pool<0x2::sui::SUI>"));
+		assert!(TypePattern::new("0xa::pool::*", &aliases).matches("0xb::pool::Pool"));
+		assert!(!TypePattern::new("0xc::pool::*", &aliases).matches("0xa::pool::Pool"));
+		assert!(TypeAliases::new(&[("0xz".to_string(), "0xa".to_string())].into_iter().collect()).is_err());
+	}
+}
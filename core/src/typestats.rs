@@ -0,0 +1,91 @@
+use bson::{doc, DateTime, Document};
+use mongodb::{
+	options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions},
+	Collection, Database, IndexModel,
+};
+
+use crate::{
+	_prelude::*,
+	etl::ObjectItem,
+	mongo::{is_duplicate_key_error, mongo_collection_name},
+};
+
+// With `typestats.enabled`, the load step maintains per-type statistics in a separate collection, e.g.
+// prod_testnet_objects_type_stats, keyed by full type: the number of live objects, how many were created and deleted,
+// and the time of the last change, so these don't require a scan of the main collection.
+// Like balances, this needs to know whether an object was already counted, so for every object we remember its type
+// and whether it's live (collection suffix `_type_stats_objects`). Only objects loaded while this is enabled count.
+pub fn type_stats_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_type_stats")
+}
+
+fn type_stats_objects_collection_name(cfg: &AppConfig) -> String {
+	mongo_collection_name(cfg, "_type_stats_objects")
+}
+
+pub async fn ensure_type_stats_indexes(cfg: &AppConfig, db: &Database) -> anyhow::Result<()> {
+	let coll = db.collection::<Document>(&type_stats_collection_name(cfg));
+	coll.create_index(IndexModel::builder().keys(doc! { "live": -1 }).build(), None).await?;
+	coll.create_index(IndexModel::builder().keys(doc! { "last_activity": -1 }).build(), None).await?;
+	Ok(())
+}
+
+// Counts all objects in `chunk`, which has just been loaded, with `fetched` holding their object data as fetched.
+// Failing to do so is not fatal for the rest of the pipeline, but leaves the affected counts off.
+pub async fn update_type_stats(cfg: &AppConfig, db: &Database, chunk: &[ObjectItem], fetched: &[Document]) {
+	let objects = db.collection::<Document>(&type_stats_objects_collection_name(cfg));
+	let stats = db.collection::<Document>(&type_stats_collection_name(cfg));
+	for (item, object) in chunk.iter().zip(fetched) {
+		if let Err(err) = update_object(&objects, &stats, item, object).await {
+			warn!(object_id = ?item.id, error = ?err, "TypeStatsWarning: Failed updating type statistics for object.");
+		}
+	}
+}
+
+async fn update_object(
+	objects: &Collection<Document>,
+	stats: &Collection<Document>,
+	item: &ObjectItem,
+	object: &Document,
+) -> anyhow::Result<()> {
+	let v = item.version.value() as i64;
+	let ts = DateTime::from_millis(item.ts_sui.map_or_else(|| DateTime::now().timestamp_millis(), |ts| ts as i64));
+	// only move forward, so replayed or out of order versions don't count twice
+	let filter = doc! { "_id": item.id.to_string(), "version_": { "$lt": v } };
+	let (update, upsert, ty) = if item.deletion {
+		// if we don't know the object, we've never counted it, so there's nothing to undo
+		(doc! { "$set": { "version_": v, "live": false } }, false, None)
+	} else {
+		let ty = object.get_str("type")?.to_string();
+		(doc! { "$set": { "version_": v, "live": true, "type": &ty } }, true, Some(ty))
+	};
+	let opts = FindOneAndUpdateOptions::builder().upsert(upsert).return_document(ReturnDocument::Before).build();
+	let previous = match objects.find_one_and_update(filter, update, opts).await {
+		Ok(previous) => previous,
+		// the object is already stored with a newer version
+		Err(err) if is_duplicate_key_error(&err) => return Ok(()),
+		Err(err) => return Err(err.into()),
+	};
+	let (ty, inc) = match (previous, ty) {
+		// first time we see the object
+		(None, Some(ty)) => (ty, doc! { "live": 1i64, "created": 1i64 }),
+		(Some(previous), None) if previous.get_bool("live")? => {
+			(previous.get_str("type")?.to_string(), doc! { "live": -1i64, "deleted": 1i64 })
+		}
+		// a change of a live object, types never change
+		(Some(_), Some(ty)) => (ty, doc! {}),
+		_ => return Ok(()),
+	};
+	stats
+		.update_one(
+			doc! { "_id": &ty },
+			doc! {
+				"$inc": inc,
+				"$max": { "last_activity": ts },
+				"$setOnInsert": { "type": &ty },
+			},
+			UpdateOptions::builder().upsert(true).build(),
+		)
+		.await?;
+	Ok(())
+}
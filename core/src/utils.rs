@@ -0,0 +1,747 @@
+use std::str::FromStr;
+use bson::{doc, Bson, DateTime, Document};
+use serde::Serialize;
+use sui_types::base_types::ObjectType;
+
+use crate::ids::canonical_id;
+
+/// ranges are inclusive on both sides
+pub fn make_descending_ranges(mut numbers: Vec<u64>) -> Vec<(u64, u64)> {
+	if numbers.is_empty() {
+		return Vec::new()
+	}
+	numbers.sort_unstable();
+	let mut start = numbers[0];
+	let mut end = numbers[0];
+	let mut ranges = Vec::new();
+	for &n in numbers.iter().skip(1) {
+		if n == end + 1 {
+			end = n;
+		} else {
+			ranges.push((end, start));
+			start = n;
+			end = n;
+		}
+	}
+	ranges.push((end, start));
+	ranges.reverse();
+	ranges
+}
+
+// Restricts descending completed checkpoint ranges (as returned by make_descending_ranges()) so that a scan using them
+// doesn't go below `lowest`, by treating every checkpoint below it as completed.
+pub fn bound_descending_ranges(ranges: Vec<(u64, u64)>, lowest: u64) -> Vec<(u64, u64)> {
+	let mut ranges = ranges
+		.into_iter()
+		.filter(|(end, _)| *end >= lowest)
+		.map(|(end, start)| (end, start.max(lowest)))
+		.collect::<Vec<_>>();
+	if lowest > 1 {
+		ranges.push((lowest - 1, 0));
+	}
+	ranges
+}
+
+// This function is used to compare object types from RPC request with object types in config file (whitelist and blacklist).
+pub(crate) fn check_obj_type_from_string_vec(input_obj_type: &ObjectType, obj_type_string_vec: Vec<String>) -> bool {
+	for item in obj_type_string_vec {
+		let item_obj_type = ObjectType::from_str(&item).unwrap();
+		if input_obj_type == &item_obj_type {
+			return true;
+		}
+	}
+	false
+}
+
+/// nearest-rank percentile of an already sorted slice, `p` in `0.0..=1.0`
+pub fn percentile<T: Copy>(sorted: &[T], p: f64) -> Option<T> {
+	if sorted.is_empty() {
+		return None
+	}
+	let rank = (p * sorted.len() as f64).ceil() as usize;
+	Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+// Checks a stored object document (the BSON form of `SuiObjectData`) against optional type and owner filters.
+// The type is matched as a prefix of the full type string, so `0x2::coin::Coin` matches all coin types.
+// The owner must already be in its full-length string form.
+pub(crate) fn object_doc_matches(doc: &Document, type_prefix: Option<&str>, owner: Option<&str>) -> bool {
+	if let Some(type_prefix) = type_prefix {
+		match doc.get_str("type") {
+			Ok(ty) if ty.starts_with(type_prefix) => {}
+			_ => return false,
+		}
+	}
+	if let Some(owner) = owner {
+		let Ok(o) = doc.get_document("owner") else { return false };
+		let doc_owner = o.get_str("AddressOwner").or_else(|_| o.get_str("ObjectOwner"));
+		if doc_owner.ok() != Some(owner) {
+			return false
+		}
+	}
+	true
+}
+
+// Extracts coin type, address owner (if owned by an address) and balance from a stored `0x2::coin::Coin<T>` object
+// document. Returns None for all other objects.
+pub(crate) fn coin_balance(doc: &Document) -> Option<(String, Option<String>, u64)> {
+	let coin_type = doc.get_str("type").ok()?.strip_prefix("0x2::coin::Coin<")?.strip_suffix('>')?;
+	let balance = doc.get_document("content").ok()?.get_document("fields").ok()?.get_str("balance").ok()?.parse().ok()?;
+	let owner = doc.get_document("owner").ok().and_then(|o| o.get_str("AddressOwner").ok()).map(String::from);
+	Some((coin_type.to_string(), owner, balance))
+}
+
+// The dynamic field a kiosk uses to hold an item, returns kiosk and item id.
+pub(crate) fn kiosk_item(doc: &Document) -> Option<(String, String)> {
+	if doc.get_str("type").ok()?
+		!= "0x2::dynamic_field::Field<0x2::dynamic_object_field::Wrapper<0x2::kiosk::Item>, 0x2::object::ID>"
+	{
+		return None
+	}
+	let kiosk = doc.get_document("owner").ok()?.get_str("ObjectOwner").ok()?;
+	let item = doc.get_document("content").ok()?.get_document("fields").ok()?.get_str("value").ok()?;
+	Some((kiosk.to_string(), item.to_string()))
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum KioskObject {
+	// the owner stored in the kiosk itself
+	Kiosk { owner: Option<String> },
+	// the address holding the cap of `kiosk`, if any
+	OwnerCap { kiosk: String, holder: Option<String> },
+}
+
+impl KioskObject {
+	pub(crate) fn from_doc(doc: &Document) -> Option<Self> {
+		let fields = doc.get_document("content").ok()?.get_document("fields").ok()?;
+		match doc.get_str("type").ok()? {
+			"0x2::kiosk::Kiosk" => Some(KioskObject::Kiosk { owner: fields.get_str("owner").ok().map(String::from) }),
+			"0x2::kiosk::KioskOwnerCap" => Some(KioskObject::OwnerCap {
+				kiosk:  fields.get_str("for").ok()?.to_string(),
+				holder: doc.get_document("owner").ok()?.get_str("AddressOwner").ok().map(String::from),
+			}),
+			_ => None,
+		}
+	}
+}
+
+// Top-level type parameters of a full type string, e.g. `["0x2::sui::SUI", "0x5::c::C<0x2::sui::SUI>"]` for
+// `0xdee9::clob_v2::Pool<0x2::sui::SUI, 0x5::c::C<0x2::sui::SUI>>`.
+pub(crate) fn type_params(ty: &str) -> Vec<&str> {
+	let Some(start) = ty.find('<') else { return Vec::new() };
+	let Some(inner) = ty[start + 1..].strip_suffix('>') else { return Vec::new() };
+	let (mut params, mut depth, mut param_start) = (Vec::new(), 0, 0);
+	for (i, c) in inner.char_indices() {
+		match c {
+			'<' => depth += 1,
+			'>' => depth -= 1,
+			',' if depth == 0 => {
+				params.push(inner[param_start..i].trim());
+				param_start = i + 1;
+			}
+			_ => {}
+		}
+	}
+	params.push(inner[param_start..].trim());
+	params
+}
+
+// Components of an object's type, stored as top-level fields next to the object so that queries by package, module,
+// struct or type parameter can use an index instead of a regex on `object.type`, e.g. `type_package: "0x0…02"`,
+// `type_module: "coin"`, `type_struct: "Coin"` and `type_params: ["0x2::sui::SUI"]` for `0x2::coin::Coin<0x2::sui::SUI>`.
+// The package is in its canonical form (see ids.rs), so it matches package ids stored elsewhere. Empty for objects
+// without a type, like packages.
+pub(crate) fn type_components(object: &Document) -> Document {
+	let Ok(ty) = object.get_str("type") else { return Document::new() };
+	let mut parts = ty.split('<').next().unwrap_or(ty).splitn(3, "::");
+	let (Some(package), Some(module), Some(struct_)) = (parts.next(), parts.next(), parts.next()) else {
+		return Document::new()
+	};
+	doc! { "type_package": canonical_id(package), "type_module": module, "type_struct": struct_, "type_params": type_params(ty) }
+}
+
+// Price of coin A in units of coin B (both in their smallest unit), from a Q64.64 fixed point square root price as
+// used by concentrated liquidity pools. Only used by the `dexpools` plugin.
+#[cfg(feature = "dexpools")]
+pub(crate) fn price_from_sqrt_price_x64(sqrt_price: u128) -> f64 {
+	let sqrt = sqrt_price as f64 / 2f64.powi(64);
+	sqrt * sqrt
+}
+
+// Normalizes the balance changes (owner, coin type, amount) of a transaction block into transfers (from, to, coin type,
+// amount). Every credit is a transfer to its owner, from the only other owner debited the same coin type. If several
+// owners were debited, we attribute it to the sender, if they're one of them. Credits without a matching debit (e.g.
+// coins taken from a pool or freshly minted) have no sender, debits without a credit (gas, burns) are no transfers.
+pub(crate) fn transfer_legs(
+	sender: &str,
+	changes: &[(String, String, i128)],
+) -> Vec<(Option<String>, String, String, u128)> {
+	changes
+		.iter()
+		.filter(|(_, _, amount)| *amount > 0)
+		.map(|(to, coin_type, amount)| {
+			let debited = changes
+				.iter()
+				.filter(|(owner, ty, amount)| ty == coin_type && *amount < 0 && owner != to)
+				.map(|(owner, _, _)| owner.as_str())
+				.collect::<Vec<_>>();
+			let from = match debited.as_slice() {
+				[from] => Some(from.to_string()),
+				_ if debited.contains(&sender) => Some(sender.to_string()),
+				_ => None,
+			};
+			(from, to.clone(), coin_type.clone(), *amount as u128)
+		})
+		.collect()
+}
+
+// Stored as `huracan_version` in every object document and version we write, to tell which release wrote it.
+pub(crate) const HURACAN_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// The update of the main collection for one object change, see load_batched(). `object` is ignored for deletions.
+// Besides the object, documents carry `ingested_at` (when we first wrote them), `updated_at` (when we last changed
+// them) and the `huracan_version` that did, all by our clock, not the chain's, and the `network` they're from.
+pub(crate) fn object_update(
+	id: &str,
+	version: &str,
+	deletion: bool,
+	object: &Document,
+	content_hash: Option<&str>,
+	network: &str,
+) -> Document {
+	let v = version.to_string();
+	let v_ = u64::from_str_radix(&v[2..], 16).unwrap();
+	// FIXME our value range here is u64, but I can't figure out how to get a BSON repr of a u64?!
+	let v_ = v_ as i64;
+	let now = DateTime::now();
+	if deletion {
+		// we're assuming each object id will ever exist only once, so when deleting
+		// we don't check for previous versions
+		// we execute the delete, whenever it may come in, and it's final
+		doc! {
+			"q": doc! { "_id": id },
+			"u": doc! {
+				"$set": {
+					"_id": id,
+					"version": v,
+					"version_": v_,
+					"deleted": true,
+					"updated_at": now,
+					"huracan_version": HURACAN_VERSION,
+					"network": network,
+				},
+				"$setOnInsert": { "ingested_at": now },
+			},
+			"upsert": true,
+			"multi": false,
+		}
+	} else {
+		let content_pending = if object.contains_key("content") { Bson::from("$$REMOVE") } else { Bson::Boolean(true) };
+		// never kept from an older version, so a stale hash can't make us skip a change, see contenthash.rs
+		let content_hash = content_hash.map_or(Bson::from("$$REMOVE"), Bson::from);
+		// we will only upsert and object if this current version is higher than any previously stored one
+		// (if the object has already been deleted, we still allow setting any other fields, including
+		// any previously valid full object state... probably not needed, but also not incorrect)
+		let mut set = doc! {
+			"_id": id,
+			// version_ must be added first, so that it's available in the next items in the pipeline
+			// it has a more complex condition, so it's also added if the field doesn't exist yet
+			// afterwards, the other fields can rely on it being present
+			"version_": {"$cond": { "if": { "$or": [ { "$lt": [ "$version_", v_ ] }, { "$lte": [ "$version", None::<i32> ] } ] }, "then": v_, "else": "$version_" }},
+			"version": {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": v.clone(), "else": "$version" }},
+			"object": {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": object.clone(), "else": "$object" }},
+			// a newer version means the object has been unwrapped again, see wraps.rs
+			"wrapped_in_tx": {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": "$$REMOVE", "else": "$wrapped_in_tx" }},
+			// stored without content, to be decoded later, see decode.rs
+			"content_pending": {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": content_pending.clone(), "else": "$content_pending" }},
+			"content_hash": {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": content_hash, "else": "$content_hash" }},
+			"ingested_at": {"$ifNull": [ "$ingested_at", now ]},
+			"updated_at": {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": now, "else": "$updated_at" }},
+			"huracan_version": {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": HURACAN_VERSION, "else": "$huracan_version" }},
+			"network": network,
+		};
+		for (key, value) in type_components(object) {
+			let current = format!("${}", key);
+			set.insert(key, doc! {"$cond": { "if": { "$lt": [ "$version_", v_ ] }, "then": { "$literal": value }, "else": current }});
+		}
+		doc! {
+			"q": doc! { "_id": id },
+			// use an aggregation pipeline in our update, so that we can conditionally update
+			// the version and object only if the previous version was lower than our current one
+			"u": vec![doc! { "$set": set }],
+			"upsert": true,
+			"multi": false,
+		}
+	}
+}
+
+// Removes the fields of a stored object document (the BSON form of `SuiObjectData`) that duplicate others, see
+// `slimdocuments`. Readers must not rely on any of them.
+pub(crate) fn slim_object(doc: &mut Document) {
+	doc.remove("objectId");
+	doc.remove("version");
+	for part in ["content", "bcs"] {
+		if let Ok(part) = doc.get_document_mut(part) {
+			part.remove("type");
+			part.remove("version");
+		}
+	}
+}
+
+// Keys of stored documents we rename with `naming.style` (see naming.rs): the key as serialized by sui-sdk, then its
+// camelCase and snake_case names. Anything not listed here keeps whatever name sui-sdk gives it.
+const SDK_KEYS: &[(&str, &str, &str)] = &[
+	// objects
+	("objectId", "objectId", "object_id"),
+	("previousTransaction", "previousTransaction", "previous_transaction"),
+	("storageRebate", "storageRebate", "storage_rebate"),
+	("dataType", "dataType", "data_type"),
+	("hasPublicTransfer", "hasPublicTransfer", "has_public_transfer"),
+	("bcsBytes", "bcsBytes", "bcs_bytes"),
+	// packages
+	("moduleMap", "moduleMap", "module_map"),
+	("typeOriginTable", "typeOriginTable", "type_origin_table"),
+	("linkageTable", "linkageTable", "linkage_table"),
+	("upgradedId", "upgradedId", "upgraded_id"),
+	("upgradedVersion", "upgradedVersion", "upgraded_version"),
+	("moduleName", "moduleName", "module_name"),
+	("structName", "structName", "struct_name"),
+	// normalized modules
+	("fileFormatVersion", "fileFormatVersion", "file_format_version"),
+	("exposedFunctions", "exposedFunctions", "exposed_functions"),
+	("typeParameters", "typeParameters", "type_parameters"),
+	("isPhantom", "isPhantom", "is_phantom"),
+	("isEntry", "isEntry", "is_entry"),
+];
+
+// Keys of a struct type in normalized modules (`{ "Struct": { .. } }`), which get names saying what they hold.
+const STRUCT_TYPE_KEYS: &[(&str, &str, &str)] = &[
+	("module", "moduleName", "module_name"),
+	("name", "structName", "struct_name"),
+	("typeArguments", "typeParams", "type_params"),
+];
+
+// Keys whose values are maps keyed by Move identifiers, object ids or Display keys, which we never rename.
+const NAMED_MAPS: &[&str] = &["structs", "exposedFunctions", "moduleMap", "linkageTable", "disassembled", "data"];
+
+// Renames the keys of a stored object or normalized module from sui-sdk's names to stable camelCase or snake_case
+// ones. Move data (an object's `content.fields`) is kept as it is.
+pub(crate) fn rename_sdk_keys(doc: &mut Document, snake: bool) {
+	rename_keys(doc, snake, SDK_KEYS)
+}
+
+fn rename_keys(doc: &mut Document, snake: bool, keys: &[(&str, &str, &str)]) {
+	*doc = std::mem::take(doc)
+		.into_iter()
+		.map(|(key, mut value)| {
+			match (key.as_str(), &mut value) {
+				("fields", Bson::Document(_)) => {}
+				("Struct", Bson::Document(struct_type)) => rename_keys(struct_type, snake, STRUCT_TYPE_KEYS),
+				(key, Bson::Document(map)) if NAMED_MAPS.contains(&key) => {
+					map.iter_mut().for_each(|(_, value)| rename_value(value, snake))
+				}
+				_ => rename_value(&mut value, snake),
+			}
+			let key = match keys.iter().find(|(sdk, ..)| *sdk == key) {
+				Some((_, camel, snake_case)) => if snake { snake_case } else { camel }.to_string(),
+				None => key,
+			};
+			(key, value)
+		})
+		.collect();
+}
+
+fn rename_value(value: &mut Bson, snake: bool) {
+	match value {
+		Bson::Document(doc) => rename_keys(doc, snake, SDK_KEYS),
+		Bson::Array(values) => values.iter_mut().for_each(|value| rename_value(value, snake)),
+		_ => {}
+	}
+}
+
+// Raw BSON of an object as we store it. Serialized straight into bytes, without building a `Document` first, as
+// that's a significant part of the CPU time per object on large backfills. Fails for content that can't be stored as
+// BSON at all, like field names containing NUL bytes, which Move doesn't allow, but we'd rather skip such an object
+// than crash on it.
+pub fn object_bson_bytes<T: Serialize>(obj: &T) -> anyhow::Result<Vec<u8>> {
+	Ok(bson::to_raw_document_buf(obj)?.into_bytes())
+}
+
+// File name of a recorded RPC response, see rpcfixtures.rs. `args` is the Debug representation of the call's
+// arguments, hashed to keep names short and valid on every filesystem.
+pub fn fixture_file_name(method: &str, args: &str) -> String {
+	use sha2::{Digest, Sha256};
+	format!("{}_{}.json", method, &hex::encode(Sha256::digest(args.as_bytes()))[..16])
+}
+
+// Hash of what a stored object document holds apart from its version and the transaction that produced it: its type,
+// owner, content and BCS form. Mutations that don't change any of them hash the same. None for documents without
+// content or BCS form, which we can't compare.
+pub(crate) fn content_hash(object: &Document) -> Option<String> {
+	use sha2::{Digest, Sha256};
+	if !object.contains_key("content") && !object.contains_key("bcs") {
+		return None
+	}
+	let mut hashed = Document::new();
+	for key in ["type", "owner", "content", "bcs"] {
+		let Some(value) = object.get(key) else { continue };
+		let mut value = value.clone();
+		if let Bson::Document(part) = &mut value {
+			part.remove("version");
+		}
+		hashed.insert(key, value);
+	}
+	let bytes = bson::to_vec(&hashed).ok()?;
+	Some(hex::encode(Sha256::digest(bytes)))
+}
+
+// Whether a transaction block is part of a sample of `rate` (between 0 and 1) of all of them. Digests are hashes
+// already, so their first bytes are uniformly distributed, and every process sampling at the same rate agrees on the
+// sample.
+pub(crate) fn sampled(digest: &[u8; 32], rate: f64) -> bool {
+	let value = u64::from_be_bytes(digest[..8].try_into().unwrap());
+	(value as f64) < rate * u64::MAX as f64
+}
+
+#[cfg(test)]
+mod test {
+	use std::io::Cursor;
+
+	use bson::{doc, Bson, Document};
+	use proptest::{collection::btree_map, collection::vec, prelude::*};
+	use sui_sdk::rpc_types::{SuiMoveStruct, SuiMoveValue};
+	use sui_types::base_types::ObjectID;
+
+	use crate::utils::{
+		bound_descending_ranges, coin_balance, content_hash, fixture_file_name, kiosk_item, make_descending_ranges, object_bson_bytes, object_doc_matches, slim_object,
+		rename_sdk_keys, sampled, type_components,
+		percentile, transfer_legs, type_params, KioskObject,
+	};
+
+	#[test]
+	fn test_make_descending_ranges() {
+		let res = make_descending_ranges(vec![3, 1, 2, 7, 9, 11, 10]);
+		assert_eq!(res, vec![(11, 9), (7, 7), (3, 1)]);
+	}
+
+	#[test]
+	fn test_bound_descending_ranges() {
+		let ranges = vec![(20, 18), (11, 9), (7, 7), (3, 1)];
+		assert_eq!(bound_descending_ranges(ranges.clone(), 8), vec![(20, 18), (11, 9), (7, 0)]);
+		assert_eq!(bound_descending_ranges(ranges.clone(), 10), vec![(20, 18), (11, 10), (9, 0)]);
+		assert_eq!(bound_descending_ranges(ranges.clone(), 1), vec![(20, 18), (11, 9), (7, 7), (3, 1)]);
+		assert_eq!(bound_descending_ranges(vec![(5, 0)], 3), vec![(5, 3), (2, 0)]);
+	}
+
+	#[test]
+	fn test_percentile() {
+		let values = (1..=100).collect::<Vec<_>>();
+		assert_eq!(percentile(&values, 0.5), Some(50));
+		assert_eq!(percentile(&values, 0.99), Some(99));
+		assert_eq!(percentile(&values, 1.0), Some(100));
+		assert_eq!(percentile(&values, 0.0), Some(1));
+		assert_eq!(percentile::<u32>(&[], 0.5), None);
+	}
+
+	#[test]
+	fn test_object_doc_matches() {
+		let doc = doc! {
+			"type": "0xdee9::clob_v2::Pool<0x2::sui::SUI, 0x5::coin::COIN>",
+			"owner": doc! { "AddressOwner": "0xabc" },
+		};
+		assert!(object_doc_matches(&doc, None, None));
+		assert!(object_doc_matches(&doc, Some("0xdee9::clob_v2::Pool"), Some("0xabc")));
+		assert!(!object_doc_matches(&doc, Some("0xdee9::clob_v2::Order"), None));
+		assert!(!object_doc_matches(&doc, None, Some("0xdef")));
+		let shared = doc! { "type": "0x2::clock::Clock", "owner": doc! { "Shared": doc! { "initial_shared_version": 1 } } };
+		assert!(!object_doc_matches(&shared, None, Some("0xabc")));
+	}
+
+	#[test]
+	fn test_coin_balance() {
+		let coin = doc! {
+			"type": "0x2::coin::Coin<0x2::sui::SUI>",
+			"owner": doc! { "AddressOwner": "0xabc" },
+			"content": doc! { "fields": doc! { "balance": "18446744073709551615", "id": doc! { "id": "0x1" } } },
+		};
+		assert_eq!(coin_balance(&coin), Some(("0x2::sui::SUI".to_string(), Some("0xabc".to_string()), u64::MAX)));
+		let wrapped = doc! {
+			"type": "0x2::coin::Coin<0x2::sui::SUI>",
+			"owner": doc! { "ObjectOwner": "0xdef" },
+			"content": doc! { "fields": doc! { "balance": "5" } },
+		};
+		assert_eq!(coin_balance(&wrapped), Some(("0x2::sui::SUI".to_string(), None, 5)));
+		let metadata = doc! { "type": "0x2::coin::CoinMetadata<0x2::sui::SUI>", "content": doc! { "fields": doc! {} } };
+		assert_eq!(coin_balance(&metadata), None);
+	}
+
+	#[test]
+	fn test_kiosk_objects() {
+		let field = doc! {
+			"type": "0x2::dynamic_field::Field<0x2::dynamic_object_field::Wrapper<0x2::kiosk::Item>, 0x2::object::ID>",
+			"owner": doc! { "ObjectOwner": "0xk" },
+			"content": doc! { "fields": doc! { "value": "0xitem" } },
+		};
+		assert_eq!(kiosk_item(&field), Some(("0xk".to_string(), "0xitem".to_string())));
+		let kiosk = doc! {
+			"type": "0x2::kiosk::Kiosk",
+			"owner": doc! { "Shared": doc! { "initial_shared_version": 1 } },
+			"content": doc! { "fields": doc! { "owner": "0xabc" } },
+		};
+		assert_eq!(kiosk_item(&kiosk), None);
+		assert_eq!(KioskObject::from_doc(&kiosk), Some(KioskObject::Kiosk { owner: Some("0xabc".to_string()) }));
+		let cap = doc! {
+			"type": "0x2::kiosk::KioskOwnerCap",
+			"owner": doc! { "AddressOwner": "0xdef" },
+			"content": doc! { "fields": doc! { "for": "0xk" } },
+		};
+		assert_eq!(
+			KioskObject::from_doc(&cap),
+			Some(KioskObject::OwnerCap { kiosk: "0xk".to_string(), holder: Some("0xdef".to_string()) })
+		);
+		let mut wrapped_cap = cap.clone();
+		wrapped_cap.insert("owner", doc! { "ObjectOwner": "0xp" });
+		assert_eq!(
+			KioskObject::from_doc(&wrapped_cap),
+			Some(KioskObject::OwnerCap { kiosk: "0xk".to_string(), holder: None })
+		);
+	}
+
+	#[test]
+	fn test_type_params() {
+		assert_eq!(type_params("0x2::sui::SUI"), Vec::<&str>::new());
+		assert_eq!(type_params("0x2::coin::Coin<0x2::sui::SUI>"), vec!["0x2::sui::SUI"]);
+		assert_eq!(
+			type_params("0xdee9::clob_v2::Pool<0x2::sui::SUI, 0x5::c::C<0x2::sui::SUI, u64>>"),
+			vec!["0x2::sui::SUI", "0x5::c::C<0x2::sui::SUI, u64>"]
+		);
+	}
+
+	#[test]
+	fn test_type_components() {
+		assert_eq!(
+			type_components(&doc! { "type": "0xdee9::clob_v2::Pool<0x2::sui::SUI, 0x5::c::C<0x2::sui::SUI, u64>>" }),
+			doc! {
+				"type_package": "0x000000000000000000000000000000000000000000000000000000000000dee9",
+				"type_module": "clob_v2",
+				"type_struct": "Pool",
+				"type_params": ["0x2::sui::SUI", "0x5::c::C<0x2::sui::SUI, u64>"],
+			}
+		);
+		assert_eq!(
+			type_components(&doc! { "type": "0x2::coin::CoinMetadata" }).get_array("type_params").unwrap(),
+			&Vec::<Bson>::new()
+		);
+		assert_eq!(type_components(&doc! { "content": { "dataType": "package" } }), doc! {});
+	}
+
+	#[cfg(feature = "dexpools")]
+	#[test]
+	fn test_price_from_sqrt_price_x64() {
+		use crate::utils::price_from_sqrt_price_x64;
+
+		assert_eq!(price_from_sqrt_price_x64(1 << 64), 1.0);
+		assert_eq!(price_from_sqrt_price_x64(2 << 64), 4.0);
+		assert_eq!(price_from_sqrt_price_x64(1 << 63), 0.25);
+	}
+
+	#[test]
+	fn test_transfer_legs() {
+		let sui = "0x2::sui::SUI".to_string();
+		let usdc = "0x5::usdc::USDC".to_string();
+		// plain transfer, the sender also paid for gas
+		assert_eq!(
+			transfer_legs("0xa", &[("0xa".into(), sui.clone(), -1_100), ("0xb".into(), sui.clone(), 1_000)]),
+			vec![(Some("0xa".to_string()), "0xb".to_string(), sui.clone(), 1_000)]
+		);
+		// swap against a pool: the sender's credit has no address to come from
+		assert_eq!(
+			transfer_legs("0xa", &[("0xa".into(), sui.clone(), -500), ("0xa".into(), usdc.clone(), 42)]),
+			vec![(None, "0xa".to_string(), usdc.clone(), 42)]
+		);
+		// several debited owners, attributed to the sender
+		assert_eq!(
+			transfer_legs(
+				"0xa",
+				&[("0xa".into(), sui.clone(), -10), ("0xc".into(), sui.clone(), -10), ("0xb".into(), sui.clone(), 15)]
+			),
+			vec![(Some("0xa".to_string()), "0xb".to_string(), sui, 15)]
+		);
+	}
+
+	#[test]
+	fn test_fixture_file_name() {
+		let name = fixture_file_name("get_checkpoint", "(SequenceNumber(1),)");
+		assert!(name.starts_with("get_checkpoint_") && name.ends_with(".json"));
+		assert_eq!(name.len(), "get_checkpoint_".len() + 16 + ".json".len());
+		// same call, same fixture
+		assert_eq!(name, fixture_file_name("get_checkpoint", "(SequenceNumber(1),)"));
+		assert_ne!(name, fixture_file_name("get_checkpoint", "(SequenceNumber(2),)"));
+		assert_ne!(name, fixture_file_name("get_chain_identifier", "(SequenceNumber(1),)"));
+	}
+
+	// field names we've seen in the wild, plus ones that have special meaning for MongoDB or can't be stored at all
+	fn field_name() -> impl Strategy<Value = String> {
+		prop_oneof![
+			"[a-z_][a-z0-9_]{0,16}",
+			"\\PC{0,32}",
+			Just("_id".to_string()),
+			Just("$type".to_string()),
+			Just("a.b".to_string()),
+			Just(String::new()),
+			Just("a\0b".to_string()),
+		]
+	}
+
+	fn move_value() -> impl Strategy<Value = SuiMoveValue> {
+		let leaf = prop_oneof![
+			any::<u32>().prop_map(SuiMoveValue::Number),
+			any::<bool>().prop_map(SuiMoveValue::Bool),
+			any::<String>().prop_map(SuiMoveValue::String),
+			"\\PC{1000,10000}".prop_map(SuiMoveValue::String),
+			any::<[u8; 32]>().prop_map(|id| SuiMoveValue::UID { id: ObjectID::new(id) }),
+		];
+		leaf.prop_recursive(6, 512, 64, |inner| {
+			prop_oneof![
+				vec(inner.clone(), 0..64).prop_map(SuiMoveValue::Vector),
+				btree_map(field_name(), inner, 0..16).prop_map(|f| SuiMoveValue::Struct(SuiMoveStruct::WithFields(f))),
+			]
+		})
+	}
+
+	fn has_nul_field_name(v: &Bson) -> bool {
+		match v {
+			Bson::Document(d) => d.iter().any(|(k, v)| k.contains('\0') || has_nul_field_name(v)),
+			Bson::Array(a) => a.iter().any(has_nul_field_name),
+			_ => false,
+		}
+	}
+
+	proptest! {
+		// what we read back from MongoDB has to be exactly what we serialized, or we'd silently store something else
+		#[test]
+		fn test_object_bson_round_trip(fields in btree_map(field_name(), move_value(), 0..16)) {
+			let content = SuiMoveStruct::WithFields(fields);
+			let expected = bson::to_bson(&content).unwrap();
+			match object_bson_bytes(&content) {
+				Ok(bytes) => {
+					let stored = Document::from_reader(&mut Cursor::new(&bytes)).unwrap();
+					prop_assert_eq!(Bson::Document(stored), expected);
+				}
+				Err(_) => prop_assert!(has_nul_field_name(&expected)),
+			}
+		}
+	}
+
+	#[test]
+	fn test_slim_object() {
+		let mut object = doc! {
+			"objectId": "0x1",
+			"version": "0x2a",
+			"type": "0x2::coin::Coin<0x2::sui::SUI>",
+			"owner": { "AddressOwner": "0xa" },
+			"content": { "dataType": "moveObject", "type": "0x2::coin::Coin<0x2::sui::SUI>", "fields": { "balance": "5" } },
+			"bcs": { "dataType": "moveObject", "type": "0x2::coin::Coin<0x2::sui::SUI>", "version": 42, "bcsBytes": "AA==" },
+		};
+		slim_object(&mut object);
+		assert_eq!(
+			object,
+			doc! {
+				"type": "0x2::coin::Coin<0x2::sui::SUI>",
+				"owner": { "AddressOwner": "0xa" },
+				"content": { "dataType": "moveObject", "fields": { "balance": "5" } },
+				"bcs": { "dataType": "moveObject", "bcsBytes": "AA==" },
+			}
+		);
+		// packages have no type, and nothing is required to be there
+		let mut package = doc! { "objectId": "0x3", "content": { "dataType": "package" } };
+		slim_object(&mut package);
+		assert_eq!(package, doc! { "content": { "dataType": "package" } });
+	}
+
+	#[test]
+	fn test_rename_sdk_keys() {
+		let object = doc! {
+			"objectId": "0x1",
+			"owner": { "AddressOwner": "0xa" },
+			"previousTransaction": "abc",
+			"content": { "dataType": "moveObject", "hasPublicTransfer": true, "fields": { "objectId": "kept", "isEntry": 1 } },
+			"display": { "data": { "storageRebate": "kept" }, "error": null },
+		};
+		let mut snake = object.clone();
+		rename_sdk_keys(&mut snake, true);
+		assert_eq!(
+			snake,
+			doc! {
+				"object_id": "0x1",
+				"owner": { "AddressOwner": "0xa" },
+				"previous_transaction": "abc",
+				"content": {
+					"data_type": "moveObject", "has_public_transfer": true, "fields": { "objectId": "kept", "isEntry": 1 },
+				},
+				"display": { "data": { "storageRebate": "kept" }, "error": null },
+			}
+		);
+		let mut camel = object.clone();
+		rename_sdk_keys(&mut camel, false);
+		assert_eq!(camel, object);
+
+		let mut module = doc! {
+			"fileFormatVersion": 6,
+			"structs": { "Coin": { "typeParameters": [{ "isPhantom": true }], "fields": [
+				{ "name": "balance", "type": { "Struct": {
+					"address": "0x2", "module": "balance", "name": "Balance", "typeArguments": [{ "TypeParameter": 0 }],
+				} } },
+			] } },
+		};
+		rename_sdk_keys(&mut module, true);
+		assert_eq!(
+			module,
+			doc! {
+				"file_format_version": 6,
+				"structs": { "Coin": { "type_parameters": [{ "is_phantom": true }], "fields": [
+					{ "name": "balance", "type": { "Struct": {
+						"address": "0x2", "module_name": "balance", "struct_name": "Balance", "type_params": [{ "TypeParameter": 0 }],
+					} } },
+				] } },
+			}
+		);
+	}
+
+	#[test]
+	fn test_sampled() {
+		let digest = |first: u64| {
+			let mut digest = [0xffu8; 32];
+			digest[..8].copy_from_slice(&first.to_be_bytes());
+			digest
+		};
+		assert!(sampled(&digest(0), 0.01));
+		assert!(sampled(&digest(u64::MAX / 200), 0.01));
+		assert!(!sampled(&digest(u64::MAX / 50), 0.01));
+		assert!(sampled(&digest(u64::MAX / 2), 1.0));
+		assert!(!sampled(&digest(0), 0.0));
+	}
+
+	#[test]
+	fn test_content_hash() {
+		let object = |version: &str, balance: &str| {
+			doc! {
+				"objectId": "0x5",
+				"version": version,
+				"previousTransaction": format!("tx{}", version),
+				"type": "0x2::coin::Coin<0x2::sui::SUI>",
+				"owner": { "AddressOwner": "0x7" },
+				"content": { "dataType": "moveObject", "fields": { "balance": balance } },
+				"bcs": { "dataType": "moveObject", "version": version, "bcsBytes": balance },
+			}
+		};
+		let hash = content_hash(&object("1", "100"));
+		assert!(hash.is_some());
+		assert_eq!(content_hash(&object("2", "100")), hash);
+		assert_ne!(content_hash(&object("2", "101")), hash);
+		let mut moved = object("2", "100");
+		moved.insert("owner", doc! { "AddressOwner": "0x8" });
+		assert_ne!(content_hash(&moved), hash);
+		assert_eq!(content_hash(&doc! { "objectId": "0x5", "version": "1" }), None);
+	}
+}
@@ -0,0 +1,99 @@
+use bson::Document;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::{mpsc, OnceCell};
+
+use crate::{
+	_prelude::*,
+	conf::{WebhookRule, WebhooksConfig},
+	etl::ObjectItem,
+	routing::{self, Matcher},
+};
+
+// Every rule with the queue of loaded items (with their object data, unless they're a deletion) matching it, waiting to
+// be delivered. Only set up by `spawn_webhooks()`, so commands that don't call it (e.g. `bench`) never fire any webhooks.
+static QUEUES: OnceCell<Vec<(Matcher, mpsc::Sender<(ObjectItem, Option<Document>)>)>> = OnceCell::const_new();
+
+// upper bound of the backoff between delivery attempts, however many retries are configured
+const MAX_BACKOFF_MS: u64 = 300_000;
+
+// Posts every loaded object change matching one of the configured rules (or routing rules with a webhook, see
+// routing.rs) to the rule's URL. Deliveries happen in the background, each rule with its own queue and task, so slow
+// receivers neither hold up loading nor the other rules' receivers; if a rule's queue is full, changes are dropped
+// with a warning.
+// With a `secret`, the request body is signed via HMAC-SHA256, sent as `X-Huracan-Signature: sha256=<hex>`.
+pub fn spawn_webhooks(cfg: &AppConfig) -> anyhow::Result<()> {
+	let network = cfg.network().to_string();
+	let aliases = cfg.types.aliases()?;
+	let mut rules = cfg
+		.webhooks
+		.rules
+		.iter()
+		.map(|rule| Ok((rule.clone(), Matcher::new(rule.type_.as_deref(), None, rule.owner.as_deref(), &aliases)?)))
+		.collect::<anyhow::Result<Vec<_>>>()?;
+	rules.extend(routing::webhook_routes(cfg)?);
+	if rules.is_empty() {
+		return Ok(())
+	}
+	let cfg = cfg.webhooks.clone();
+	let client = reqwest::Client::builder().timeout(Duration::from_millis(cfg.timeoutms)).build()?;
+	let mut queues = Vec::with_capacity(rules.len());
+	for (rule, matcher) in rules {
+		let (tx, mut rx) = mpsc::channel(cfg.queuesize);
+		queues.push((matcher, tx));
+		let (cfg, network, client) = (cfg.clone(), network.clone(), client.clone());
+		tokio::spawn(async move {
+			while let Some((item, object)) = rx.recv().await {
+				deliver(&client, &cfg, &network, &rule, &item, object.as_ref()).await;
+			}
+		});
+	}
+	QUEUES.set(queues).map_err(|_| anyhow!("webhooks have already been set up"))?;
+	Ok(())
+}
+
+// To be called for every item after it has been loaded successfully, with `objects` as fetched.
+pub async fn notify(items: &[ObjectItem], objects: &[Document]) {
+	let Some(queues) = QUEUES.get() else { return };
+	for (item, object) in items.iter().zip(objects) {
+		let object = (!item.deletion).then_some(object);
+		for (_, tx) in queues.iter().filter(|(matcher, _)| matcher.matches(object)) {
+			if tx.try_send((item.clone(), object.cloned())).is_err() {
+				warn!(object_id = ?item.id, "WebhookWarning: Webhook queue is full, dropping change.");
+			}
+		}
+	}
+}
+
+async fn deliver(
+	client: &reqwest::Client,
+	cfg: &WebhooksConfig,
+	network: &str,
+	rule: &WebhookRule,
+	item: &ObjectItem,
+	object: Option<&Document>,
+) {
+	let body = routing::snapshot(item, object, network).to_string();
+	let signature = rule.secret.as_ref().map(|secret| {
+		let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+		mac.update(body.as_bytes());
+		format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+	});
+	for attempt in 0..=cfg.retries {
+		if attempt > 0 {
+			let backoff = cfg.backoffms.saturating_mul(1 << (attempt - 1).min(16)).min(MAX_BACKOFF_MS);
+			tokio::time::sleep(Duration::from_millis(backoff)).await;
+		}
+		let mut req = client.post(&rule.url).header("content-type", "application/json").body(body.clone());
+		if let Some(signature) = &signature {
+			req = req.header("x-huracan-signature", signature);
+		}
+		match req.send().await.and_then(|res| res.error_for_status()) {
+			Ok(_) => return,
+			Err(err) => {
+				warn!(url = rule.url, object_id = ?item.id, attempt, error = ?err, "WebhookWarning: Failed delivering webhook.")
+			}
+		}
+	}
+	error!(url = rule.url, object_id = ?item.id, "WebhookWarning: Giving up on delivering webhook.");
+}
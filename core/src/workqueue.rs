@@ -0,0 +1,140 @@
+use bson::{doc, oid::ObjectId, DateTime, Document};
+use mongodb::{
+	options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions},
+	Collection,
+};
+
+use crate::{
+	_prelude::*, cli::CoordinateArgs, commands::spawn_sinks, etl::spawn_backfill_pipeline, filters,
+	mongo::{completed_checkpoints, mongo_collection_name},
+};
+
+// Work items are checkpoint ranges stored in e.g. prod_testnet_objects_workitems, so that any number of worker
+// processes (on any number of machines) can share a large backfill:
+// { _id: "<start>-<end>", start, end, done, owner, leased_until, attempts }
+// Workers lease one item at a time and keep renewing that lease while processing it. If a worker dies, its lease
+// expires and the item is picked up by another worker. Items are only done once all of their checkpoints are completed,
+// which they aren't while objects in them failed without being stored to be retried later (see retries.rs); otherwise
+// the lease is left to expire as well, and the next attempt processes the checkpoints that didn't complete again.
+fn work_items(db: &mongodb::Database, cfg: &AppConfig) -> Collection<Document> {
+	db.collection::<Document>(&mongo_collection_name(cfg, "_workitems"))
+}
+
+// Entrypoint for the `coordinate` subcommand.
+// Creating work items is idempotent, so re-running this with the same arguments doesn't reset any progress.
+pub async fn run_coordinate(cfg: &AppConfig, args: CoordinateArgs) -> anyhow::Result<()> {
+	let to = match args.to {
+		Some(to) => to,
+		None => cfg.sui().await?.get_latest_checkpoint_sequence_number().await?,
+	};
+	if args.from > to || args.chunk_size == 0 {
+		return Err(anyhow!("nothing to do for --from {} --to {} --chunk-size {}", args.from, to, args.chunk_size))
+	}
+	let db = cfg.mongo.client(&cfg.backfill.mongo).await?;
+	let coll = work_items(&db, cfg);
+	let (mut created, mut start) = (0, args.from);
+	while start <= to {
+		let end = (start + args.chunk_size - 1).min(to);
+		let res = coll
+			.update_one(
+				doc! { "_id": format!("{}-{}", start, end) },
+				doc! { "$setOnInsert": { "start": start as i64, "end": end as i64, "done": false, "attempts": 0 } },
+				UpdateOptions::builder().upsert(true).build(),
+			)
+			.await?;
+		if res.upserted_id.is_some() {
+			created += 1;
+		}
+		start = end + 1;
+	}
+	println!("created {} work items for checkpoints {} to {}", created, args.from, to);
+	Ok(())
+}
+
+// Entrypoint for the `work` subcommand.
+pub async fn run_worker(cfg: &AppConfig) -> anyhow::Result<()> {
+	let db = cfg.mongo.client(&cfg.backfill.mongo).await?;
+	filters::spawn_watchlist_refresh(cfg, &db).await?;
+	spawn_sinks(cfg).await?;
+	let coll = work_items(&db, cfg);
+	let id = ObjectId::new().to_hex();
+	let lease = Duration::from_millis(cfg.lock.leasems);
+	info!(worker = %id, "WorkerInfo: Starting worker.");
+
+	loop {
+		let Some(item) = lease_next(&coll, &id, lease).await? else {
+			if coll.count_documents(doc! { "done": false }, None).await? == 0 {
+				info!(worker = %id, "WorkerInfo: No work left, exiting.");
+				return Ok(())
+			}
+			// everything left is currently leased by other workers, but they might die
+			tokio::time::sleep(lease / 3).await;
+			continue
+		};
+		let item_id = item.get_str("_id")?.to_string();
+		let (start, end) = (item.get_i64("start")? as u64, item.get_i64("end")? as u64);
+		info!(worker = %id, item = %item_id, attempt = item.get_i32("attempts").unwrap_or(0), "WorkerInfo: Processing checkpoints {} to {}.", start, end);
+
+		let heartbeat = tokio::spawn({
+			let (coll, id, item_id) = (coll.clone(), id.clone(), item_id.clone());
+			async move {
+				loop {
+					tokio::time::sleep(lease / 3).await;
+					let res = coll
+						.update_one(
+							doc! { "_id": &item_id, "owner": &id },
+							doc! { "$set": { "leased_until": leased_until(lease) } },
+							None,
+						)
+						.await;
+					match res {
+						Ok(res) if res.matched_count == 0 => {
+							// someone else took over after our lease expired; both of us finishing it is harmless
+							warn!(worker = %id, item = %item_id, "WorkerWarning: Lost lease on work item.");
+							break
+						}
+						Ok(_) => {}
+						Err(err) => warn!(worker = %id, item = %item_id, error = ?err, "WorkerWarning: Failed renewing lease, will retry."),
+					}
+				}
+			}
+		});
+
+		let sui = cfg.sui().await?;
+		let (_, handle) = spawn_backfill_pipeline(cfg, &cfg.backfill, sui, Some(end), Some(start)).await?;
+		let res = handle.await;
+		heartbeat.abort();
+		if let Err(err) = res {
+			// leave it to expire, so it's retried by whichever worker gets to it first
+			error!(worker = %id, item = %item_id, error = ?err, "WorkerError: Failed processing work item.");
+			continue
+		}
+		let completed = completed_checkpoints(cfg, &db, start, end).await?;
+		if completed < end - start + 1 {
+			warn!(worker = %id, item = %item_id, "WorkerWarning: {} of {} checkpoints not completed, leaving work item to be retried.", end - start + 1 - completed, end - start + 1);
+			continue
+		}
+		coll.update_one(
+			doc! { "_id": &item_id },
+			doc! { "$set": { "done": true }, "$unset": { "owner": "", "leased_until": "" } },
+			None,
+		)
+		.await?;
+		info!(worker = %id, item = %item_id, "WorkerInfo: Completed work item.");
+	}
+}
+
+// Leases the highest pending work item that's either not leased at all, or whose lease has expired.
+async fn lease_next(coll: &Collection<Document>, id: &str, lease: Duration) -> anyhow::Result<Option<Document>> {
+	Ok(coll
+		.find_one_and_update(
+			doc! { "done": false, "$or": [ { "leased_until": null }, { "leased_until": { "$lt": DateTime::now() } } ] },
+			doc! { "$set": { "owner": id, "leased_until": leased_until(lease) }, "$inc": { "attempts": 1 } },
+			FindOneAndUpdateOptions::builder().sort(doc! { "end": -1 }).return_document(ReturnDocument::After).build(),
+		)
+		.await?)
+}
+
+fn leased_until(lease: Duration) -> DateTime {
+	DateTime::from_millis(DateTime::now().timestamp_millis() + lease.as_millis() as i64)
+}
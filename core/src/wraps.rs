@@ -0,0 +1,69 @@
+use bson::doc;
+use mongodb::Database;
+use sui_sdk::rpc_types::{ObjectChange, SuiTransactionBlockResponse};
+
+use crate::{
+	_prelude::*, influx::write_metric_mongo_write_error, mongo::mongo_collection_name, routing::routed_collections,
+};
+
+// An object wrapped into another one is no longer accessible on its own, but it hasn't been destroyed either: once
+// unwrapped, it re-appears with the same id and a newer version. We mark wrapped objects in the main collection with
+// `wrapped_in_tx` (keeping their last known state), and the load step removes the mark again as soon as it stores
+// a newer version of the object.
+// The version of a wrap is that of the wrapping transaction, so older versions arriving late can't undo the mark.
+// Objects we haven't stored yet are not upserted, as we'd have no state to show for them. Wraps don't tell the wrapped
+// object's type, so they're applied to every routed collection (see routing.rs), too.
+pub async fn mark_wrapped(
+	cfg: &AppConfig,
+	pc: &PipelineConfig,
+	db: &Database,
+	blocks: &[SuiTransactionBlockResponse],
+) {
+	let updates = blocks
+		.iter()
+		.flat_map(|block| block.object_changes.iter().flatten().map(move |change| (block, change)))
+		.filter_map(|(block, change)| match change {
+			ObjectChange::Wrapped { object_id, version, .. } => Some((block.digest, *object_id, *version)),
+			_ => None,
+		})
+		.map(|(digest, object_id, version)| {
+			let v = version.to_string();
+			let v_ = version.value() as i64;
+			let newer = doc! { "$lt": [ { "$ifNull": [ "$version_", -1i64 ] }, v_ ] };
+			doc! {
+				"q": doc! { "_id": object_id.to_string() },
+				"u": vec![doc! {
+					"$set": {
+						"version_": { "$cond": { "if": newer.clone(), "then": v_, "else": "$version_" } },
+						"version": { "$cond": { "if": newer.clone(), "then": v, "else": "$version" } },
+						"wrapped_in_tx": { "$cond": { "if": newer, "then": digest.to_string(), "else": "$wrapped_in_tx" } },
+					},
+				}],
+				"upsert": false,
+				"multi": false,
+			}
+		})
+		.collect::<Vec<_>>();
+	if updates.is_empty() {
+		return
+	}
+	let mut collections = vec![mongo_collection_name(cfg, "")];
+	collections.extend(routed_collections(cfg).await);
+	for collection in collections {
+		let mut retries_left = pc.mongo.retries;
+		loop {
+			match db.run_command(doc! { "update": &collection, "updates": updates.clone() }, None).await {
+				Ok(_) => break,
+				Err(err) => {
+					write_metric_mongo_write_error().await;
+					if retries_left == 0 {
+						error!(error = ?err, "failed to mark {} objects as wrapped", updates.len());
+						break
+					}
+					warn!("error marking objects as wrapped, will retry {} more times: {:?}", retries_left, err);
+					retries_left -= 1;
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,137 @@
+// shared by several test binaries, each of which only uses some of it
+#![allow(dead_code)]
+
+use std::{
+	path::{Path, PathBuf},
+	str::FromStr,
+};
+
+use bson::{doc, Document};
+use huracan_core::{
+	cli::{Command, RunArgs},
+	conf::AppConfig,
+	etl::{object_data_options, SUI_QUERY_MAX_RESULT_LIMIT},
+	rpcfixtures,
+};
+use mongodb::{Client, Database};
+use sui_sdk::rpc_types::{SuiTransactionBlockResponseOptions, SuiTransactionBlockResponseQuery, TransactionFilter};
+use sui_types::base_types::{ObjectID, TransactionDigest};
+
+// Config for backfilling checkpoint 5 from the recorded responses in fixtures/backfill into a throwaway database, see
+// rpcfixtures.rs. Needs a MongoDB at HURACAN_TEST_MONGO, and with the `pulsar` feature, a Pulsar at
+// HURACAN_TEST_PULSAR for the dead-letter store (with HURACAN_TEST_PULSAR_TOKEN, if it requires authentication), which
+// is why the tests using it are ignored by default, see .github/workflows/integration.yml. `adjust` is applied before
+// the config singleton is set up.
+pub async fn test_config(name: &str, adjust: impl FnOnce(&mut AppConfig)) -> (AppConfig, Database, PathBuf) {
+	let uri = std::env::var("HURACAN_TEST_MONGO").expect("HURACAN_TEST_MONGO must be set to a MongoDB URI");
+	let mut cfg = AppConfig::load("../main/config.yaml").unwrap();
+	#[cfg(feature = "pulsar")]
+	{
+		cfg.pulsar.url = std::env::var("HURACAN_TEST_PULSAR").expect("HURACAN_TEST_PULSAR must be set to a Pulsar URL");
+		// a standalone Pulsar without authentication ignores the token
+		cfg.pulsar.token = Some(std::env::var("HURACAN_TEST_PULSAR_TOKEN").unwrap_or_default());
+		cfg.pulsar.tokenfile = None;
+	}
+	let tmp = std::env::temp_dir().join(format!("huracan_test_{}_{}", name, std::process::id()));
+	std::fs::create_dir_all(&tmp).unwrap();
+	cfg.mongo.uri = uri.clone();
+	cfg.mongo.urifile = None;
+	cfg.mongo.db = format!("huracan_test_{}_{}", name, std::process::id());
+	cfg.rpcfixtures.mode = "replay".into();
+	cfg.rpcfixtures.dir = tmp.join("fixtures").to_string_lossy().to_string();
+	cfg.rocksdbfile = tmp.join("backfill.db").to_string_lossy().to_string();
+	cfg.metrics.influx = false;
+	cfg.lock.enabled = false;
+	cfg.backfillonly = true;
+	cfg.backfillstartcheckpoint = Some(5);
+	cfg.extract.start = "checkpoint".into();
+	cfg.extract.checkpoint = Some(5);
+	cfg.backfill.workers.checkpoint = Some(1);
+	cfg.backfill.workers.object = Some(1);
+	cfg.backfill.workers.mongo = Some(1);
+	cfg.backfill.workers.ordered = false;
+	// chunks are only cut by size or at the end of the stream, never by time, so the requests are always the same
+	cfg.backfill.objectqueries.batchsize = 50;
+	cfg.backfill.objectqueries.batchwaittimeoutms = 60_000;
+	cfg.backfill.objectqueries.adaptive = false;
+	cfg.backfill.mongo.batchsize = 2;
+	cfg.backfill.mongo.batchwaittimeoutms = 60_000;
+	adjust(&mut cfg);
+	// the sentry guard is a no-op without a DSN, which tests never have
+	let _ = huracan_core::init(&cfg).await.unwrap();
+	install_fixtures(&cfg.rpcfixtures.dir);
+	let db = Client::with_uri_str(&uri).await.unwrap().database(&cfg.mongo.db);
+	(cfg, db, tmp)
+}
+
+pub fn backfill() -> Command {
+	Command::Run(RunArgs { skip_self_check: true, ..Default::default() })
+}
+
+pub fn object_id(n: u64) -> ObjectID {
+	ObjectID::from_str(&format!("{:#x}", n)).unwrap()
+}
+
+pub async fn stored(cfg: &AppConfig, db: &Database, id: u64) -> Option<Document> {
+	let objects = db.collection::<Document>(&format!("{}_{}_{}", cfg.env, cfg.net, cfg.mongo.collectionbase));
+	objects.find_one(doc! { "_id": object_id(id).to_string() }, None).await.unwrap()
+}
+
+// Everything checkpoint 5 changed is stored, and it counts as completed.
+pub async fn assert_backfilled(cfg: &AppConfig, db: &Database) {
+	for (id, version, balance) in [(0xa, 10, "1000"), (0xb, 10, "2000"), (0xc, 11, "3000")] {
+		let object = stored(cfg, db, id).await.unwrap_or_else(|| panic!("object {:x} not stored", id));
+		assert_eq!(object.get_i64("version_").unwrap(), version);
+		let content = object.get_document("object").unwrap().get_document("content").unwrap();
+		assert_eq!(content.get_document("fields").unwrap().get_str("balance").unwrap(), balance);
+	}
+	assert!(stored(cfg, db, 0xd).await.unwrap().get_bool("deleted").unwrap());
+	let checkpoints =
+		db.collection::<Document>(&format!("{}_{}_{}_checkpoints", cfg.env, cfg.net, cfg.mongo.collectionbase));
+	assert!(checkpoints.find_one(doc! { "_id": 5i64, "stop": { "$ne": true } }, None).await.unwrap().is_some());
+}
+
+pub async fn cleanup(db: Database, tmp: PathBuf) {
+	db.drop(None).await.unwrap();
+	std::fs::remove_dir_all(&tmp).ok();
+}
+
+// The fixtures are kept under readable names, and copied to the names the ClientPool looks them up by, which depend
+// on the Debug representation of the exact arguments of each call.
+fn install_fixtures(dir: &str) {
+	let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/backfill");
+	std::fs::create_dir_all(dir).unwrap();
+	let query = SuiTransactionBlockResponseQuery::new(
+		Some(TransactionFilter::Checkpoint(5)),
+		Some(SuiTransactionBlockResponseOptions::new().with_object_changes()),
+	);
+	let first_page_end = TransactionDigest::from_str("8aa3PPhXJkUJzgYy7wLaTsnKG5hGbyt6kyGPFkq2ev5g").unwrap();
+	let limit = Some(SUI_QUERY_MAX_RESULT_LIMIT);
+	let options = object_data_options();
+	let calls = [
+		("get_chain_identifier.json", rpcfixtures::file_name("get_chain_identifier", &())),
+		(
+			"query_transaction_blocks_5_page1.json",
+			rpcfixtures::file_name("query_transaction_blocks", &(&query, &None::<TransactionDigest>, &limit, &true)),
+		),
+		(
+			"query_transaction_blocks_5_page2.json",
+			rpcfixtures::file_name("query_transaction_blocks", &(&query, &Some(first_page_end), &limit, &true)),
+		),
+		// deletions aren't fetched, and the rest in the order they were extracted in
+		(
+			"multi_get_object_with_options.json",
+			rpcfixtures::file_name(
+				"multi_get_object_with_options",
+				&(&vec![object_id(0xa), object_id(0xb), object_id(0xc)], &options),
+			),
+		),
+		(
+			"get_object_with_options_b.json",
+			rpcfixtures::file_name("get_object_with_options", &(&object_id(0xb), &options)),
+		),
+	];
+	for (fixture, name) in calls {
+		std::fs::copy(src.join(fixture), Path::new(dir).join(name)).unwrap();
+	}
+}
@@ -0,0 +1,29 @@
+use clap::Parser;
+use huracan_core::{cli::Args, conf::AppConfig};
+
+// CLI flags take precedence over everything from the config file, including credentials read from files.
+#[tokio::test]
+async fn test_mongo_uri_override_wins_over_urifile() {
+	let mut cfg = AppConfig::load("../main/config.yaml").unwrap();
+	let path = std::env::temp_dir().join(format!("huracan_test_mongouri_{}", std::process::id()));
+	std::fs::write(&path, "mongodb://from-file:27017\n").unwrap();
+	cfg.mongo.urifile = Some(path.to_string_lossy().to_string());
+
+	Args::parse_from(["indexer", "--mongo-uri", "mongodb://from-cli:27017"]).apply_overrides(&mut cfg);
+	cfg.load_secrets().await.unwrap();
+	std::fs::remove_file(&path).ok();
+	assert_eq!(cfg.mongo.uri, "mongodb://from-cli:27017");
+}
+
+#[tokio::test]
+async fn test_urifile_without_override() {
+	let mut cfg = AppConfig::load("../main/config.yaml").unwrap();
+	let path = std::env::temp_dir().join(format!("huracan_test_mongouri_plain_{}", std::process::id()));
+	std::fs::write(&path, "mongodb://from-file:27017\n").unwrap();
+	cfg.mongo.urifile = Some(path.to_string_lossy().to_string());
+
+	Args::parse_from(["indexer"]).apply_overrides(&mut cfg);
+	cfg.load_secrets().await.unwrap();
+	std::fs::remove_file(&path).ok();
+	assert_eq!(cfg.mongo.uri, "mongodb://from-file:27017");
+}
@@ -0,0 +1,23 @@
+mod common;
+
+use huracan_core::commands;
+
+use crate::common::{assert_backfilled, backfill, cleanup, test_config};
+
+// Backfills checkpoint 5 from the recorded responses in fixtures/backfill into a throwaway database, see
+// common/mod.rs:
+// - extraction: the checkpoint's transaction blocks come in two pages, and the checkpoint only counts as completed
+//   once the changes of both have been loaded
+// - transform: object B is missing from the multi-get response, so it has to be fetched on its own
+// - load: with a mongo batch size of 2, the 4 changes are written in two bulk writes
+// The database is dropped afterwards.
+#[tokio::test]
+#[ignore = "needs MongoDB (and Pulsar), see common/mod.rs"]
+async fn test_backfill_from_fixtures() {
+	let (cfg, db, tmp) = test_config("pipeline", |_| {}).await;
+
+	commands::run(&cfg, backfill()).await.unwrap();
+
+	assert_backfilled(&cfg, &db).await;
+	cleanup(db, tmp).await;
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use huracan_core::{
+	cli::{Command, RewindArgs},
+	commands,
+};
+
+use crate::common::{assert_backfilled, backfill, cleanup, stored, test_config};
+
+// Rewinding with --invalidate to before checkpoint 5 removes everything the backfill stored for it. The idempotency
+// guard has to forget about these changes as well, or backfilling checkpoint 5 again would skip all of them as
+// already applied.
+#[tokio::test]
+#[ignore = "needs MongoDB (and Pulsar), see common/mod.rs"]
+async fn test_rewind_and_reload() {
+	let (cfg, db, tmp) = test_config("rewind", |cfg| {
+		cfg.history.enabled = true;
+		cfg.idempotency.enabled = true;
+	})
+	.await;
+	commands::run(&cfg, backfill()).await.unwrap();
+	assert_backfilled(&cfg, &db).await;
+
+	let rewind = RewindArgs { checkpoint: Some(4), digest: None, invalidate: true };
+	commands::run(&cfg, Command::Rewind(rewind)).await.unwrap();
+	for id in [0xa, 0xb, 0xc, 0xd] {
+		assert!(stored(&cfg, &db, id).await.is_none(), "object {:x} still stored after rewinding", id);
+	}
+
+	commands::run(&cfg, backfill()).await.unwrap();
+	assert_backfilled(&cfg, &db).await;
+	cleanup(db, tmp).await;
+}
@@ -1,5 +1,5 @@
 use quote::quote;
-use syn::{parse_macro_input, parse_quote, DeriveInput, ItemFn, Stmt};
+use syn::{parse_macro_input, parse_quote, DeriveInput, FnArg, ItemFn, Pat, Stmt};
 
 #[proc_macro_derive(PulsarMessage)]
 pub fn pulsar_message(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
@@ -36,6 +36,19 @@ pub fn with_client_rotation(_attr: proc_macro::TokenStream, item: proc_macro::To
 	});
 
 	let Stmt::Expr(call, _) = block.stmts[0].clone() else { panic!("body of function must be an expression like `some_sui_api_call(... args ...).await`!")};
+	// recorded responses are looked up by method name + arguments, see rpcfixtures.rs in the indexer
+	let method = sig.ident.to_string();
+	let args = sig
+		.inputs
+		.iter()
+		.filter_map(|arg| match arg {
+			FnArg::Typed(arg) => match &*arg.pat {
+				Pat::Ident(pat) => Some(pat.ident.clone()),
+				_ => panic!("arguments must be plain identifiers!"),
+			},
+			FnArg::Receiver(_) => None,
+		})
+		.collect::<Vec<_>>();
 
 	let code = quote! {
 		#(#attrs)* #vis #sig {
@@ -54,18 +67,30 @@ pub fn with_client_rotation(_attr: proc_macro::TokenStream, item: proc_macro::To
 			// 1) spawn the next client, if any left, and use that
 			// 2) select the client whose backoff interval is expiring the soonest
 
+			let fixtures = crate::rpcfixtures::mode();
+			let fixture_args = if fixtures == crate::rpcfixtures::Mode::Off { String::new() } else { format!("{:?}", (#(&#args,)*)) };
+			if fixtures == crate::rpcfixtures::Mode::Replay {
+				return crate::rpcfixtures::replay(#method, &fixture_args)
+			}
+
 			let client = &mut self.clients[0];
 			let api = client.read_api();
+			#[cfg(feature = "chaos")]
+			crate::chaos::maybe_delay_rpc().await;
 			let res = api.#call;
+			#[cfg(feature = "chaos")]
+			let res = crate::chaos::maybe_fail_rpc(res);
 			client.reqs += 1;
-			let limited = if let Err(sui_sdk::error::Error::RpcError(jsonrpsee::core::Error::Transport(err))) = res.as_ref() && format!("{}", err).contains("429") {
-				true
-			} else {
-				false
-			};
+			let limited = matches!(
+				res.as_ref(),
+				Err(sui_sdk::error::Error::RpcError(jsonrpsee::core::Error::Transport(err))) if format!("{}", err).contains("429")
+			);
 			if !limited {
 				// client is OK, so reset backoff, if any
 				client.backoff = None;
+				if fixtures == crate::rpcfixtures::Mode::Record {
+					crate::rpcfixtures::record(#method, &fixture_args, &res);
+				}
 				return res
 			}
 
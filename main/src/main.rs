@@ -1,64 +1,42 @@
-#![feature(drain_filter)]
-#![feature(btree_drain_filter)]
-#![feature(slice_group_by)]
-#![feature(let_chains)]
-#![feature(iter_advance_by)]
-#![feature(int_roundings)]
-#![feature(map_try_insert)]
+use std::{fs::File, sync::Mutex};
 
-#[macro_use]
-extern crate serde;
-
-use std::fs::File;
-use std::sync::atomic::{AtomicBool, Ordering};
-
-use _prelude::*;
-use conf::AppConfig;
+use anyhow::Context as _;
+use clap::Parser;
 use dotenv::dotenv;
+use huracan_core::{
+	cli::{Args, Command},
+	commands,
+	conf::AppConfig,
+};
 use tracing_subscriber::filter::EnvFilter;
-use crate::conf::{setup_config_singleton, setup_influx_singleton};
-use crate::pulsar::setup_pulsar_singleton;
-
-mod _prelude;
-mod client;
-mod conf;
-mod etl;
-mod mongo;
-mod pulsar;
-mod utils;
-
-mod influx;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
 	dotenv().ok();
 
-	let cfg = AppConfig::new()?;
+	let args = Args::parse();
+	let mut cfg = AppConfig::new()?;
+	// before loading secrets, so secret references given via CLI flags are resolved, too
+	args.apply_overrides(&mut cfg);
+	cfg.load_secrets().await.context("cannot load secrets")?;
 
 	if cfg.log.tokioconsole == true {
-		setup_console_tracing(&cfg).context("cannot setup tracing")?;
+		#[cfg(feature = "diagnostics")]
+		huracan_core::diagnostics::setup_console_tracing().context("cannot setup tracing")?;
+		#[cfg(not(feature = "diagnostics"))]
+		return Err(anyhow::anyhow!("log.tokioconsole needs a build with the diagnostics feature"))
 	}
 	else {
 		setup_tracing(&cfg).context("cannot setup tracing")?;
 	}
 
-	setup_config_singleton(&cfg).await;
-	setup_influx_singleton().await;
-	setup_pulsar_singleton().await;
-
-	if cfg.backfillonly == true && cfg.livescanonly == true {
-		panic!("livescanonly is true AND backfillonly is true. Reconfigure in config.yaml");
-	}
-	if cfg.backfillonly == true && cfg.livescanonly == false {
-		let start_checkpoint = cfg.backfillstartcheckpoint;
-		etl::run_backfill_only(&cfg, start_checkpoint).await?;
-	}
-	else {
-		etl::run(&cfg).await.unwrap();
+	#[cfg(not(feature = "chaos"))]
+	if cfg.chaos.enabled {
+		tracing::warn!("ChaosWarning: chaos.enabled has no effect, as this build doesn't include the chaos feature.");
 	}
+	let _sentry = huracan_core::init(&cfg).await?;
 
-
-	Ok(())
+	commands::run(&cfg, args.command.unwrap_or_else(|| Command::Run(Default::default()))).await
 }
 
 // Setup default tracing mode, which does not enable tokio-console
@@ -109,23 +87,3 @@ fn setup_tracing(cfg: &AppConfig) -> anyhow::Result<()> {
 	Ok(())
 
 }
-
-// Setup tracing with tokio-console enabled.
-// See: https://tokio.rs/tokio/topics/tracing-next-steps
-fn setup_console_tracing(cfg: &AppConfig) -> anyhow::Result<()> {
-	console_subscriber::init();
-	Ok(())
-}
-
-// Ensure Tokio threads are drained on a smooth shutdown
-pub fn ctrl_c_bool() -> Arc<AtomicBool> {
-	let stop = Arc::new(AtomicBool::new(false));
-	tokio::spawn({
-		let stop = stop.clone();
-		async move {
-			tokio::signal::ctrl_c().await.unwrap();
-			stop.store(true, Ordering::Relaxed);
-		}
-	});
-	stop
-}
@@ -0,0 +1,116 @@
+use std::{
+	collections::{HashMap, HashSet},
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::changes::ChangeFeed;
+
+// In-process cache for hot reads, keyed by the normalized query (e.g. the MongoDB filter it translates to).
+// Entries expire after APP_CACHE_TTL_SECS (default 10, 0 disables caching), and are dropped as soon as one of the
+// objects they contain changes, as reported by the change feed. Queries newly matching a changed object are only
+// picked up once their entry expires.
+pub struct ResponseCache {
+	ttl:      Duration,
+	capacity: usize,
+	inner:    Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+	entries:   HashMap<String, Entry>,
+	// object id -> keys of all entries containing it
+	by_object: HashMap<String, HashSet<String>>,
+}
+
+struct Entry {
+	at:      Instant,
+	value:   serde_json::Value,
+	objects: Vec<String>,
+}
+
+impl Inner {
+	fn remove(&mut self, key: &str) {
+		let Some(entry) = self.entries.remove(key) else { return };
+		for id in entry.objects {
+			if let Some(keys) = self.by_object.get_mut(&id) {
+				keys.remove(key);
+				if keys.is_empty() {
+					self.by_object.remove(&id);
+				}
+			}
+		}
+	}
+}
+
+impl ResponseCache {
+	pub fn from_env() -> Self {
+		let var = |name: &str, default| std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default);
+		Self {
+			ttl:      Duration::from_secs(var("APP_CACHE_TTL_SECS", 10) as u64),
+			capacity: var("APP_CACHE_ENTRIES", 10_000),
+			inner:    Default::default(),
+		}
+	}
+
+	pub fn get(&self, key: &str) -> Option<serde_json::Value> {
+		let inner = self.inner.lock().unwrap();
+		inner.entries.get(key).filter(|e| e.at.elapsed() < self.ttl).map(|e| e.value.clone())
+	}
+
+	pub fn insert(&self, key: String, value: serde_json::Value, objects: Vec<String>) {
+		if self.ttl.is_zero() {
+			return
+		}
+		let mut inner = self.inner.lock().unwrap();
+		if inner.entries.len() >= self.capacity {
+			let expired = inner
+				.entries
+				.iter()
+				.filter(|(_, e)| e.at.elapsed() >= self.ttl)
+				.map(|(k, _)| k.clone())
+				.collect::<Vec<_>>();
+			for key in expired {
+				inner.remove(&key);
+			}
+			// still full of live entries: start over rather than tracking recency
+			if inner.entries.len() >= self.capacity {
+				*inner = Default::default();
+			}
+		}
+		inner.remove(&key);
+		for id in &objects {
+			inner.by_object.entry(id.clone()).or_default().insert(key.clone());
+		}
+		inner.entries.insert(key, Entry { at: Instant::now(), value, objects });
+	}
+
+	fn invalidate(&self, id: &str) {
+		let mut inner = self.inner.lock().unwrap();
+		for key in inner.by_object.remove(id).unwrap_or_default() {
+			inner.remove(&key);
+		}
+	}
+
+	fn clear(&self) {
+		*self.inner.lock().unwrap() = Default::default();
+	}
+
+	// Drops cached entries whenever an object they contain changes.
+	pub fn spawn_invalidation(self: &Arc<Self>, feed: &ChangeFeed) {
+		let cache = self.clone();
+		let mut rx = feed.subscribe();
+		tokio::spawn(async move {
+			loop {
+				match rx.recv().await {
+					Ok(change) => cache.invalidate(&change.object._id),
+					// we can't know which entries are affected by the changes we missed
+					Err(RecvError::Lagged(_)) => cache.clear(),
+					Err(RecvError::Closed) => break,
+				}
+			}
+		});
+	}
+}
@@ -0,0 +1,102 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::StreamExt;
+use mongodb::{
+	bson::{doc, Document},
+	change_stream::event::ResumeToken,
+	options::{ChangeStreamOptions, FullDocumentType},
+	Collection,
+};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+
+use crate::{canonical_type_prefix, ids::canonical_id, parse, types::glob_matches, SuiIndexedObject};
+
+// How many changes a slow subscriber may fall behind before it's disconnected.
+const CHANNEL_CAPACITY: usize = 4096;
+
+#[derive(Clone, Debug)]
+pub struct ObjectChange {
+	pub object:  SuiIndexedObject,
+	pub deleted: bool,
+}
+
+// Every object change loaded by the indexer, read from a MongoDB change stream on the objects collection and fanned
+// out to any number of subscribers. Change streams require MongoDB to run as a replica set (as Atlas does).
+pub struct ChangeFeed(broadcast::Sender<Arc<ObjectChange>>);
+
+impl ChangeFeed {
+	pub fn spawn(coll: Collection<Document>) -> Arc<Self> {
+		let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+		let feed = Arc::new(Self(tx.clone()));
+		tokio::spawn(async move {
+			let mut resume_token = None;
+			loop {
+				if let Err(err) = watch(&coll, &tx, &mut resume_token).await {
+					eprintln!("change stream failed, reconnecting: {:?}", err);
+				}
+				tokio::time::sleep(Duration::from_secs(5)).await;
+			}
+		});
+		feed
+	}
+
+	pub fn subscribe(&self) -> broadcast::Receiver<Arc<ObjectChange>> {
+		self.0.subscribe()
+	}
+}
+
+async fn watch(
+	coll: &Collection<Document>,
+	tx: &broadcast::Sender<Arc<ObjectChange>>,
+	resume_token: &mut Option<ResumeToken>,
+) -> anyhow::Result<()> {
+	let pipeline = [doc! { "$match": { "operationType": { "$in": ["insert", "update", "replace"] } } }];
+	let opts = ChangeStreamOptions::builder()
+		.full_document(Some(FullDocumentType::UpdateLookup))
+		.resume_after(resume_token.clone())
+		.build();
+	let mut changes = coll.watch(pipeline, opts).await?;
+	while let Some(event) = changes.next().await {
+		let event = event?;
+		*resume_token = changes.resume_token();
+		// deletions keep the last known object state, so we can still match them against filters
+		let Some(o) = event.full_document else { continue };
+		if !o.contains_key("object") {
+			continue
+		}
+		let deleted = o.get_bool("deleted").unwrap_or_default();
+		// no subscribers is fine
+		let _ = tx.send(Arc::new(ObjectChange { object: parse(&o), deleted }));
+	}
+	Ok(())
+}
+
+// Empty fields match everything.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ChangeFilter {
+	// full object type, `*` matches any sequence of characters
+	#[serde(rename = "type")]
+	pub type_:   Option<String>,
+	// address or object id
+	pub owner:   Option<String>,
+	pub package: Option<String>,
+}
+
+impl ChangeFilter {
+	// types, owners and packages are compared in their canonical forms, see ids.rs and types.rs
+	pub fn normalized(self) -> Self {
+		Self {
+			type_:   self.type_.as_deref().map(canonical_type_prefix),
+			owner:   self.owner.as_deref().map(canonical_id),
+			package: self.package.as_deref().map(canonical_id),
+		}
+	}
+
+	pub fn matches(&self, change: &ObjectChange) -> bool {
+		let o = &change.object;
+		self.type_.as_deref().map_or(true, |pattern| glob_matches(pattern, &o.type_.full))
+			&& self.owner.as_ref().map_or(true, |owner| o.owner.as_ref() == Some(owner))
+			&& self.package.as_ref().map_or(true, |package| &canonical_id(&o.type_.package) == package)
+	}
+}
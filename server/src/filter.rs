@@ -0,0 +1,339 @@
+use mongodb::bson::{doc, Bson, Document};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// Limits on filter size, so a single query can't get arbitrarily expensive.
+const MAX_DEPTH: usize = 4;
+const MAX_CONDITIONS: usize = 16;
+
+// Content fields that may be filtered on, as dot separated paths below `object.content.fields`. We create an index
+// for each of them on startup, so filters never result in full collection scans.
+// Configured via APP_FILTER_PATHS (comma separated).
+#[derive(Clone, Debug)]
+pub struct FilterPaths(pub Vec<String>);
+
+impl FilterPaths {
+	pub fn from_env() -> Self {
+		let paths = std::env::var("APP_FILTER_PATHS").unwrap_or("value.fields.owner".into());
+		Self(paths.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect())
+	}
+
+	pub fn mongo_path(path: &str) -> String {
+		format!("object.content.fields.{}", path)
+	}
+}
+
+// Filter on content fields, e.g.
+// {"and": [{"path": "value.fields.owner", "op": "eq", "value": "0xabc"}, {"path": "balance", "op": "gte", "value": "100"}]}
+// Sui encodes u64 and larger numbers as strings, so range comparisons (gt, gte, lt, lte) with a string value compare
+// numerically, and only take numeric strings. These can't use the path's index, so they're only accepted within an
+// `and` that also has an `eq` or `in` condition, directly or further up, which the index is used for instead.
+// Values are plain JSON: objects with keys starting with `$` are rejected, as they'd be taken as extended JSON (e.g.
+// `$regularExpression`) or operators.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ContentFilter {
+	And(AllOf),
+	Or(AnyOf),
+	Compare(Comparison),
+}
+
+// Untagged enums ignore `deny_unknown_fields`, so each variant is a struct of its own, to reject e.g. typos in `op`
+// or conditions mixing `and` with `path`, instead of silently matching a different variant.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllOf {
+	pub and: Vec<ContentFilter>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnyOf {
+	pub or: Vec<ContentFilter>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Comparison {
+	pub path:  String,
+	pub op:    CompareOp,
+	pub value: serde_json::Value,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareOp {
+	Eq,
+	Ne,
+	Gt,
+	Gte,
+	Lt,
+	Lte,
+	// value must be an array
+	In,
+	// value must be a bool
+	Exists,
+}
+
+#[derive(Error, Debug)]
+pub enum FilterError {
+	#[error("filtering on `{0}` is not allowed, allowed paths are: {1}")]
+	PathNotAllowed(String, String),
+	#[error("filter is too complex (max depth {MAX_DEPTH}, max {MAX_CONDITIONS} conditions)")]
+	TooComplex,
+	#[error("invalid value for `{0}`: {1}")]
+	InvalidValue(String, String),
+	#[error("`and` / `or` need at least one condition")]
+	Empty,
+	#[error("numeric range filters on `{0}` can't use an index, combine them with an `eq` or `in` condition in an `and`")]
+	Unindexed(String),
+}
+
+impl ContentFilter {
+	// Translates the filter into a MongoDB query document.
+	pub fn to_mongo(&self, paths: &FilterPaths) -> Result<Document, FilterError> {
+		let mut conditions = 0;
+		self.translate(paths, 0, false, &mut conditions)
+	}
+
+	// `indexed` tells whether the filter is part of an `and` with an equality condition, see `numeric_comparison()`.
+	fn translate(
+		&self,
+		paths: &FilterPaths,
+		depth: usize,
+		indexed: bool,
+		conditions: &mut usize,
+	) -> Result<Document, FilterError> {
+		if depth >= MAX_DEPTH {
+			return Err(FilterError::TooComplex)
+		}
+		match self {
+			Self::And(AllOf { and }) => {
+				let indexed = indexed || and.iter().any(Self::is_equality);
+				Ok(doc! { "$and": Self::translate_all(and, paths, depth, indexed, conditions)? })
+			}
+			Self::Or(AnyOf { or }) => Ok(doc! { "$or": Self::translate_all(or, paths, depth, indexed, conditions)? }),
+			Self::Compare(Comparison { path, op, value }) => {
+				*conditions += 1;
+				if *conditions > MAX_CONDITIONS {
+					return Err(FilterError::TooComplex)
+				}
+				if !paths.0.contains(path) {
+					return Err(FilterError::PathNotAllowed(path.clone(), paths.0.join(", ")))
+				}
+				let invalid = |msg: &str| FilterError::InvalidValue(path.clone(), msg.to_string());
+				if has_operator(value) {
+					return Err(invalid("keys starting with `$` aren't allowed"))
+				}
+				let value = Bson::try_from(value.clone()).map_err(|e| invalid(&e.to_string()))?;
+				let range = match op {
+					CompareOp::Gt => Some("$gt"),
+					CompareOp::Gte => Some("$gte"),
+					CompareOp::Lt => Some("$lt"),
+					CompareOp::Lte => Some("$lte"),
+					_ => None,
+				};
+				if let (Some(range), Bson::String(number)) = (range, &value) {
+					if !is_number(number) {
+						return Err(invalid("expected a number"))
+					}
+					if !indexed {
+						return Err(FilterError::Unindexed(path.clone()))
+					}
+					return Ok(numeric_comparison(path, range, number))
+				}
+				let condition = match op {
+					CompareOp::Eq => doc! { "$eq": value },
+					CompareOp::Ne => doc! { "$ne": value },
+					CompareOp::Gt => doc! { "$gt": value },
+					CompareOp::Gte => doc! { "$gte": value },
+					CompareOp::Lt => doc! { "$lt": value },
+					CompareOp::Lte => doc! { "$lte": value },
+					CompareOp::In if value.as_array().is_some() => doc! { "$in": value },
+					CompareOp::In => return Err(invalid("expected an array")),
+					CompareOp::Exists if value.as_bool().is_some() => doc! { "$exists": value },
+					CompareOp::Exists => return Err(invalid("expected a bool")),
+				};
+				Ok(doc! { FilterPaths::mongo_path(path): condition })
+			}
+		}
+	}
+
+	fn translate_all(
+		filters: &[ContentFilter],
+		paths: &FilterPaths,
+		depth: usize,
+		indexed: bool,
+		conditions: &mut usize,
+	) -> Result<Vec<Document>, FilterError> {
+		if filters.is_empty() {
+			return Err(FilterError::Empty)
+		}
+		filters.iter().map(|f| f.translate(paths, depth + 1, indexed, conditions)).collect()
+	}
+
+	fn is_equality(&self) -> bool {
+		matches!(self, Self::Compare(Comparison { op: CompareOp::Eq | CompareOp::In, .. }))
+	}
+}
+
+// Whether `value` has an object with a key starting with `$` anywhere.
+fn has_operator(value: &serde_json::Value) -> bool {
+	match value {
+		serde_json::Value::Object(map) => map.iter().any(|(key, value)| key.starts_with('$') || has_operator(value)),
+		serde_json::Value::Array(values) => values.iter().any(has_operator),
+		_ => false,
+	}
+}
+
+// Decimal numbers as Sui encodes them in strings, e.g. "100" or "-1.5".
+fn is_number(s: &str) -> bool {
+	let digits = s.strip_prefix('-').unwrap_or(s);
+	let (int, frac) = digits.split_once('.').unwrap_or((digits, "0"));
+	!int.is_empty() && !frac.is_empty() && int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+}
+
+// `path` `op` `number`, with both sides converted to decimals. Documents where the field is missing or isn't a
+// number never match, as null would compare lower than any number. This is an `$expr`, which can't use an index, so
+// it's only accepted next to an equality condition that can.
+fn numeric_comparison(path: &str, op: &str, number: &str) -> Document {
+	let field = doc! {
+		"$convert": {
+			"input": format!("${}", FilterPaths::mongo_path(path)),
+			"to": "decimal",
+			"onError": Bson::Null,
+			"onNull": Bson::Null,
+		}
+	};
+	doc! {
+		"$expr": {
+			"$and": [
+				{ "$isNumber": field.clone() },
+				{ op: [field, { "$toDecimal": number }] },
+			]
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn paths() -> FilterPaths {
+		FilterPaths(vec!["balance".into(), "value.fields.owner".into()])
+	}
+
+	fn parse(json: &str) -> Result<ContentFilter, serde_json::Error> {
+		serde_json::from_str(json)
+	}
+
+	fn compare(path: &str) -> String {
+		format!(r#"{{"path": "{}", "op": "eq", "value": "0xabc"}}"#, path)
+	}
+
+	#[test]
+	fn test_translate_equality() {
+		let filter = parse(r#"{"and": [{"path": "value.fields.owner", "op": "eq", "value": "0xabc"}]}"#).unwrap();
+		assert_eq!(
+			filter.to_mongo(&paths()).unwrap(),
+			doc! { "$and": [{ "object.content.fields.value.fields.owner": { "$eq": "0xabc" } }] }
+		);
+	}
+
+	// `range` in an `and` with an equality condition
+	fn with_equality(range: &str) -> String {
+		format!(r#"{{"and": [{}, {}]}}"#, compare("value.fields.owner"), range)
+	}
+
+	#[test]
+	fn test_range_on_string_is_numeric() {
+		let filter = parse(&with_equality(r#"{"path": "balance", "op": "gte", "value": "100"}"#)).unwrap();
+		let query = filter.to_mongo(&paths()).unwrap();
+		let range = query.get_array("$and").unwrap()[1].as_document().unwrap();
+		let and = range.get_document("$expr").unwrap().get_array("$and").unwrap();
+		let comparison = and[1].as_document().unwrap().get_array("$gte").unwrap();
+		assert_eq!(comparison[1], Bson::Document(doc! { "$toDecimal": "100" }));
+		// "9" would be greater than "100" lexicographically
+		for value in ["\"abc\"", "\"1e3\"", "\"\"", "\"1.\""] {
+			let filter = parse(&format!(r#"{{"path": "balance", "op": "lt", "value": {}}}"#, value)).unwrap();
+			assert!(matches!(filter.to_mongo(&paths()), Err(FilterError::InvalidValue(..))), "{}", value);
+		}
+		// numbers and other ops compare as they are
+		let filter = parse(r#"{"path": "balance", "op": "gt", "value": 5}"#).unwrap();
+		assert_eq!(filter.to_mongo(&paths()).unwrap(), doc! { "object.content.fields.balance": { "$gt": 5i64 } });
+	}
+
+	#[test]
+	fn test_numeric_range_needs_equality() {
+		let range = r#"{"path": "balance", "op": "gt", "value": "100"}"#;
+		let err = parse(range).unwrap().to_mongo(&paths()).unwrap_err();
+		assert!(matches!(err, FilterError::Unindexed(path) if path == "balance"));
+		// an equality in an `or` doesn't narrow down the other branches
+		let filter = parse(&format!(r#"{{"or": [{}, {}]}}"#, compare("value.fields.owner"), range)).unwrap();
+		assert!(matches!(filter.to_mongo(&paths()), Err(FilterError::Unindexed(..))));
+		let filter = parse(&format!(r#"{{"and": [{{"path": "balance", "op": "ne", "value": "1"}}, {}]}}"#, range));
+		assert!(matches!(filter.unwrap().to_mongo(&paths()), Err(FilterError::Unindexed(..))));
+		// but one further up does
+		let nested = format!(r#"{{"or": [{}, {}]}}"#, range, range);
+		assert!(parse(&with_equality(&nested)).unwrap().to_mongo(&paths()).is_ok());
+	}
+
+	#[test]
+	fn test_extended_json_rejected() {
+		for value in [
+			r#"{"$regularExpression": {"pattern": ".*", "options": ""}}"#,
+			r#"{"$numberLong": "1"}"#,
+			r#"[{"a": {"$gt": 1}}]"#,
+		] {
+			let filter = parse(&format!(r#"{{"path": "balance", "op": "eq", "value": {}}}"#, value)).unwrap();
+			assert!(matches!(filter.to_mongo(&paths()), Err(FilterError::InvalidValue(..))), "{}", value);
+		}
+		// plain objects are fine
+		let filter = parse(r#"{"path": "balance", "op": "eq", "value": {"a": 1}}"#).unwrap();
+		assert!(filter.to_mongo(&paths()).is_ok());
+	}
+
+	#[test]
+	fn test_is_number() {
+		assert!(is_number("0") && is_number("100") && is_number("-7") && is_number("1.5"));
+		assert!(!is_number("") && !is_number("-") && !is_number("1.") && !is_number(".5") && !is_number("0x10"));
+	}
+
+	#[test]
+	fn test_unknown_fields_rejected() {
+		assert!(parse(r#"{"path": "balance", "op": "eq", "value": "1", "extra": true}"#).is_err());
+		assert!(parse(r#"{"and": [], "path": "balance"}"#).is_err());
+		assert!(parse(r#"{"not": []}"#).is_err());
+		assert!(parse(r#"{"path": "balance", "op": "like", "value": "1"}"#).is_err());
+	}
+
+	#[test]
+	fn test_path_allowlist() {
+		let filter = parse(&compare("value.fields.secret")).unwrap();
+		let err = filter.to_mongo(&paths()).unwrap_err();
+		assert!(matches!(err, FilterError::PathNotAllowed(path, _) if path == "value.fields.secret"));
+		// nested conditions are checked, too
+		let filter = parse(&format!(r#"{{"or": [{}, {}]}}"#, compare("balance"), compare("value"))).unwrap();
+		assert!(matches!(filter.to_mongo(&paths()), Err(FilterError::PathNotAllowed(..))));
+	}
+
+	#[test]
+	fn test_limits() {
+		// MAX_DEPTH levels of nesting, the innermost being the comparison
+		let mut nested = compare("balance");
+		for _ in 1..MAX_DEPTH {
+			nested = format!(r#"{{"and": [{}]}}"#, nested);
+		}
+		assert!(parse(&nested).unwrap().to_mongo(&paths()).is_ok());
+		let deeper = format!(r#"{{"and": [{}]}}"#, nested);
+		assert!(matches!(parse(&deeper).unwrap().to_mongo(&paths()), Err(FilterError::TooComplex)));
+
+		let conditions = |n: usize| vec![compare("balance"); n].join(", ");
+		let filter = parse(&format!(r#"{{"or": [{}]}}"#, conditions(MAX_CONDITIONS))).unwrap();
+		assert!(filter.to_mongo(&paths()).is_ok());
+		let filter = parse(&format!(r#"{{"or": [{}]}}"#, conditions(MAX_CONDITIONS + 1))).unwrap();
+		assert!(matches!(filter.to_mongo(&paths()), Err(FilterError::TooComplex)));
+
+		assert!(matches!(parse(r#"{"and": []}"#).unwrap().to_mongo(&paths()), Err(FilterError::Empty)));
+	}
+}
@@ -0,0 +1,74 @@
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+
+use async_stream::stream;
+use futures::Stream;
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::changes::{ChangeFeed, ChangeFilter, ObjectChange};
+
+pub mod proto {
+	tonic::include_proto!("huracan");
+}
+
+use proto::{
+	objects_server::{Objects, ObjectsServer},
+	SubscribeRequest,
+};
+
+// Filtered firehose of object changes for backend services, see proto/huracan.proto.
+struct ObjectsService {
+	feed: Arc<ChangeFeed>,
+}
+
+fn non_empty(s: String) -> Option<String> {
+	if s.is_empty() { None } else { Some(s) }
+}
+
+fn to_proto(change: &ObjectChange) -> proto::ObjectChange {
+	let o = &change.object;
+	proto::ObjectChange {
+		id:      o._id.clone(),
+		version: o.version,
+		deleted: change.deleted,
+		r#type:  o.type_.full.clone(),
+		owner:   o.owner.clone().unwrap_or_default(),
+		json:    serde_json::to_string(o).unwrap_or_default(),
+	}
+}
+
+#[tonic::async_trait]
+impl Objects for ObjectsService {
+	type SubscribeStream = Pin<Box<dyn Stream<Item = Result<proto::ObjectChange, Status>> + Send>>;
+
+	async fn subscribe(&self, req: Request<SubscribeRequest>) -> Result<Response<Self::SubscribeStream>, Status> {
+		let req = req.into_inner();
+		let filter = ChangeFilter {
+			type_:   non_empty(req.type_pattern),
+			owner:   non_empty(req.owner),
+			package: non_empty(req.package),
+		}
+		.normalized();
+		let mut rx = self.feed.subscribe();
+		Ok(Response::new(Box::pin(stream! {
+			loop {
+				match rx.recv().await {
+					Ok(change) => if filter.matches(&change) {
+						yield Ok(to_proto(&change))
+					},
+					// clients need to know they missed something, so they can re-sync
+					Err(RecvError::Lagged(n)) => {
+						yield Err(Status::data_loss(format!("subscriber too slow, missed {} changes", n)));
+						break
+					}
+					Err(RecvError::Closed) => break,
+				}
+			}
+		})))
+	}
+}
+
+pub async fn serve(addr: SocketAddr, feed: Arc<ChangeFeed>) -> anyhow::Result<()> {
+	Server::builder().add_service(ObjectsServer::new(ObjectsService { feed })).serve(addr).await?;
+	Ok(())
+}
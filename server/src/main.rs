@@ -1,8 +1,9 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, sync::Arc};
 
 use actix_cors::Cors;
 use actix_web::{get, guard, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder, Result as WebResult};
 use async_graphql::{
+	connection::{Connection, Edge},
 	http::GraphiQLSource, ComplexObject, Context, EmptyMutation, Enum, InputObject, Json, Object, Schema, SimpleObject,
 	Subscription, Union, ID,
 };
@@ -22,6 +23,31 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use web::{resource, Data};
 
+use crate::{
+	cache::ResponseCache,
+	filter::{ContentFilter, FilterPaths},
+	ids::canonical_id,
+	types::{TypeAliases, TypePattern},
+};
+
+mod cache;
+mod changes;
+// The indexer's own modules for ids, types and history deltas, so we always read and compare them the way they're
+// stored. They only depend on each other and bson. We only need parts of them.
+#[allow(dead_code)]
+#[path = "../../core/src/delta.rs"]
+mod delta;
+mod filter;
+mod grpc;
+#[path = "../../core/src/ids.rs"]
+mod ids;
+mod rest;
+mod stats;
+#[allow(dead_code)]
+#[path = "../../core/src/types.rs"]
+mod types;
+mod ws;
+
 type RootSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
 
 struct QueryRoot;
@@ -43,6 +69,92 @@ struct ObjectArgsInput {
 	dynamic_field: Option<DynamicFieldFieldFilterInput>,
 }
 
+// All given filters must match. Unlike `ObjectArgsInput`, this is meant to be combined freely, and is used with
+// cursor based pagination (see `QueryRoot::objects_connection`).
+#[derive(InputObject, Default)]
+struct ObjectFilterInput {
+	ids:        Option<Vec<String>>,
+	// address or object id
+	owner:      Option<String>,
+	// type prefix, e.g. just $package, or $p::$module, or a full type
+	#[graphql(name = "type")]
+	type_:      Option<String>,
+	// individual type components, matching exactly
+	package:    Option<String>,
+	module:     Option<String>,
+	#[graphql(name = "struct")]
+	struct_:    Option<String>,
+	// a full type among the top-level type parameters, e.g. `0x2::sui::SUI` for all `$p::$m::$s<0x2::sui::SUI, ..>`
+	type_param: Option<String>,
+	// equality on content fields, e.g. { path: "balance", value: "100" }, paths must be in `FilterPaths`
+	fields:     Option<Vec<FieldFilterInput>>,
+	// arbitrary conditions on content fields, see `ContentFilter`
+	content:    Option<Json<ContentFilter>>,
+}
+
+#[derive(InputObject)]
+struct FieldFilterInput {
+	// dot separated path below the object's content fields, e.g. `value.fields.owner`
+	path:  String,
+	value: String,
+}
+
+impl ObjectFilterInput {
+	fn to_document(&self, paths: &FilterPaths) -> Result<Document, QueryError> {
+		let mut and = Vec::new();
+		if let Some(ids) = &self.ids {
+			and.push(doc! { "_id": { "$in": ids.iter().map(|id| canonical_id(id)).collect::<Vec<_>>() } });
+		}
+		if let Some(owner) = self.owner.as_deref().map(canonical_id) {
+			and.push(doc! { "$or": [ { "object.owner.AddressOwner": &owner }, { "object.owner.ObjectOwner": &owner } ] });
+		}
+		if let Some(ty) = &self.type_ {
+			and.push(doc! { "object.type": { "$regex": format!("^{}", escape_regex(&canonical_type_prefix(ty))) } });
+		}
+		// stored next to the object by the indexer, see its `utils::type_components()`
+		let package = self.package.as_deref().map(canonical_id);
+		for (key, component) in [
+			("type_package", &package),
+			("type_module", &self.module),
+			("type_struct", &self.struct_),
+			("type_params", &self.type_param),
+		] {
+			if let Some(component) = component {
+				and.push(doc! { key: component });
+			}
+		}
+		for field in self.fields.iter().flatten() {
+			if !paths.0.contains(&field.path) {
+				return Err(QueryError::InvalidFilter(format!("filtering on `{}` is not allowed", field.path)))
+			}
+			and.push(doc! { FilterPaths::mongo_path(&field.path): &field.value });
+		}
+		if let Some(content) = &self.content {
+			and.push(content.to_mongo(paths).map_err(|e| QueryError::InvalidFilter(e.to_string()))?);
+		}
+		Ok(if and.is_empty() { doc! {} } else { doc! { "$and": and } })
+	}
+}
+
+// A type or type prefix from a query in the form stored types have, whatever form of addresses it uses.
+fn canonical_type_prefix(ty: &str) -> String {
+	TypePattern::new(ty, &TypeAliases::default()).as_str().to_string()
+}
+
+// type prefixes are matched literally, so e.g. `0x2::coin::Coin<0x2::sui::SUI>` doesn't need escaping
+fn escape_regex(s: &str) -> String {
+	let mut escaped = String::with_capacity(s.len());
+	for c in s.chars() {
+		if "\\^$.|?*+()[]{}".contains(c) {
+			escaped.push('\\');
+		}
+		escaped.push(c);
+	}
+	escaped
+}
+
+const MAX_PAGE_SIZE: usize = 1000;
+
 #[derive(InputObject, Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
 struct DynamicFieldFieldFilterInput {
 	field_type: DynamicFieldTypeInput,
@@ -77,6 +189,12 @@ pub struct SuiIndexedObject {
 	pub type_:                  SuiIndexedType,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub owner:                  Option<String>,
+	// for items placed in a kiosk: the kiosk's owner, requires `kiosk.enabled` in the indexer
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub kiosk_owner:            Option<String>,
+	// set while the object is wrapped into another one, the digest of the wrapping transaction
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub wrapped_in_tx:          Option<String>,
 	pub ownership_type:         SuiOwnershipType,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub initial_shared_version: Option<u64>,
@@ -258,6 +376,8 @@ pub enum QueryError {
 	DbError(String),
 	#[error("invalid query")]
 	InvalidQuery,
+	#[error("invalid filter: {0}")]
+	InvalidFilter(String),
 }
 
 impl From<mongodb::error::Error> for QueryError {
@@ -270,7 +390,16 @@ impl From<mongodb::error::Error> for QueryError {
 impl QueryRoot {
 	async fn object(&self, ctx: &Context<'_>, id: ID) -> Result<Option<SuiIndexedObject>, QueryError> {
 		let c: &Collection<Document> = ctx.data_unchecked();
-		let res = c.find_one(doc! {"_id": id.to_string()}, None).await?.map(|o| parse(&o));
+		let cache: &Arc<ResponseCache> = ctx.data_unchecked();
+		let id = canonical_id(&id);
+		let key = format!("graphql_object:{}", id);
+		if let Some(cached) = cache.get(&key) {
+			return Ok(serde_json::from_value(cached).ok())
+		}
+		let res = c.find_one(doc! {"_id": &id}, None).await?.map(|o| parse(&o));
+		if let Some(o) = &res {
+			cache.insert(key, serde_json::to_value(o).unwrap_or_default(), vec![id]);
+		}
 		Ok(res)
 	}
 
@@ -279,16 +408,18 @@ impl QueryRoot {
 		let opts =
 			Some(FindOptions::builder().limit(args.limit.map(|l| l as i64)).skip(args.skip.map(|l| l as u64)).build());
 		match if let Some(ids) = args.ids {
+			let ids = ids.iter().map(|id| canonical_id(id)).collect::<Vec<_>>();
 			c.find(doc! {"_id": doc! {"$in": ids }}, opts).await
-		} else if let Some(owner) = args.owner {
+		} else if let Some(owner) = args.owner.as_deref().map(canonical_id) {
 			c.find(doc! { "$or": vec![doc! {"object.owner.AddressOwner": doc! {"$in": vec![owner.clone()] }}, doc! {"object.owner.ObjectOwner": doc! {"$in": vec![owner] }} ]}, opts).await
 		} else if let Some(owners) = args.owners {
+			let owners = owners.iter().map(|owner| canonical_id(owner)).collect::<Vec<_>>();
 			c.find(doc! { "$or": vec![doc! {"object.owner.AddressOwner": doc! {"$in": owners.clone() }}, doc! {"object.owner.ObjectOwner": doc! {"$in": owners }} ]}, opts).await
 		} else if let Some(ty) = args.type_ {
-			c.find(doc! {"object.type": doc! {"$regex": format!("^{}", ty) }}, opts).await
+			c.find(doc! {"object.type": doc! {"$regex": format!("^{}", canonical_type_prefix(&ty)) }}, opts).await
 		} else if let Some(types) = args.types {
 			c.find(
-				doc! {"object.type": doc! {"$regex": types.iter().map(|ty| format!("^{}", ty)).collect::<Vec<_>>().join("|") }},
+				doc! {"object.type": doc! {"$regex": types.iter().map(|ty| format!("^{}", canonical_type_prefix(ty))).collect::<Vec<_>>().join("|") }},
 				opts,
 			)
 			.await
@@ -346,6 +477,32 @@ impl QueryRoot {
 		}
 	}
 
+	// Relay style pagination over objects matching `filter`, ordered by id. Pass the `endCursor` of a page as
+	// `after` to get the next one.
+	async fn objects_connection(
+		&self,
+		ctx: &Context<'_>,
+		#[graphql(default)] filter: ObjectFilterInput,
+		after: Option<String>,
+		#[graphql(default = 50)] first: usize,
+	) -> Result<Connection<String, SuiIndexedObject>, QueryError> {
+		let c: &Collection<Document> = ctx.data_unchecked();
+		let paths: &FilterPaths = ctx.data_unchecked();
+		let first = first.clamp(1, MAX_PAGE_SIZE);
+		let mut filter = filter.to_document(paths)?;
+		if let Some(after) = &after {
+			filter = doc! { "$and": [ filter, { "_id": { "$gt": after } } ] };
+		}
+		// one more than requested, to know whether there's a next page
+		let opts = FindOptions::builder().sort(doc! { "_id": 1 }).limit(first as i64 + 1).build();
+		let mut docs: Vec<Document> = c.find(filter, opts).await?.try_collect().await?;
+		let has_next = docs.len() > first;
+		docs.truncate(first);
+		let mut connection = Connection::new(after.is_some(), has_next);
+		connection.edges.extend(docs.iter().map(parse).map(|o| Edge::new(o._id.clone(), o)));
+		Ok(connection)
+	}
+
 	// + owners
 	// async fn owner(&self, ctx: &Context<'_>, address: ID) -> String {
 	// 	let _c: &Collection<Document> = ctx.data_unchecked();
@@ -371,6 +528,8 @@ fn parse(o: &Document) -> SuiIndexedObject {
 	// items from top-level document
 	let id = o.get_str("_id").unwrap().to_string();
 	let version = o.get_i64("version_").unwrap() as u64;
+	let kiosk_owner = o.get_str("kiosk_owner").ok().map(String::from);
+	let wrapped_in_tx = o.get_str("wrapped_in_tx").ok().map(String::from);
 	// from here on we're working with the actual object in "object" field:
 	let o = o.get_document("object").unwrap();
 	// type
@@ -417,13 +576,14 @@ fn parse(o: &Document) -> SuiIndexedObject {
 		_ => (None, SuiOwnershipType::Immutable, None),
 	};
 
-	// fields: only for moveObject-s
-	let content = o.get_document("content").unwrap();
-	let fields =
-		if let Ok("moveObject") = content.get_str("dataType") { parse_fields(content) } else { Default::default() };
+	// fields: only for moveObject-s, and not available yet for objects stored without content (`bcsonly`)
+	let fields = match o.get_document("content") {
+		Ok(content) if matches!(stored_str(content, "dataType", "data_type"), Ok("moveObject")) => parse_fields(content),
+		_ => Default::default(),
+	};
 	// TODO move bcs into function, so we don't have to allocate + decode base64 unless asked for
 	let bcs = {
-		let bcs_val = o.get_document("bcs").unwrap().get_str("bcsBytes").unwrap();
+		let bcs_val = stored_str(o.get_document("bcs").unwrap(), "bcsBytes", "bcs_bytes").unwrap();
 		let mut bcs = vec![0u8; base64::decoded_len_estimate(bcs_val.len())];
 		base64::engine::general_purpose::STANDARD.decode_slice(bcs_val, &mut bcs).unwrap();
 		bcs
@@ -435,16 +595,24 @@ fn parse(o: &Document) -> SuiIndexedObject {
 		digest: o.get_str("digest").unwrap().to_string(),
 		type_: SuiIndexedType { full: full_ty.to_string(), package, module, struct_, generics },
 		owner,
+		kiosk_owner,
+		wrapped_in_tx,
 		ownership_type,
 		initial_shared_version,
-		previous_transaction: o.get_str("previousTransaction").unwrap().to_string(),
-		storage_rebate: o.get_str("storageRebate").ok().map(|v| v.parse().unwrap()),
+		previous_transaction: stored_str(o, "previousTransaction", "previous_transaction").unwrap().to_string(),
+		storage_rebate: stored_str(o, "storageRebate", "storage_rebate").ok().map(|v| v.parse().unwrap()),
 		fields,
 		bcs,
 	};
 	o
 }
 
+// A string field of a stored object, which the indexer may have stored under its snake_case name instead of the one
+// sui-sdk gives it, with `naming.style: snake`.
+pub(crate) fn stored_str<'a>(o: &'a Document, key: &str, snake_case: &str) -> bson::document::ValueAccessResult<&'a str> {
+	o.get_str(key).or_else(|_| o.get_str(snake_case))
+}
+
 fn parse_fields(o: &Document) -> BTreeMap<String, SuiMoveValue> {
 	o.get_document("fields").unwrap().iter().map(|(k, v)| (k.clone(), parse_value(v))).collect()
 }
@@ -514,7 +682,8 @@ async fn main() -> anyhow::Result<()> {
 	let env = std::env::var("APP_ENV").unwrap_or("dev".into());
 	let net = std::env::var("APP_NET").unwrap_or("testnet".into());
 
-	let coll = {
+	let filter_paths = FilterPaths::from_env();
+	let (coll, history, balances, owner_index, type_stats) = {
 		let mongo_uri = std::env::var("APP_MONGO_URI").unwrap();
 		let mongo_db = std::env::var("APP_MONGO_DB").unwrap_or("sui".into());
 		let mongo_collection = {
@@ -588,11 +757,46 @@ async fn main() -> anyhow::Result<()> {
 		.await
 		.unwrap();
 		println!("ensured index exists: capsules object owner");
-		coll
+		for path in &filter_paths.0 {
+			let path = FilterPaths::mongo_path(path);
+			coll.create_index(IndexModel::builder().keys(doc! { &path: 1 }).build(), None).await.unwrap();
+			println!("ensured index exists: {}", path);
+		}
+		// written by the indexer with history.enabled
+		let history = db.collection::<Document>(&format!("{}_history", mongo_collection));
+		// written by the indexer with balances.enabled
+		let balances = db.collection::<Document>(&format!("{}_balances", mongo_collection));
+		// written by the indexer with owners.enabled
+		let owner_index = db.collection::<Document>(&format!("{}_owner_index", mongo_collection));
+		// written by the indexer with typestats.enabled
+		let type_stats = db.collection::<Document>(&format!("{}_type_stats", mongo_collection));
+		(
+			coll,
+			Data::new(rest::HistoryCollection(history)),
+			Data::new(rest::BalancesCollection(balances)),
+			Data::new(rest::OwnerIndexCollection(owner_index)),
+			Data::new(stats::TypeStatsCollection(type_stats)),
+		)
 	};
 
+	let feed = changes::ChangeFeed::spawn(coll.clone());
+	let cache = Arc::new(cache::ResponseCache::from_env());
+	cache.spawn_invalidation(&feed);
+	let grpc_port = std::env::var("APP_GRPC_PORT").map_or(Ok(50051), |p| p.parse())?;
+	tokio::spawn({
+		let feed = feed.clone();
+		async move {
+			if let Err(err) = grpc::serve(([0, 0, 0, 0], grpc_port).into(), feed).await {
+				eprintln!("gRPC server failed: {:?}", err);
+			}
+		}
+	});
+	println!("gRPC server listening on port {}", grpc_port);
+
 	let schema = Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
-		.data(coll)
+		.data(coll.clone())
+		.data(filter_paths.clone())
+		.data(cache.clone())
 		// TODO activate later or on demand or something, don't need that noise for now
 		// .extension(async_graphql::extensions::ApolloTracing)
 		.limit_depth(10)
@@ -601,12 +805,27 @@ async fn main() -> anyhow::Result<()> {
 		.limit_complexity(1000)
 		.finish();
 
+	// shared between all workers
+	let stats_cache = Data::new(stats::StatsCache::from_env());
+
 	Ok(HttpServer::new(move || {
 		App::new()
 			.wrap(Cors::default().allow_any_origin().allow_any_method().allow_any_header())
 			.app_data(Data::new(schema.clone()))
+			.app_data(Data::new(coll.clone()))
+			.app_data(history.clone())
+			.app_data(balances.clone())
+			.app_data(owner_index.clone())
+			.app_data(type_stats.clone())
+			.app_data(Data::new(feed.clone()))
+			.app_data(Data::new(filter_paths.clone()))
+			.app_data(stats_cache.clone())
+			.app_data(Data::new(cache.clone()))
 			.service(
 				web::scope(API_PREFIX)
+					.configure(rest::configure)
+					.configure(stats::configure)
+					.service(ws::changes)
 					.service(index)
 					// not sure how to make this configuration line shorter, if at all possible
 					// actix-web doesn't seem to go very far in their support for config via attributes
@@ -0,0 +1,431 @@
+use std::sync::Arc;
+
+use actix_web::{get, web, HttpResponse, Responder};
+use futures_util::TryStreamExt;
+use mongodb::{
+	bson::{doc, Document},
+	options::{FindOneOptions, FindOptions},
+	Collection,
+};
+use serde::{Deserialize, Serialize};
+use web::{Data, Path, Query};
+
+use crate::{
+	cache::ResponseCache,
+	canonical_type_prefix, delta, escape_regex,
+	filter::{ContentFilter, FilterPaths},
+	ids::canonical_id,
+	parse, stored_str, SuiIndexedObject,
+};
+
+// Plain REST endpoints next to the GraphQL API, for clients that just want to look up objects without learning
+// GraphQL or our storage schema. Objects are returned in the same shape as via GraphQL.
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 1000;
+
+// Versions of each object, written by the indexer with `history.enabled`, e.g. prod_mainnet_objects_history.
+pub struct HistoryCollection(pub Collection<Document>);
+
+// Current owner of every object, written by the indexer with `owners.enabled`, e.g. prod_mainnet_objects_owner_index.
+pub struct OwnerIndexCollection(pub Collection<Document>);
+
+// Balance per address and coin type, written by the indexer with `balances.enabled`, e.g. prod_mainnet_objects_balances.
+pub struct BalancesCollection(pub Collection<Document>);
+
+#[derive(Serialize)]
+struct ErrorResponse {
+	error: String,
+}
+
+fn bad_request(error: &str) -> HttpResponse {
+	HttpResponse::BadRequest().json(ErrorResponse { error: error.to_string() })
+}
+
+fn db_error(err: mongodb::error::Error) -> HttpResponse {
+	HttpResponse::InternalServerError().json(ErrorResponse { error: format!("internal DB error: {:?}", err) })
+}
+
+fn limit(limit: Option<i64>) -> i64 {
+	limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+}
+
+#[derive(Deserialize)]
+struct ObjectQuery {
+	// return the object as of this checkpoint, i.e. its latest version at or before it
+	at_checkpoint: Option<u64>,
+	// return the object as of this version, i.e. its latest version at or before it
+	at_version:    Option<u64>,
+}
+
+// With `history.deltas` in the indexer, most versions only hold a patch against the version before them (see
+// delta.rs). Restores the objects of `versions` (all of object `id`, oldest first), fetching the earlier versions
+// their patches start from as needed.
+async fn restore(h: &HistoryCollection, id: &str, versions: &mut Vec<Document>) -> mongodb::error::Result<()> {
+	delta::resolve(versions);
+	let keyframe = versions
+		.iter()
+		.filter(|v| v.contains_key("patch") && !v.contains_key("object"))
+		.filter_map(|v| v.get_i64("keyframe_").ok())
+		.min();
+	let (Some(keyframe), Some(oldest)) = (keyframe, versions.first().and_then(|v| v.get_i64("version_").ok())) else {
+		return Ok(())
+	};
+	let opts = FindOptions::builder().sort(doc! { "version_": 1 }).build();
+	let filter = doc! { "object_id": id, "version_": { "$gte": keyframe, "$lt": oldest } };
+	let mut all = h.0.find(filter, opts).await?.try_collect::<Vec<_>>().await?;
+	let n = all.len();
+	all.append(versions);
+	delta::resolve(&mut all);
+	*versions = all.split_off(n);
+	Ok(())
+}
+
+fn not_found(error: &str) -> HttpResponse {
+	HttpResponse::NotFound().json(ErrorResponse { error: error.to_string() })
+}
+
+// With `at_checkpoint` or `at_version`, the object is looked up in the history collection (requires
+// `history.enabled` in the indexer). Versions ingested via polling don't know their checkpoint, so they're only
+// considered for `at_version`.
+#[get("/objects/{id}")]
+async fn object(
+	c: Data<Collection<Document>>,
+	h: Data<HistoryCollection>,
+	cache: Data<Arc<ResponseCache>>,
+	id: Path<String>,
+	q: Query<ObjectQuery>,
+) -> impl Responder {
+	let id = canonical_id(&id);
+	let filter = match (q.at_checkpoint, q.at_version) {
+		(None, None) => {
+			let key = format!("object:{}", id);
+			if let Some(cached) = cache.get(&key) {
+				return HttpResponse::Ok().json(cached)
+			}
+			return match c.find_one(doc! { "_id": &id }, None).await {
+				Ok(Some(o)) if o.contains_key("object") => {
+					let value = serde_json::to_value(parse(&o)).unwrap_or_default();
+					cache.insert(key, value.clone(), vec![id]);
+					HttpResponse::Ok().json(value)
+				}
+				Ok(_) => not_found("object not found"),
+				Err(err) => db_error(err),
+			}
+		}
+		(Some(cp), None) => doc! { "object_id": &id, "cp": { "$lte": cp as i64, "$gt": 0 } },
+		(None, Some(v)) => doc! { "object_id": &id, "version_": { "$lte": v as i64 } },
+		(Some(_), Some(_)) => return bad_request("only one of `at_checkpoint` or `at_version` may be given"),
+	};
+	let opts = FindOneOptions::builder().sort(doc! { "version_": -1 }).build();
+	let res = match h.0.find_one(filter, opts).await {
+		Ok(Some(v)) => {
+			let mut versions = vec![v];
+			restore(&h, &id, &mut versions).await.map(|_| versions.pop())
+		}
+		res => res,
+	};
+	match res {
+		Ok(Some(v)) if v.get_bool("deleted").unwrap_or_default() => not_found("object was deleted at that point"),
+		Ok(Some(v)) => match v.get_document("object") {
+			Ok(o) => {
+				let version = v.get_i64("version_").unwrap_or_default();
+				HttpResponse::Ok().json(parse(&doc! { "_id": &id, "version_": version, "object": o.clone() }))
+			}
+			Err(_) => not_found("no object data for that version"),
+		},
+		Ok(None) => not_found("object did not exist at that point"),
+		Err(err) => db_error(err),
+	}
+}
+
+#[derive(Deserialize)]
+struct ObjectsQuery {
+	// prefix of the object type, e.g. a package, `$package::$module` or a full type
+	#[serde(rename = "type")]
+	type_: Option<String>,
+	// address or object id owning the objects
+	owner: Option<String>,
+	// JSON encoded `ContentFilter`
+	#[serde(rename = "where")]
+	where_: Option<String>,
+	limit: Option<i64>,
+	skip:  Option<u64>,
+}
+
+#[get("/objects")]
+async fn objects(
+	c: Data<Collection<Document>>,
+	paths: Data<FilterPaths>,
+	cache: Data<Arc<ResponseCache>>,
+	q: Query<ObjectsQuery>,
+) -> impl Responder {
+	let q = q.into_inner();
+	// we only allow queries we have an index for
+	let mut filter = Document::new();
+	if let Some(ty) = &q.type_ {
+		filter.insert("object.type", doc! { "$regex": format!("^{}", escape_regex(&canonical_type_prefix(ty))) });
+	}
+	if let Some(owner) = q.owner.as_deref().map(canonical_id) {
+		filter.insert(
+			"$or",
+			vec![doc! { "object.owner.AddressOwner": &owner }, doc! { "object.owner.ObjectOwner": &owner }],
+		);
+	}
+	if let Some(where_) = &q.where_ {
+		let content = serde_json::from_str::<ContentFilter>(where_)
+			.map_err(|e| e.to_string())
+			.and_then(|f| f.to_mongo(&paths).map_err(|e| e.to_string()));
+		match content {
+			Ok(content) => filter.insert("$and", vec![content]),
+			Err(err) => return bad_request(&format!("invalid filter: {}", err)),
+		};
+	}
+	if filter.is_empty() {
+		return bad_request("at least one of `type`, `owner` or `where` is required")
+	}
+	let key = format!("objects:{}:{}:{}", filter, limit(q.limit), q.skip.unwrap_or_default());
+	if let Some(cached) = cache.get(&key) {
+		return HttpResponse::Ok().json(cached)
+	}
+	let opts = FindOptions::builder().limit(limit(q.limit)).skip(q.skip).sort(doc! { "_id": 1 }).build();
+	let res = match c.find(filter, opts).await {
+		Ok(cursor) => cursor.try_collect::<Vec<_>>().await,
+		Err(err) => Err(err),
+	};
+	match res {
+		Ok(docs) => {
+			let objects = docs.iter().filter(|o| o.contains_key("object")).map(parse).collect::<Vec<SuiIndexedObject>>();
+			let ids = objects.iter().map(|o| o._id.clone()).collect();
+			let value = serde_json::to_value(objects).unwrap_or_default();
+			cache.insert(key, value.clone(), ids);
+			HttpResponse::Ok().json(value)
+		}
+		Err(err) => db_error(err),
+	}
+}
+
+#[derive(Serialize)]
+struct ObjectVersion {
+	version:    u64,
+	checkpoint: Option<u64>,
+	deleted:    bool,
+	// unix timestamp in ms, if known
+	timestamp:  Option<i64>,
+	object:     Option<SuiIndexedObject>,
+}
+
+#[derive(Deserialize)]
+struct VersionsQuery {
+	limit: Option<i64>,
+	skip:  Option<u64>,
+}
+
+// latest version first
+#[get("/objects/{id}/versions")]
+async fn object_versions(h: Data<HistoryCollection>, id: Path<String>, q: Query<VersionsQuery>) -> impl Responder {
+	let id = canonical_id(&id);
+	let opts = FindOptions::builder().limit(limit(q.limit)).skip(q.skip).sort(doc! { "version_": -1 }).build();
+	let res = match h.0.find(doc! { "object_id": &id }, opts).await {
+		Ok(cursor) => cursor.try_collect::<Vec<_>>().await,
+		Err(err) => Err(err),
+	};
+	let mut versions = match res {
+		Ok(versions) => versions,
+		Err(err) => return db_error(err),
+	};
+	versions.reverse();
+	if let Err(err) = restore(&h, &id, &mut versions).await {
+		return db_error(err)
+	}
+	let versions = versions
+		.into_iter()
+		.rev()
+		.map(|v| {
+			let version = v.get_i64("version_").unwrap_or_default();
+			ObjectVersion {
+				version:    version as u64,
+				// versions ingested via polling don't know their checkpoint
+				checkpoint: v.get_i64("cp").ok().filter(|cp| *cp > 0).map(|cp| cp as u64),
+				deleted:    v.get_bool("deleted").unwrap_or_default(),
+				timestamp:  v.get_datetime("ts").ok().map(|ts| ts.timestamp_millis()),
+				// parse() expects the same layout as in the main collection
+				object:     v
+					.get_document("object")
+					.ok()
+					.map(|o| parse(&doc! { "_id": &id, "version_": version, "object": o.clone() })),
+			}
+		})
+		.collect::<Vec<_>>();
+	HttpResponse::Ok().json(versions)
+}
+
+#[derive(Serialize, Clone)]
+struct ProvenanceEvent {
+	// the transaction that produced this version, unknown for deletions
+	tx:         Option<String>,
+	version:    u64,
+	checkpoint: Option<u64>,
+	// unix timestamp in ms, if known
+	timestamp:  Option<i64>,
+}
+
+#[derive(Serialize)]
+struct Transfer {
+	#[serde(flatten)]
+	event: ProvenanceEvent,
+	from:  Option<String>,
+	to:    Option<String>,
+}
+
+#[derive(Serialize)]
+struct Provenance {
+	// the earliest version we know of, which is where the object was created if we've indexed it from its start
+	created:   Option<ProvenanceEvent>,
+	// all later versions, including transfers
+	mutations: Vec<ProvenanceEvent>,
+	transfers: Vec<Transfer>,
+	deleted:   Option<ProvenanceEvent>,
+}
+
+// Owner as stored in an object document: the owning address or object, "shared" or "immutable".
+fn owner_of(object: &Document) -> Option<String> {
+	let owner = object.get_document("owner").ok();
+	match owner {
+		Some(o) => o
+			.get_str("AddressOwner")
+			.or_else(|_| o.get_str("ObjectOwner"))
+			.map(String::from)
+			.ok()
+			.or_else(|| o.contains_key("Shared").then(|| "shared".to_string())),
+		None => object.get_str("owner").ok().map(|o| o.to_lowercase()),
+	}
+}
+
+// Derived from the versions in the history collection (requires `history.enabled` in the indexer): every version
+// knows the transaction that produced it, and its owner, so every change of owner between two versions is a transfer.
+#[get("/objects/{id}/provenance")]
+async fn object_provenance(h: Data<HistoryCollection>, id: Path<String>) -> impl Responder {
+	let id = canonical_id(&id);
+	let opts = FindOptions::builder().sort(doc! { "version_": 1 }).build();
+	let res = match h.0.find(doc! { "object_id": &id }, opts).await {
+		Ok(cursor) => cursor.try_collect::<Vec<_>>().await,
+		Err(err) => Err(err),
+	};
+	let mut versions = match res {
+		Ok(versions) if versions.is_empty() => return not_found("no versions known for this object"),
+		Ok(versions) => versions,
+		Err(err) => return db_error(err),
+	};
+	if let Err(err) = restore(&h, &id, &mut versions).await {
+		return db_error(err)
+	}
+	let mut provenance = Provenance { created: None, mutations: Vec::new(), transfers: Vec::new(), deleted: None };
+	let mut previous_owner = None;
+	for v in versions {
+		let object = v.get_document("object").ok();
+		let event = ProvenanceEvent {
+			tx:         object
+				.and_then(|o| stored_str(o, "previousTransaction", "previous_transaction").ok())
+				.map(String::from),
+			version:    v.get_i64("version_").unwrap_or_default() as u64,
+			// versions ingested via polling don't know their checkpoint
+			checkpoint: v.get_i64("cp").ok().filter(|cp| *cp > 0).map(|cp| cp as u64),
+			timestamp:  v.get_datetime("ts").ok().map(|ts| ts.timestamp_millis()),
+		};
+		if v.get_bool("deleted").unwrap_or_default() {
+			provenance.deleted = Some(event);
+			break
+		}
+		let Some(object) = object else { continue };
+		let owner = owner_of(object);
+		if provenance.created.is_none() {
+			provenance.created = Some(event);
+		} else {
+			if owner != previous_owner {
+				provenance.transfers.push(Transfer {
+					event: event.clone(),
+					from:  previous_owner.clone(),
+					to:    owner.clone(),
+				});
+			}
+			provenance.mutations.push(event);
+		}
+		previous_owner = owner;
+	}
+	HttpResponse::Ok().json(provenance)
+}
+
+#[derive(Deserialize)]
+struct BalancesQuery {
+	coin_type: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Balance {
+	coin_type: String,
+	// Sui encodes balances as strings, too
+	balance:   String,
+	coins:     i64,
+}
+
+// all balances of an address, or just the one of `coin_type`
+#[get("/balances/{address}")]
+async fn balances(b: Data<BalancesCollection>, address: Path<String>, q: Query<BalancesQuery>) -> impl Responder {
+	let mut filter = doc! { "address": canonical_id(&address) };
+	if let Some(coin_type) = &q.coin_type {
+		filter.insert("coin_type", coin_type);
+	}
+	let opts = FindOptions::builder().sort(doc! { "coin_type": 1 }).limit(MAX_LIMIT).build();
+	let res = match b.0.find(filter, opts).await {
+		Ok(cursor) => cursor.try_collect::<Vec<_>>().await,
+		Err(err) => Err(err),
+	};
+	match res {
+		Ok(docs) => HttpResponse::Ok().json(
+			docs.iter()
+				.map(|b| Balance {
+					coin_type: b.get_str("coin_type").unwrap_or_default().to_string(),
+					balance:   b.get_i64("balance").unwrap_or_default().to_string(),
+					coins:     b.get_i64("coins").unwrap_or_default(),
+				})
+				.collect::<Vec<_>>(),
+		),
+		Err(err) => db_error(err),
+	}
+}
+
+#[derive(Deserialize)]
+struct OwnedQuery {
+	limit: Option<i64>,
+	skip:  Option<u64>,
+}
+
+// ids of all objects currently owned by an address (or object), ordered by id
+#[get("/owners/{address}/objects")]
+async fn owned_objects(o: Data<OwnerIndexCollection>, address: Path<String>, q: Query<OwnedQuery>) -> impl Responder {
+	let opts = FindOptions::builder()
+		.limit(limit(q.limit))
+		.skip(q.skip)
+		.sort(doc! { "_id": 1 })
+		.projection(doc! { "_id": 1 })
+		.build();
+	let res = match o.0.find(doc! { "owner": canonical_id(&address) }, opts).await {
+		Ok(cursor) => cursor.try_collect::<Vec<_>>().await,
+		Err(err) => Err(err),
+	};
+	match res {
+		Ok(docs) => HttpResponse::Ok()
+			.json(docs.iter().filter_map(|d| d.get_str("_id").ok().map(String::from)).collect::<Vec<_>>()),
+		Err(err) => db_error(err),
+	}
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+	cfg.service(objects)
+		.service(object_versions)
+		.service(object_provenance)
+		.service(object)
+		.service(balances)
+		.service(owned_objects);
+}
@@ -0,0 +1,189 @@
+use std::{
+	collections::HashMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use actix_web::{get, web, HttpResponse, Responder};
+use futures_util::TryStreamExt;
+use mongodb::{
+	bson::{doc, Bson, DateTime, Document},
+	options::AggregateOptions,
+	Collection,
+};
+use serde::Deserialize;
+use web::{Data, Query};
+
+use crate::{canonical_type_prefix, escape_regex, rest::HistoryCollection};
+
+// Statistics per type, written by the indexer with `typestats.enabled`, e.g. prod_mainnet_objects_type_stats.
+pub struct TypeStatsCollection(pub Collection<Document>);
+
+// Common aggregates over the indexed objects. They can be expensive to compute, so results are cached for
+// APP_STATS_CACHE_SECS (default 60) per distinct request.
+pub struct StatsCache {
+	ttl:     Duration,
+	entries: Mutex<HashMap<String, (Instant, serde_json::Value)>>,
+}
+
+impl StatsCache {
+	pub fn from_env() -> Self {
+		let secs = std::env::var("APP_STATS_CACHE_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(60);
+		Self { ttl: Duration::from_secs(secs), entries: Mutex::new(HashMap::new()) }
+	}
+
+	async fn get_or_compute(
+		&self,
+		key: String,
+		c: &Collection<Document>,
+		pipeline: Vec<Document>,
+	) -> Result<serde_json::Value, mongodb::error::Error> {
+		if let Some((at, value)) = self.entries.lock().unwrap().get(&key) {
+			if at.elapsed() < self.ttl {
+				return Ok(value.clone())
+			}
+		}
+		let opts = AggregateOptions::builder().allow_disk_use(true).build();
+		let docs: Vec<Document> = c.aggregate(pipeline, opts).await?.try_collect().await?;
+		let value = Bson::Array(docs.into_iter().map(Bson::Document).collect()).into_relaxed_extjson();
+		let mut entries = self.entries.lock().unwrap();
+		entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+		entries.insert(key, (Instant::now(), value.clone()));
+		Ok(value)
+	}
+}
+
+fn respond(res: Result<serde_json::Value, mongodb::error::Error>) -> HttpResponse {
+	match res {
+		Ok(value) => HttpResponse::Ok().json(value),
+		Err(err) => HttpResponse::InternalServerError().json(doc! { "error": format!("internal DB error: {:?}", err) }),
+	}
+}
+
+// deleted objects keep their last state, but shouldn't be counted
+fn live() -> Document {
+	doc! { "deleted": { "$ne": true }, "object": { "$exists": true } }
+}
+
+#[derive(Deserialize)]
+struct TypesQuery {
+	limit: Option<i64>,
+}
+
+// Number of objects per type, most common first. If the indexer maintains type statistics, we just read those (which
+// also come with created / deleted totals and the last activity), otherwise we have to group the whole collection.
+#[get("/stats/types")]
+async fn types(
+	c: Data<Collection<Document>>,
+	s: Data<TypeStatsCollection>,
+	cache: Data<StatsCache>,
+	q: Query<TypesQuery>,
+) -> impl Responder {
+	let limit = q.limit.unwrap_or(100).clamp(1, 1000);
+	if s.0.estimated_document_count(None).await.map_or(false, |n| n > 0) {
+		let pipeline = vec![
+			doc! { "$sort": { "live": -1 } },
+			doc! { "$limit": limit },
+			doc! { "$project": {
+				"_id": 0,
+				"type": "$_id",
+				"count": "$live",
+				"created": 1,
+				"deleted": 1,
+				"last_activity": 1,
+			}},
+		];
+		return respond(cache.get_or_compute(format!("type_stats:{}", limit), &s.0, pipeline).await)
+	}
+	let pipeline = vec![
+		doc! { "$match": live() },
+		doc! { "$group": { "_id": "$object.type", "count": { "$sum": 1 } } },
+		doc! { "$sort": { "count": -1 } },
+		doc! { "$limit": limit },
+		doc! { "$project": { "_id": 0, "type": "$_id", "count": 1 } },
+	];
+	respond(cache.get_or_compute(format!("types:{}", limit), &c, pipeline).await)
+}
+
+#[derive(Deserialize)]
+struct HoldersQuery {
+	// full type of the collection's NFTs, e.g. `0xabc::capy::Capy`
+	#[serde(rename = "type")]
+	type_: String,
+}
+
+// Number of distinct addresses holding objects of a type, plus the total number of such objects.
+#[get("/stats/holders")]
+async fn holders(c: Data<Collection<Document>>, cache: Data<StatsCache>, q: Query<HoldersQuery>) -> impl Responder {
+	let mut filter = live();
+	filter.insert("object.type", canonical_type_prefix(&q.type_));
+	filter.insert("object.owner.AddressOwner", doc! { "$exists": true });
+	let pipeline = vec![
+		doc! { "$match": filter },
+		doc! { "$group": { "_id": "$object.owner.AddressOwner", "objects": { "$sum": 1 } } },
+		doc! { "$group": { "_id": Bson::Null, "holders": { "$sum": 1 }, "objects": { "$sum": "$objects" } } },
+		doc! { "$project": { "_id": 0, "holders": 1, "objects": 1 } },
+	];
+	respond(cache.get_or_compute(format!("holders:{}", q.type_), &c, pipeline).await)
+}
+
+#[derive(Deserialize)]
+struct SupplyQuery {
+	// coin type, e.g. `0x2::sui::SUI`
+	coin: String,
+}
+
+// Sum of the balances of all `Coin<T>` objects of a coin type. Sui encodes balances as strings, so we sum them up
+// as decimals, and return the total as a string as well.
+#[get("/stats/supply")]
+async fn supply(c: Data<Collection<Document>>, cache: Data<StatsCache>, q: Query<SupplyQuery>) -> impl Responder {
+	let mut filter = live();
+	filter.insert("object.type", format!("0x2::coin::Coin<{}>", canonical_type_prefix(&q.coin)));
+	let pipeline = vec![
+		doc! { "$match": filter },
+		doc! { "$group": {
+			"_id": Bson::Null,
+			"supply": { "$sum": { "$toDecimal": "$object.content.fields.balance" } },
+			"coins": { "$sum": 1 },
+		}},
+		doc! { "$project": { "_id": 0, "supply": { "$toString": "$supply" }, "coins": 1 } },
+	];
+	respond(cache.get_or_compute(format!("supply:{}", q.coin), &c, pipeline).await)
+}
+
+#[derive(Deserialize)]
+struct CreatedQuery {
+	days: Option<i64>,
+	// only count objects whose type starts with this
+	#[serde(rename = "type")]
+	type_: Option<String>,
+}
+
+// Number of objects created per day (UTC), for the last `days` days. An object's creation is its first version in
+// the history collection, so this requires `history.enabled` in the indexer, and only covers what it has seen.
+#[get("/stats/created")]
+async fn created(h: Data<HistoryCollection>, cache: Data<StatsCache>, q: Query<CreatedQuery>) -> impl Responder {
+	let days = q.days.unwrap_or(30).clamp(1, 365);
+	let since = DateTime::from_millis(DateTime::now().timestamp_millis() - days * 24 * 60 * 60 * 1000);
+	let mut filter = doc! { "ts": { "$exists": true } };
+	if let Some(ty) = &q.type_ {
+		filter.insert("object.type", doc! { "$regex": format!("^{}", escape_regex(&canonical_type_prefix(ty))) });
+	}
+	let pipeline = vec![
+		doc! { "$match": filter },
+		doc! { "$group": { "_id": "$object_id", "created": { "$min": "$ts" } } },
+		doc! { "$match": { "created": { "$gte": since } } },
+		doc! { "$group": {
+			"_id": { "$dateToString": { "format": "%Y-%m-%d", "date": "$created" } },
+			"count": { "$sum": 1 },
+		}},
+		doc! { "$sort": { "_id": 1 } },
+		doc! { "$project": { "_id": 0, "day": "$_id", "count": 1 } },
+	];
+	let key = format!("created:{}:{}", days, q.type_.as_deref().unwrap_or_default());
+	respond(cache.get_or_compute(key, &h.0, pipeline).await)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+	cfg.service(types).service(holders).service(supply).service(created);
+}
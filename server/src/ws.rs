@@ -0,0 +1,91 @@
+use std::{collections::HashMap, sync::Arc};
+
+use actix_web::{get, web, HttpRequest, HttpResponse, Result as WebResult};
+use actix_ws::Message;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use web::Data;
+
+use crate::{
+	changes::{ChangeFeed, ChangeFilter},
+	SuiIndexedObject,
+};
+
+// Live object changes over WebSocket, for dashboards and bots. Clients register any number of filters:
+//   -> {"op": "subscribe", "id": "coins", "filter": {"type": "0x2::coin::Coin<*>", "owner": "0x..."}}
+//   -> {"op": "unsubscribe", "id": "coins"}
+// and receive every change matching one of them, tagged with the id of the first matching filter:
+//   <- {"subscription": "coins", "deleted": false, "object": {...}}
+// Errors are reported as {"error": "..."}. Clients falling too far behind are disconnected.
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum ClientMessage {
+	Subscribe { id: String, filter: ChangeFilter },
+	Unsubscribe { id: String },
+}
+
+#[derive(Serialize)]
+struct ChangeMessage<'a> {
+	subscription: &'a str,
+	deleted:      bool,
+	object:       &'a SuiIndexedObject,
+}
+
+#[derive(Serialize)]
+struct ErrorMessage {
+	error: String,
+}
+
+#[get("/changes")]
+async fn changes(req: HttpRequest, body: web::Payload, feed: Data<Arc<ChangeFeed>>) -> WebResult<HttpResponse> {
+	let (res, mut session, mut msgs) = actix_ws::handle(&req, body)?;
+	let mut rx = feed.subscribe();
+	actix_web::rt::spawn(async move {
+		let mut filters: HashMap<String, ChangeFilter> = HashMap::new();
+		loop {
+			let reply = tokio::select! {
+				msg = msgs.next() => match msg {
+					Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+						Ok(ClientMessage::Subscribe { id, filter }) => {
+							filters.insert(id, filter.normalized());
+							continue
+						}
+						Ok(ClientMessage::Unsubscribe { id }) => {
+							filters.remove(&id);
+							continue
+						}
+						Err(err) => serde_json::to_string(&ErrorMessage { error: format!("invalid message: {}", err) }),
+					},
+					Some(Ok(Message::Ping(bytes))) => {
+						if session.pong(&bytes).await.is_err() {
+							return
+						}
+						continue
+					}
+					Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+					Some(Ok(_)) => continue,
+				},
+				change = rx.recv() => match change {
+					Ok(change) => {
+						let Some((id, _)) = filters.iter().find(|(_, f)| f.matches(&change)) else { continue };
+						let deleted = change.deleted;
+						serde_json::to_string(&ChangeMessage { subscription: id, deleted, object: &change.object })
+					}
+					Err(RecvError::Lagged(n)) => {
+						let error = format!("too slow, missed {} changes", n);
+						let _ = session.text(serde_json::to_string(&ErrorMessage { error }).unwrap_or_default()).await;
+						break
+					}
+					Err(RecvError::Closed) => break,
+				},
+			};
+			if session.text(reply.unwrap_or_default()).await.is_err() {
+				return
+			}
+		}
+		let _ = session.close(None).await;
+	});
+	Ok(res)
+}